@@ -0,0 +1,155 @@
+//! Shell completion scripts
+//!
+//! `doo completions <shell>` prints a completion script for bash, zsh, fish
+//! or PowerShell. Besides doo's own subcommands and flags, the scripts
+//! complete the commands of the current config: they call the hidden
+//! `doo __complete_commands` subcommand, which prints one command name per
+//! line, every time completion runs.
+//!
+//! Installation:
+//!
+//! - bash: add `source <(doo completions bash)` to `~/.bashrc`, or write the
+//!   script to `~/.local/share/bash-completion/completions/doo`
+//! - zsh: add `source <(doo completions zsh)` to `~/.zshrc` after `compinit`,
+//!   or write the script to a directory in `$fpath` as `_doo`
+//! - fish: `doo completions fish > ~/.config/fish/completions/doo.fish`
+//! - PowerShell: add `doo completions powershell | Out-String | Invoke-Expression`
+//!   to your `$PROFILE`
+
+use anyhow::{anyhow, Result};
+use clap::Command;
+use clap_complete::Shell;
+use std::io::Write;
+
+/// Name of the hidden subcommand that lists the configured commands
+pub const COMPLETE_COMMANDS: &str = "__complete_commands";
+
+/// Shells `doo completions` supports
+pub const SHELLS: &[&str] = &["bash", "zsh", "fish", "powershell"];
+
+/// Write the completion script for `shell` (one of [`SHELLS`]) to `out`
+pub fn write_completions(shell: &str, cmd: &mut Command, out: &mut dyn Write) -> Result<()> {
+    let bin_name = cmd.get_name().to_string();
+    let script = generate(shell, cmd, &bin_name)?;
+    out.write_all(script.as_bytes())?;
+    Ok(())
+}
+
+fn generate(shell: &str, cmd: &mut Command, bin_name: &str) -> Result<String> {
+    let parsed = match shell {
+        "bash" => Shell::Bash,
+        "zsh" => Shell::Zsh,
+        "fish" => Shell::Fish,
+        "powershell" => Shell::PowerShell,
+        _ => return Err(anyhow!("Unsupported shell '{}'", shell)),
+    };
+
+    let mut buffer = Vec::new();
+    clap_complete::generate(parsed, cmd, bin_name, &mut buffer);
+    let script = String::from_utf8(buffer)?;
+
+    let script = match parsed {
+        Shell::Bash => format!(
+            r#"{script}
+_{bin}_with_commands() {{
+    _{bin} "$@"
+    if [[ ${{COMP_CWORD}} -eq 1 ]]; then
+        COMPREPLY+=( $(compgen -W "$({bin} {COMPLETE_COMMANDS} 2>/dev/null)" -- "${{COMP_WORDS[1]}}") )
+    fi
+}}
+complete -F _{bin}_with_commands -o bashdefault -o default {bin}
+"#,
+            bin = bin_name
+        ),
+        Shell::Zsh => {
+            let function = zsh_commands_function(cmd, bin_name);
+            // Must come before the block that runs `_doo` when autoloaded
+            let marker = format!("if [ \"$funcstack[1]\" = \"_{bin_name}\" ]");
+            match script.rfind(&marker) {
+                Some(index) => format!("{}{}\n{}", &script[..index], function, &script[index..]),
+                None => format!("{script}\n{function}"),
+            }
+        }
+        Shell::Fish => format!(
+            "{script}complete -c {bin_name} -n \"__fish_use_subcommand\" -f -a \"({bin_name} {COMPLETE_COMMANDS} 2>/dev/null)\"\n"
+        ),
+        Shell::PowerShell => {
+            let case = format!("        '{bin_name}' {{\n");
+            let line = format!(
+                "            {bin_name} {COMPLETE_COMMANDS} 2>$null | ForEach-Object {{ [CompletionResult]::new($_, $_, [CompletionResultType]::ParameterValue, $_) }}\n"
+            );
+            script.replacen(&case, &format!("{case}{line}"), 1)
+        }
+        _ => script,
+    };
+    Ok(script)
+}
+
+/// Replacement for the generated `_doo_commands` that adds the configured
+/// commands to doo's own subcommands
+fn zsh_commands_function(cmd: &Command, bin_name: &str) -> String {
+    let builtins: String = cmd
+        .get_subcommands()
+        .filter(|sub| !sub.is_hide_set())
+        .map(|sub| {
+            let about = sub
+                .get_about()
+                .map(|about| about.to_string())
+                .unwrap_or_default();
+            format!(
+                "'{}:{}' \\\n",
+                sub.get_name(),
+                about.replace('\'', "'\\''").replace(':', "\\:")
+            )
+        })
+        .collect();
+    format!(
+        r#"_{bin}_commands() {{
+    local commands; commands=(
+{builtins}    )
+    local -a configured
+    configured=(${{(f)"$({bin} {COMPLETE_COMMANDS} 2>/dev/null)"}})
+    _describe -t commands '{bin} commands' commands "$@"
+    _describe -t configured-commands 'configured commands' configured "$@"
+}}
+"#,
+        bin = bin_name
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Arg;
+
+    fn cli() -> Command {
+        Command::new("doo")
+            .subcommand(Command::new("var").about("Manage variables"))
+            .subcommand(Command::new(COMPLETE_COMMANDS).hide(true))
+            .arg(Arg::new("name").index(1))
+    }
+
+    #[test]
+    fn test_scripts_complete_configured_commands() {
+        for shell in SHELLS {
+            let mut out = Vec::new();
+            write_completions(shell, &mut cli(), &mut out).unwrap();
+            let script = String::from_utf8(out).unwrap();
+            assert!(
+                script.contains("doo __complete_commands"),
+                "{shell} script doesn't list configured commands"
+            );
+        }
+        assert!(write_completions("tcsh", &mut cli(), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_zsh_override_precedes_autoload_call() {
+        let script = generate("zsh", &mut cli(), "doo").unwrap();
+        let function = script.rfind("_doo_commands() {").unwrap();
+        let autoload = script.rfind("if [ \"$funcstack[1]\" = \"_doo\" ]").unwrap();
+        assert!(function < autoload);
+        assert!(script.contains("'var:Manage variables'"));
+        assert!(!script[function..].contains("'__complete_commands:"));
+    }
+}
@@ -1,20 +1,383 @@
 use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
 use dialoguer::Confirm;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use std::cell::OnceCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs;
+use std::io::IsTerminal;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::Duration;
 use tempfile::TempDir;
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+use crate::history::DEFAULT_HISTORY_LIMIT;
+use crate::lock::ConfigLock;
+use crate::providers::{self, Provider, PROVIDER_TIMEOUT};
+use crate::util::{atomic_write, atomic_write_with_backup, read_or_recover};
+use crate::variables::{TemplateWarning, VariableManager};
+
+/// Top-level entries of the config directory that belong to doo and are
+/// included in backups
+const BACKUP_ENTRIES: &[&str] = &[
+    "config.yaml",
+    "config.toml",
+    "config.json",
+    "configs",
+    "variables",
+    "current_context",
+    "context_meta.yaml",
+];
+
+/// Syntax of a config file, chosen by its extension. All describe the same
+/// [`Config`]; in TOML, commands are a `[commands]` table of strings or
+/// inline tables. JSON is still read but deprecated in favor of YAML.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// Extension of files in this format, without the dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Yaml => "yaml",
+            Self::Toml => "toml",
+            Self::Json => "json",
+        }
+    }
+
+    /// Parse a `--format` value
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "yaml" | "yml" => Ok(Self::Yaml),
+            "toml" => Ok(Self::Toml),
+            "json" => Ok(Self::Json),
+            _ => Err(anyhow!("Unknown config format '{}'", name)),
+        }
+    }
+
+    pub fn parse(self, contents: &str) -> Result<Config> {
+        match self {
+            Self::Yaml => Ok(serde_yaml::from_str(contents)?),
+            Self::Toml => Ok(toml::from_str(contents)?),
+            Self::Json => Ok(serde_json::from_str(contents)?),
+        }
+    }
+
+    pub fn serialize(self, config: &Config) -> Result<String> {
+        match self {
+            Self::Yaml => serde_yaml::to_string(config).context("Failed to serialize config"),
+            Self::Toml => toml::to_string(config).context("Failed to serialize config"),
+            Self::Json => {
+                serde_json::to_string_pretty(config).context("Failed to serialize config")
+            }
+        }
+    }
+}
+
+/// [`ConfigFormat::Toml`] for `.toml` files, [`ConfigFormat::Json`] for
+/// `.json` files, YAML for everything else
+pub fn detect_format(path: &Path) -> ConfigFormat {
+    match path.extension() {
+        Some(ext) if ext.eq_ignore_ascii_case("toml") => ConfigFormat::Toml,
+        Some(ext) if ext.eq_ignore_ascii_case("json") => ConfigFormat::Json,
+        _ => ConfigFormat::Yaml,
+    }
+}
+
+/// The main config file: `config.yaml`, or else `config.toml` or
+/// `config.json` if one of them exists
+pub fn main_config_file(config_dir: &Path) -> PathBuf {
+    let yaml = config_dir.join("config.yaml");
+    if yaml.exists() {
+        return yaml;
+    }
+    [ConfigFormat::Toml, ConfigFormat::Json]
+        .iter()
+        .map(|format| config_dir.join(format!("config.{}", format.extension())))
+        .find(|path| path.exists())
+        .unwrap_or(yaml)
+}
+
+/// Parse the main config at `path`, offering to restore its backup like
+/// [`read_yaml_or_recover`](crate::util::read_yaml_or_recover)
+fn read_main_config(path: &Path, interactive: bool) -> Result<Config> {
+    let format = detect_format(path);
+    read_or_recover(path, interactive, |contents| format.parse(contents))
+}
+
+/// Name the project config goes by in place of a config name
+pub const PROJECT_CONFIG: &str = "project";
+
+/// Nearest `.doo.yaml` or `.doo/config.yaml` in `start` or one of its
+/// ancestors, found like git finds `.git`
+pub fn find_project_config(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .flat_map(|dir| [dir.join(".doo.yaml"), dir.join(".doo").join("config.yaml")])
+        .find(|path| path.is_file())
+}
+
+/// Schema comment that lets editors validate and complete config files
+const SCHEMA_COMMENT: &str =
+    "# yaml-language-server: $schema=https://bucket.u11g.com/doo-config.schema.json";
+
+/// Example commands of the `.doo.yaml` written by `doo init` without a terminal
+const PROJECT_CONFIG_EXAMPLES: &str = "\
+commands:
+  hello:
+    command: \"echo Hello from #name\"
+    description: Greet from the project config
+    defaults:
+      '#name': this project
+  changes: git status --short
+  recent: \"git log --oneline -n #1\"
+";
+
+/// Comment lines at the top of a project config
+fn project_config_header(name: Option<&str>, schema_comment: bool) -> String {
+    let mut header = String::new();
+    if schema_comment {
+        header.push_str(SCHEMA_COMMENT);
+        header.push('\n');
+    }
+    match name {
+        Some(name) => header.push_str(&format!("# Commands of the {name} project.")),
+        None => header.push_str("# Commands of this project."),
+    }
+    header.push_str(
+        " In this directory and below, they take\n\
+         # precedence over the commands of your main and imported configs.\n",
+    );
+    header
+}
+
+/// The project config directly in `dir`, if it has one
+pub fn project_config_in(dir: &Path) -> Option<PathBuf> {
+    [dir.join(".doo.yaml"), dir.join(".doo").join("config.yaml")]
+        .into_iter()
+        .find(|path| path.is_file())
+}
+
+/// What `doo init` writes to a project config
+#[derive(Debug, Clone, Default)]
+pub struct ProjectInit {
+    pub name: String,
+    /// Commands in the order they were entered
+    pub commands: Vec<(String, CommandEntry)>,
+    /// Start the file with the schema comment for editor support
+    pub schema_comment: bool,
+}
+
+/// Manifest of imported repository directories, stored in the configs directory
+pub const REPO_MANIFEST_FILE: &str = "repos.yaml";
+
+/// Environment variable with a GitHub personal access token, used by `doo
+/// sync` for configs imported with `--token` instead of asking for it
+pub const GITHUB_TOKEN_ENV: &str = "DOO_GITHUB_TOKEN";
+
+/// Base URL of the GitHub API. DOO_GITHUB_API_URL points doo at a different
+/// one, e.g. GitHub Enterprise or a test server.
+pub fn github_api_url() -> String {
+    std::env::var("DOO_GITHUB_API_URL").unwrap_or_else(|_| "https://api.github.com".to_string())
+}
+
+/// GET request to a GitHub API `path`, authenticated with `token` if given
+fn github_get(
+    client: &reqwest::Client,
+    path: &str,
+    token: Option<&str>,
+) -> reqwest::RequestBuilder {
+    let request = client
+        .get(format!("{}{}", github_api_url(), path))
+        .header("User-Agent", "doo-cli/0.1.0");
+    match token {
+        Some(token) => request.bearer_auth(token),
+        None => request,
+    }
+}
+
+/// Environment for git commands that sends `token` to GitHub. Passing it
+/// this way keeps it out of the process list and out of the cloned
+/// repository's config.
+fn git_token_env(token: &str) -> Vec<(&'static str, String)> {
+    let credentials = base64::encode(format!("x-access-token:{token}"));
+    vec![
+        ("GIT_TERMINAL_PROMPT", "0".to_string()),
+        ("GIT_CONFIG_COUNT", "1".to_string()),
+        (
+            "GIT_CONFIG_KEY_0",
+            "http.https://github.com/.extraheader".to_string(),
+        ),
+        (
+            "GIT_CONFIG_VALUE_0",
+            format!("Authorization: Basic {credentials}"),
+        ),
+    ]
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Config {
+    /// Written sorted by name, so saving the same config gives the same file
+    #[serde(serialize_with = "serialize_sorted")]
     pub commands: HashMap<String, CommandEntry>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub origin: Option<ConfigOrigin>,
+    /// Prefix the commands are run with, as `<namespace>:<command>` (set by
+    /// `doo import --as`, only honored in imported configs)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    /// Paths of external command providers (only honored in the main config)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub providers: Vec<String>,
+    /// Also use the `doo-provider-*` executables on `PATH` as providers
+    /// (only honored in the main config)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub discover_providers: bool,
+    /// Provide the entries of `./.env` as variables (only honored in the
+    /// main config)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub load_dotenv: bool,
+    /// Where `doo var --secret` keeps values (only honored in the main
+    /// config)
+    #[serde(default, skip_serializing_if = "SecretStore::is_file")]
+    pub secret_store: SecretStore,
+    /// Executed commands kept in the history, `0` to keep none (only
+    /// honored in the main config)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history_limit: Option<usize>,
+    /// Settings of contexts by name (only honored in the main config)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub contexts: BTreeMap<String, ContextSettings>,
+}
+
+/// Settings of a context in the `contexts` section of the main config
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ContextSettings {
+    /// Run when switching to the context
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_switch: Option<ContextHook>,
+    /// Environment variables set for every command run in the context;
+    /// values may use placeholders
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+}
+
+/// Command run by a context hook, where `#name` is the name of the context
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ContextHook {
+    /// Simple string form: on_switch: "command template"
+    Simple(String),
+    Detailed {
+        command: String,
+        /// Undo the switch if the command fails
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        strict: bool,
+    },
+}
+
+impl ContextHook {
+    pub fn command_str(&self) -> &str {
+        match self {
+            ContextHook::Simple(command) => command,
+            ContextHook::Detailed { command, .. } => command,
+        }
+    }
+    pub fn strict(&self) -> bool {
+        match self {
+            ContextHook::Simple(_) => false,
+            ContextHook::Detailed { strict, .. } => *strict,
+        }
+    }
+}
+
+/// Where secret variables are stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SecretStore {
+    /// `variables/<context>.secrets.yaml`, readable only by the current user
+    #[default]
+    File,
+    /// The OS keychain; the secrets file only holds `keyring:` references
+    Keyring,
+}
+
+impl SecretStore {
+    fn is_file(&self) -> bool {
+        *self == SecretStore::File
+    }
+}
+
+impl Config {
+    /// Name the command `name` of this config is run as
+    pub fn qualified_name(&self, name: &str) -> String {
+        match &self.namespace {
+            Some(namespace) => format!("{namespace}:{name}"),
+            None => name.to_string(),
+        }
+    }
+
+    /// Command that is run as `name`, which includes the namespace if the
+    /// config has one
+    pub fn command(&self, name: &str) -> Option<&CommandEntry> {
+        match &self.namespace {
+            Some(namespace) => name
+                .strip_prefix(namespace.as_str())
+                .and_then(|name| name.strip_prefix(':'))
+                .and_then(|name| self.commands.get(name)),
+            None => self.commands.get(name),
+        }
+    }
+
+    /// Commands of this config and `other` in one config. Commands both
+    /// define differently are conflicts and keep this config's definition;
+    /// identical definitions are not.
+    pub fn merge(&self, other: &Config) -> (Config, Vec<MergeConflict>) {
+        let mut commands = self.commands.clone();
+        let mut conflicts = Vec::new();
+        for (name, entry) in &other.commands {
+            match commands.get(name) {
+                Some(existing) if existing != entry => conflicts.push(MergeConflict {
+                    name: name.clone(),
+                    first: existing.clone(),
+                    second: entry.clone(),
+                }),
+                Some(_) => {}
+                None => {
+                    commands.insert(name.clone(), entry.clone());
+                }
+            }
+        }
+        conflicts.sort_by(|a, b| a.name.cmp(&b.name));
+        let merged = Config {
+            commands,
+            ..Config::default()
+        };
+        (merged, conflicts)
+    }
+}
+
+fn serialize_sorted<S: serde::Serializer>(
+    commands: &HashMap<String, CommandEntry>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    commands
+        .iter()
+        .collect::<BTreeMap<_, _>>()
+        .serialize(serializer)
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// Entries live in maps loaded once per run, so the size of the detailed
+// form doesn't matter and boxing it would only get in the way of matching
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum CommandEntry {
     /// Simple string form: name: "command template"
@@ -24,10 +387,153 @@ pub enum CommandEntry {
         command: String,
         #[serde(skip_serializing_if = "Option::is_none")]
         description: Option<String>,
+        /// Fallback values for placeholders, keyed by variable name (`"#1"`)
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        defaults: BTreeMap<String, String>,
+        /// Terminate the command if it runs longer than this many seconds
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout_secs: Option<u64>,
+        /// Run the command again if it exits non-zero
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        retry: Option<RetryConfig>,
+        /// Environment variables set for the command; values may use placeholders
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        env: BTreeMap<String, String>,
+        /// Working directory of the command; may use placeholders
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cwd: Option<String>,
+        /// Constraints for placeholder values, keyed like `defaults`
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        args: BTreeMap<String, ArgSpec>,
+        /// Quote substituted values so each stays a single argument
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        quote_args: bool,
+        /// Run the resolved command line with the user's shell
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        shell: bool,
+        /// Names, defaults and descriptions of placeholders, keyed like `defaults`
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        params: BTreeMap<String, ParamSpec>,
+        /// Labels for finding the command, e.g. `[kubernetes, debugging]`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tags: Option<Vec<String>>,
+        /// Example invocations, e.g. `doo logs my-pod -f`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        examples: Option<Vec<String>>,
     },
 }
 
+/// What a placeholder stands for, shown instead of the placeholder in the
+/// interactive menu and when asking for a value
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ParamSpec {
+    /// Readable name, e.g. `namespace` for `#1`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// `doo config validate` fails if the parameter has no default and no
+    /// stored value
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub required: bool,
+    /// Used when the placeholder has no value, like an entry in `defaults`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// How often a command is run again after exiting non-zero
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetryConfig {
+    /// Runs in total, the first one included
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubled for every further one
+    #[serde(default = "default_backoff_secs")]
+    pub backoff_secs: u32,
+}
+
+fn default_backoff_secs() -> u32 {
+    1
+}
+
+impl RetryConfig {
+    pub fn backoff(&self) -> Duration {
+        Duration::from_secs(self.backoff_secs.into())
+    }
+}
+
+/// Allowed values of a placeholder, checked after resolution
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ArgSpec {
+    /// Regular expression the value must match
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    /// The only values allowed; offered as a selection when prompting
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub choices: Vec<String>,
+}
+
+impl ArgSpec {
+    /// Fail with a message naming `placeholder` if `value` is not allowed
+    pub fn check(&self, command: &str, placeholder: &str, value: &str) -> Result<()> {
+        if !self.choices.is_empty() && !self.choices.iter().any(|choice| choice == value) {
+            return Err(anyhow!(
+                "Invalid value '{}' for {} of command '{}': expected one of {}",
+                value,
+                placeholder,
+                command,
+                self.choices.join(", ")
+            ));
+        }
+        if let Some(pattern) = &self.pattern {
+            let regex = regex::Regex::new(pattern).map_err(|e| {
+                anyhow!(
+                    "Invalid pattern for {} of command '{}': {}",
+                    placeholder,
+                    command,
+                    e
+                )
+            })?;
+            if !regex.is_match(value) {
+                return Err(anyhow!(
+                    "Invalid value '{}' for {} of command '{}': expected a value matching '{}'",
+                    value,
+                    placeholder,
+                    command,
+                    pattern
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
 impl CommandEntry {
+    /// A command with an optional description and tags, in the detailed form
+    /// only if it has either
+    pub fn new(command: &str, description: Option<&str>, tags: &[String]) -> Self {
+        match (description, tags) {
+            (None, []) => CommandEntry::Simple(command.to_string()),
+            _ => CommandEntry::Detailed {
+                command: command.to_string(),
+                description: description.map(str::to_string),
+                defaults: BTreeMap::new(),
+                timeout_secs: None,
+                retry: None,
+                env: BTreeMap::new(),
+                cwd: None,
+                args: BTreeMap::new(),
+                quote_args: false,
+                shell: false,
+                params: BTreeMap::new(),
+                tags: Some(tags.to_vec()).filter(|tags| !tags.is_empty()),
+                examples: None,
+            },
+        }
+    }
+
     pub fn command_str(&self) -> &str {
         match self {
             CommandEntry::Simple(s) => s,
@@ -40,18 +546,254 @@ impl CommandEntry {
             CommandEntry::Detailed { description, .. } => description.as_deref(),
         }
     }
+    pub fn defaults(&self) -> Option<&BTreeMap<String, String>> {
+        match self {
+            CommandEntry::Simple(_) => None,
+            CommandEntry::Detailed { defaults, .. } => Some(defaults),
+        }
+    }
+    pub fn timeout_secs(&self) -> Option<u64> {
+        match self {
+            CommandEntry::Simple(_) => None,
+            CommandEntry::Detailed { timeout_secs, .. } => *timeout_secs,
+        }
+    }
+    pub fn retry(&self) -> Option<&RetryConfig> {
+        match self {
+            CommandEntry::Simple(_) => None,
+            CommandEntry::Detailed { retry, .. } => retry.as_ref(),
+        }
+    }
+    pub fn env(&self) -> Option<&BTreeMap<String, String>> {
+        match self {
+            CommandEntry::Simple(_) => None,
+            CommandEntry::Detailed { env, .. } => Some(env),
+        }
+    }
+    pub fn cwd(&self) -> Option<&str> {
+        match self {
+            CommandEntry::Simple(_) => None,
+            CommandEntry::Detailed { cwd, .. } => cwd.as_deref(),
+        }
+    }
+    pub fn args(&self) -> Option<&BTreeMap<String, ArgSpec>> {
+        match self {
+            CommandEntry::Simple(_) => None,
+            CommandEntry::Detailed { args, .. } => Some(args),
+        }
+    }
+    pub fn quote_args(&self) -> bool {
+        match self {
+            CommandEntry::Simple(_) => false,
+            CommandEntry::Detailed { quote_args, .. } => *quote_args,
+        }
+    }
+    pub fn shell(&self) -> bool {
+        match self {
+            CommandEntry::Simple(_) => false,
+            CommandEntry::Detailed { shell, .. } => *shell,
+        }
+    }
+    pub fn params(&self) -> Option<&BTreeMap<String, ParamSpec>> {
+        match self {
+            CommandEntry::Simple(_) => None,
+            CommandEntry::Detailed { params, .. } => Some(params),
+        }
+    }
+    pub fn tags(&self) -> &[String] {
+        match self {
+            CommandEntry::Simple(_) => &[],
+            CommandEntry::Detailed { tags, .. } => tags.as_deref().unwrap_or_default(),
+        }
+    }
+    pub fn examples(&self) -> &[String] {
+        match self {
+            CommandEntry::Simple(_) => &[],
+            CommandEntry::Detailed { examples, .. } => examples.as_deref().unwrap_or_default(),
+        }
+    }
+    /// `defaults` extended with the defaults of `params`
+    fn all_defaults(&self) -> BTreeMap<String, String> {
+        let mut defaults = self.defaults().cloned().unwrap_or_default();
+        for (key, spec) in self.params().into_iter().flatten() {
+            if let Some(default) = &spec.default {
+                defaults
+                    .entry(key.clone())
+                    .or_insert_with(|| default.clone());
+            }
+        }
+        defaults
+    }
+    fn source(&self, name: &str, source_file: &str, kind: ConfigSource) -> CommandSource {
+        CommandSource {
+            name: name.to_string(),
+            command: self.command_str().to_string(),
+            description: self.description().map(|s| s.to_string()),
+            defaults: self.all_defaults(),
+            timeout_secs: self.timeout_secs(),
+            retry: self.retry().cloned(),
+            env: self.env().cloned().unwrap_or_default(),
+            cwd: self.cwd().map(str::to_string),
+            args: self.args().cloned().unwrap_or_default(),
+            quote_args: self.quote_args(),
+            shell: self.shell(),
+            params: self.params().cloned().unwrap_or_default(),
+            examples: self.examples().to_vec(),
+            source_file: source_file.to_string(),
+            kind,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ConfigOrigin {
-    pub repo: String, // owner/repo format
+    pub repo: String, // owner/repo format, or the URL of a config imported from one
     pub import_type: ImportType,
+    /// When the config was last imported or synced; `None` for configs
+    /// imported before doo recorded it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub synced_at: Option<DateTime<Utc>>,
+}
+
+impl ConfigOrigin {
+    /// Web address of the origin: `repo` itself for URLs, the GitHub page
+    /// for `owner/repo`
+    pub fn url(&self) -> String {
+        if self.repo.contains("://") {
+            self.repo.clone()
+        } else {
+            format!("https://github.com/{}", self.repo)
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum ImportType {
     Public,
     Private,
+    /// Private, accessed with a personal access token that is not stored
+    Token,
+}
+
+/// Kind of config a command is defined in, in order of precedence
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigSource {
+    /// `.doo.yaml` or `.doo/config.yaml` of the project doo runs in
+    ProjectLocal,
+    Main,
+    Imported,
+    Provider,
+}
+
+/// How a config file changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigChange {
+    Added,
+    Modified,
+    Removed,
+}
+
+/// Config file that changed while [`ConfigManager::watch`] was watching
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigChangeEvent {
+    /// `main`, `project` or the name of an imported config
+    pub name: String,
+    pub path: PathBuf,
+    pub change: ConfigChange,
+}
+
+/// Keeps [`ConfigManager::watch`] watching until it's dropped
+#[derive(Debug)]
+pub struct WatchHandle {
+    _watcher: RecommendedWatcher,
+}
+
+/// Config files below the watched directories, and which of them exist, to
+/// tell added files from modified ones
+struct WatchedConfigs {
+    config_dir: PathBuf,
+    configs_dir: PathBuf,
+    project_file: Option<PathBuf>,
+    existing: BTreeSet<PathBuf>,
+}
+
+impl WatchedConfigs {
+    fn new(config_dir: &Path, configs_dir: &Path, project_file: Option<&Path>) -> Self {
+        let mut watched = Self {
+            config_dir: canonical_path(config_dir),
+            configs_dir: canonical_path(configs_dir),
+            project_file: project_file.map(canonical_path),
+            existing: BTreeSet::new(),
+        };
+        let mut dirs = vec![watched.config_dir.clone(), watched.configs_dir.clone()];
+        if let Ok(entries) = fs::read_dir(&watched.configs_dir) {
+            dirs.extend(entries.flatten().map(|entry| entry.path()));
+        }
+        let mut files: Vec<PathBuf> = dirs
+            .iter()
+            .filter_map(|dir| fs::read_dir(dir).ok())
+            .flat_map(|entries| entries.flatten().map(|entry| entry.path()))
+            .collect();
+        files.extend(watched.project_file.clone());
+        watched.existing = files
+            .into_iter()
+            .filter(|path| path.is_file() && watched.config_name(path).is_some())
+            .collect();
+        watched
+    }
+
+    /// Name of the config read from `path`, `None` if doo doesn't read it
+    fn config_name(&self, path: &Path) -> Option<String> {
+        if self.project_file.as_deref() == Some(path) {
+            return Some(PROJECT_CONFIG.to_string());
+        }
+        let parent = path.parent()?;
+        let file_name = path.file_name()?.to_str()?;
+        if parent == self.config_dir {
+            let is_main = ["config.yaml", "config.toml", "config.json"].contains(&file_name);
+            return is_main.then(|| "main".to_string());
+        }
+        if !is_config_file(path) {
+            return None;
+        }
+        let stem = path.file_stem()?.to_str()?;
+        if parent == self.configs_dir {
+            return (file_name != REPO_MANIFEST_FILE).then(|| stem.to_string());
+        }
+        let dir_name = parent.file_name()?.to_str()?;
+        (parent.parent() == Some(self.configs_dir.as_path())
+            && !dir_name.starts_with('.')
+            && detect_format(path) != ConfigFormat::Json)
+            .then(|| format!("{dir_name}_{stem}"))
+    }
+
+    /// What happened to the config at `path`, if it's one
+    fn change(&mut self, path: &Path) -> Option<ConfigChangeEvent> {
+        let name = self.config_name(path)?;
+        let change = match (path.is_file(), self.existing.contains(path)) {
+            (true, true) => ConfigChange::Modified,
+            (true, false) => {
+                self.existing.insert(path.to_path_buf());
+                ConfigChange::Added
+            }
+            (false, true) => {
+                self.existing.remove(path);
+                ConfigChange::Removed
+            }
+            (false, false) => return None,
+        };
+        Some(ConfigChangeEvent {
+            name,
+            path: path.to_path_buf(),
+            change,
+        })
+    }
+}
+
+/// `path` with symlinks resolved, as file watchers report it on some systems
+fn canonical_path(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
 }
 
 #[derive(Debug, Clone)]
@@ -60,58 +802,404 @@ pub struct CommandSource {
     pub name: String,
     pub command: String,
     pub description: Option<String>,
+    /// Per-command placeholder defaults from the config, including those
+    /// of `params`
+    pub defaults: BTreeMap<String, String>,
+    pub timeout_secs: Option<u64>,
+    pub retry: Option<RetryConfig>,
+    /// Environment variables for the command, before placeholder resolution
+    pub env: BTreeMap<String, String>,
+    /// Working directory for the command, before placeholder resolution
+    pub cwd: Option<String>,
+    /// Constraints for placeholder values
+    pub args: BTreeMap<String, ArgSpec>,
+    /// Whether substituted values are quoted for the command line
+    pub quote_args: bool,
+    /// Whether the command line is run with the user's shell
+    pub shell: bool,
+    /// Names and descriptions of placeholders
+    pub params: BTreeMap<String, ParamSpec>,
+    /// Example invocations
+    pub examples: Vec<String>,
     pub source_file: String,
+    pub kind: ConfigSource,
 }
 
-#[derive(Debug, Clone)]
-pub struct CommandSearchResult {
-    pub name: String,
-    pub command: String,
-    pub description: Option<String>,
-}
+impl CommandSource {
+    /// Constraints for the placeholder with `label` (`#1`, `$1`, `#name`);
+    /// like in `defaults`, variables may be keyed without the `#`
+    pub fn arg_spec(&self, label: &str) -> Option<&ArgSpec> {
+        self.args
+            .get(label)
+            .or_else(|| label.strip_prefix('#').and_then(|name| self.args.get(name)))
+    }
 
-#[derive(Debug, Deserialize)]
-struct GitHubContent {
-    #[allow(dead_code)]
-    name: String,
-    content: String,
-    encoding: String,
+    /// Parameter spec for the placeholder with `label`, keyed like `args`
+    pub fn param_spec(&self, label: &str) -> Option<&ParamSpec> {
+        param_spec(&self.params, label)
+    }
+
+    /// The command template with named parameters shown as `<name>`
+    pub fn display_command(&self) -> String {
+        display_command(&self.command, &self.params)
+    }
+
+    /// Check the values substituted for placeholders, keyed by label,
+    /// against the command's `args` constraints
+    pub fn check_args(&self, values: &BTreeMap<String, String>) -> Result<()> {
+        for (label, value) in values {
+            if let Some(spec) = self.arg_spec(label) {
+                spec.check(&self.name, label, value)?;
+            }
+        }
+        Ok(())
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct GitHubRepo {
-    #[allow(dead_code)]
-    name: String,
-    #[allow(dead_code)]
-    description: Option<String>,
+/// Problem in a config file found by [`ConfigManager::validate`]. `file` is
+/// `main` for the main config and the config name for imported configs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The file can't be read or isn't a valid doo config
+    InvalidFile {
+        file: String,
+        message: String,
+    },
+    /// A command name appears more than once in the same file, so all but
+    /// the last definition are ignored
+    DuplicateCommand {
+        file: String,
+        name: String,
+    },
+    EmptyCommandName {
+        file: String,
+    },
+    /// A template that can't be run, e.g. an empty one or one with an
+    /// unterminated quote
+    InvalidTemplate {
+        file: String,
+        name: String,
+        message: String,
+    },
+    /// Text that looks like a placeholder but is never substituted, e.g. `${HOME`
+    MalformedPlaceholder {
+        file: String,
+        name: String,
+        placeholder: String,
+    },
+    /// A field of a detailed entry that doesn't fit the rest of it, e.g. a
+    /// default for a placeholder the template doesn't use
+    InconsistentEntry {
+        file: String,
+        name: String,
+        message: String,
+    },
+    /// A template that runs, but likely not as intended, e.g. `#3` without `#2`
+    SuspiciousTemplate {
+        file: String,
+        name: String,
+        warning: TemplateWarning,
+    },
+    /// A required parameter with neither a default nor a stored value
+    MissingRequiredParam {
+        file: String,
+        name: String,
+        param: String,
+    },
 }
 
-pub struct ConfigManager {
-    config_dir: PathBuf,
-    configs_dir: PathBuf,
-    main_config: Config,
-    imported_configs: HashMap<String, Config>,
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationError::InvalidFile { file, message } => write!(f, "{file}: {message}"),
+            ValidationError::DuplicateCommand { file, name } => {
+                write!(f, "{file}: command '{name}' is defined more than once")
+            }
+            ValidationError::EmptyCommandName { file } => {
+                write!(f, "{file}: a command has an empty name")
+            }
+            ValidationError::InvalidTemplate {
+                file,
+                name,
+                message,
+            }
+            | ValidationError::InconsistentEntry {
+                file,
+                name,
+                message,
+            } => write!(f, "{file}: command '{name}': {message}"),
+            ValidationError::MalformedPlaceholder {
+                file,
+                name,
+                placeholder,
+            } => write!(
+                f,
+                "{file}: command '{name}': malformed placeholder '{placeholder}'"
+            ),
+            ValidationError::SuspiciousTemplate {
+                file,
+                name,
+                warning,
+            } => write!(f, "{file}: command '{name}': {warning}"),
+            ValidationError::MissingRequiredParam { file, name, param } => write!(
+                f,
+                "{file}: command '{name}': required parameter {param} has no default and no stored value"
+            ),
+        }
+    }
 }
 
-#[allow(dead_code)]
-impl ConfigManager {
-    pub fn new() -> Result<Self> {
-        let config_dir = dirs::config_dir()
-            .context("Failed to get config directory")?
-            .join("doo");
+impl std::error::Error for ValidationError {}
 
-        // Create config directory if it doesn't exist
-        fs::create_dir_all(&config_dir).context("Failed to create config directory")?;
+/// Directory name, path and origin of a cloned repository
+type RepoSyncTarget = (String, PathBuf, ConfigOrigin);
 
-        // Create configs subdirectory for imported configs
-        let configs_dir = config_dir.join("configs");
-        fs::create_dir_all(&configs_dir).context("Failed to create configs directory")?;
+/// Command defined differently by both configs given to
+/// [`ConfigManager::merge_configs`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeConflict {
+    pub name: String,
+    pub first: CommandEntry,
+    pub second: CommandEntry,
+}
 
-        let config_file = config_dir.join("config.yaml");
-        let main_config = if config_file.exists() {
-            let contents =
-                fs::read_to_string(&config_file).context("Failed to read config file")?;
-            serde_yaml::from_str(&contents).context("Failed to parse config file")?
+/// Result of [`ConfigManager::merge_configs`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeOutcome {
+    /// The merged config was written with this many commands
+    Merged { commands: usize },
+    /// Commands both configs define differently; nothing was written
+    Conflicts(Vec<MergeConflict>),
+}
+
+/// Differences between a local config file and what syncing would write
+#[derive(Debug, Clone)]
+pub struct ConfigDiff {
+    /// Config name, or `directory/file` for a file of a repository
+    pub name: String,
+    /// Names of commands only in the remote config
+    pub added: Vec<String>,
+    /// Names of commands only in the local config
+    pub removed: Vec<String>,
+    /// Names of commands whose entries differ
+    pub changed: Vec<String>,
+    /// Unified diff of the file contents; empty if they are equal
+    pub unified: String,
+}
+
+impl ConfigDiff {
+    pub fn new(name: &str, local: &str, remote: &str) -> Self {
+        let commands = |contents: &str| {
+            serde_yaml::from_str::<Config>(contents)
+                .map(|config| config.commands)
+                .unwrap_or_default()
+        };
+        let (local_commands, remote_commands) = (commands(local), commands(remote));
+        let entry = |entry: &CommandEntry| serde_yaml::to_value(entry).ok();
+
+        let mut added: Vec<String> = remote_commands
+            .keys()
+            .filter(|name| !local_commands.contains_key(*name))
+            .cloned()
+            .collect();
+        let mut removed: Vec<String> = local_commands
+            .keys()
+            .filter(|name| !remote_commands.contains_key(*name))
+            .cloned()
+            .collect();
+        let mut changed: Vec<String> = local_commands
+            .iter()
+            .filter(|(name, local)| {
+                remote_commands
+                    .get(*name)
+                    .is_some_and(|remote| entry(local) != entry(remote))
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        added.sort();
+        removed.sort();
+        changed.sort();
+
+        let unified = if local == remote {
+            String::new()
+        } else {
+            similar::TextDiff::from_lines(local, remote)
+                .unified_diff()
+                .header(&format!("{name} (local)"), &format!("{name} (remote)"))
+                .to_string()
+        };
+
+        ConfigDiff {
+            name: name.to_string(),
+            added,
+            removed,
+            changed,
+            unified,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.unified.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandSearchResult {
+    pub name: String,
+    pub command: String,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+    /// Parameter specs of the command, for display
+    #[serde(skip)]
+    pub params: BTreeMap<String, ParamSpec>,
+    /// Example invocations, for display
+    #[serde(skip)]
+    pub examples: Vec<String>,
+}
+
+impl CommandSearchResult {
+    fn new(name: String, entry: &CommandEntry) -> Self {
+        Self {
+            name,
+            command: entry.command_str().to_string(),
+            description: entry.description().map(str::to_string),
+            tags: entry.tags().to_vec(),
+            params: entry.params().cloned().unwrap_or_default(),
+            examples: entry.examples().to_vec(),
+        }
+    }
+
+    /// Whether the name, template, description or one of the tags contains
+    /// `query`, ignoring case. Every command matches an empty query.
+    pub fn matches(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+        let contains = |text: &str| text.to_lowercase().contains(&query);
+        query.is_empty()
+            || contains(&self.name)
+            || contains(&self.command)
+            || self.description.as_deref().is_some_and(contains)
+            || self.tags.iter().any(|tag| contains(tag))
+    }
+
+    /// Whether the command has `tag`, ignoring case
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t.eq_ignore_ascii_case(tag))
+    }
+}
+
+/// Spec in `params` for the placeholder with `label` (`#1`, `$1`, `#name`);
+/// variables may be keyed without the `#`
+fn param_spec<'a>(params: &'a BTreeMap<String, ParamSpec>, label: &str) -> Option<&'a ParamSpec> {
+    params
+        .get(label)
+        .or_else(|| label.strip_prefix('#').and_then(|name| params.get(name)))
+}
+
+/// `template` with every placeholder that has a named parameter in `params`
+/// replaced by `<name>`, e.g. `kubectl -n <namespace> get #2`
+pub fn display_command(template: &str, params: &BTreeMap<String, ParamSpec>) -> String {
+    if params.is_empty() {
+        return template.to_string();
+    }
+    crate::template::parse(template)
+        .iter()
+        .map(|token| match token {
+            crate::template::Token::Literal(text) => text.clone(),
+            crate::template::Token::Placeholder(placeholder) => {
+                param_spec(params, &placeholder.label())
+                    .and_then(|spec| spec.name.as_deref())
+                    .map(|name| format!("<{name}>"))
+                    .unwrap_or_else(|| placeholder.raw.clone())
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubContent {
+    #[allow(dead_code)]
+    name: String,
+    content: String,
+    encoding: String,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize)]
+struct GitHubRepo {
+    #[allow(dead_code)]
+    name: String,
+    #[allow(dead_code)]
+    description: Option<String>,
+}
+
+/// Maps repository directories in `configs/` to the origin they were cloned
+/// from, so sync never has to guess from directory names
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct RepoManifest {
+    pub repos: BTreeMap<String, ConfigOrigin>,
+}
+
+impl RepoManifest {
+    fn load(configs_dir: &Path) -> Result<Self> {
+        let manifest_file = configs_dir.join(REPO_MANIFEST_FILE);
+        if !manifest_file.exists() {
+            return Ok(Self::default());
+        }
+        let contents =
+            fs::read_to_string(&manifest_file).context("Failed to read repository manifest")?;
+        serde_yaml::from_str(&contents).context("Failed to parse repository manifest")
+    }
+
+    fn save(&self, configs_dir: &Path) -> Result<()> {
+        let yaml_content =
+            serde_yaml::to_string(self).context("Failed to serialize repository manifest")?;
+        atomic_write(&configs_dir.join(REPO_MANIFEST_FILE), &yaml_content)
+            .context("Failed to write repository manifest")
+    }
+}
+
+/// Commands contributed by external providers, keyed by source label
+#[derive(Default)]
+struct PluginCommands {
+    configs: Vec<(Provider, Config)>,
+    /// Providers that failed to list their commands, with the error
+    errors: Vec<(String, String)>,
+}
+
+pub struct ConfigManager {
+    config_dir: PathBuf,
+    configs_dir: PathBuf,
+    main_config: Config,
+    imported_configs: HashMap<String, Config>,
+    /// File each imported config was loaded from
+    config_paths: HashMap<String, PathBuf>,
+    repo_manifest: RepoManifest,
+    /// Provider commands, loaded on first use and cached for this invocation
+    plugins: OnceCell<PluginCommands>,
+    /// GitHub token entered during this invocation; never written to disk
+    github_token: Option<String>,
+    /// Config of the project doo runs in and the file it was read from
+    project_config: Option<(PathBuf, Config)>,
+}
+
+#[allow(dead_code)]
+impl ConfigManager {
+    pub fn new() -> Result<Self> {
+        let config_dir = Self::default_config_dir()?;
+
+        // Create config directory if it doesn't exist
+        fs::create_dir_all(&config_dir).context("Failed to create config directory")?;
+
+        // Create configs subdirectory for imported configs
+        let configs_dir = config_dir.join("configs");
+        fs::create_dir_all(&configs_dir).context("Failed to create configs directory")?;
+
+        let config_file = main_config_file(&config_dir);
+        let main_config = if config_file.exists() {
+            crate::timed_span!("config.parse", path = config_file.display());
+            // Offer to restore config.yaml.bak if the config is broken
+            let interactive = std::io::stdin().is_terminal() && std::io::stderr().is_terminal();
+            read_main_config(&config_file, interactive)?
         } else {
             // Create default config with some examples
             let default_config = Config {
@@ -121,6 +1209,17 @@ impl ConfigManager {
                         CommandEntry::Detailed {
                             command: "watch kubectl -n #1 get pods".to_string(),
                             description: Some("Watch pods in current namespace (#1)".to_string()),
+                            defaults: BTreeMap::new(),
+                            timeout_secs: None,
+                            retry: None,
+                            env: BTreeMap::new(),
+                            cwd: None,
+                            args: BTreeMap::new(),
+                            quote_args: false,
+                            shell: false,
+                            params: BTreeMap::new(),
+                            tags: None,
+                            examples: None,
                         },
                     ),
                     (
@@ -137,27 +1236,195 @@ impl ConfigManager {
                     ),
                 ]),
                 origin: None, // Main config has no origin
+                namespace: None,
+                providers: Vec::new(),
+                discover_providers: false,
+                load_dotenv: false,
+                secret_store: SecretStore::File,
+                history_limit: None,
+                contexts: BTreeMap::new(),
             };
 
             let yaml_content = serde_yaml::to_string(&default_config)
                 .context("Failed to serialize default config")?;
-            fs::write(&config_file, yaml_content).context("Failed to write default config file")?;
+            atomic_write(&config_file, &yaml_content)
+                .context("Failed to write default config file")?;
 
             default_config
         };
 
-        // Load all imported configs from files and repository directories
-        let mut imported_configs = HashMap::new();
+        let mut manager = Self::load(config_dir, main_config)?;
+        if let Ok(cwd) = std::env::current_dir() {
+            manager.load_project_config(&cwd)?;
+        }
+        Ok(manager)
+    }
+
+    /// Load the project config found from `start` upwards, if there is one.
+    /// Its commands take precedence over those of all other configs.
+    pub fn load_project_config(&mut self, start: &Path) -> Result<()> {
+        self.project_config = None;
+        let Some(path) =
+            find_project_config(start).filter(|path| *path != main_config_file(&self.config_dir))
+        else {
+            return Ok(());
+        };
+        crate::timed_span!("config.parse", path = path.display());
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read project config: {path:?}"))?;
+        let config = ConfigFormat::Yaml
+            .parse(&contents)
+            .with_context(|| format!("Failed to parse project config: {path:?}"))?;
+        self.project_config = Some((path, config));
+        Ok(())
+    }
+
+    /// Create a `.doo.yaml` with example commands in `dir`, unless it
+    /// already has a project config
+    pub fn init_project_config(dir: &Path) -> Result<()> {
+        if let Some(existing) = project_config_in(dir) {
+            return Err(anyhow!("{} already exists", existing.display()));
+        }
+        let contents = format!(
+            "{}{PROJECT_CONFIG_EXAMPLES}",
+            project_config_header(None, true)
+        );
+        atomic_write(&dir.join(".doo.yaml"), &contents).context("Failed to write .doo.yaml")
+    }
+
+    /// Write `init` to the project config in `dir`, `.doo.yaml` unless it
+    /// has a `.doo/config.yaml`. With `merge`, the commands are added to
+    /// those already in the file, replacing any with the same name;
+    /// otherwise the file is replaced. Returns the file and the config
+    /// written to it.
+    pub fn write_project_config(
+        dir: &Path,
+        init: &ProjectInit,
+        merge: bool,
+    ) -> Result<(PathBuf, Config)> {
+        let existing = project_config_in(dir);
+        let path = existing.clone().unwrap_or_else(|| dir.join(".doo.yaml"));
+        let mut config = match existing {
+            Some(path) if merge => {
+                let contents = fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                ConfigFormat::Yaml
+                    .parse(&contents)
+                    .with_context(|| format!("Failed to parse {}", path.display()))?
+            }
+            _ => Config::default(),
+        };
+        for (name, entry) in &init.commands {
+            config.commands.insert(name.clone(), entry.clone());
+        }
+
+        let contents = format!(
+            "{}{}",
+            project_config_header(Some(&init.name), init.schema_comment),
+            ConfigFormat::Yaml.serialize(&config)?
+        );
+        atomic_write(&path, &contents)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok((path, config))
+    }
+
+    /// File of the loaded project config
+    pub fn project_config_file(&self) -> Option<&Path> {
+        self.project_config.as_ref().map(|(path, _)| path.as_path())
+    }
+
+    /// Call `callback` whenever the main config, an imported config or the
+    /// project config is added, modified or removed, so long-running callers
+    /// can reload. A single save can report the same change more than once.
+    /// Watching stops when the returned handle is dropped.
+    pub fn watch(
+        &self,
+        callback: impl Fn(ConfigChangeEvent) + Send + 'static,
+    ) -> Result<WatchHandle> {
+        let mut watched = WatchedConfigs::new(
+            &self.config_dir,
+            &self.configs_dir,
+            self.project_config_file(),
+        );
+        let config_dir = watched.config_dir.clone();
+        let project_dir = watched
+            .project_file
+            .as_deref()
+            .and_then(Path::parent)
+            .filter(|dir| !dir.starts_with(&config_dir))
+            .map(Path::to_path_buf);
+
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let Ok(event) = event else {
+                    return;
+                };
+                if event.kind.is_access() {
+                    return;
+                }
+                for path in &event.paths {
+                    if let Some(change) = watched.change(path) {
+                        callback(change);
+                    }
+                }
+            })
+            .context("Failed to start watching the configs")?;
+        watcher
+            .watch(&config_dir, RecursiveMode::Recursive)
+            .with_context(|| format!("Failed to watch {config_dir:?}"))?;
+        if let Some(project_dir) = project_dir {
+            watcher
+                .watch(&project_dir, RecursiveMode::NonRecursive)
+                .with_context(|| format!("Failed to watch {project_dir:?}"))?;
+        }
+        Ok(WatchHandle { _watcher: watcher })
+    }
+
+    fn project_commands(&self) -> Option<&HashMap<String, CommandEntry>> {
+        self.project_config
+            .as_ref()
+            .map(|(_, config)| &config.commands)
+    }
+
+    /// Build a manager around an already loaded main config and load all
+    /// imported configs from the configs directory
+    fn load(config_dir: PathBuf, main_config: Config) -> Result<Self> {
+        let configs_dir = config_dir.join("configs");
+        let repo_manifest = RepoManifest::load(&configs_dir)?;
+
+        let mut manager = Self {
+            config_dir,
+            configs_dir,
+            main_config,
+            imported_configs: HashMap::new(),
+            config_paths: HashMap::new(),
+            repo_manifest,
+            plugins: OnceCell::new(),
+            github_token: None,
+            project_config: None,
+        };
+        manager.load_imported_configs()?;
+        manager.migrate_repo_manifest()?;
+
+        Ok(manager)
+    }
+
+    /// Load all imported configs from files and repository directories
+    fn load_imported_configs(&mut self) -> Result<()> {
+        if !self.configs_dir.exists() {
+            return Ok(());
+        }
+        crate::timed_span!("config.scan", dir = self.configs_dir.display());
 
         // Load configs from files in configs directory
-        for entry in fs::read_dir(&configs_dir)? {
-            let entry = entry?;
-            let path = entry.path();
+        for entry in fs::read_dir(&self.configs_dir)? {
+            let path = entry?.path();
 
             if path.is_file()
+                && is_config_file(&path)
                 && path
-                    .extension()
-                    .is_some_and(|ext| ext == "yaml" || ext == "yml")
+                    .file_name()
+                    .is_some_and(|name| name != REPO_MANIFEST_FILE)
             {
                 let file_name = path
                     .file_stem()
@@ -165,68 +1432,136 @@ impl ConfigManager {
                     .context("Invalid config file name")?
                     .to_string();
 
+                crate::timed_span!("config.parse", path = path.display());
                 let contents = fs::read_to_string(&path)
                     .with_context(|| format!("Failed to read config file: {path:?}"))?;
-                let config: Config = serde_yaml::from_str(&contents)
+                let config = detect_format(&path)
+                    .parse(&contents)
                     .with_context(|| format!("Failed to parse config file: {path:?}"))?;
 
-                imported_configs.insert(file_name, config);
+                self.imported_configs.insert(file_name.clone(), config);
+                self.config_paths.insert(file_name, path);
             }
         }
 
         // Load configs from repository directories
-        for entry in fs::read_dir(&configs_dir)? {
-            let entry = entry?;
-            let path = entry.path();
+        for entry in fs::read_dir(&self.configs_dir)? {
+            let path = entry?.path();
+            let dir_name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string());
 
-            if path.is_dir() && !path.file_name().unwrap().to_str().unwrap().starts_with('.') {
-                // This is a repository directory, scan for YAML files
-                for repo_entry in fs::read_dir(&path)? {
-                    let repo_entry = repo_entry?;
-                    let repo_file_path = repo_entry.path();
-
-                    if repo_file_path.is_file()
-                        && repo_file_path
-                            .extension()
-                            .is_some_and(|ext| ext == "yaml" || ext == "yml")
-                    {
-                        let repo_name = path.file_name().unwrap().to_str().unwrap();
-                        let file_stem = repo_file_path
-                            .file_stem()
-                            .and_then(|name| name.to_str())
-                            .unwrap_or("config");
-
-                        // Create unique config name: repo_filename
-                        let config_name = format!("{repo_name}_{file_stem}");
-
-                        let contents = fs::read_to_string(&repo_file_path).with_context(|| {
-                            format!("Failed to read repo config file: {repo_file_path:?}")
-                        })?;
-
-                        if let Ok(config) = serde_yaml::from_str::<Config>(&contents) {
-                            // Only add if it's a valid doo config with commands
-                            if !config.commands.is_empty() {
-                                imported_configs.insert(config_name, config);
-                            }
-                        }
-                    }
+            if let Some(dir_name) = dir_name.filter(|name| path.is_dir() && !name.starts_with('.'))
+            {
+                self.load_repo_dir_configs(&path, &dir_name)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Load all doo configs from the root of a repository directory. Files that
+    /// aren't valid doo configs are skipped.
+    fn load_repo_dir_configs(&mut self, repo_path: &Path, dir_name: &str) -> Result<()> {
+        for entry in fs::read_dir(repo_path)? {
+            let path = entry?.path();
+            // Repositories are full of unrelated JSON files, like package.json
+            if !path.is_file()
+                || !is_config_file(&path)
+                || detect_format(&path) == ConfigFormat::Json
+            {
+                continue;
+            }
+
+            let file_stem = path
+                .file_stem()
+                .and_then(|name| name.to_str())
+                .unwrap_or("config");
+
+            // Create unique config name: repo_filename
+            let config_name = format!("{dir_name}_{file_stem}");
+
+            crate::timed_span!("config.parse", path = path.display());
+            let contents = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read repo config file: {path:?}"))?;
+
+            if let Ok(config) = detect_format(&path).parse(&contents) {
+                // Only add if it's a valid doo config with commands
+                if !config.commands.is_empty() {
+                    self.imported_configs.insert(config_name.clone(), config);
+                    self.config_paths.insert(config_name, path);
                 }
             }
         }
+        Ok(())
+    }
 
-        Ok(Self {
-            config_dir,
-            configs_dir,
-            main_config,
-            imported_configs,
+    /// Record origins of repository directories that were imported before the
+    /// manifest existed, inferred from the `origin` field of their configs
+    fn migrate_repo_manifest(&mut self) -> Result<()> {
+        let mut inferred = BTreeMap::new();
+
+        for (config_name, path) in &self.config_paths {
+            let Some(dir_name) = path
+                .parent()
+                .filter(|parent| *parent != self.configs_dir)
+                .and_then(|parent| parent.file_name())
+                .map(|name| name.to_string_lossy().to_string())
+            else {
+                continue;
+            };
+            if self.repo_manifest.repos.contains_key(&dir_name) {
+                continue;
+            }
+            if let Some(origin) = self
+                .imported_configs
+                .get(config_name)
+                .and_then(|config| config.origin.clone())
+            {
+                inferred.insert(dir_name, origin);
+            }
+        }
+
+        if inferred.is_empty() {
+            return Ok(());
+        }
+        self.update_repo_manifest(|manifest| {
+            for (dir_name, origin) in inferred {
+                manifest.repos.entry(dir_name).or_insert(origin);
+            }
         })
     }
 
+    /// Directory [`new`](Self::new) loads the configs from, without creating
+    /// or reading anything
+    pub fn default_config_dir() -> Result<PathBuf> {
+        // DOO_CONFIG_DIR points doo at a different config directory, e.g. for tests
+        match std::env::var_os("DOO_CONFIG_DIR") {
+            Some(dir) => Ok(PathBuf::from(dir)),
+            None => Ok(dirs::config_dir()
+                .context("Failed to get config directory")?
+                .join("doo")),
+        }
+    }
+
     pub fn config_dir(&self) -> &PathBuf {
         &self.config_dir
     }
 
+    /// Import the config file at `source_path`, in TOML or JSON if it ends in
+    /// `.toml` or `.json` and YAML otherwise. The file keeps its format in the
+    /// configs directory.
     pub fn import_config(&mut self, source_path: &str) -> Result<String> {
+        let format = detect_format(Path::new(source_path));
+        self.import_config_file(source_path, format)
+    }
+
+    /// Import the TOML config file at `source_path`, whatever its extension
+    pub fn import_config_toml(&mut self, source_path: &str) -> Result<String> {
+        self.import_config_file(source_path, ConfigFormat::Toml)
+    }
+
+    fn import_config_file(&mut self, source_path: &str, format: ConfigFormat) -> Result<String> {
         let source_path = PathBuf::from(source_path);
 
         if !source_path.exists() {
@@ -239,34 +1574,221 @@ impl ConfigManager {
         // Read and validate the config
         let contents =
             fs::read_to_string(&source_path).context("Failed to read source config file")?;
-        let config: Config =
-            serde_yaml::from_str(&contents).context("Failed to parse source config file")?;
+        let config = format
+            .parse(&contents)
+            .context("Failed to parse source config file")?;
 
         // Generate a unique filename
-        let base_name = source_path
-            .file_stem()
-            .and_then(|name| name.to_str())
-            .unwrap_or("imported");
+        let base_name = sanitize_path_component(
+            source_path
+                .file_stem()
+                .and_then(|name| name.to_str())
+                .unwrap_or("imported"),
+        );
 
+        let _lock = ConfigLock::acquire(&self.config_dir)?;
         let mut target_name = base_name.to_string();
         let mut counter = 1;
 
         // Find a unique name if there's a conflict
-        while self.imported_configs.contains_key(&target_name) {
+        while self.config_name_taken(&target_name) {
             target_name = format!("{base_name}_{counter}");
             counter += 1;
         }
 
         // Copy the file to configs directory
-        let target_path = self.configs_dir.join(format!("{target_name}.yaml"));
+        let target_path = self
+            .configs_dir
+            .join(format!("{target_name}.{}", format.extension()));
         fs::copy(&source_path, &target_path).context("Failed to copy config file")?;
 
         // Add to imported configs
         self.imported_configs.insert(target_name.clone(), config);
+        self.config_paths.insert(target_name.clone(), target_path);
 
         Ok(target_name)
     }
 
+    /// Check that an imported config can be stored under `namespace`
+    pub fn check_namespace(&self, namespace: &str) -> Result<()> {
+        check_namespace_name(namespace)?;
+        if self.config_name_taken(namespace) {
+            return Err(anyhow!(
+                "A config named '{}' already exists; choose another namespace",
+                namespace
+            ));
+        }
+        Ok(())
+    }
+
+    fn config_name_taken(&self, name: &str) -> bool {
+        self.imported_configs.contains_key(name)
+            || [ConfigFormat::Yaml, ConfigFormat::Toml, ConfigFormat::Json]
+                .iter()
+                .any(|format| {
+                    self.configs_dir
+                        .join(format!("{name}.{}", format.extension()))
+                        .exists()
+                })
+    }
+
+    /// Store the imported config `config_name` under `namespace` and run its
+    /// commands as `<namespace>:<command>`. Returns the new config name.
+    pub fn namespace_config(&mut self, config_name: &str, namespace: &str) -> Result<String> {
+        if self.is_repo_config(config_name) {
+            return Err(anyhow!(
+                "Config '{}' belongs to a repository and can't be namespaced",
+                config_name
+            ));
+        }
+        if namespace != config_name {
+            self.check_namespace(namespace)?;
+        } else {
+            check_namespace_name(namespace)?;
+        }
+
+        let _lock = ConfigLock::acquire(&self.config_dir)?;
+        let mut config = self
+            .imported_configs
+            .remove(config_name)
+            .ok_or_else(|| anyhow!("Config '{}' not found", config_name))?;
+        config.namespace = Some(namespace.to_string());
+        let format = self
+            .config_paths
+            .get(config_name)
+            .map_or(ConfigFormat::Yaml, |path| detect_format(path));
+        let content = format.serialize(&config)?;
+        let target_path = self
+            .configs_dir
+            .join(format!("{namespace}.{}", format.extension()));
+        atomic_write(&target_path, &content).context("Failed to write config file")?;
+        if let Some(path) = self
+            .config_paths
+            .remove(config_name)
+            .filter(|path| *path != target_path)
+        {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+
+        self.imported_configs.insert(namespace.to_string(), config);
+        self.config_paths.insert(namespace.to_string(), target_path);
+        Ok(namespace.to_string())
+    }
+
+    /// Rewrite the imported config `config_name`, or main for the main
+    /// config, in `format` and remove the old file. Returns the new file.
+    pub fn convert_config(&mut self, config_name: &str, format: ConfigFormat) -> Result<PathBuf> {
+        if self.is_repo_config(config_name) {
+            return Err(anyhow!(
+                "Config '{}' belongs to a repository and can't be converted",
+                config_name
+            ));
+        }
+
+        let _lock = ConfigLock::acquire(&self.config_dir)?;
+        let (source, target) = if config_name == "main" {
+            let source = main_config_file(&self.config_dir);
+            if source.exists() {
+                self.main_config = read_main_config(&source, false)?;
+            }
+            let target = self
+                .config_dir
+                .join(format!("config.{}", format.extension()));
+            (source, target)
+        } else {
+            let source = self
+                .config_paths
+                .get(config_name)
+                .cloned()
+                .ok_or_else(|| anyhow!("Config '{}' not found", config_name))?;
+            let target = self
+                .configs_dir
+                .join(format!("{config_name}.{}", format.extension()));
+            (source, target)
+        };
+        if detect_format(&source) == format {
+            return Err(anyhow!(
+                "Config '{}' is already {}",
+                config_name,
+                format.extension()
+            ));
+        }
+        if target.exists() {
+            return Err(anyhow!("{} already exists", target.display()));
+        }
+
+        let config = if config_name == "main" {
+            &self.main_config
+        } else {
+            &self.imported_configs[config_name]
+        };
+        atomic_write(&target, &format.serialize(config)?).context("Failed to write config file")?;
+        if source.exists() {
+            fs::remove_file(&source)
+                .with_context(|| format!("Failed to remove {}", source.display()))?;
+        }
+        if config_name != "main" {
+            self.config_paths
+                .insert(config_name.to_string(), target.clone());
+        }
+        Ok(target)
+    }
+
+    /// Names of the configs read from JSON files, which are deprecated,
+    /// including main
+    pub fn json_configs(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .config_paths
+            .iter()
+            .filter(|(_, path)| detect_format(path) == ConfigFormat::Json)
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+        if detect_format(&main_config_file(&self.config_dir)) == ConfigFormat::Json {
+            names.insert(0, "main".to_string());
+        }
+        names
+    }
+
+    /// Combine the imported configs `config_a` and `config_b` into a new
+    /// config `output_name`. The result has no origin, so it isn't synced;
+    /// the source configs are kept.
+    pub fn merge_configs(
+        &mut self,
+        config_a: &str,
+        config_b: &str,
+        output_name: &str,
+    ) -> Result<MergeOutcome> {
+        let config = |name: &str| {
+            self.imported_configs
+                .get(name)
+                .ok_or_else(|| anyhow!("Config '{}' not found", name))
+        };
+        let (merged, conflicts) = config(config_a)?.merge(config(config_b)?);
+        if sanitize_path_component(output_name) != output_name {
+            return Err(anyhow!("Invalid config name '{}'", output_name));
+        }
+
+        let _lock = ConfigLock::acquire(&self.config_dir)?;
+        if self.config_name_taken(output_name) {
+            return Err(anyhow!("A config named '{}' already exists", output_name));
+        }
+        if !conflicts.is_empty() {
+            return Ok(MergeOutcome::Conflicts(conflicts));
+        }
+
+        let yaml_content = serde_yaml::to_string(&merged).context("Failed to serialize config")?;
+        let target_path = self.configs_dir.join(format!("{output_name}.yaml"));
+        atomic_write(&target_path, &yaml_content).context("Failed to write merged config")?;
+        let commands = merged.commands.len();
+        self.imported_configs
+            .insert(output_name.to_string(), merged);
+        self.config_paths
+            .insert(output_name.to_string(), target_path);
+        Ok(MergeOutcome::Merged { commands })
+    }
+
     pub async fn import_config_from_github(&mut self, repo: &str) -> Result<String> {
         // Parse repository format (owner/repo)
         let parts: Vec<&str> = repo.split('/').collect();
@@ -286,7 +1808,7 @@ impl ConfigManager {
         }
 
         // First try public API access
-        match self.import_from_public_github(owner, repo_name).await {
+        match self.import_from_public_github(owner, repo_name, None).await {
             Ok(result) => Ok(result),
             Err(e) => {
                 // Check if it might be a private repository or access issue
@@ -304,98 +1826,122 @@ impl ConfigManager {
         }
     }
 
-    async fn import_from_public_github(&mut self, owner: &str, repo_name: &str) -> Result<String> {
-        let client = reqwest::Client::new();
-        client
-            .get("https://api.github.com/user")
-            .header("User-Agent", "doo-cli/0.1.0")
-            .send()
+    /// Import the `doo.yaml` of a private GitHub repository through the API,
+    /// authenticated with a personal access token. The token is not stored;
+    /// `doo sync` reads it from [`GITHUB_TOKEN_ENV`] or asks for it.
+    pub async fn import_config_from_github_with_token(
+        &mut self,
+        repo: &str,
+        token: &str,
+    ) -> Result<String> {
+        let Some((owner, repo_name)) = repo
+            .split_once('/')
+            .filter(|(owner, name)| !owner.is_empty() && !name.is_empty() && !name.contains('/'))
+        else {
+            return Err(anyhow!(
+                "Invalid GitHub repository format. Expected: owner/repo (e.g., username/my-configs)"
+            ));
+        };
+        self.import_from_public_github(owner, repo_name, Some(token))
             .await
-            .map_err(|_| {
-                anyhow!("Failed to connect to GitHub. Please check your internet connection")
-            })?;
+    }
 
-        // First, verify the repository exists
-        let repo_url = format!("https://api.github.com/repos/{owner}/{repo_name}");
-        let repo_response = client
-            .get(&repo_url)
-            .header("User-Agent", "doo-cli/0.1.0")
-            .send()
-            .await
-            .map_err(|_| anyhow!("Failed to connect to GitHub API"))?;
+    /// Import the config file at an `http://` or `https://` URL. The URL is
+    /// kept as the origin, so `doo sync` fetches it again.
+    pub async fn import_config_from_url(&mut self, url: &str) -> Result<String> {
+        let config_content = self.fetch_url_config_content(url).await?;
+        // Name the config after the file in the URL path
+        let file_stem = url
+            .split(['?', '#'])
+            .next()
+            .and_then(|path| path.rsplit('/').next())
+            .and_then(|file| Path::new(file).file_stem())
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("imported");
 
-        if repo_response.status() == 404 {
-            return Err(anyhow!(
-                "Repository '{}/{}' not found. Please check:\n  • Repository exists\n  • Repository is public\n  • Repository name is spelled correctly", 
-                owner, repo_name
-            ));
-        } else if !repo_response.status().is_success() {
-            return Err(anyhow!(
-                "Failed to access repository '{}/{}': HTTP {}",
-                owner,
-                repo_name,
-                repo_response.status()
-            ));
-        }
-
-        // Look for doo.yaml or doo.yml in the repository root
-        let config_files = ["doo.yaml", "doo.yml"];
-        let mut config_content = None;
-
-        for config_file in config_files {
-            let file_url =
-                format!("https://api.github.com/repos/{owner}/{repo_name}/contents/{config_file}");
+        self.save_imported_config(file_stem, &config_content, url, ImportType::Public)
+    }
 
-            let response = client
-                .get(&file_url)
-                .header("User-Agent", "doo-cli/0.1.0")
+    async fn import_from_public_github(
+        &mut self,
+        owner: &str,
+        repo_name: &str,
+        token: Option<&str>,
+    ) -> Result<String> {
+        crate::timed_future!("github.fetch", repo = format!("{owner}/{repo_name}"); async {
+            let client = reqwest::Client::new();
+            github_get(&client, "/user", token)
                 .send()
                 .await
-                .map_err(|_| anyhow!("Failed to fetch config file from GitHub"))?;
-
-            if response.status().is_success() {
-                let github_content: GitHubContent = response
-                    .json()
-                    .await
-                    .map_err(|_| anyhow!("Failed to parse GitHub API response"))?;
+                .map_err(|_| {
+                    anyhow!("Failed to connect to GitHub. Please check your internet connection")
+                })?;
 
-                if github_content.encoding == "base64" {
-                    let decoded_content = base64::decode(github_content.content.replace('\n', ""))
-                        .map_err(|_| anyhow!("Failed to decode base64 content from GitHub"))?;
-
-                    config_content = Some(
-                        String::from_utf8(decoded_content)
-                            .map_err(|_| anyhow!("Config file contains invalid UTF-8"))?,
-                    );
-                    break;
-                }
+            // First, verify the repository exists
+            let repo_response = github_get(&client, &format!("/repos/{owner}/{repo_name}"), token)
+                .send()
+                .await
+                .map_err(|_| anyhow!("Failed to connect to GitHub API"))?;
+
+            if repo_response.status() == 404 && token.is_some() {
+                return Err(anyhow!(
+                    "Repository '{}/{}' not found. Please check:\n  • Repository exists\n  • The token has read access to it\n  • Repository name is spelled correctly",
+                    owner, repo_name
+                ));
+            } else if repo_response.status() == 404 {
+                return Err(anyhow!(
+                    "Repository '{}/{}' not found. Please check:\n  • Repository exists\n  • Repository is public\n  • Repository name is spelled correctly", 
+                    owner, repo_name
+                ));
+            } else if repo_response.status() == 401 {
+                return Err(anyhow!(
+                    "GitHub rejected the token for '{}/{}': HTTP {}",
+                    owner,
+                    repo_name,
+                    repo_response.status()
+                ));
+            } else if !repo_response.status().is_success() {
+                return Err(anyhow!(
+                    "Failed to access repository '{}/{}': HTTP {}",
+                    owner,
+                    repo_name,
+                    repo_response.status()
+                ));
             }
-        }
 
-        let config_content = config_content.ok_or_else(|| {
-            anyhow!(
-                "No doo configuration file found in repository '{}/{}'. \
-                Expected 'doo.yaml' or 'doo.yml' in the repository root.\n\
-                \nTo create a shareable config repository:\n\
-                1. Create a new GitHub repository\n\
-                2. Add a 'doo.yaml' file with your commands:\n\
-                   ```yaml\n\
-                   commands:\n\
-                     command-name: \"command template with #1 #2\"\n\
-                   ```\n\
-                3. Make the repository public\n\
-                4. Share the repository with: doo import owner/repo-name",
-                owner,
-                repo_name
-            )
-        })?;
+            // Look for doo.yaml or doo.yml in the repository root
+            let config_content = fetch_github_config(&client, owner, repo_name, token).await?;
+
+            let config_content = config_content.ok_or_else(|| {
+                anyhow!(
+                    "No doo configuration file found in repository '{}/{}'. \
+                    Expected 'doo.yaml' or 'doo.yml' in the repository root.\n\
+                    \nTo create a shareable config repository:\n\
+                    1. Create a new GitHub repository\n\
+                    2. Add a 'doo.yaml' file with your commands:\n\
+                       ```yaml\n\
+                       commands:\n\
+                         command-name: \"command template with #1 #2\"\n\
+                       ```\n\
+                    3. Make the repository public\n\
+                    4. Share the repository with: doo import owner/repo-name",
+                    owner,
+                    repo_name
+                )
+            })?;
 
-        self.save_imported_config(
-            repo_name,
-            &config_content,
-            &format!("{owner}/{repo_name}"),
-            ImportType::Public,
-        )
+            let import_type = match token {
+                Some(_) => ImportType::Token,
+                None => ImportType::Public,
+            };
+            self.save_imported_config(
+                repo_name,
+                &config_content,
+                &format!("{owner}/{repo_name}"),
+                import_type,
+            )
+        })
+        .await
     }
 
     async fn import_from_private_github(&mut self, owner: &str, repo_name: &str) -> Result<String> {
@@ -430,6 +1976,7 @@ impl ConfigManager {
         let mut last_error = String::new();
 
         for git_url in &git_urls {
+            crate::timed_span!("git.clone", url = git_url);
             println!("📥 Trying to clone: {git_url}");
 
             let clone_result = Command::new("git")
@@ -537,15 +2084,23 @@ impl ConfigManager {
         config.origin = Some(ConfigOrigin {
             repo: repo.to_string(),
             import_type,
+            synced_at: Some(Utc::now()),
         });
 
         // Generate a unique filename based on the repository name
-        let mut target_name = repo_name.to_string();
+        let _lock = ConfigLock::acquire(&self.config_dir)?;
+        let base_name = sanitize_path_component(repo_name);
+        let mut target_name = base_name.clone();
         let mut counter = 1;
 
         // Find a unique name if there's a conflict
-        while self.imported_configs.contains_key(&target_name) {
-            target_name = format!("{repo_name}_{counter}");
+        while self.imported_configs.contains_key(&target_name)
+            || self
+                .configs_dir
+                .join(format!("{target_name}.yaml"))
+                .exists()
+        {
+            target_name = format!("{base_name}_{counter}");
             counter += 1;
         }
 
@@ -553,16 +2108,24 @@ impl ConfigManager {
         let config_with_origin = serde_yaml::to_string(&config)
             .context("Failed to serialize config with origin information")?;
         let target_path = self.configs_dir.join(format!("{target_name}.yaml"));
-        fs::write(&target_path, config_with_origin)
+        atomic_write(&target_path, &config_with_origin)
             .context("Failed to save imported config file")?;
 
         // Add to imported configs
         self.imported_configs.insert(target_name.clone(), config);
+        self.config_paths.insert(target_name.clone(), target_path);
 
         Ok(target_name)
     }
 
-    pub async fn import_repo_configs(&mut self, repo: &str) -> Result<Vec<String>> {
+    /// Clone a GitHub repository into the configs directory and import all
+    /// its YAML files. With a `token`, the repository is cloned over HTTPS
+    /// with it; the token is not stored.
+    pub async fn import_repo_configs(
+        &mut self,
+        repo: &str,
+        token: Option<&str>,
+    ) -> Result<Vec<String>> {
         // Parse repository format (owner/repo)
         let parts: Vec<&str> = repo.split('/').collect();
         if parts.len() != 2 {
@@ -593,32 +2156,44 @@ impl ConfigManager {
         }
 
         // Create repository-specific directory in configs
-        let repo_dir = self.configs_dir.join(format!("{owner}-{repo_name}"));
+        let dir_name = self.repo_dir_name(owner, repo_name, repo);
+        let repo_dir = self.configs_dir.join(&dir_name);
 
         // If directory already exists, remove it first
         if repo_dir.exists() {
             println!("📁 Repository already imported, updating...");
             fs::remove_dir_all(&repo_dir)
                 .context("Failed to remove existing repository directory")?;
+            self.forget_configs_in(&repo_dir);
         }
 
         fs::create_dir_all(&repo_dir).context("Failed to create repository directory")?;
 
-        println!("🔐 Cloning repository (using your Git credentials)...");
-
-        // Try different Git URL formats
-        let git_urls = [
-            format!("git@github.com:{repo}.git"),     // SSH
-            format!("https://github.com/{repo}.git"), // HTTPS
-        ];
+        // Try different Git URL formats; a token only works over HTTPS
+        let git_urls = if token.is_some() {
+            println!("🔑 Cloning repository (using the token)...");
+            vec![format!("https://github.com/{repo}.git")]
+        } else {
+            println!("🔐 Cloning repository (using your Git credentials)...");
+            vec![
+                format!("git@github.com:{repo}.git"),     // SSH
+                format!("https://github.com/{repo}.git"), // HTTPS
+            ]
+        };
+        let import_type = match token {
+            Some(_) => ImportType::Token,
+            None => ImportType::Private,
+        };
 
         let mut clone_success = false;
         let mut last_error = String::new();
 
         for git_url in &git_urls {
+            crate::timed_span!("git.clone", url = git_url);
             println!("📥 Trying to clone: {git_url}");
 
             let clone_result = Command::new("git")
+                .envs(token.map(git_token_env).unwrap_or_default())
                 .arg("clone")
                 .arg("--depth=1") // Shallow clone for efficiency
                 .arg("--quiet") // Reduce noise
@@ -673,7 +2248,12 @@ impl ConfigManager {
                 if let Some(extension) = path.extension() {
                     if let Some(ext_str) = extension.to_str() {
                         if yaml_extensions.contains(&ext_str) {
-                            match self.process_repo_yaml_file(&path, repo, &repo_dir) {
+                            match self.process_repo_yaml_file(
+                                &path,
+                                repo,
+                                &repo_dir,
+                                import_type.clone(),
+                            ) {
                                 Ok(config_name) => {
                                     imported_configs.push(config_name);
                                     println!(
@@ -698,6 +2278,11 @@ impl ConfigManager {
         if imported_configs.is_empty() {
             // Clean up empty directory
             let _ = fs::remove_dir_all(&repo_dir);
+            if self.repo_manifest.repos.contains_key(&dir_name) {
+                self.update_repo_manifest(|manifest| {
+                    manifest.repos.remove(&dir_name);
+                })?;
+            }
             return Err(anyhow!(
                 "No valid YAML configuration files found in repository '{}' root directory.\n\
                 \nTo create a multi-config repository:\n\
@@ -714,6 +2299,17 @@ impl ConfigManager {
             ));
         }
 
+        self.update_repo_manifest(|manifest| {
+            manifest.repos.insert(
+                dir_name,
+                ConfigOrigin {
+                    repo: repo.to_string(),
+                    import_type,
+                    synced_at: Some(Utc::now()),
+                },
+            );
+        })?;
+
         println!(
             "🎉 Successfully imported {} config file(s) from repository '{}'",
             imported_configs.len(),
@@ -726,7 +2322,8 @@ impl ConfigManager {
         &mut self,
         file_path: &PathBuf,
         repo: &str,
-        _repo_dir: &Path,
+        repo_dir: &Path,
+        import_type: ImportType,
     ) -> Result<String> {
         let contents = fs::read_to_string(file_path)
             .with_context(|| format!("Failed to read file: {file_path:?}"))?;
@@ -745,7 +2342,8 @@ impl ConfigManager {
         // Add origin information
         config.origin = Some(ConfigOrigin {
             repo: repo.to_string(),
-            import_type: ImportType::Private, // Repository imports are treated as private
+            import_type, // Repository imports are private or use a token
+            synced_at: None,
         });
 
         // Generate config name from file name and repository
@@ -754,9 +2352,12 @@ impl ConfigManager {
             .and_then(|name| name.to_str())
             .context("Invalid file name")?;
 
-        let repo_parts: Vec<&str> = repo.split('/').collect();
-        let repo_name = repo_parts.get(1).unwrap_or(&repo_parts[0]);
-        let config_name = format!("{repo_name}_{file_stem}");
+        // Same naming as when loading repository directories on startup
+        let dir_name = repo_dir
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .context("Invalid repository directory")?;
+        let config_name = format!("{dir_name}_{file_stem}");
 
         // Check for conflicts and generate unique name
         let mut unique_name = config_name.clone();
@@ -794,20 +2395,25 @@ impl ConfigManager {
         };
 
         // Keep the file in the repository directory with its original name
-        fs::write(file_path, config_with_schema)
+        atomic_write(file_path, &config_with_schema)
             .context("Failed to update config file with origin information")?;
 
         // Add to imported configs with the unique name as key but store repo path info
         self.imported_configs.insert(unique_name.clone(), config);
+        self.config_paths
+            .insert(unique_name.clone(), file_path.to_path_buf());
 
         Ok(unique_name)
     }
 
-    pub async fn sync_all_configs(&mut self) -> Result<()> {
-        // Collect configs that have origins
+    /// Individually imported configs with origins, and repository
+    /// directories; configs inside repository directories are synced with
+    /// their repository
+    fn sync_targets(&self) -> (Vec<(String, ConfigOrigin)>, Vec<RepoSyncTarget>) {
         let syncable_configs: Vec<(String, ConfigOrigin)> = self
             .imported_configs
             .iter()
+            .filter(|(name, _)| !self.is_repo_config(name))
             .filter_map(|(name, config)| {
                 config
                     .origin
@@ -816,28 +2422,66 @@ impl ConfigManager {
             })
             .collect();
 
-        // Also collect GitHub repository directories
-        let mut github_repos = Vec::new();
-        if self.configs_dir.exists() {
-            for entry in fs::read_dir(&self.configs_dir)? {
-                let entry = entry?;
-                let path = entry.path();
-
-                if path.is_dir() && !path.file_name().unwrap().to_str().unwrap().starts_with('.') {
-                    // Check if this looks like a GitHub repo directory (contains owner-repo format)
-                    if let Some(dir_name) = path.file_name().and_then(|n| n.to_str()) {
-                        if dir_name.contains('-') {
-                            // Check if there's a .git directory or if we can determine it's a GitHub repo
-                            let git_dir = path.join(".git");
-                            if git_dir.exists() || self.looks_like_github_repo(&path) {
-                                github_repos.push((dir_name.to_string(), path.clone()));
-                            }
-                        }
-                    }
-                }
-            }
+        // Also collect repository directories recorded in the manifest
+        let github_repos: Vec<RepoSyncTarget> = self
+            .repo_manifest
+            .repos
+            .iter()
+            .map(|(dir_name, origin)| {
+                (
+                    dir_name.clone(),
+                    self.configs_dir.join(dir_name),
+                    origin.clone(),
+                )
+            })
+            .filter(|(_, path, _)| path.is_dir())
+            .collect();
+
+        (syncable_configs, github_repos)
+    }
+
+    /// Imported configs and repository directories with a remote origin,
+    /// sorted by name; configs inside repository directories are covered by
+    /// their directory
+    pub fn remote_origins(&self) -> Vec<(String, ConfigOrigin)> {
+        let (configs, repos) = self.sync_targets();
+        let mut origins: Vec<_> = configs
+            .into_iter()
+            .chain(repos.into_iter().map(|(name, _, origin)| (name, origin)))
+            .collect();
+        origins.sort_by(|a, b| a.0.cmp(&b.0));
+        origins
+    }
+
+    /// Compare every imported config and repository directory with its
+    /// origin without changing them
+    pub async fn preview_sync_all(&mut self) -> Result<Vec<ConfigDiff>> {
+        let (syncable_configs, github_repos) = self.sync_targets();
+        let mut diffs = Vec::new();
+
+        for (config_name, origin) in syncable_configs {
+            let diff = self
+                .preview_sync_config(&config_name, &origin)
+                .await
+                .with_context(|| format!("Failed to check {config_name}"))?;
+            diffs.push(diff);
+        }
+        for (repo_name, repo_path, origin) in github_repos {
+            let token = match origin.import_type {
+                ImportType::Token => Some(self.github_token(&origin.repo)?),
+                _ => None,
+            };
+            let repo_diffs = preview_sync_repository(&repo_name, &repo_path, token.as_deref())
+                .with_context(|| format!("Failed to check repository {repo_name}"))?;
+            diffs.extend(repo_diffs);
         }
 
+        Ok(diffs)
+    }
+
+    pub async fn sync_all_configs(&mut self) -> Result<()> {
+        let (syncable_configs, github_repos) = self.sync_targets();
+
         if syncable_configs.is_empty() && github_repos.is_empty() {
             println!("📦 No imported configs with remote origins found. Nothing to sync.");
             return Ok(());
@@ -856,6 +2500,7 @@ impl ConfigManager {
                 let sync_type = match origin.import_type {
                     ImportType::Public => "📖 Public",
                     ImportType::Private => "🔐 Private",
+                    ImportType::Token => "🔑 Token",
                 };
                 println!("  • {name} → {sync_type} ({}) ", origin.repo);
             }
@@ -866,8 +2511,12 @@ impl ConfigManager {
                 "Found {} GitHub repository director(ies):",
                 github_repos.len()
             );
-            for (repo_name, _) in &github_repos {
-                println!("  • {repo_name} → 🔐 Git Repository");
+            for (repo_name, _, origin) in &github_repos {
+                let sync_type = match origin.import_type {
+                    ImportType::Token => "🔑 Git Repository (token)",
+                    _ => "🔐 Git Repository",
+                };
+                println!("  • {repo_name} → {sync_type}");
             }
         }
 
@@ -890,6 +2539,10 @@ impl ConfigManager {
 
         // Sync individual configs with origins
         for (config_name, origin) in syncable_configs {
+            // Ask for a token before the progress line
+            if matches!(origin.import_type, ImportType::Token) {
+                self.github_token(&origin.repo)?;
+            }
             print!("🔄 Syncing {config_name} from {}... ", origin.repo);
 
             match self.sync_single_config(&config_name, &origin).await {
@@ -906,13 +2559,28 @@ impl ConfigManager {
         }
 
         // Sync GitHub repository directories using git commands
-        for (repo_name, repo_path) in github_repos {
+        for (repo_name, repo_path, origin) in github_repos {
+            let token = match origin.import_type {
+                ImportType::Token => Some(self.github_token(&origin.repo)?),
+                _ => None,
+            };
             print!("🔄 Syncing repository {repo_name}... ");
 
-            match self.sync_github_repository(&repo_path).await {
+            match self
+                .sync_github_repository(&repo_path, token.as_deref())
+                .await
+            {
                 Ok(()) => {
                     println!("✅ Success");
                     sync_results.push((repo_name.clone(), true, None));
+                    let recorded = self.update_repo_manifest(|manifest| {
+                        if let Some(origin) = manifest.repos.get_mut(&repo_name) {
+                            origin.synced_at = Some(Utc::now());
+                        }
+                    });
+                    if let Err(e) = recorded {
+                        println!("⚠️  Warning: Failed to record the sync of {repo_name}: {e}");
+                    }
 
                     // Reload configs from the updated repository
                     if let Err(e) = self.reload_repo_configs(&repo_path, &repo_name) {
@@ -958,21 +2626,132 @@ impl ConfigManager {
         Ok(())
     }
 
-    async fn sync_single_config(&mut self, config_name: &str, origin: &ConfigOrigin) -> Result<()> {
-        let parts: Vec<&str> = origin.repo.split('/').collect();
-        if parts.len() != 2 {
+    /// Origin of an individually imported config, for syncing it on its own
+    pub fn sync_origin(&self, config_name: &str) -> Result<ConfigOrigin> {
+        let config = self
+            .imported_configs
+            .get(config_name)
+            .ok_or_else(|| anyhow!("Config '{}' not found", config_name))?;
+        let origin = config.origin.clone().ok_or_else(|| {
+            anyhow!(
+                "Config '{}' has no remote origin; only imported configs can be synced",
+                config_name
+            )
+        })?;
+        if self.is_repo_config(config_name) {
             return Err(anyhow!(
-                "Invalid repository format in origin: {}",
+                "Config '{}' belongs to the repository {}; run 'doo sync' to sync the repository",
+                config_name,
                 origin.repo
             ));
         }
+        Ok(origin)
+    }
 
-        let (owner, repo_name) = (parts[0], parts[1]);
+    /// SHA-256 of the file an imported config was loaded from. The time of
+    /// the last sync is left out, so syncing an unchanged config keeps it.
+    pub fn config_checksum(&self, config_name: &str) -> Result<String> {
+        let path = self
+            .config_paths
+            .get(config_name)
+            .ok_or_else(|| anyhow!("Config '{}' not found", config_name))?;
+        let mut contents = fs::read(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        if let Ok(mut value) = serde_yaml::from_slice::<serde_yaml::Value>(&contents) {
+            let origin = value.get_mut("origin").and_then(|o| o.as_mapping_mut());
+            if origin
+                .and_then(|origin| origin.remove("synced_at"))
+                .is_some()
+            {
+                contents = serde_yaml::to_string(&value)?.into_bytes();
+            }
+        }
+        Ok(Sha256::digest(&contents)
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect())
+    }
+
+    /// Replace an imported config with the latest content from its origin
+    pub async fn sync_single_config(
+        &mut self,
+        config_name: &str,
+        origin: &ConfigOrigin,
+    ) -> Result<()> {
+        let (mut config, _) = self.fetch_synced_config(config_name, origin).await?;
+        if let Some(origin) = &mut config.origin {
+            origin.synced_at = Some(Utc::now());
+        }
+        let config_with_origin =
+            serde_yaml::to_string(&config).context("Failed to serialize updated config")?;
+
+        // Update the config file on disk
+        let _lock = ConfigLock::acquire(&self.config_dir)?;
+        let target_path = self.synced_config_path(config_name);
+        atomic_write(&target_path, &config_with_origin)
+            .context("Failed to save updated config file")?;
+
+        // Update in-memory config
+        self.imported_configs
+            .insert(config_name.to_string(), config);
+        self.config_paths
+            .insert(config_name.to_string(), target_path);
+
+        Ok(())
+    }
+
+    /// Compare an imported config with its origin without writing anything
+    pub async fn preview_sync_config(
+        &mut self,
+        config_name: &str,
+        origin: &ConfigOrigin,
+    ) -> Result<ConfigDiff> {
+        let (_, remote) = self.fetch_synced_config(config_name, origin).await?;
+        let local = fs::read_to_string(self.synced_config_path(config_name)).unwrap_or_default();
+        Ok(ConfigDiff::new(config_name, &local, &remote))
+    }
+
+    fn synced_config_path(&self, config_name: &str) -> PathBuf {
+        self.config_paths
+            .get(config_name)
+            .cloned()
+            .unwrap_or_else(|| self.configs_dir.join(format!("{config_name}.yaml")))
+    }
+
+    /// Fetch the latest version of `config_name` from `origin`, returning it
+    /// with the origin and namespace recorded and the file contents a sync
+    /// would write
+    async fn fetch_synced_config(
+        &mut self,
+        config_name: &str,
+        origin: &ConfigOrigin,
+    ) -> Result<(Config, String)> {
+        let config_content = if is_url(&origin.repo) {
+            self.fetch_url_config_content(&origin.repo).await?
+        } else {
+            let parts: Vec<&str> = origin.repo.split('/').collect();
+            if parts.len() != 2 {
+                return Err(anyhow!(
+                    "Invalid repository format in origin: {}",
+                    origin.repo
+                ));
+            }
+
+            let (owner, repo_name) = (parts[0], parts[1]);
 
-        // Fetch the latest config content based on the import type
-        let config_content = match origin.import_type {
-            ImportType::Public => self.fetch_public_config_content(owner, repo_name).await?,
-            ImportType::Private => self.fetch_private_config_content(owner, repo_name).await?,
+            // Fetch the latest config content based on the import type
+            match origin.import_type {
+                ImportType::Public => {
+                    self.fetch_public_config_content(owner, repo_name, None)
+                        .await?
+                }
+                ImportType::Private => self.fetch_private_config_content(owner, repo_name).await?,
+                ImportType::Token => {
+                    let token = self.github_token(&origin.repo)?;
+                    self.fetch_public_config_content(owner, repo_name, Some(&token))
+                        .await?
+                }
+            }
         };
 
         // Parse and validate the config
@@ -983,79 +2762,74 @@ impl ConfigManager {
             return Err(anyhow!("Updated config file contains no commands"));
         }
 
-        // Preserve the origin information
+        // Preserve the origin information and the namespace it was imported as
         config.origin = Some(origin.clone());
+        if let Some(namespace) = self
+            .imported_configs
+            .get(config_name)
+            .and_then(|config| config.namespace.clone())
+        {
+            config.namespace = Some(namespace);
+        }
 
-        // Update the config file on disk
         let config_with_origin =
             serde_yaml::to_string(&config).context("Failed to serialize updated config")?;
-        let target_path = self.configs_dir.join(format!("{config_name}.yaml"));
-        fs::write(&target_path, config_with_origin)
-            .context("Failed to save updated config file")?;
-
-        // Update in-memory config
-        self.imported_configs
-            .insert(config_name.to_string(), config);
+        Ok((config, config_with_origin))
+    }
 
-        Ok(())
+    /// Whether an imported config was loaded from a repository directory
+    fn is_repo_config(&self, config_name: &str) -> bool {
+        self.config_paths
+            .get(config_name)
+            .and_then(|path| path.parent())
+            .is_some_and(|parent| parent != self.configs_dir)
     }
 
-    /// Check if a directory looks like a GitHub repository directory
-    fn looks_like_github_repo(&self, path: &Path) -> bool {
-        // Check if directory contains YAML files (typical for imported repos)
-        if let Ok(entries) = fs::read_dir(path) {
-            for entry in entries.flatten() {
-                let file_path = entry.path();
-                if file_path.is_file() {
-                    if let Some(extension) = file_path.extension() {
-                        if extension == "yaml" || extension == "yml" {
-                            return true;
-                        }
-                    }
-                }
-            }
+    /// Forget all loaded configs that were read from files inside `dir`
+    fn forget_configs_in(&mut self, dir: &Path) {
+        let names: Vec<String> = self
+            .config_paths
+            .iter()
+            .filter(|(_, path)| path.parent() == Some(dir))
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        for name in names {
+            self.imported_configs.remove(&name);
+            self.config_paths.remove(&name);
         }
-        false
     }
 
-    /// Sync a GitHub repository directory using git commands
-    async fn sync_github_repository(&self, repo_path: &Path) -> Result<()> {
-        // Check if git is available
-        let git_check = Command::new("git").arg("--version").output();
-        if git_check.is_err() {
-            return Err(anyhow!(
-                "Git command not found. Repository sync requires Git to be installed and available in PATH"
-            ));
+    /// Directory name (inside the configs directory) for a cloned repository.
+    /// An existing manifest entry for the same repository is reused; otherwise
+    /// a sanitized `owner-repo` name is chosen that doesn't clash with other
+    /// repositories or hand-created directories.
+    fn repo_dir_name(&self, owner: &str, repo_name: &str, repo: &str) -> String {
+        if let Some((dir_name, _)) = self
+            .repo_manifest
+            .repos
+            .iter()
+            .find(|(_, origin)| origin.repo == repo)
+        {
+            return dir_name.clone();
         }
 
-        // Check if this is a git repository
-        let git_dir = repo_path.join(".git");
-        if !git_dir.exists() {
-            return Err(anyhow!(
-                "Directory is not a git repository. Cannot sync without git history."
-            ));
+        let base_name = sanitize_path_component(&format!("{owner}-{repo_name}"));
+        let mut dir_name = base_name.clone();
+        let mut counter = 1;
+        while self.repo_manifest.repos.contains_key(&dir_name)
+            || self.configs_dir.join(&dir_name).exists()
+        {
+            dir_name = format!("{base_name}_{counter}");
+            counter += 1;
         }
+        dir_name
+    }
 
-        // Change to the repository directory and run git commands
-        // First, fetch all remote changes
-        let fetch_result = Command::new("git")
-            .current_dir(repo_path)
-            .arg("fetch")
-            .arg("--all")
-            .arg("--prune")
-            .output();
-
-        match fetch_result {
-            Ok(output) => {
-                if !output.status.success() {
-                    let stderr = String::from_utf8_lossy(&output.stderr);
-                    return Err(anyhow!("Failed to fetch remote changes: {}", stderr.trim()));
-                }
-            }
-            Err(e) => {
-                return Err(anyhow!("Failed to execute git fetch: {}", e));
-            }
-        }
+    /// Sync a GitHub repository directory using git commands
+    async fn sync_github_repository(&self, repo_path: &Path, token: Option<&str>) -> Result<()> {
+        crate::timed_span!("git.sync", repo = repo_path.display());
+        git_fetch(repo_path, token)?;
 
         // Force reset to origin/main (or master) - this will overwrite local changes
         let branches = ["origin/main", "origin/master"];
@@ -1110,85 +2884,83 @@ impl ConfigManager {
     /// Reload configs from an updated repository directory
     fn reload_repo_configs(&mut self, repo_path: &Path, repo_name: &str) -> Result<()> {
         // Remove old configs from this repository
-        let keys_to_remove: Vec<String> = self
-            .imported_configs
-            .keys()
-            .filter(|key| key.starts_with(&format!("{}_", repo_name)))
-            .cloned()
-            .collect();
-
-        for key in keys_to_remove {
-            self.imported_configs.remove(&key);
-        }
+        self.forget_configs_in(repo_path);
 
         // Reload configs from the repository directory
-        let yaml_extensions = ["yaml", "yml"];
-        for entry in fs::read_dir(repo_path)? {
-            let entry = entry?;
-            let path = entry.path();
-
-            if path.is_file() {
-                if let Some(extension) = path.extension() {
-                    if let Some(ext_str) = extension.to_str() {
-                        if yaml_extensions.contains(&ext_str) {
-                            // Try to load as a doo config
-                            if let Ok(contents) = fs::read_to_string(&path) {
-                                if let Ok(config) = serde_yaml::from_str::<Config>(&contents) {
-                                    if !config.commands.is_empty() {
-                                        let file_stem = path
-                                            .file_stem()
-                                            .and_then(|name| name.to_str())
-                                            .unwrap_or("config");
-                                        let config_name = format!("{repo_name}_{file_stem}");
-                                        self.imported_configs.insert(config_name, config);
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
-
-        Ok(())
+        self.load_repo_dir_configs(repo_path, repo_name)
     }
 
-    async fn fetch_public_config_content(&self, owner: &str, repo_name: &str) -> Result<String> {
-        let client = reqwest::Client::new();
-
-        // Look for doo.yaml or doo.yml in the repository root
-        let config_files = ["doo.yaml", "doo.yml"];
-
-        for config_file in config_files {
-            let file_url =
-                format!("https://api.github.com/repos/{owner}/{repo_name}/contents/{config_file}");
-
-            let response = client
-                .get(&file_url)
+    async fn fetch_url_config_content(&self, url: &str) -> Result<String> {
+        crate::timed_future!("url.fetch", url = url; async {
+            let response = reqwest::Client::new()
+                .get(url)
                 .header("User-Agent", "doo-cli/0.1.0")
                 .send()
                 .await
-                .map_err(|_| anyhow!("Failed to fetch config file from GitHub"))?;
+                .map_err(|e| anyhow!("Failed to fetch {}: {}", url, e))?;
+
+            if !response.status().is_success() {
+                return Err(anyhow!(
+                    "Failed to fetch {}: HTTP {}",
+                    url,
+                    response.status()
+                ));
+            }
+            if let Some(content_type) = response
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+            {
+                if !is_config_content_type(content_type) {
+                    return Err(anyhow!(
+                        "Unexpected content type '{}' from {}: expected a YAML config file",
+                        content_type,
+                        url
+                    ));
+                }
+            }
 
-            if response.status().is_success() {
-                let github_content: GitHubContent = response
-                    .json()
-                    .await
-                    .map_err(|_| anyhow!("Failed to parse GitHub API response"))?;
+            response
+                .text()
+                .await
+                .map_err(|e| anyhow!("Failed to read the response from {}: {}", url, e))
+        })
+        .await
+    }
 
-                if github_content.encoding == "base64" {
-                    let decoded_content = base64::decode(github_content.content.replace('\n', ""))
-                        .map_err(|_| anyhow!("Failed to decode base64 content from GitHub"))?;
+    async fn fetch_public_config_content(
+        &self,
+        owner: &str,
+        repo_name: &str,
+        token: Option<&str>,
+    ) -> Result<String> {
+        crate::timed_future!("github.fetch", repo = format!("{owner}/{repo_name}"); async {
+            let client = reqwest::Client::new();
+            fetch_github_config(&client, owner, repo_name, token)
+                .await?
+                .ok_or_else(|| {
+                    anyhow!("No doo configuration file found in repository '{owner}/{repo_name}'")
+                })
+        })
+        .await
+    }
 
-                    return String::from_utf8(decoded_content)
-                        .map_err(|_| anyhow!("Config file contains invalid UTF-8"));
-                }
-            }
+    /// Token for GitHub repositories imported with `--token`: from
+    /// [`GITHUB_TOKEN_ENV`], or asked for once per invocation
+    fn github_token(&mut self, repo: &str) -> Result<String> {
+        if let Some(token) = &self.github_token {
+            return Ok(token.clone());
         }
-
-        Err(anyhow!(
-            "No doo configuration file found in repository '{owner}/{repo_name}'"
-        ))
+        let token = match std::env::var(GITHUB_TOKEN_ENV) {
+            Ok(token) if !token.is_empty() => token,
+            _ => dialoguer::Password::new()
+                .with_prompt(format!(
+                    "GitHub token for {repo} (or set {GITHUB_TOKEN_ENV})"
+                ))
+                .interact()?,
+        };
+        self.github_token = Some(token.clone());
+        Ok(token)
     }
 
     async fn fetch_private_config_content(&self, owner: &str, repo_name: &str) -> Result<String> {
@@ -1208,6 +2980,7 @@ impl ConfigManager {
         let mut clone_success = false;
 
         for git_url in &git_urls {
+            crate::timed_span!("git.clone", url = git_url);
             let clone_result = Command::new("git")
                 .arg("clone")
                 .arg("--depth=1") // Shallow clone for efficiency
@@ -1251,64 +3024,100 @@ impl ConfigManager {
     }
 
     pub fn get_command(&self, name: &str) -> Result<Option<String>> {
-        // First check main config
+        if let Some(entry) = self
+            .project_commands()
+            .and_then(|commands| commands.get(name))
+        {
+            return Ok(Some(entry.command_str().to_string()));
+        }
+        // Then check main config
         if let Some(entry) = self.main_config.commands.get(name) {
             return Ok(Some(entry.command_str().to_string()));
         }
 
         for config in self.imported_configs.values() {
-            if let Some(entry) = config.commands.get(name) {
+            if let Some(entry) = config.command(name) {
                 return Ok(Some(entry.command_str().to_string()));
             }
         }
-        Ok(None)
+        Ok(self
+            .get_command_conflicts(name)
+            .into_iter()
+            .next()
+            .map(|source| source.command))
     }
 
     pub fn get_command_with_source(&self, name: &str) -> Result<Option<CommandSource>> {
-        // First check main config
+        if let Some(entry) = self
+            .project_commands()
+            .and_then(|commands| commands.get(name))
+        {
+            return Ok(Some(entry.source(
+                name,
+                PROJECT_CONFIG,
+                ConfigSource::ProjectLocal,
+            )));
+        }
+        // Then check main config
         if let Some(entry) = self.main_config.commands.get(name) {
-            return Ok(Some(CommandSource {
-                name: name.to_string(),
-                command: entry.command_str().to_string(),
-                description: entry.description().map(|s| s.to_string()),
-                source_file: "main".to_string(),
-            }));
+            return Ok(Some(entry.source(name, "main", ConfigSource::Main)));
         }
         for (config_name, config) in &self.imported_configs {
-            if let Some(entry) = config.commands.get(name) {
-                return Ok(Some(CommandSource {
-                    name: name.to_string(),
-                    command: entry.command_str().to_string(),
-                    description: entry.description().map(|s| s.to_string()),
-                    source_file: config_name.clone(),
-                }));
+            if let Some(entry) = config.command(name) {
+                return Ok(Some(entry.source(
+                    name,
+                    config_name,
+                    ConfigSource::Imported,
+                )));
             }
         }
-        Ok(None)
+        Ok(self.get_command_conflicts(name).into_iter().next())
     }
 
     pub fn get_command_conflicts(&self, name: &str) -> Vec<CommandSource> {
         let mut conflicts = Vec::new();
 
+        if let Some(entry) = self
+            .project_commands()
+            .and_then(|commands| commands.get(name))
+        {
+            conflicts.push(entry.source(name, PROJECT_CONFIG, ConfigSource::ProjectLocal));
+        }
         // Check main config
         if let Some(entry) = self.main_config.commands.get(name) {
-            conflicts.push(CommandSource {
-                name: name.to_string(),
-                command: entry.command_str().to_string(),
-                description: entry.description().map(|s| s.to_string()),
-                source_file: "main".to_string(),
-            });
+            conflicts.push(entry.source(name, "main", ConfigSource::Main));
         }
         for (config_name, config) in &self.imported_configs {
-            if let Some(entry) = config.commands.get(name) {
-                conflicts.push(CommandSource {
-                    name: name.to_string(),
-                    command: entry.command_str().to_string(),
-                    description: entry.description().map(|s| s.to_string()),
-                    source_file: config_name.clone(),
-                });
+            if let Some(entry) = config.command(name) {
+                conflicts.push(entry.source(name, config_name, ConfigSource::Imported));
             }
         }
+        // Config files take precedence, so providers are only asked for
+        // names no config defines
+        if !conflicts.is_empty() {
+            return conflicts;
+        }
+        for (provider, config) in &self.plugins().configs {
+            let Some(entry) = config.commands.get(name) else {
+                continue;
+            };
+            // Dynamic commands have their template resolved on demand
+            let command = if entry.command_str().is_empty() {
+                match provider.resolve(name, PROVIDER_TIMEOUT) {
+                    Ok(template) => template,
+                    Err(e) => {
+                        eprintln!("⚠ {e}");
+                        continue;
+                    }
+                }
+            } else {
+                entry.command_str().to_string()
+            };
+            conflicts.push(CommandSource {
+                command,
+                ..entry.source(name, &provider.source_label(), ConfigSource::Provider)
+            });
+        }
         conflicts
     }
 
@@ -1317,6 +3126,11 @@ impl ConfigManager {
         name: &str,
         chosen_source: &str,
     ) -> Result<Option<String>> {
+        if chosen_source == PROJECT_CONFIG {
+            if let Some(commands) = self.project_commands() {
+                return Ok(commands.get(name).map(|e| e.command_str().to_string()));
+            }
+        }
         if chosen_source == "main" {
             return Ok(self
                 .main_config
@@ -1325,27 +3139,55 @@ impl ConfigManager {
                 .map(|e| e.command_str().to_string()));
         }
         if let Some(config) = self.imported_configs.get(chosen_source) {
-            return Ok(config
-                .commands
-                .get(name)
-                .map(|e| e.command_str().to_string()));
+            return Ok(config.command(name).map(|e| e.command_str().to_string()));
+        }
+        if self
+            .plugins()
+            .configs
+            .iter()
+            .any(|(provider, _)| provider.source_label() == chosen_source)
+        {
+            return Ok(self
+                .get_command_conflicts(name)
+                .into_iter()
+                .find(|source| source.source_file == chosen_source)
+                .map(|source| source.command));
         }
         Err(anyhow!("Invalid source file: {}", chosen_source))
     }
 
     pub fn add_command(&mut self, name: &str, command: &str) -> Result<()> {
-        self.main_config
-            .commands
-            .insert(name.to_string(), CommandEntry::Simple(command.to_string()));
-        self.save_main_config()
+        self.add_command_with_description(name, command, None)
+    }
+
+    /// Add `name` to the main config, or replace it if it exists. Commands
+    /// with a description are stored in the detailed form.
+    pub fn add_command_with_description(
+        &mut self,
+        name: &str,
+        command: &str,
+        description: Option<&str>,
+    ) -> Result<()> {
+        self.add_command_with_tags(name, command, description, &[])
+    }
+
+    /// Add or replace a command of the main config, as a detailed entry if
+    /// it has a description or tags
+    pub fn add_command_with_tags(
+        &mut self,
+        name: &str,
+        command: &str,
+        description: Option<&str>,
+        tags: &[String],
+    ) -> Result<()> {
+        let entry = CommandEntry::new(command, description, tags);
+        self.update_main_config(|config| {
+            config.commands.insert(name.to_string(), entry);
+        })
     }
 
     pub fn remove_command(&mut self, name: &str) -> Result<bool> {
-        let removed = self.main_config.commands.remove(name).is_some();
-        if removed {
-            self.save_main_config()?;
-        }
-        Ok(removed)
+        self.update_main_config(|config| config.commands.remove(name).is_some())
     }
 
     pub fn list_commands(&self) -> HashMap<String, String> {
@@ -1355,52 +3197,646 @@ impl ConfigManager {
         }
         for config in self.imported_configs.values() {
             for (name, entry) in &config.commands {
-                all_commands.insert(name.clone(), entry.command_str().to_string());
+                all_commands.insert(config.qualified_name(name), entry.command_str().to_string());
+            }
+        }
+        for (name, entry) in self.project_commands().into_iter().flatten() {
+            all_commands.insert(name.clone(), entry.command_str().to_string());
+        }
+        for (_, config) in &self.plugins().configs {
+            for (name, entry) in &config.commands {
+                all_commands
+                    .entry(name.clone())
+                    .or_insert_with(|| entry.command_str().to_string());
             }
         }
         all_commands
     }
 
+    /// Names of the commands [`list_commands`](Self::list_commands) lists
+    /// that match the glob `pattern`, sorted
+    pub fn commands_matching(&self, pattern: &str) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .list_commands()
+            .into_keys()
+            .filter(|name| glob_match(pattern, name))
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Every command definition of the project config, the main config, the
+    /// imported configs and the providers with where it comes from (`project`
+    /// and `main` for the first two), sorted by name. Templates of dynamic
+    /// provider commands are left empty.
+    pub fn list_command_sources(&self) -> Vec<CommandSource> {
+        let mut sources: Vec<CommandSource> = self
+            .project_commands()
+            .into_iter()
+            .flatten()
+            .map(|(name, entry)| entry.source(name, PROJECT_CONFIG, ConfigSource::ProjectLocal))
+            .collect();
+        sources.extend(
+            self.main_config
+                .commands
+                .iter()
+                .map(|(name, entry)| entry.source(name, "main", ConfigSource::Main)),
+        );
+        let mut imported: Vec<_> = self.imported_configs.iter().collect();
+        imported.sort_by_key(|(config_name, _)| *config_name);
+        for (config_name, config) in imported {
+            for (name, entry) in &config.commands {
+                sources.push(entry.source(
+                    &config.qualified_name(name),
+                    config_name,
+                    ConfigSource::Imported,
+                ));
+            }
+        }
+        for (provider, config) in &self.plugins().configs {
+            for (name, entry) in &config.commands {
+                sources.push(entry.source(name, &provider.source_label(), ConfigSource::Provider));
+            }
+        }
+        // Stable sort keeps the project config before main before imported
+        // configs before providers
+        sources.sort_by(|a, b| a.name.cmp(&b.name));
+        sources
+    }
+
+    /// Command names defined by more than one config or provider, with the
+    /// configs defining them in order of precedence. The project config
+    /// overrides the others without asking, so it isn't part of conflicts;
+    /// see [`Self::project_overrides`].
+    pub fn conflicting_commands(&self) -> BTreeMap<String, Vec<String>> {
+        let mut sources: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for source in self.list_command_sources() {
+            if source.kind != ConfigSource::ProjectLocal {
+                sources
+                    .entry(source.name)
+                    .or_default()
+                    .push(source.source_file);
+            }
+        }
+        sources.retain(|_, files| files.len() > 1);
+        sources
+    }
+
+    /// Variables (`#1`, `#name`, `#NAME` for `${NAME}`) that the templates,
+    /// environment and working directories of the commands refer to, or only
+    /// those of `command`
+    pub fn referenced_variables(&self, command: Option<&str>) -> BTreeSet<String> {
+        let mut keys = BTreeSet::new();
+        for source in self.list_command_sources() {
+            if command.is_some_and(|command| command != source.name) {
+                continue;
+            }
+            let texts = std::iter::once(&source.command)
+                .chain(source.env.values())
+                .chain(source.cwd.as_ref());
+            for text in texts {
+                let tokens = crate::template::parse(text);
+                keys.extend(
+                    crate::template::placeholders(&tokens)
+                        .filter_map(crate::template::Placeholder::reference_key),
+                );
+            }
+        }
+        keys
+    }
+
+    /// Commands of the project config that are also defined elsewhere, with
+    /// the configs they override
+    pub fn project_overrides(&self) -> BTreeMap<String, Vec<String>> {
+        let mut overridden: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        let sources = self.list_command_sources();
+        for source in &sources {
+            if source.kind != ConfigSource::ProjectLocal
+                && self
+                    .project_commands()
+                    .is_some_and(|commands| commands.contains_key(&source.name))
+            {
+                overridden
+                    .entry(source.name.clone())
+                    .or_default()
+                    .push(source.source_file.clone());
+            }
+        }
+        overridden
+    }
+
+    /// Check the main config and all imported configs for problems that
+    /// would only show when a command runs, main config first. Files are
+    /// read again so duplicate command names, which loading silently
+    /// collapses, are found too. Provider commands are not checked.
+    pub fn validate(&self) -> Vec<ValidationError> {
+        let main_file = main_config_file(&self.config_dir);
+        let mut files = Vec::new();
+        if let Some(path) = self.project_config_file() {
+            files.push((PROJECT_CONFIG.to_string(), path.to_path_buf()));
+        }
+        if main_file.exists() {
+            files.push(("main".to_string(), main_file));
+        }
+        let mut imported: Vec<_> = self.config_paths.iter().collect();
+        imported.sort();
+        files.extend(
+            imported
+                .into_iter()
+                .map(|(name, path)| (name.clone(), path.clone())),
+        );
+
+        let mut errors = Vec::new();
+        for (file, path) in files {
+            let names = fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|contents| command_names(&contents, detect_format(&path)));
+            let mut names = match names {
+                Ok(names) => names,
+                Err(e) => {
+                    errors.push(ValidationError::InvalidFile {
+                        file,
+                        message: format!("{}: {}", path.display(), e),
+                    });
+                    continue;
+                }
+            };
+            names.sort();
+            let mut duplicates: Vec<&String> = names
+                .windows(2)
+                .filter(|pair| pair[0] == pair[1])
+                .map(|pair| &pair[0])
+                .collect();
+            duplicates.dedup();
+            for name in duplicates {
+                errors.push(ValidationError::DuplicateCommand {
+                    file: file.clone(),
+                    name: name.clone(),
+                });
+            }
+            if names.iter().any(|name| name.trim().is_empty()) {
+                errors.push(ValidationError::EmptyCommandName { file: file.clone() });
+            }
+
+            let config = match (file.as_str(), &self.project_config) {
+                (PROJECT_CONFIG, Some((_, config))) => config,
+                ("main", _) => &self.main_config,
+                _ => &self.imported_configs[&file],
+            };
+            let mut commands: Vec<_> = config.commands.iter().collect();
+            commands.sort_by_key(|(name, _)| *name);
+            for (name, entry) in commands {
+                errors.extend(validate_entry(&file, name, entry));
+            }
+        }
+        errors
+    }
+
+    /// Required `params` of the commands in all config files that have
+    /// neither a default nor a value stored in `context`
+    pub fn validate_required_params(
+        &self,
+        variable_manager: &VariableManager,
+        context: &str,
+    ) -> Result<Vec<ValidationError>> {
+        let mut configs: Vec<_> = self
+            .project_config
+            .iter()
+            .map(|(_, config)| (PROJECT_CONFIG, ConfigSource::ProjectLocal, config))
+            .collect();
+        configs.push(("main", ConfigSource::Main, &self.main_config));
+        let mut imported: Vec<_> = self
+            .imported_configs
+            .iter()
+            .map(|(name, config)| (name.as_str(), ConfigSource::Imported, config))
+            .collect();
+        imported.sort_by_key(|(name, _, _)| *name);
+        configs.extend(imported);
+
+        let mut errors = Vec::new();
+        for (file, kind, config) in configs {
+            let mut commands: Vec<_> = config.commands.iter().collect();
+            commands.sort_by_key(|(name, _)| *name);
+            for (name, entry) in commands {
+                let source = entry.source(&config.qualified_name(name), file, kind);
+                let required: Vec<_> = source
+                    .params
+                    .iter()
+                    .filter(|(_, spec)| spec.required && spec.default.is_none())
+                    .collect();
+                if required.is_empty() {
+                    continue;
+                }
+                let missing = variable_manager.missing_placeholders(
+                    context,
+                    Some(&source.name),
+                    &source.command,
+                    &[],
+                    &source.defaults,
+                )?;
+                for (key, spec) in required {
+                    let unset = missing
+                        .iter()
+                        .any(|label| label == key || label.strip_prefix('#') == Some(key));
+                    if unset {
+                        let param = match &spec.name {
+                            Some(param_name) => format!("'{param_name}' ({key})"),
+                            None => format!("'{key}'"),
+                        };
+                        errors.push(ValidationError::MissingRequiredParam {
+                            file: file.to_string(),
+                            name: name.clone(),
+                            param,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(errors)
+    }
+
     pub fn search_commands(&self, query: &str) -> Vec<CommandSearchResult> {
-        let q = query.to_lowercase();
-        let mut results = Vec::new();
+        self.searchable_commands()
+            .into_iter()
+            .filter(|result| result.matches(query))
+            .collect()
+    }
+
+    /// Commands tagged with `tag`, ignoring case, sorted by name
+    pub fn search_commands_by_tag(&self, tag: &str) -> Vec<CommandSearchResult> {
+        self.searchable_commands()
+            .into_iter()
+            .filter(|result| result.has_tag(tag))
+            .collect()
+    }
 
-        // Iterate through merged view (imported override main). We'll prefer imported variant already handled by iteration order (main then imported overwrite) but for description we just display whichever ends up.
+    /// All commands sorted by name. Imported commands go by their qualified
+    /// name and override main config commands of the same name; provider
+    /// commands never shadow commands from config files.
+    fn searchable_commands(&self) -> Vec<CommandSearchResult> {
         let mut merged: HashMap<String, &CommandEntry> = HashMap::new();
         for (name, entry) in &self.main_config.commands {
             merged.insert(name.clone(), entry);
         }
         for config in self.imported_configs.values() {
             for (name, entry) in &config.commands {
-                merged.insert(name.clone(), entry); // override
+                merged.insert(config.qualified_name(name), entry);
             }
         }
-
-        for (name, entry) in merged {
-            let cmd = entry.command_str();
-            let desc = entry.description();
-            if q.is_empty()
-                || name.to_lowercase().contains(&q)
-                || cmd.to_lowercase().contains(&q)
-                || desc.map(|d| d.to_lowercase().contains(&q)).unwrap_or(false)
-            {
-                results.push(CommandSearchResult {
-                    name,
-                    command: cmd.to_string(),
-                    description: desc.map(|s| s.to_string()),
-                });
+        for (_, config) in &self.plugins().configs {
+            for (name, entry) in &config.commands {
+                merged.entry(name.clone()).or_insert(entry);
             }
         }
+
+        let mut results: Vec<CommandSearchResult> = merged
+            .into_iter()
+            .map(|(name, entry)| CommandSearchResult::new(name, entry))
+            .collect();
         // Sort by name for stable display
         results.sort_by(|a, b| a.name.cmp(&b.name));
         results
     }
 
+    /// YAML of the config `config_name` (`main` for the main config), or of
+    /// all commands merged into one config if `None`. In the merged view
+    /// imported configs override the main config, in name order, and
+    /// namespaced commands keep their prefix. Origins are left out, so the
+    /// result imports like any local file.
+    pub fn export_to_yaml(&self, config_name: Option<&str>) -> Result<String> {
+        let config = match config_name {
+            Some("main") => self.main_config.clone(),
+            Some(name) => self
+                .imported_configs
+                .get(name)
+                .cloned()
+                .ok_or_else(|| anyhow!("Config '{}' not found", name))?,
+            None => {
+                let mut commands = self.main_config.commands.clone();
+                let mut imported: Vec<_> = self.imported_configs.iter().collect();
+                imported.sort_by_key(|(config_name, _)| *config_name);
+                for (_, config) in imported {
+                    for (name, entry) in &config.commands {
+                        commands.insert(config.qualified_name(name), entry.clone());
+                    }
+                }
+                Config {
+                    commands,
+                    ..Config::default()
+                }
+            }
+        };
+        let config = Config {
+            origin: None,
+            ..config
+        };
+        serde_yaml::to_string(&config).context("Failed to serialize config")
+    }
+
+    /// The main config
+    pub fn main_config(&self) -> &Config {
+        &self.main_config
+    }
+
+    /// An imported config by name
+    /// Names of the imported configs, sorted
+    pub fn imported_config_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.imported_configs.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    pub fn get_imported_config(&self, name: &str) -> Option<&Config> {
+        self.imported_configs.get(name)
+    }
+
+    /// File the config `name` was loaded from, `main` for the main config
+    /// and `project` for the project config
+    pub fn config_file(&self, name: &str) -> Option<PathBuf> {
+        match name {
+            "main" => Some(main_config_file(&self.config_dir)),
+            PROJECT_CONFIG if self.project_config.is_some() => {
+                self.project_config_file().map(Path::to_path_buf)
+            }
+            _ => self.config_paths.get(name).cloned(),
+        }
+    }
+
+    /// Commands defined in the main config file
+    pub fn main_commands(&self) -> &HashMap<String, CommandEntry> {
+        &self.main_config.commands
+    }
+
+    /// Whether the main config asks for `./.env` to be loaded
+    pub fn load_dotenv(&self) -> bool {
+        self.main_config.load_dotenv
+    }
+
+    /// Where the main config asks for secret variables to be stored
+    pub fn secret_store(&self) -> SecretStore {
+        self.main_config.secret_store
+    }
+
+    /// Command the main config runs when switching to `context`
+    pub fn context_hook(&self, context: &str) -> Option<&ContextHook> {
+        self.main_config
+            .contexts
+            .get(context)
+            .and_then(|settings| settings.on_switch.as_ref())
+    }
+
+    /// Environment variables the main config sets for every command run in
+    /// `context`, before placeholder resolution
+    pub fn context_env(&self, context: &str) -> BTreeMap<String, String> {
+        self.main_config
+            .contexts
+            .get(context)
+            .map(|settings| settings.env.clone())
+            .unwrap_or_default()
+    }
+
+    /// Environment variables for running `source` in `context`: those of
+    /// the context, overridden by the command's own `env`
+    pub fn command_env(&self, context: &str, source: &CommandSource) -> BTreeMap<String, String> {
+        let mut env = self.context_env(context);
+        env.extend(source.env.clone());
+        env
+    }
+
+    /// Executed commands to keep in the history
+    pub fn history_limit(&self) -> usize {
+        self.main_config
+            .history_limit
+            .unwrap_or(DEFAULT_HISTORY_LIMIT)
+    }
+
+    /// Commands of all external providers, queried once per invocation
+    fn plugins(&self) -> &PluginCommands {
+        self.plugins.get_or_init(|| {
+            let mut plugins = PluginCommands::default();
+            let providers = providers::discover(
+                &self.main_config.providers,
+                self.main_config.discover_providers,
+            );
+            for provider in providers {
+                match provider.list(PROVIDER_TIMEOUT) {
+                    Ok(commands) => {
+                        let commands = commands
+                            .into_iter()
+                            .map(|command| {
+                                let entry = CommandEntry::Detailed {
+                                    command: command.template.unwrap_or_default(),
+                                    description: command.description,
+                                    defaults: BTreeMap::new(),
+                                    timeout_secs: None,
+                                    retry: None,
+                                    env: BTreeMap::new(),
+                                    cwd: None,
+                                    args: BTreeMap::new(),
+                                    quote_args: false,
+                                    shell: false,
+                                    params: BTreeMap::new(),
+                                    tags: Some(command.tags).filter(|tags| !tags.is_empty()),
+                                    examples: None,
+                                };
+                                (command.name, entry)
+                            })
+                            .collect();
+                        let config = Config {
+                            commands,
+                            ..Config::default()
+                        };
+                        plugins.configs.push((provider, config));
+                    }
+                    Err(e) => {
+                        eprintln!("⚠ Skipping command provider '{}': {e}", provider.name);
+                        plugins.errors.push((provider.name.clone(), e.to_string()));
+                    }
+                }
+            }
+            plugins
+        })
+    }
+
+    /// Providers that failed to list their commands in this invocation, with
+    /// the reason
+    pub fn provider_errors(&self) -> &[(String, String)] {
+        &self.plugins().errors
+    }
+
+    /// Write a gzipped tarball of all doo state (main config, imported configs,
+    /// variables and the current context) to `archive_path`.
+    ///
+    /// When `include_git` is false, `.git` directories of imported repositories
+    /// are skipped. Returns the number of files written to the archive.
+    pub fn create_backup(&self, archive_path: &Path, include_git: bool) -> Result<usize> {
+        let _lock = ConfigLock::acquire(&self.config_dir)?;
+        let file = fs::File::create(archive_path)
+            .with_context(|| format!("Failed to create backup file: {archive_path:?}"))?;
+        let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+
+        let files = self.backup_files(include_git)?;
+        for relative in &files {
+            builder
+                .append_path_with_name(self.config_dir.join(relative), relative)
+                .with_context(|| format!("Failed to add {relative:?} to backup"))?;
+        }
+
+        builder
+            .into_inner()
+            .context("Failed to finish backup archive")?
+            .finish()
+            .context("Failed to compress backup archive")?;
+
+        Ok(files.len())
+    }
+
+    /// Restore doo state from an archive created by [`ConfigManager::create_backup`].
+    ///
+    /// The archive is unpacked into a staging directory first, so a corrupt
+    /// or unrelated file fails before anything is changed. Without `merge`,
+    /// the existing doo files are then replaced by the restored ones; with
+    /// `merge`, only files that don't exist yet are restored. Returns the
+    /// number of files restored.
+    pub fn restore_backup(&self, archive_path: &Path, merge: bool) -> Result<usize> {
+        let _lock = ConfigLock::acquire(&self.config_dir)?;
+        let staging = tempfile::Builder::new()
+            .prefix(".restore-")
+            .tempdir_in(&self.config_dir)
+            .context("Failed to create a staging directory for the restore")?;
+        let staged = unpack_backup(archive_path, staging.path())?;
+        if staged.is_empty() {
+            return Err(anyhow!(
+                "{} contains no doo files; is it a backup made with 'doo backup'?",
+                archive_path.display()
+            ));
+        }
+        // A main config that doesn't parse would leave doo unable to start
+        if let Some(config) = staged.iter().find(|path| {
+            path.components().count() == 1
+                && path.file_stem() == Some("config".as_ref())
+                && is_config_file(path)
+        }) {
+            let path = staging.path().join(config);
+            let contents =
+                fs::read_to_string(&path).with_context(|| format!("Failed to read {config:?}"))?;
+            detect_format(&path)
+                .parse(&contents)
+                .with_context(|| format!("The backup has an invalid {}", config.display()))?;
+        }
+
+        let restored = if merge {
+            let mut restored = 0;
+            for relative in &staged {
+                let target = self.config_dir.join(relative);
+                if target.exists() {
+                    continue;
+                }
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)
+                        .with_context(|| format!("Failed to create {parent:?}"))?;
+                }
+                fs::rename(staging.path().join(relative), &target)
+                    .with_context(|| format!("Failed to restore {relative:?}"))?;
+                restored += 1;
+            }
+            restored
+        } else {
+            self.replace_backup_entries(staging.path())?;
+            staged.len()
+        };
+
+        // Directories are expected to exist even if the backup had none
+        fs::create_dir_all(&self.configs_dir).context("Failed to create configs directory")?;
+
+        Ok(restored)
+    }
+
+    /// Swap the doo entries of the config directory for those in `staging`.
+    /// The current ones are moved aside first and put back if a move fails.
+    fn replace_backup_entries(&self, staging: &Path) -> Result<()> {
+        let previous = tempfile::Builder::new()
+            .prefix(".previous-")
+            .tempdir_in(&self.config_dir)
+            .context("Failed to create a directory for the current files")?;
+        let mut moved_aside = Vec::new();
+        let mut moved_in = Vec::new();
+        let result = BACKUP_ENTRIES.iter().try_for_each(|entry| -> Result<()> {
+            let path = self.config_dir.join(entry);
+            if path.exists() {
+                fs::rename(&path, previous.path().join(entry))
+                    .with_context(|| format!("Failed to move {path:?} aside"))?;
+                moved_aside.push(*entry);
+            }
+            let restored = staging.join(entry);
+            if restored.exists() {
+                fs::rename(&restored, &path)
+                    .with_context(|| format!("Failed to restore {entry}"))?;
+                moved_in.push(*entry);
+            }
+            Ok(())
+        });
+        if result.is_err() {
+            for entry in moved_in {
+                let path = self.config_dir.join(entry);
+                let _ = if path.is_dir() {
+                    fs::remove_dir_all(&path)
+                } else {
+                    fs::remove_file(&path)
+                };
+            }
+            for entry in moved_aside {
+                let _ = fs::rename(previous.path().join(entry), self.config_dir.join(entry));
+            }
+        }
+        result
+    }
+
+    /// List all files belonging to doo, relative to the config directory
+    fn backup_files(&self, include_git: bool) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for entry in BACKUP_ENTRIES {
+            let path = self.config_dir.join(entry);
+            if path.is_file() {
+                files.push(PathBuf::from(entry));
+            } else if path.is_dir() {
+                collect_files(&self.config_dir, &path, include_git, &mut files)?;
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    /// Apply `update` to the main config as currently stored on disk and save
+    /// it, holding the config lock so concurrent doo processes can't lose each
+    /// other's changes
+    fn update_main_config<R>(&mut self, update: impl FnOnce(&mut Config) -> R) -> Result<R> {
+        let _lock = ConfigLock::acquire(&self.config_dir)?;
+
+        let config_file = main_config_file(&self.config_dir);
+        if config_file.exists() {
+            self.main_config = read_main_config(&config_file, false)?;
+        }
+
+        let result = update(&mut self.main_config);
+        self.save_main_config()?;
+        Ok(result)
+    }
+
+    /// Apply `update` to the repository manifest as currently stored on disk
+    /// and save it while holding the config lock
+    fn update_repo_manifest(&mut self, update: impl FnOnce(&mut RepoManifest)) -> Result<()> {
+        let _lock = ConfigLock::acquire(&self.config_dir)?;
+        self.repo_manifest = RepoManifest::load(&self.configs_dir)?;
+        update(&mut self.repo_manifest);
+        self.repo_manifest.save(&self.configs_dir)
+    }
+
     fn save_main_config(&self) -> Result<()> {
-        let config_file = self.config_dir.join("config.yaml");
-        let yaml_content =
-            serde_yaml::to_string(&self.main_config).context("Failed to serialize config")?;
-        fs::write(&config_file, yaml_content).context("Failed to write config file")?;
+        let config_file = main_config_file(&self.config_dir);
+        let content = detect_format(&config_file).serialize(&self.main_config)?;
+        atomic_write_with_backup(&config_file, &content).context("Failed to write config file")?;
         Ok(())
     }
 
@@ -1413,124 +3849,1790 @@ impl ConfigManager {
         let configs_dir = config_dir.join("configs");
         fs::create_dir_all(&configs_dir).context("Failed to create configs directory")?;
 
-        let config_file = config_dir.join("config.yaml");
+        let config_file = main_config_file(&config_dir);
         let main_config = if config_file.exists() {
-            let contents =
-                fs::read_to_string(&config_file).context("Failed to read config file")?;
-            serde_yaml::from_str(&contents).context("Failed to parse config file")?
+            crate::timed_span!("config.parse", path = config_file.display());
+            read_main_config(&config_file, false)?
         } else {
             Config::default()
         };
 
-        // Load all imported configs from files and repository directories
-        let mut imported_configs = HashMap::new();
-        if configs_dir.exists() {
-            // Load configs from files in configs directory
-            for entry in fs::read_dir(&configs_dir)? {
-                let entry = entry?;
-                let path = entry.path();
-
-                if path.is_file()
-                    && path
-                        .extension()
-                        .is_some_and(|ext| ext == "yaml" || ext == "yml")
-                {
-                    let file_name = path
-                        .file_stem()
-                        .and_then(|name| name.to_str())
-                        .context("Invalid config file name")?
-                        .to_string();
-
-                    let contents = fs::read_to_string(&path)
-                        .with_context(|| format!("Failed to read config file: {path:?}"))?;
-                    let config: Config = serde_yaml::from_str(&contents)
-                        .with_context(|| format!("Failed to parse config file: {path:?}"))?;
-
-                    imported_configs.insert(file_name, config);
+        Self::load(config_dir, main_config)
+    }
+}
+
+/// Contents of `doo.yaml` (or `doo.yml`) in the root of a GitHub repository,
+/// `None` if it has neither
+async fn fetch_github_config(
+    client: &reqwest::Client,
+    owner: &str,
+    repo_name: &str,
+    token: Option<&str>,
+) -> Result<Option<String>> {
+    for config_file in ["doo.yaml", "doo.yml"] {
+        let response = github_get(
+            client,
+            &format!("/repos/{owner}/{repo_name}/contents/{config_file}"),
+            token,
+        )
+        .send()
+        .await
+        .map_err(|_| anyhow!("Failed to fetch config file from GitHub"))?;
+
+        if response.status().is_success() {
+            let github_content: GitHubContent = response
+                .json()
+                .await
+                .map_err(|_| anyhow!("Failed to parse GitHub API response"))?;
+
+            if github_content.encoding == "base64" {
+                let decoded_content = base64::decode(github_content.content.replace('\n', ""))
+                    .map_err(|_| anyhow!("Failed to decode base64 content from GitHub"))?;
+
+                return String::from_utf8(decoded_content)
+                    .map(Some)
+                    .map_err(|_| anyhow!("Config file contains invalid UTF-8"));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Whether an import source or origin is an `http://` or `https://` URL
+pub fn is_url(source: &str) -> bool {
+    source.starts_with("https://") || source.starts_with("http://")
+}
+
+/// Whether a `Content-Type` header fits a YAML config file. Servers often
+/// send raw files as plain text or bytes; HTML is usually an error or login
+/// page.
+fn is_config_content_type(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    mime.contains("yaml")
+        || mime.contains("yml")
+        || matches!(
+            mime.as_str(),
+            "text/plain" | "application/octet-stream" | "application/json"
+        )
+}
+
+/// Command names of a config file in the order they appear, including
+/// duplicates
+fn command_names(contents: &str, format: ConfigFormat) -> Result<Vec<String>> {
+    struct Keys(Vec<String>);
+
+    impl<'de> Deserialize<'de> for Keys {
+        fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            struct KeysVisitor;
+
+            impl<'de> serde::de::Visitor<'de> for KeysVisitor {
+                type Value = Keys;
+
+                fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    f.write_str("a map of commands")
+                }
+
+                fn visit_map<A: serde::de::MapAccess<'de>>(
+                    self,
+                    mut map: A,
+                ) -> Result<Keys, A::Error> {
+                    let mut keys = Vec::new();
+                    while let Some(key) = map.next_key::<String>()? {
+                        map.next_value::<serde::de::IgnoredAny>()?;
+                        keys.push(key);
+                    }
+                    Ok(Keys(keys))
                 }
             }
 
-            // Load configs from repository directories
-            for entry in fs::read_dir(&configs_dir)? {
-                let entry = entry?;
-                let path = entry.path();
-
-                if path.is_dir() && !path.file_name().unwrap().to_str().unwrap().starts_with('.') {
-                    // This is a repository directory, scan for YAML files
-                    for repo_entry in fs::read_dir(&path)? {
-                        let repo_entry = repo_entry?;
-                        let repo_file_path = repo_entry.path();
-
-                        if repo_file_path.is_file()
-                            && repo_file_path
-                                .extension()
-                                .is_some_and(|ext| ext == "yaml" || ext == "yml")
-                        {
-                            let repo_name = path.file_name().unwrap().to_str().unwrap();
-                            let file_stem = repo_file_path
-                                .file_stem()
-                                .and_then(|name| name.to_str())
-                                .unwrap_or("config");
-
-                            // Create unique config name: repo_filename
-                            let config_name = format!("{repo_name}_{file_stem}");
-
-                            let contents =
-                                fs::read_to_string(&repo_file_path).with_context(|| {
-                                    format!("Failed to read repo config file: {repo_file_path:?}")
-                                })?;
-
-                            if let Ok(config) = serde_yaml::from_str::<Config>(&contents) {
-                                // Only add if it's a valid doo config with commands
-                                if !config.commands.is_empty() {
-                                    imported_configs.insert(config_name, config);
-                                }
-                            }
-                        }
+            deserializer.deserialize_map(KeysVisitor)
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct CommandKeys {
+        commands: Keys,
+    }
+
+    let keys: CommandKeys = match format {
+        ConfigFormat::Yaml => serde_yaml::from_str(contents)?,
+        ConfigFormat::Toml => toml::from_str(contents)?,
+        ConfigFormat::Json => serde_json::from_str(contents)?,
+    };
+    Ok(keys.commands.0)
+}
+
+/// Problems of a single command definition
+fn validate_entry(file: &str, name: &str, entry: &CommandEntry) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+    let invalid_template = |message: String| ValidationError::InvalidTemplate {
+        file: file.to_string(),
+        name: name.to_string(),
+        message,
+    };
+    let inconsistent = |message: String| ValidationError::InconsistentEntry {
+        file: file.to_string(),
+        name: name.to_string(),
+        message,
+    };
+
+    let command = entry.command_str();
+    if command.trim().is_empty() {
+        errors.push(invalid_template("the command is empty".to_string()));
+    } else if let Err(e) = shell_words::split(command) {
+        errors.push(invalid_template(e.to_string()));
+    }
+
+    // The command, env values and cwd all may use placeholders
+    let mut templates = vec![command];
+    templates.extend(
+        entry
+            .env()
+            .into_iter()
+            .flat_map(|env| env.values().map(String::as_str)),
+    );
+    templates.extend(entry.cwd());
+    let mut keys = BTreeSet::new();
+    for text in &templates {
+        for warning in VariableManager::validate_template(text) {
+            errors.push(match warning {
+                TemplateWarning::UnknownSyntax(placeholder) => {
+                    ValidationError::MalformedPlaceholder {
+                        file: file.to_string(),
+                        name: name.to_string(),
+                        placeholder,
                     }
                 }
+                TemplateWarning::UnknownFilter(message) => invalid_template(message),
+                warning => ValidationError::SuspiciousTemplate {
+                    file: file.to_string(),
+                    name: name.to_string(),
+                    warning,
+                },
+            });
+        }
+        let tokens = crate::template::parse(text);
+        for placeholder in crate::template::placeholders(&tokens) {
+            keys.insert(placeholder.label());
+            if let Some(variable) = placeholder.variable_name() {
+                keys.insert(variable.trim_start_matches('#').to_string());
             }
         }
+    }
 
-        Ok(Self {
-            config_dir,
-            configs_dir,
-            main_config,
-            imported_configs,
-        })
+    let CommandEntry::Detailed {
+        defaults,
+        timeout_secs,
+        retry,
+        env,
+        args,
+        params,
+        ..
+    } = entry
+    else {
+        return errors;
+    };
+    for key in defaults.keys().filter(|key| !keys.contains(*key)) {
+        errors.push(inconsistent(format!(
+            "default for '{key}' is not used by any placeholder"
+        )));
+    }
+    for (key, spec) in params {
+        if !keys.contains(key) {
+            errors.push(inconsistent(format!(
+                "parameter '{key}' is not used by any placeholder"
+            )));
+        }
+        if spec.default.is_some() && defaults.contains_key(key) {
+            errors.push(inconsistent(format!(
+                "'{key}' has a default both in defaults and in params"
+            )));
+        }
+    }
+    for (key, spec) in args {
+        if !keys.contains(key) {
+            errors.push(inconsistent(format!(
+                "constraint for '{key}' is not used by any placeholder"
+            )));
+        }
+        if let Some(pattern) = &spec.pattern {
+            if let Err(e) = regex::Regex::new(pattern) {
+                errors.push(inconsistent(format!("invalid pattern for '{key}': {e}")));
+            }
+        }
+    }
+    if *timeout_secs == Some(0) {
+        errors.push(inconsistent(
+            "timeout_secs must be greater than 0".to_string(),
+        ));
+    }
+    if retry.as_ref().is_some_and(|retry| retry.max_attempts == 0) {
+        errors.push(inconsistent(
+            "retry.max_attempts must be greater than 0".to_string(),
+        ));
+    }
+    for key in env.keys() {
+        if key.is_empty() || key.contains('=') {
+            errors.push(inconsistent(format!(
+                "invalid environment variable name '{key}'"
+            )));
+        }
     }
+    errors
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tempfile::TempDir;
+/// Fetch all remote changes of a cloned repository without touching the
+/// working tree
+fn git_fetch(repo_path: &Path, token: Option<&str>) -> Result<()> {
+    // Check if git is available
+    let git_check = Command::new("git").arg("--version").output();
+    if git_check.is_err() {
+        return Err(anyhow!(
+                "Git command not found. Repository sync requires Git to be installed and available in PATH"
+            ));
+    }
 
-    #[test]
-    fn test_config_creation() {
-        let temp_dir = TempDir::new().unwrap();
-        let config_dir = temp_dir.path().join(".config").join("doo");
+    // Check if this is a git repository
+    let git_dir = repo_path.join(".git");
+    if !git_dir.exists() {
+        return Err(anyhow!(
+            "Directory is not a git repository. Cannot sync without git history."
+        ));
+    }
 
-        let config_manager = ConfigManager::new_with_dir(config_dir);
-        assert!(config_manager.is_ok());
+    // Change to the repository directory and run git commands
+    // First, fetch all remote changes
+    let fetch_result = Command::new("git")
+        .current_dir(repo_path)
+        .envs(token.map(git_token_env).unwrap_or_default())
+        .arg("fetch")
+        .arg("--all")
+        .arg("--prune")
+        .output();
+
+    match fetch_result {
+        Ok(output) => {
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(anyhow!("Failed to fetch remote changes: {}", stderr.trim()));
+            }
+        }
+        Err(e) => {
+            return Err(anyhow!("Failed to execute git fetch: {}", e));
+        }
     }
 
-    #[test]
-    fn test_command_operations() {
-        let temp_dir = TempDir::new().unwrap();
-        let config_dir = temp_dir.path().join(".config").join("doo");
+    Ok(())
+}
 
-        let mut config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+/// Compare the config files of a repository directory with the remote
+/// branch a sync would reset it to
+fn preview_sync_repository(
+    repo_name: &str,
+    repo_path: &Path,
+    token: Option<&str>,
+) -> Result<Vec<ConfigDiff>> {
+    git_fetch(repo_path, token)?;
+
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .current_dir(repo_path)
+            .args(args)
+            .output()
+            .map_err(|e| anyhow!("Failed to execute git: {}", e))
+    };
+    let branch = ["origin/main", "origin/master"]
+        .into_iter()
+        .find(|branch| {
+            git(&["rev-parse", "--verify", "--quiet", branch])
+                .is_ok_and(|output| output.status.success())
+        })
+        .ok_or_else(|| anyhow!("Neither origin/main nor origin/master exists"))?;
+
+    let listing = git(&["ls-tree", "--name-only", branch])?;
+    let mut files: Vec<String> = String::from_utf8_lossy(&listing.stdout)
+        .lines()
+        .filter(|file| is_yaml_file(Path::new(file)))
+        .map(str::to_string)
+        .collect();
+    for entry in fs::read_dir(repo_path)? {
+        let path = entry?.path();
+        if path.is_file() && is_yaml_file(&path) {
+            files.extend(
+                path.file_name()
+                    .map(|name| name.to_string_lossy().to_string()),
+            );
+        }
+    }
+    files.sort();
+    files.dedup();
+
+    let mut diffs = Vec::new();
+    for file in files {
+        let local = fs::read_to_string(repo_path.join(&file)).unwrap_or_default();
+        let shown = git(&["show", &format!("{branch}:{file}")])?;
+        let remote = if shown.status.success() {
+            String::from_utf8_lossy(&shown.stdout).to_string()
+        } else {
+            String::new()
+        };
+        diffs.push(ConfigDiff::new(
+            &format!("{repo_name}/{file}"),
+            &local,
+            &remote,
+        ));
+    }
+    Ok(diffs)
+}
 
-        // Test adding command
-        config_manager.add_command("test", "echo hello").unwrap();
-        assert_eq!(
-            config_manager.get_command("test").unwrap(),
-            Some("echo hello".to_string())
-        );
+fn is_yaml_file(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext == "yaml" || ext == "yml")
+}
+
+/// Whether `path` is a YAML, TOML or JSON file
+fn is_config_file(path: &Path) -> bool {
+    is_yaml_file(path) || detect_format(path) != ConfigFormat::Yaml
+}
+
+/// Whether `text` matches `pattern`, in which `*` stands for any run of
+/// characters and `?` for a single one
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and of the text it was matched up to
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            // Let the `*` take one more character
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Check that `namespace` can prefix command names and name a config file
+fn check_namespace_name(namespace: &str) -> Result<()> {
+    if namespace.is_empty()
+        || !namespace
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(anyhow!(
+            "Invalid namespace '{}': use letters, digits, '-' and '_'",
+            namespace
+        ));
+    }
+    Ok(())
+}
+
+/// Make `name` safe to use as a single path component on every platform by
+/// replacing separators and characters that are invalid on NTFS, and by
+/// avoiding names that Windows reserves for devices
+pub fn sanitize_path_component(name: &str) -> String {
+    const RESERVED: &[&str] = &[
+        "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+        "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+    ];
+
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    // Windows silently strips trailing dots and spaces
+    while sanitized.ends_with('.') || sanitized.ends_with(' ') {
+        sanitized.pop();
+    }
+    if sanitized.is_empty() {
+        sanitized.push('_');
+    }
+
+    let stem = sanitized.split('.').next().unwrap_or_default();
+    if RESERVED
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    {
+        sanitized.insert(0, '_');
+    }
+
+    sanitized
+}
+
+/// Unpack the doo entries of the backup at `archive_path` into `dir`,
+/// returning the unpacked files relative to it. Anything else in the archive
+/// is ignored.
+fn unpack_backup(archive_path: &Path, dir: &Path) -> Result<Vec<PathBuf>> {
+    let file = fs::File::open(archive_path)
+        .with_context(|| format!("Failed to open backup file: {archive_path:?}"))?;
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+    let mut files = Vec::new();
+    for entry in archive.entries().context("Failed to read backup archive")? {
+        let mut entry = entry.context("Failed to read backup archive entry")?;
+        let relative = entry.path()?.into_owned();
+        let belongs_to_doo = relative
+            .components()
+            .next()
+            .and_then(|c| c.as_os_str().to_str())
+            .is_some_and(|first| BACKUP_ENTRIES.contains(&first));
+        if !belongs_to_doo {
+            continue;
+        }
+        if entry
+            .unpack_in(dir)
+            .with_context(|| format!("Failed to restore {relative:?}"))?
+            && entry.header().entry_type().is_file()
+        {
+            files.push(relative);
+        }
+    }
+    Ok(files)
+}
+
+/// Recursively collect all files below `dir` as paths relative to `base`
+fn collect_files(
+    base: &Path,
+    dir: &Path,
+    include_git: bool,
+    files: &mut Vec<PathBuf>,
+) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read directory: {dir:?}"))? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if !include_git && path.file_name().is_some_and(|name| name == ".git") {
+                continue;
+            }
+            collect_files(base, &path, include_git, files)?;
+        } else if let Ok(relative) = path.strip_prefix(base) {
+            files.push(relative.to_path_buf());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_config_creation() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+
+        let config_manager = ConfigManager::new_with_dir(config_dir);
+        assert!(config_manager.is_ok());
+    }
+
+    #[test]
+    fn test_command_operations() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+
+        let mut config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+
+        // Test adding command
+        config_manager.add_command("test", "echo hello").unwrap();
+        assert_eq!(
+            config_manager.get_command("test").unwrap(),
+            Some("echo hello".to_string())
+        );
 
         // Test removing command
         assert!(config_manager.remove_command("test").unwrap());
         assert_eq!(config_manager.get_command("test").unwrap(), None);
+        assert!(!config_manager.remove_command("test").unwrap());
+    }
+
+    #[test]
+    fn test_commands_matching() {
+        assert!(glob_match("test-*", "test-api"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*-test", "api-test"));
+        assert!(glob_match("t?st*", "test"));
+        assert!(glob_match("a*b*c", "axxbyyc"));
+        assert!(!glob_match("test-*", "api-test"));
+        assert!(!glob_match("a*b*c", "axxbyy"));
+        assert!(!glob_match("t?st", "tst"));
+
+        let temp_dir = TempDir::new().unwrap();
+        let mut config_manager = ConfigManager::new_with_dir(temp_dir.path().join("doo")).unwrap();
+        for name in ["test-web", "test-api", "build", "test"] {
+            config_manager.add_command(name, "true").unwrap();
+        }
+        assert_eq!(
+            config_manager.commands_matching("test-*"),
+            vec!["test-api", "test-web"]
+        );
+        assert_eq!(config_manager.commands_matching("test").len(), 1);
+        assert!(config_manager.commands_matching("deploy*").is_empty());
+    }
+
+    #[test]
+    fn test_list_command_sources() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        fs::create_dir_all(config_dir.join("configs")).unwrap();
+        fs::write(config_dir.join("config.yaml"), "commands: {}\n").unwrap();
+        fs::write(
+            config_dir.join("configs").join("docker.yaml"),
+            "commands:\n  ps: docker ps\n  build: docker build .\n",
+        )
+        .unwrap();
+
+        let mut config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+        config_manager
+            .add_command_with_description("ps", "ps aux", Some("Local processes"))
+            .unwrap();
+
+        let sources: Vec<(String, String)> = config_manager
+            .list_command_sources()
+            .into_iter()
+            .map(|source| (source.name, source.source_file))
+            .collect();
+        assert_eq!(
+            sources,
+            vec![
+                ("build".to_string(), "docker".to_string()),
+                ("ps".to_string(), "main".to_string()),
+                ("ps".to_string(), "docker".to_string()),
+            ]
+        );
+        assert_eq!(
+            config_manager.main_commands()["ps"].description(),
+            Some("Local processes")
+        );
+    }
+
+    #[test]
+    fn test_status_sources() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        fs::create_dir_all(config_dir.join("configs")).unwrap();
+        fs::write(config_dir.join("config.yaml"), "commands:\n  ps: ps aux\n").unwrap();
+        fs::write(
+            config_dir.join("configs").join("docker.yaml"),
+            "commands:\n  ps: docker ps\norigin:\n  repo: team/docker\n  import_type: Public\n  synced_at: 2026-10-01T12:00:00Z\n",
+        )
+        .unwrap();
+        fs::write(
+            config_dir.join("configs").join("local.yaml"),
+            "commands:\n  ps: podman ps\n  top: htop\n",
+        )
+        .unwrap();
+
+        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+        let conflicts = config_manager.conflicting_commands();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts["ps"][0], "main");
+        assert_eq!(conflicts["ps"].len(), 3);
+
+        let origins = config_manager.remote_origins();
+        assert_eq!(origins.len(), 1);
+        assert_eq!(origins[0].0, "docker");
+        assert_eq!(
+            origins[0].1.synced_at,
+            Some("2026-10-01T12:00:00Z".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_namespaced_import() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        fs::create_dir_all(config_dir.join("configs")).unwrap();
+        fs::write(config_dir.join("config.yaml"), "commands: {}\n").unwrap();
+        fs::write(
+            config_dir.join("configs").join("team.yaml"),
+            "commands:\n  deploy: ./deploy.sh\n",
+        )
+        .unwrap();
+        let devops = temp_dir.path().join("devops.yaml");
+        fs::write(&devops, "commands:\n  deploy: \"kubectl apply -f #1\"\n").unwrap();
+
+        let mut config_manager = ConfigManager::new_with_dir(config_dir.clone()).unwrap();
+        config_manager.check_namespace("infra").unwrap();
+        let imported = config_manager
+            .import_config(devops.to_str().unwrap())
+            .unwrap();
+        assert_eq!(
+            config_manager.namespace_config(&imported, "infra").unwrap(),
+            "infra"
+        );
+        assert!(!config_dir.join("configs").join("devops.yaml").exists());
+
+        assert_eq!(
+            config_manager
+                .get_command("infra:deploy")
+                .unwrap()
+                .as_deref(),
+            Some("kubectl apply -f #1")
+        );
+        assert_eq!(
+            config_manager.get_command("deploy").unwrap().as_deref(),
+            Some("./deploy.sh")
+        );
+        assert_eq!(config_manager.get_command_conflicts("deploy").len(), 1);
+        let names: Vec<String> = config_manager
+            .list_command_sources()
+            .into_iter()
+            .map(|source| source.name)
+            .collect();
+        assert_eq!(names, vec!["deploy", "infra:deploy"]);
+
+        // The namespace is kept when the config is loaded again
+        let reloaded = ConfigManager::new_with_dir(config_dir).unwrap();
+        let source = reloaded
+            .get_command_with_source("infra:deploy")
+            .unwrap()
+            .unwrap();
+        assert_eq!(source.source_file, "infra");
+
+        let err = reloaded.check_namespace("infra").unwrap_err();
+        assert!(err.to_string().contains("already exists"));
+        let err = reloaded.check_namespace("in:fra").unwrap_err();
+        assert!(err.to_string().starts_with("Invalid namespace"));
+    }
+
+    #[test]
+    fn test_merge_configs() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let configs_dir = config_dir.join("configs");
+        fs::create_dir_all(&configs_dir).unwrap();
+        fs::write(config_dir.join("config.yaml"), "commands: {}\n").unwrap();
+        fs::write(
+            configs_dir.join("docker.yaml"),
+            "commands:\n  ps: docker ps\n  logs: docker logs -f $1\n",
+        )
+        .unwrap();
+        fs::write(
+            configs_dir.join("k8s.yaml"),
+            "commands:\n  pods: kubectl get pods\n  logs: docker logs -f $1\n",
+        )
+        .unwrap();
+        fs::write(configs_dir.join("other.yaml"), "commands:\n  ps: ps aux\n").unwrap();
+
+        let mut config_manager = ConfigManager::new_with_dir(config_dir.clone()).unwrap();
+        assert_eq!(
+            config_manager
+                .merge_configs("docker", "k8s", "devops")
+                .unwrap(),
+            MergeOutcome::Merged { commands: 3 }
+        );
+
+        // The merged file loads like any other imported config
+        let reloaded = ConfigManager::new_with_dir(config_dir.clone()).unwrap();
+        let source = reloaded.get_command_with_source("pods").unwrap().unwrap();
+        assert!(["devops", "k8s"].contains(&source.source_file.as_str()));
+        assert_eq!(
+            reloaded.resolve_command_conflict("ps", "devops").unwrap(),
+            Some("docker ps".to_string())
+        );
+
+        let outcome = config_manager
+            .merge_configs("docker", "other", "mixed")
+            .unwrap();
+        let MergeOutcome::Conflicts(conflicts) = outcome else {
+            panic!("expected conflicts, got {outcome:?}");
+        };
+        assert_eq!(
+            conflicts,
+            vec![MergeConflict {
+                name: "ps".to_string(),
+                first: CommandEntry::Simple("docker ps".to_string()),
+                second: CommandEntry::Simple("ps aux".to_string()),
+            }]
+        );
+        assert!(!configs_dir.join("mixed.yaml").exists());
+
+        let err = config_manager
+            .merge_configs("docker", "k8s", "devops")
+            .unwrap_err();
+        assert_eq!(err.to_string(), "A config named 'devops' already exists");
+        let err = config_manager
+            .merge_configs("docker", "missing", "all")
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Config 'missing' not found");
+    }
+
+    #[test]
+    fn test_export_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let configs_dir = config_dir.join("configs");
+        fs::create_dir_all(&configs_dir).unwrap();
+        fs::write(
+            config_dir.join("config.yaml"),
+            "commands:\n  ps: ps aux\n  hello: echo hello\n",
+        )
+        .unwrap();
+        fs::write(
+            configs_dir.join("docker.yaml"),
+            "commands:\n  ps: docker ps\n  build:\n    command: docker build .\n    description: Build the image\norigin:\n  repo: acme/docker\n  import_type: Public\n",
+        )
+        .unwrap();
+
+        let config_manager = ConfigManager::new_with_dir(config_dir.clone()).unwrap();
+        let merged = config_manager.export_to_yaml(None).unwrap();
+        assert!(!merged.contains("origin"));
+        let exported: Config = serde_yaml::from_str(&merged).unwrap();
+        assert_eq!(exported.commands.len(), 3);
+        assert_eq!(exported.commands["ps"].command_str(), "docker ps");
+
+        let single = config_manager.export_to_yaml(Some("docker")).unwrap();
+        assert!(!single.contains("acme/docker"));
+        assert!(config_manager.export_to_yaml(Some("missing")).is_err());
+
+        // The export imports back with the same commands
+        let other_dir = temp_dir.path().join("other");
+        let mut other = ConfigManager::new_with_dir(other_dir).unwrap();
+        let export_file = temp_dir.path().join("all.yaml");
+        fs::write(&export_file, &merged).unwrap();
+        let imported = other.import_config(export_file.to_str().unwrap()).unwrap();
+        let reexported: Config =
+            serde_yaml::from_str(&other.export_to_yaml(Some(&imported)).unwrap()).unwrap();
+        assert_eq!(reexported.commands.len(), 3);
+        assert_eq!(
+            reexported.commands["build"].description(),
+            Some("Build the image")
+        );
+    }
+
+    #[test]
+    fn test_toml_round_trip() {
+        let yaml = "\
+commands:
+  ps: ps aux
+  logs:
+    command: kubectl logs -n '#1' '#pod'
+    description: Follow the logs of a pod
+    defaults:
+      '#1': default
+    timeout_secs: 30
+    retry:
+      max_attempts: 3
+    env:
+      KUBECONFIG: ~/.kube/staging
+    params:
+      pod:
+        name: pod
+        required: true
+    tags: [kubernetes]
+origin:
+  repo: acme/tools
+  import_type: Token
+  synced_at: 2026-10-17T09:00:00Z
+namespace: acme
+history_limit: 50
+";
+        let config = ConfigFormat::Yaml.parse(yaml).unwrap();
+        let toml = ConfigFormat::Toml.serialize(&config).unwrap();
+        let parsed = ConfigFormat::Toml.parse(&toml).unwrap();
+        assert_eq!(
+            serde_yaml::to_string(&parsed).unwrap(),
+            serde_yaml::to_string(&config).unwrap()
+        );
+        assert_eq!(parsed.commands["ps"].command_str(), "ps aux");
+        assert_eq!(parsed.commands["logs"].timeout_secs(), Some(30));
+        assert_eq!(
+            parsed.commands["logs"].retry(),
+            Some(&RetryConfig {
+                max_attempts: 3,
+                backoff_secs: 1,
+            })
+        );
+
+        // Detailed entries may be inline tables
+        let config = ConfigFormat::Toml
+            .parse(
+                "[commands]\nps = \"ps aux\"\nbuild = { command = \"docker build .\", description = \"Build\" }\n",
+            )
+            .unwrap();
+        assert_eq!(config.commands["build"].description(), Some("Build"));
+        assert_eq!(
+            ConfigFormat::Toml
+                .parse(&ConfigFormat::Toml.serialize(&config).unwrap())
+                .unwrap()
+                .commands
+                .len(),
+            2
+        );
+
+        assert_eq!(detect_format(Path::new("tools.toml")), ConfigFormat::Toml);
+        assert_eq!(detect_format(Path::new("tools.yml")), ConfigFormat::Yaml);
+        assert_eq!(detect_format(Path::new("tools")), ConfigFormat::Yaml);
+    }
+
+    #[test]
+    fn test_toml_configs() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let configs_dir = config_dir.join("configs");
+        fs::create_dir_all(&configs_dir).unwrap();
+        fs::write(
+            config_dir.join("config.toml"),
+            "[commands]\nps = \"ps aux\"\n",
+        )
+        .unwrap();
+        fs::write(
+            configs_dir.join("docker.toml"),
+            "[commands]\ndps = \"docker ps\"\n",
+        )
+        .unwrap();
+
+        let mut config_manager = ConfigManager::new_with_dir(config_dir.clone()).unwrap();
+        assert_eq!(config_manager.get_command("ps").unwrap().unwrap(), "ps aux");
+        assert_eq!(
+            config_manager.get_command("dps").unwrap().unwrap(),
+            "docker ps"
+        );
+        assert!(config_manager.validate().is_empty());
+
+        // The main config stays TOML when saved
+        config_manager.add_command("hello", "echo hello").unwrap();
+        assert!(!config_dir.join("config.yaml").exists());
+        let saved = fs::read_to_string(config_dir.join("config.toml")).unwrap();
+        assert!(ConfigFormat::Toml.parse(&saved).unwrap().commands["hello"]
+            .command_str()
+            .contains("echo hello"));
+        assert_eq!(
+            config_manager.config_file("main"),
+            Some(config_dir.join("config.toml"))
+        );
+
+        // TOML files are imported as TOML, by extension or explicitly
+        let k8s = temp_dir.path().join("k8s.toml");
+        fs::write(&k8s, "[commands]\npods = \"kubectl get pods\"\n").unwrap();
+        assert_eq!(
+            config_manager.import_config(k8s.to_str().unwrap()).unwrap(),
+            "k8s"
+        );
+        assert!(configs_dir.join("k8s.toml").exists());
+        let tools = temp_dir.path().join("tools.conf");
+        fs::write(&tools, "[commands]\nt = \"echo t\"\n").unwrap();
+        assert_eq!(
+            config_manager
+                .import_config_toml(tools.to_str().unwrap())
+                .unwrap(),
+            "tools"
+        );
+        assert!(configs_dir.join("tools.toml").exists());
+        // A name taken by a TOML config isn't reused for a YAML one
+        let yaml = temp_dir.path().join("k8s.yaml");
+        fs::write(&yaml, "commands:\n  nodes: kubectl get nodes\n").unwrap();
+        assert_eq!(
+            config_manager
+                .import_config(yaml.to_str().unwrap())
+                .unwrap(),
+            "k8s_1"
+        );
+
+        let reloaded = ConfigManager::new_with_dir(config_dir.clone()).unwrap();
+        assert_eq!(
+            reloaded.get_command("pods").unwrap().unwrap(),
+            "kubectl get pods"
+        );
+        assert_eq!(reloaded.get_command("t").unwrap().unwrap(), "echo t");
+
+        // config.yaml wins when both exist
+        fs::write(config_dir.join("config.yaml"), "commands:\n  ls: ls -la\n").unwrap();
+        let reloaded = ConfigManager::new_with_dir(config_dir).unwrap();
+        assert!(reloaded.main_commands().contains_key("ls"));
+        assert!(!reloaded.main_commands().contains_key("ps"));
+    }
+
+    /// Next change of the config `name`, skipping others and repeats
+    fn next_change(
+        events: &std::sync::mpsc::Receiver<ConfigChangeEvent>,
+        name: &str,
+        change: ConfigChange,
+    ) -> ConfigChangeEvent {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        loop {
+            let timeout = deadline.saturating_duration_since(std::time::Instant::now());
+            let event = events
+                .recv_timeout(timeout)
+                .unwrap_or_else(|_| panic!("No {change:?} event for {name}"));
+            if event.name == name && event.change == change {
+                return event;
+            }
+        }
+    }
+
+    #[test]
+    fn test_watch() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir.clone()).unwrap();
+        let (sender, events) = std::sync::mpsc::channel();
+        let handle = config_manager
+            .watch(move |event| {
+                let _ = sender.send(event);
+            })
+            .unwrap();
+
+        let tools = config_dir.join("configs").join("tools.yaml");
+        fs::write(&tools, "commands:\n  ps: docker ps\n").unwrap();
+        let event = next_change(&events, "tools", ConfigChange::Added);
+        assert_eq!(event.path.file_name(), tools.file_name());
+        fs::write(&tools, "commands:\n  ps: docker ps -a\n").unwrap();
+        next_change(&events, "tools", ConfigChange::Modified);
+        fs::remove_file(&tools).unwrap();
+        next_change(&events, "tools", ConfigChange::Removed);
+
+        let mut config_manager = config_manager;
+        config_manager.add_command("hello", "echo hello").unwrap();
+        next_change(&events, "main", ConfigChange::Added);
+        let repo_dir = config_dir.join("configs").join("team");
+        fs::create_dir(&repo_dir).unwrap();
+        // Give the watcher time to pick up the new directory
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        fs::write(repo_dir.join("doo.yaml"), "commands:\n  up: make up\n").unwrap();
+        next_change(&events, "team_doo", ConfigChange::Added);
+
+        // Files doo doesn't read as configs are ignored
+        fs::write(config_dir.join("current_context"), "staging").unwrap();
+        fs::write(config_dir.join("configs").join("notes.txt"), "").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        assert!(events.try_iter().all(|event| event.name != "notes"
+            && event.path.file_name() != Some("current_context".as_ref())));
+
+        drop(handle);
+        fs::write(&tools, "commands: {}\n").unwrap();
+        assert!(events
+            .recv_timeout(std::time::Duration::from_millis(500))
+            .is_err());
+    }
+
+    #[test]
+    fn test_project_config() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let project = temp_dir.path().join("project");
+        let nested = project.join("src").join("deep");
+        fs::create_dir_all(&nested).unwrap();
+        fs::create_dir_all(config_dir.join("configs")).unwrap();
+        fs::write(
+            config_dir.join("config.yaml"),
+            "commands:\n  build: make\n  ps: ps aux\n",
+        )
+        .unwrap();
+        fs::write(
+            config_dir.join("configs").join("tools.yaml"),
+            "commands:\n  build: ninja\n",
+        )
+        .unwrap();
+        fs::write(
+            project.join(".doo.yaml"),
+            "commands:\n  build: cargo build\n  test: cargo test\n",
+        )
+        .unwrap();
+
+        assert_eq!(find_project_config(temp_dir.path()), None);
+        assert_eq!(
+            find_project_config(&nested),
+            Some(project.join(".doo.yaml"))
+        );
+
+        let mut config_manager = ConfigManager::new_with_dir(config_dir.clone()).unwrap();
+        assert_eq!(
+            config_manager.get_command("build").unwrap().unwrap(),
+            "make"
+        );
+        config_manager.load_project_config(&nested).unwrap();
+        assert_eq!(
+            config_manager.project_config_file(),
+            Some(project.join(".doo.yaml").as_path())
+        );
+
+        // Project commands come first, then main, then imported configs
+        let source = config_manager
+            .get_command_with_source("build")
+            .unwrap()
+            .unwrap();
+        assert_eq!(source.command, "cargo build");
+        assert_eq!(source.kind, ConfigSource::ProjectLocal);
+        let conflicts = config_manager.get_command_conflicts("build");
+        let kinds: Vec<_> = conflicts.iter().map(|source| source.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                ConfigSource::ProjectLocal,
+                ConfigSource::Main,
+                ConfigSource::Imported
+            ]
+        );
+        assert_eq!(config_manager.list_commands()["build"], "cargo build");
+        assert_eq!(config_manager.get_command("ps").unwrap().unwrap(), "ps aux");
+        assert_eq!(
+            config_manager
+                .resolve_command_conflict("build", PROJECT_CONFIG)
+                .unwrap()
+                .unwrap(),
+            "cargo build"
+        );
+
+        // Overrides are reported apart from conflicts between global configs
+        assert_eq!(
+            config_manager.conflicting_commands()["build"],
+            ["main", "tools"]
+        );
+        assert_eq!(
+            config_manager.project_overrides(),
+            BTreeMap::from([(
+                "build".to_string(),
+                vec!["main".to_string(), "tools".to_string()]
+            )])
+        );
+        assert!(config_manager.validate().is_empty());
+
+        // .doo/config.yaml works as well, and the nearest project wins
+        let inner = project.join("src");
+        fs::create_dir_all(inner.join(".doo")).unwrap();
+        fs::write(
+            inner.join(".doo").join("config.yaml"),
+            "commands:\n  build: cargo build --release\n",
+        )
+        .unwrap();
+        config_manager.load_project_config(&nested).unwrap();
+        assert_eq!(
+            config_manager.get_command("build").unwrap().unwrap(),
+            "cargo build --release"
+        );
+        assert!(config_manager.get_command("test").unwrap().is_none());
+
+        // A broken project config is an error
+        fs::write(project.join(".doo.yaml"), "commands: [").unwrap();
+        assert!(config_manager.load_project_config(&project).is_err());
+    }
+
+    #[test]
+    fn test_init_project_config() {
+        let temp_dir = TempDir::new().unwrap();
+        ConfigManager::init_project_config(temp_dir.path()).unwrap();
+        let path = temp_dir.path().join(".doo.yaml");
+        let config = ConfigFormat::Yaml
+            .parse(&fs::read_to_string(&path).unwrap())
+            .unwrap();
+        assert_eq!(
+            config.commands["hello"].command_str(),
+            "echo Hello from #name"
+        );
+        assert!(ConfigManager::init_project_config(temp_dir.path()).is_err());
+
+        // Merging keeps the commands already in the file
+        let mut init = ProjectInit {
+            name: "shop".to_string(),
+            commands: vec![
+                (
+                    "build".to_string(),
+                    CommandEntry::new("cargo build", Some("Build it"), &[]),
+                ),
+                ("hello".to_string(), CommandEntry::new("echo hi", None, &[])),
+            ],
+            schema_comment: false,
+        };
+        let (written, config) =
+            ConfigManager::write_project_config(temp_dir.path(), &init, true).unwrap();
+        assert_eq!(written, path);
+        assert_eq!(config.commands.len(), 4);
+        assert_eq!(config.commands["hello"].command_str(), "echo hi");
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.starts_with("# Commands of the shop project."));
+        assert_eq!(
+            ConfigFormat::Yaml.parse(&contents).unwrap().commands.len(),
+            4
+        );
+
+        // Overwriting replaces them
+        init.schema_comment = true;
+        let (_, config) =
+            ConfigManager::write_project_config(temp_dir.path(), &init, false).unwrap();
+        assert_eq!(config.commands.len(), 2);
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.starts_with(SCHEMA_COMMENT));
+        assert_eq!(
+            ConfigFormat::Yaml.parse(&contents).unwrap().commands["build"].description(),
+            Some("Build it")
+        );
+    }
+
+    #[test]
+    fn test_json_configs() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let configs_dir = config_dir.join("configs");
+        fs::create_dir_all(configs_dir.join("acme_tools")).unwrap();
+        fs::write(
+            config_dir.join("config.json"),
+            r#"{"commands": {"ps": "ps aux"}}"#,
+        )
+        .unwrap();
+        fs::write(
+            configs_dir.join("docker.json"),
+            r#"{"commands": {"dps": {"command": "docker ps -f #1", "description": "List"}}}"#,
+        )
+        .unwrap();
+        // JSON files in repository directories aren't configs
+        fs::write(
+            configs_dir.join("acme_tools").join("package.json"),
+            r#"{"commands": {"npm": "npm test"}}"#,
+        )
+        .unwrap();
+
+        let mut config_manager = ConfigManager::new_with_dir(config_dir.clone()).unwrap();
+        assert_eq!(config_manager.get_command("ps").unwrap().unwrap(), "ps aux");
+        assert_eq!(
+            config_manager.get_command("dps").unwrap().unwrap(),
+            "docker ps -f #1"
+        );
+        assert!(config_manager.get_command("npm").unwrap().is_none());
+        assert!(config_manager.validate().is_empty());
+        assert_eq!(config_manager.json_configs(), ["main", "docker"]);
+        assert!(config_manager
+            .export_to_yaml(Some("docker"))
+            .unwrap()
+            .contains("docker ps -f #1"));
+
+        // JSON files are imported as JSON
+        let k8s = temp_dir.path().join("k8s.json");
+        fs::write(&k8s, r#"{"commands": {"pods": "kubectl get pods"}}"#).unwrap();
+        assert_eq!(
+            config_manager.import_config(k8s.to_str().unwrap()).unwrap(),
+            "k8s"
+        );
+        assert!(configs_dir.join("k8s.json").exists());
+
+        // Converting replaces the JSON file
+        let path = config_manager
+            .convert_config("docker", ConfigFormat::Yaml)
+            .unwrap();
+        assert_eq!(path, configs_dir.join("docker.yaml"));
+        assert!(!configs_dir.join("docker.json").exists());
+        assert!(config_manager
+            .convert_config("docker", ConfigFormat::Yaml)
+            .is_err());
+        config_manager
+            .convert_config("main", ConfigFormat::Yaml)
+            .unwrap();
+        assert!(!config_dir.join("config.json").exists());
+        assert!(config_manager
+            .convert_config("missing", ConfigFormat::Yaml)
+            .is_err());
+
+        let reloaded = ConfigManager::new_with_dir(config_dir).unwrap();
+        assert_eq!(reloaded.json_configs(), ["k8s"]);
+        assert_eq!(reloaded.get_command("ps").unwrap().unwrap(), "ps aux");
+        assert_eq!(
+            reloaded.get_command("dps").unwrap().unwrap(),
+            "docker ps -f #1"
+        );
+    }
+
+    #[test]
+    fn test_arg_constraints() {
+        let config: Config = serde_yaml::from_str(
+            r##"commands:
+  deploy:
+    command: "./deploy.sh #1 #version"
+    args:
+      "#1":
+        choices: [staging, production]
+      version:
+        pattern: "^v[0-9]+$"
+"##,
+        )
+        .unwrap();
+        let source = config.commands["deploy"].source("deploy", "main", ConfigSource::Main);
+        assert!(source.arg_spec("#version").is_some());
+        assert!(source.arg_spec("#2").is_none());
+
+        let values = |pairs: &[(&str, &str)]| -> BTreeMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        assert!(source
+            .check_args(&values(&[
+                ("#1", "staging"),
+                ("#version", "v2"),
+                ("#2", "x")
+            ]))
+            .is_ok());
+        let err = source.check_args(&values(&[("#1", "prod")])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid value 'prod' for #1 of command 'deploy': expected one of staging, production"
+        );
+        let err = source
+            .check_args(&values(&[("#version", "2")]))
+            .unwrap_err();
+        assert!(err.to_string().contains("'2' for #version"));
+        assert!(err.to_string().contains("'^v[0-9]+$'"));
+
+        let invalid = ArgSpec {
+            pattern: Some("(".to_string()),
+            choices: Vec::new(),
+        };
+        assert!(invalid
+            .check("deploy", "#1", "x")
+            .unwrap_err()
+            .to_string()
+            .starts_with("Invalid pattern for #1"));
+    }
+
+    #[test]
+    fn test_config_diff() {
+        let local = "commands:\n  a: echo a\n  b: echo b\n  c:\n    command: echo c\n";
+        let remote =
+            "commands:\n  a: echo a\n  c:\n    command: echo c\n    timeout_secs: 5\n  d: echo d\n";
+
+        let diff = ConfigDiff::new("team", local, remote);
+        assert!(!diff.is_empty());
+        assert_eq!(diff.added, ["d"]);
+        assert_eq!(diff.removed, ["b"]);
+        assert_eq!(diff.changed, ["c"]);
+        assert!(diff
+            .unified
+            .starts_with("--- team (local)\n+++ team (remote)\n"));
+        assert!(diff.unified.contains("\n-  b: echo b\n"));
+
+        let diff = ConfigDiff::new("team", local, local);
+        assert!(diff.is_empty());
+        assert!(diff.added.is_empty() && diff.removed.is_empty() && diff.changed.is_empty());
+    }
+
+    #[test]
+    fn test_url_sources() {
+        assert!(is_url("https://example.com/doo.yaml"));
+        assert!(is_url("http://localhost:8080/doo.yaml"));
+        assert!(!is_url("owner/repo"));
+        assert!(!is_url("./https/doo.yaml"));
+
+        assert!(is_config_content_type("application/yaml"));
+        assert!(is_config_content_type("application/x-yaml; charset=utf-8"));
+        assert!(is_config_content_type("text/plain; charset=utf-8"));
+        assert!(is_config_content_type("application/octet-stream"));
+        assert!(!is_config_content_type("text/html; charset=utf-8"));
+        assert!(!is_config_content_type("image/png"));
+    }
+
+    #[test]
+    fn test_validate() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join("doo");
+        fs::create_dir_all(config_dir.join("configs")).unwrap();
+        fs::write(
+            config_dir.join("config.yaml"),
+            r##"commands:
+  logs: "kubectl logs -n #1 #2"
+  logs: "kubectl logs #1"
+  "": "echo"
+  broken: "echo ${HOME 'unterminated"
+  deploy:
+    command: "./deploy.sh #1 $0"
+    defaults:
+      "#1": staging
+      "#3": unused
+    args:
+      version:
+        pattern: "(["
+    timeout_secs: 0
+    retry:
+      max_attempts: 0
+"##,
+        )
+        .unwrap();
+        fs::write(
+            config_dir.join("configs").join("team.yaml"),
+            "commands:\n  ok:\n    command: \"echo #name\"\n    defaults:\n      name: x\n  copy: \"cp #1 #3\"\n",
+        )
+        .unwrap();
+
+        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+        let mut errors = config_manager.validate();
+        let pattern_error = errors.remove(7);
+        assert!(pattern_error
+            .to_string()
+            .starts_with("main: command 'deploy': invalid pattern for 'version'"));
+        let file = "main".to_string();
+        let name = |name: &str| name.to_string();
+        assert_eq!(
+            errors,
+            vec![
+                ValidationError::DuplicateCommand {
+                    file: file.clone(),
+                    name: name("logs"),
+                },
+                ValidationError::EmptyCommandName { file: file.clone() },
+                ValidationError::InvalidTemplate {
+                    file: file.clone(),
+                    name: name("broken"),
+                    message: "missing closing quote".to_string(),
+                },
+                ValidationError::MalformedPlaceholder {
+                    file: file.clone(),
+                    name: name("broken"),
+                    placeholder: "${HOME".to_string(),
+                },
+                ValidationError::MalformedPlaceholder {
+                    file: file.clone(),
+                    name: name("deploy"),
+                    placeholder: "$0".to_string(),
+                },
+                ValidationError::InconsistentEntry {
+                    file: file.clone(),
+                    name: name("deploy"),
+                    message: "default for '#3' is not used by any placeholder".to_string(),
+                },
+                ValidationError::InconsistentEntry {
+                    file: file.clone(),
+                    name: name("deploy"),
+                    message: "constraint for 'version' is not used by any placeholder".to_string(),
+                },
+                ValidationError::InconsistentEntry {
+                    file: file.clone(),
+                    name: name("deploy"),
+                    message: "timeout_secs must be greater than 0".to_string(),
+                },
+                ValidationError::InconsistentEntry {
+                    file: file.clone(),
+                    name: name("deploy"),
+                    message: "retry.max_attempts must be greater than 0".to_string(),
+                },
+                ValidationError::SuspiciousTemplate {
+                    file: "team".to_string(),
+                    name: name("copy"),
+                    warning: TemplateWarning::SkippedPosition {
+                        placeholder: "#3".to_string(),
+                        missing: "#2".to_string(),
+                    },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_detailed_entry_defaults() {
+        let config: Config = serde_yaml::from_str(
+            "commands:\n  logs:\n    command: kubectl logs -n #1\n    defaults:\n      \"#1\": kube-system\n",
+        )
+        .unwrap();
+        let source = config.commands["logs"].source("logs", "main", ConfigSource::Main);
+        assert_eq!(source.defaults["#1"], "kube-system");
+
+        let yaml = serde_yaml::to_string(&config).unwrap();
+        assert!(yaml.contains("defaults"));
+    }
+
+    #[test]
+    fn test_param_specs() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join("doo");
+        fs::create_dir_all(&config_dir).unwrap();
+        fs::write(
+            config_dir.join("config.yaml"),
+            r##"commands:
+  watch:
+    command: "watch kubectl -n #1 get #2"
+    params:
+      "#1": { name: namespace, required: true, description: Kubernetes namespace }
+      "#2": { name: resource, default: pods }
+  top:
+    command: "kubectl top #kind"
+    defaults:
+      kind: nodes
+    params:
+      kind: { default: pods }
+      "#3": { name: unused }
+"##,
+        )
+        .unwrap();
+
+        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+        let source = config_manager
+            .get_command_with_source("watch")
+            .unwrap()
+            .unwrap();
+        assert_eq!(source.defaults["#2"], "pods");
+        assert_eq!(
+            source.param_spec("#1").unwrap().description.as_deref(),
+            Some("Kubernetes namespace")
+        );
+        assert_eq!(
+            source.display_command(),
+            "watch kubectl -n <namespace> get <resource>"
+        );
+        // Explicit defaults win over those of params
+        let top = config_manager
+            .get_command_with_source("top")
+            .unwrap()
+            .unwrap();
+        assert_eq!(top.defaults["kind"], "nodes");
+        assert_eq!(top.display_command(), "kubectl top #kind");
+
+        let name = |name: &str| name.to_string();
+        assert_eq!(
+            config_manager.validate(),
+            vec![
+                ValidationError::InconsistentEntry {
+                    file: name("main"),
+                    name: name("top"),
+                    message: "parameter '#3' is not used by any placeholder".to_string(),
+                },
+                ValidationError::InconsistentEntry {
+                    file: name("main"),
+                    name: name("top"),
+                    message: "'kind' has a default both in defaults and in params".to_string(),
+                },
+            ]
+        );
+
+        // A required parameter needs a default or a stored value
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+        let errors = config_manager
+            .validate_required_params(&variable_manager, "default")
+            .unwrap();
+        assert_eq!(
+            errors,
+            vec![ValidationError::MissingRequiredParam {
+                file: name("main"),
+                name: name("watch"),
+                param: "'namespace' (#1)".to_string(),
+            }]
+        );
+        assert_eq!(
+            errors[0].to_string(),
+            "main: command 'watch': required parameter 'namespace' (#1) has no default and no stored value"
+        );
+        variable_manager
+            .set_variable("default", "#1", "kube-system")
+            .unwrap();
+        assert!(config_manager
+            .validate_required_params(&variable_manager, "default")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_backup_skips_git_internals() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+
+        let mut config_manager = ConfigManager::new_with_dir(config_dir.clone()).unwrap();
+        config_manager.add_command("test", "echo hello").unwrap();
+        let repo_dir = config_dir.join("configs").join("owner-repo");
+        fs::create_dir_all(repo_dir.join(".git")).unwrap();
+        fs::write(repo_dir.join(".git").join("HEAD"), "ref: refs/heads/main").unwrap();
+        fs::write(repo_dir.join("docker.yaml"), "commands:\n  ps: docker ps\n").unwrap();
+
+        let without_git = config_manager.backup_files(false).unwrap();
+        assert!(without_git.contains(&PathBuf::from("config.yaml")));
+        assert!(without_git.contains(&Path::new("configs").join("owner-repo").join("docker.yaml")));
+        assert!(!without_git
+            .iter()
+            .any(|p| p.components().any(|c| c.as_os_str() == ".git")));
+
+        let with_git = config_manager.backup_files(true).unwrap();
+        assert_eq!(with_git.len(), without_git.len() + 1);
+    }
+
+    #[test]
+    fn test_sanitize_path_component() {
+        assert_eq!(sanitize_path_component("owner-repo"), "owner-repo");
+        assert_eq!(sanitize_path_component("a/b\\c:d*e?f"), "a_b_c_d_e_f");
+        assert_eq!(sanitize_path_component("<\"|>"), "____");
+        assert_eq!(sanitize_path_component("trailing. "), "trailing");
+        assert_eq!(sanitize_path_component("..."), "_");
+        assert_eq!(sanitize_path_component("con"), "_con");
+        assert_eq!(sanitize_path_component("LPT1.yaml"), "_LPT1.yaml");
+        assert_eq!(sanitize_path_component("console"), "console");
+    }
+
+    #[test]
+    fn test_repo_manifest_migration() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let configs_dir = config_dir.join("configs");
+
+        // A repository imported before the manifest existed
+        let repo_dir = configs_dir.join("my-org-my-repo");
+        fs::create_dir_all(&repo_dir).unwrap();
+        fs::write(
+            repo_dir.join("docker.yaml"),
+            "commands:\n  ps: docker ps\norigin:\n  repo: my-org/my-repo\n  import_type: Private\n",
+        )
+        .unwrap();
+        // A hand-created directory whose name merely contains a dash
+        let manual_dir = configs_dir.join("my-commands");
+        fs::create_dir_all(&manual_dir).unwrap();
+        fs::write(manual_dir.join("tools.yaml"), "commands:\n  t: echo t\n").unwrap();
+
+        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+        assert_eq!(config_manager.repo_manifest.repos.len(), 1);
+        assert_eq!(
+            config_manager.repo_manifest.repos["my-org-my-repo"].repo,
+            "my-org/my-repo"
+        );
+        assert!(configs_dir.join(REPO_MANIFEST_FILE).exists());
+        assert!(config_manager.is_repo_config("my-org-my-repo_docker"));
+        assert!(config_manager.is_repo_config("my-commands_tools"));
+
+        // The manifest is not mistaken for an imported config
+        let reloaded = ConfigManager::new_with_dir(temp_dir.path().join(".config").join("doo"));
+        assert!(reloaded.is_ok());
+        assert!(!reloaded.unwrap().imported_configs.contains_key("repos"));
+    }
+
+    #[test]
+    fn test_repo_dir_name_avoids_clashes() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let mut config_manager = ConfigManager::new_with_dir(config_dir.clone()).unwrap();
+
+        config_manager.repo_manifest.repos.insert(
+            "a-b-c".to_string(),
+            ConfigOrigin {
+                repo: "a-b/c".to_string(),
+                import_type: ImportType::Private,
+                synced_at: None,
+            },
+        );
+
+        // Same repository reuses its directory, an ambiguous one gets a new name
+        assert_eq!(config_manager.repo_dir_name("a-b", "c", "a-b/c"), "a-b-c");
+        assert_eq!(config_manager.repo_dir_name("a", "b-c", "a/b-c"), "a-b-c_1");
+
+        // Hand-created directories are never reused for a repository
+        fs::create_dir_all(config_dir.join("configs").join("x-y")).unwrap();
+        assert_eq!(config_manager.repo_dir_name("x", "y", "x/y"), "x-y_1");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_windows_repo_paths() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir.clone()).unwrap();
+
+        let dir_name = config_manager.repo_dir_name("owner", "aux", "owner/aux");
+        let repo_dir = config_dir.join("configs").join(&dir_name);
+        assert_eq!(
+            repo_dir.parent(),
+            Some(config_dir.join("configs").as_path())
+        );
+        fs::create_dir_all(&repo_dir).unwrap();
+        assert!(repo_dir.is_dir());
+
+        let reserved = sanitize_path_component("nul");
+        let file = config_dir.join("configs").join(format!("{reserved}.yaml"));
+        fs::write(&file, "commands:\n  a: echo a\n").unwrap();
+        assert!(file.is_file());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_provider_commands_are_merged() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let provider = temp_dir.path().join("doo-provider-catalog");
+        fs::write(
+            &provider,
+            "#!/bin/sh\ncase \"$1\" in\n  list) echo '[{\"name\":\"svc\",\"template\":\"echo svc #1\",\"description\":\"From catalog\"},{\"name\":\"main-cmd\",\"template\":\"echo shadowed\"},{\"name\":\"dyn\"}]' ;;\n  resolve) echo \"echo dynamic $2\" ;;\nesac\n",
+        )
+        .unwrap();
+        fs::set_permissions(&provider, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::create_dir_all(&config_dir).unwrap();
+        fs::write(
+            config_dir.join("config.yaml"),
+            format!(
+                "commands:\n  main-cmd: echo main\nproviders:\n  - {}\n",
+                provider.display()
+            ),
+        )
+        .unwrap();
+
+        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+
+        let conflicts = config_manager.get_command_conflicts("svc");
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].source_file, "plugin:catalog");
+        assert_eq!(conflicts[0].command, "echo svc #1");
+
+        // Config files win over providers
+        assert_eq!(
+            config_manager.get_command("main-cmd").unwrap(),
+            Some("echo main".to_string())
+        );
+        assert_eq!(config_manager.get_command_conflicts("main-cmd").len(), 1);
+
+        // Dynamic commands are resolved on lookup
+        assert_eq!(
+            config_manager.get_command("dyn").unwrap(),
+            Some("echo dynamic dyn".to_string())
+        );
+
+        let results = config_manager.search_commands("catalog");
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].name, "svc");
+        assert!(config_manager.provider_errors().is_empty());
+    }
+
+    #[test]
+    fn test_command_tags() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join("doo");
+        fs::create_dir_all(&config_dir).unwrap();
+        fs::write(
+            config_dir.join("config.yaml"),
+            r##"commands:
+  events:
+    command: "kubectl get events -n #1"
+    tags: [kubernetes, debugging]
+  pods:
+    command: "kubectl get pods -n #1"
+    tags: [Kubernetes]
+  ps: "ps aux"
+"##,
+        )
+        .unwrap();
+        let mut config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+
+        let names = |results: Vec<CommandSearchResult>| {
+            results
+                .into_iter()
+                .map(|result| result.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(config_manager.search_commands_by_tag("kubernetes")),
+            vec!["events", "pods"]
+        );
+        assert_eq!(
+            names(config_manager.search_commands_by_tag("debugging")),
+            vec!["events"]
+        );
+        // Tags match as a whole, unlike the text search
+        assert!(config_manager.search_commands_by_tag("debug").is_empty());
+        assert_eq!(
+            names(config_manager.search_commands("debug")),
+            vec!["events"]
+        );
+
+        let events = config_manager.search_commands_by_tag("debugging");
+        assert_eq!(events[0].tags, vec!["kubernetes", "debugging"]);
+
+        config_manager
+            .add_command_with_tags("top", "kubectl top pods", None, &["kubernetes".into()])
+            .unwrap();
+        config_manager
+            .add_command_with_tags("df", "df -h", None, &[])
+            .unwrap();
+        assert_eq!(
+            names(config_manager.search_commands_by_tag("kubernetes")),
+            vec!["events", "pods", "top"]
+        );
+        assert!(matches!(
+            config_manager.main_commands()["df"],
+            CommandEntry::Simple(_)
+        ));
+    }
+
+    #[test]
+    fn test_context_env() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join("doo");
+        fs::create_dir_all(&config_dir).unwrap();
+        fs::write(
+            config_dir.join("config.yaml"),
+            r##"commands:
+  deploy:
+    command: "./deploy.sh"
+    env:
+      REGION: eu-west-1
+contexts:
+  prod:
+    env:
+      AWS_PROFILE: "#profile"
+      REGION: us-east-1
+"##,
+        )
+        .unwrap();
+        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+        let source = config_manager
+            .get_command_with_source("deploy")
+            .unwrap()
+            .unwrap();
+
+        // The command's own env wins over the context's
+        assert_eq!(
+            config_manager.command_env("prod", &source),
+            BTreeMap::from([
+                ("AWS_PROFILE".to_string(), "#profile".to_string()),
+                ("REGION".to_string(), "eu-west-1".to_string()),
+            ])
+        );
+        assert_eq!(config_manager.command_env("default", &source), source.env);
+        assert!(config_manager.context_env("default").is_empty());
+    }
+
+    #[test]
+    fn test_command_examples() {
+        let config: Config = serde_yaml::from_str(
+            r##"commands:
+  logs:
+    command: "kubectl logs -n #ns #1 #*"
+    examples:
+      - doo logs my-pod -f
+      - doo logs -n staging my-pod
+  pods:
+    command: "kubectl get pods"
+  ps: "ps aux"
+"##,
+        )
+        .unwrap();
+        assert_eq!(
+            config.commands["logs"].examples(),
+            ["doo logs my-pod -f", "doo logs -n staging my-pod"]
+        );
+        assert!(config.commands["pods"].examples().is_empty());
+        assert!(config.commands["ps"].examples().is_empty());
+
+        let source = config.commands["logs"].source("logs", "main", ConfigSource::Main);
+        assert_eq!(source.examples.len(), 2);
+        let result = CommandSearchResult::new("logs".to_string(), &config.commands["logs"]);
+        assert_eq!(result.examples, source.examples);
+
+        // Saved back only where they were given
+        let yaml = serde_yaml::to_string(&config).unwrap();
+        assert_eq!(yaml.matches("examples:").count(), 1);
+        assert!(serde_yaml::from_str::<Config>(
+            "commands:\n  x:\n    command: x\n    examples: doo x\n"
+        )
+        .is_err());
     }
 }
@@ -1,69 +1,714 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use crate::config::ConfigManager;
+use crate::config::{ConfigManager, ContextHook};
+use crate::encryption;
+use crate::executor::{exit_code, CommandExecutor};
+use crate::lock::ConfigLock;
+use crate::util::{atomic_write, backup_path};
+use crate::variables::{history_file, secrets_file, HISTORY_SUFFIX, SECRETS_SUFFIX};
+
+/// Metadata of all contexts, stored in the config directory
+pub const CONTEXT_META_FILE: &str = "context_meta.yaml";
+
+/// File in a project directory naming the context to use in it
+pub const CONTEXT_PIN_FILE: &str = ".doo-context";
+
+/// Nearest `.doo-context` in `start` or one of its ancestors, up to the root
+/// of the git repository `start` is in
+pub fn find_context_pin(start: &Path) -> Option<PathBuf> {
+    for dir in start.ancestors() {
+        let pin = dir.join(CONTEXT_PIN_FILE);
+        if pin.is_file() {
+            return Some(pin);
+        }
+        if dir.join(".git").exists() {
+            break;
+        }
+    }
+    None
+}
+
+/// Context stored in `current_context` of `config_dir`, `default` if none
+/// or an invalid one is
+fn persisted_context(config_dir: &Path) -> Result<String> {
+    let context_file = config_dir.join("current_context");
+    if !context_file.exists() {
+        return Ok("default".to_string());
+    }
+    let context = fs::read_to_string(&context_file)
+        .context("Failed to read current context file")?
+        .trim()
+        .to_string();
+    if let Err(e) = check_context_name(&context) {
+        eprintln!("{} {}; using the default context", "⚠".yellow(), e);
+        return Ok("default".to_string());
+    }
+    Ok(context)
+}
+
+/// Run `hook` for switching to `context`, with `#name` replaced by the name
+/// of the context. The error says how the command failed.
+fn run_hook(hook: &ContextHook, context: &str) -> std::result::Result<(), String> {
+    let tokens = crate::template::parse(hook.command_str());
+    let command = crate::template::render(&tokens, |placeholder| {
+        (placeholder.kind == crate::template::PlaceholderKind::Named("name".to_string()))
+            .then(|| context.to_string())
+    });
+    println!("{} {}", "on_switch:".green().bold(), command.bright_white());
+    match CommandExecutor::new().execute(&command, &BTreeMap::new(), None, None) {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("failed with exit code {}", exit_code(status))),
+        Err(e) => Err(format!("failed: {e:#}")),
+    }
+}
+
+/// Longest allowed context name
+pub const MAX_CONTEXT_NAME_LEN: usize = 64;
+
+/// Most ancestors a context can inherit variables from
+pub const MAX_CONTEXT_DEPTH: usize = 16;
+
+/// Check that `context` can name a context: it's part of file names, so
+/// only letters, digits, '-' and '_' are allowed. Dots would let a context
+/// like `a.secrets` share the files of another one.
+pub fn check_context_name(context: &str) -> Result<()> {
+    let problem = if context.is_empty() {
+        "it is empty"
+    } else if context.len() > MAX_CONTEXT_NAME_LEN {
+        "it is longer than 64 characters"
+    } else if context.contains(['/', '\\']) {
+        "it can't contain path separators"
+    } else if context.starts_with('.') {
+        "it can't start with '.'"
+    } else if !context
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        "use letters, digits, '-' and '_'"
+    } else {
+        return Ok(());
+    };
+    Err(anyhow!("Invalid context name '{}': {}", context, problem))
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ContextMeta {
+    #[serde(default)]
+    pub contexts: BTreeMap<String, ContextInfo>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ContextInfo {
+    /// Context whose variables are used when this context doesn't set them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+}
+
+impl ContextMeta {
+    pub fn load(config_dir: &Path) -> Result<Self> {
+        let meta_file = config_dir.join(CONTEXT_META_FILE);
+        if !meta_file.exists() {
+            return Ok(Self::default());
+        }
+        let contents = fs::read_to_string(&meta_file).context("Failed to read context metadata")?;
+        serde_yaml::from_str(&contents).context("Failed to parse context metadata")
+    }
+
+    fn save(&self, config_dir: &Path) -> Result<()> {
+        let yaml_content =
+            serde_yaml::to_string(self).context("Failed to serialize context metadata")?;
+        atomic_write(&config_dir.join(CONTEXT_META_FILE), &yaml_content)
+            .context("Failed to write context metadata")
+    }
+
+    pub fn parent(&self, context: &str) -> Option<&str> {
+        self.contexts
+            .get(context)
+            .and_then(|info| info.parent.as_deref())
+    }
+
+    /// `context` followed by its ancestors, nearest first. Parent cycles,
+    /// which only a hand-edited file can contain, and chains deeper than
+    /// [`MAX_CONTEXT_DEPTH`] are errors.
+    pub fn chain(&self, context: &str) -> Result<Vec<String>> {
+        let mut chain = vec![context.to_string()];
+        let mut current = context;
+        while let Some(parent) = self.parent(current) {
+            chain.push(parent.to_string());
+            if chain[..chain.len() - 1].iter().any(|name| name == parent) {
+                return Err(anyhow!(
+                    "The parents of context '{}' form a cycle: {}; fix {}",
+                    context,
+                    chain.join(" -> "),
+                    CONTEXT_META_FILE
+                ));
+            }
+            if chain.len() > MAX_CONTEXT_DEPTH + 1 {
+                return Err(anyhow!(
+                    "Context '{}' has more than {} ancestors",
+                    context,
+                    MAX_CONTEXT_DEPTH
+                ));
+            }
+            current = parent;
+        }
+        Ok(chain)
+    }
+}
+
+/// A context as listed by `doo context list --output json`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListedContext {
+    pub name: String,
+    pub parent: Option<String>,
+    /// Whether this is the current context
+    pub active: bool,
+    /// Number of variables and secrets set in the context; `None` if its
+    /// files are encrypted
+    pub variables: Option<usize>,
+}
+
+/// A context with the contexts inheriting from it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContextNode {
+    pub name: String,
+    pub children: Vec<ContextNode>,
+}
 
 pub struct ContextManager {
     config_dir: PathBuf,
     current_context: String,
+    /// `.doo-context` that chose `current_context` instead of the persisted
+    /// one
+    pin: Option<PathBuf>,
+    /// Whether `current_context` was given for this run only, with
+    /// `--context`
+    one_off: bool,
+    /// `on_switch` hooks of the main config by context
+    hooks: BTreeMap<String, ContextHook>,
+    run_hooks: bool,
 }
 
 #[allow(dead_code)]
 impl ContextManager {
+    /// Context manager for the current directory, where a `.doo-context`
+    /// overrides the persisted context
     pub fn new(config_manager: &ConfigManager) -> Result<Self> {
-        let config_dir = config_manager.config_dir().clone();
-        let context_file = config_dir.join("current_context");
-
-        let current_context = if context_file.exists() {
-            fs::read_to_string(&context_file)
-                .context("Failed to read current context file")?
-                .trim()
-                .to_string()
-        } else {
-            "default".to_string()
-        };
+        let dir = std::env::current_dir().context("Failed to read the working directory")?;
+        Self::new_in(config_manager, &dir)
+    }
 
-        Ok(Self {
+    /// Like [`new`](Self::new), looking for a `.doo-context` from `dir`
+    pub fn new_in(config_manager: &ConfigManager, dir: &Path) -> Result<Self> {
+        let config_dir = config_manager.config_dir().clone();
+        let mut manager = Self {
+            current_context: persisted_context(&config_dir)?,
             config_dir,
-            current_context,
-        })
+            pin: None,
+            one_off: false,
+            hooks: config_manager
+                .main_config()
+                .contexts
+                .keys()
+                .filter_map(|context| {
+                    let hook = config_manager.context_hook(context)?;
+                    Some((context.clone(), hook.clone()))
+                })
+                .collect(),
+            run_hooks: true,
+        };
+        if let Some(pin) = find_context_pin(dir) {
+            let context = fs::read_to_string(&pin)
+                .with_context(|| format!("Failed to read {}", pin.display()))?
+                .trim()
+                .to_string();
+            check_context_name(&context).with_context(|| format!("In {}", pin.display()))?;
+            manager.current_context = context;
+            manager.pin = Some(pin);
+        }
+        Ok(manager)
     }
 
     pub fn current_context(&self) -> &str {
         &self.current_context
     }
 
-    pub fn switch_context(&mut self, context: &str) -> Result<()> {
+    /// Use `context` for this run only, over a `.doo-context` and the
+    /// persisted context, which stays as it is
+    pub fn override_context(&mut self, context: &str) -> Result<()> {
+        check_context_name(context)?;
         self.current_context = context.to_string();
-        let context_file = self.config_dir.join("current_context");
-        fs::write(&context_file, &self.current_context)
+        self.pin = None;
+        self.one_off = true;
+        Ok(())
+    }
+
+    /// Whether the active context was given for this run only
+    pub fn is_one_off(&self) -> bool {
+        self.one_off
+    }
+
+    /// `.doo-context` the active context comes from, `None` if it is the
+    /// persisted one
+    pub fn pin_file(&self) -> Option<&Path> {
+        self.pin.as_deref()
+    }
+
+    /// Write a `.doo-context` to `dir` that makes `context` the active
+    /// context in it and below. Returns the file.
+    pub fn pin_context(dir: &Path, context: &str) -> Result<PathBuf> {
+        check_context_name(context)?;
+        let pin = dir.join(CONTEXT_PIN_FILE);
+        atomic_write(&pin, &format!("{context}\n"))
+            .with_context(|| format!("Failed to write {}", pin.display()))?;
+        Ok(pin)
+    }
+
+    /// Remove the `.doo-context` the active context comes from, going back
+    /// to the persisted context. Returns the removed file, `None` if no file
+    /// pins the context.
+    pub fn unpin_context(&mut self) -> Result<Option<PathBuf>> {
+        let Some(pin) = self.pin.take() else {
+            return Ok(None);
+        };
+        fs::remove_file(&pin).with_context(|| format!("Failed to remove {}", pin.display()))?;
+        if !self.one_off {
+            self.current_context = persisted_context(&self.config_dir)?;
+        }
+        Ok(Some(pin))
+    }
+
+    /// Whether [`switch_context`](Self::switch_context) runs the
+    /// `on_switch` hook of the main config (default: true)
+    pub fn set_run_hooks(&mut self, run_hooks: bool) {
+        self.run_hooks = run_hooks;
+    }
+
+    /// Switch to `context`, optionally making it inherit variables from
+    /// `parent`, and run its `on_switch` hook. A failing hook is reported,
+    /// and only undoes the switch if it is `strict`.
+    pub fn switch_context(&mut self, context: &str, parent: Option<&str>) -> Result<()> {
+        // The lock is released before the hook runs, which may take a while
+        let persisted = {
+            let _lock = ConfigLock::acquire(&self.config_dir)?;
+            let mut meta = ContextMeta::load(&self.config_dir)?;
+            self.persist_switch(&mut meta, context, parent)?
+        };
+        let previous = (
+            std::mem::replace(&mut self.current_context, context.to_string()),
+            std::mem::replace(&mut self.one_off, false),
+        );
+
+        let Some(hook) = self.hooks.get(context).filter(|_| self.run_hooks) else {
+            return Ok(());
+        };
+        let Err(failure) = run_hook(hook, context) else {
+            return Ok(());
+        };
+        if !hook.strict() {
+            eprintln!(
+                "{} The on_switch hook of context {} {}",
+                "⚠".yellow(),
+                context,
+                failure
+            );
+            return Ok(());
+        }
+        (self.current_context, self.one_off) = previous;
+        atomic_write(&self.config_dir.join("current_context"), &persisted)
             .context("Failed to write current context file")?;
+        Err(anyhow!(
+            "The on_switch hook of context '{}' {}, so the switch was undone",
+            context,
+            failure
+        ))
+    }
+
+    /// Record the switch to `context` in `meta` and `current_context`,
+    /// returning the context persisted before
+    fn persist_switch(
+        &self,
+        meta: &mut ContextMeta,
+        context: &str,
+        parent: Option<&str>,
+    ) -> Result<String> {
+        check_context_name(context)?;
+        if let Some(parent) = parent {
+            check_context_name(parent)?;
+            let ancestors = meta.chain(parent)?;
+            if ancestors.iter().any(|name| name == context) {
+                return Err(anyhow!(
+                    "Context '{}' can't inherit from '{}': that would create a cycle",
+                    context,
+                    parent
+                ));
+            }
+            if ancestors.len() > MAX_CONTEXT_DEPTH {
+                return Err(anyhow!(
+                    "Context '{}' can't inherit from '{}': contexts can have at most {} ancestors",
+                    context,
+                    parent,
+                    MAX_CONTEXT_DEPTH
+                ));
+            }
+            meta.contexts.entry(context.to_string()).or_default().parent = Some(parent.to_string());
+            meta.save(&self.config_dir)?;
+        }
+        let persisted = persisted_context(&self.config_dir)?;
+        let context_file = self.config_dir.join("current_context");
+        atomic_write(&context_file, context).context("Failed to write current context file")?;
+        Ok(persisted)
+    }
+
+    /// Delete `context` and its variables, secrets and history. The active
+    /// context is only deleted with `force`, which switches back to `default`.
+    pub fn delete_context(&mut self, context: &str, force: bool) -> Result<()> {
+        check_context_name(context)?;
+        if context == "default" {
+            return Err(anyhow!("The default context can't be deleted"));
+        }
+        if self.current_context == context && !force {
+            return Err(anyhow!(
+                "Context '{}' is active; switch to another one first or use --force to delete it and switch back to default",
+                context
+            ));
+        }
+
+        let _lock = ConfigLock::acquire(&self.config_dir)?;
+        let mut meta = ContextMeta::load(&self.config_dir)?;
+        let children: Vec<&str> = meta
+            .contexts
+            .iter()
+            .filter(|(_, info)| info.parent.as_deref() == Some(context))
+            .map(|(name, _)| name.as_str())
+            .collect();
+        if !children.is_empty() {
+            return Err(anyhow!(
+                "Context '{}' can't be deleted while other contexts inherit from it: {}",
+                context,
+                children.join(", ")
+            ));
+        }
+
+        let variables_file = self
+            .config_dir
+            .join("variables")
+            .join(format!("{context}.yaml"));
+        let secrets_file = secrets_file(&self.config_dir, context);
+        let had_meta = meta.contexts.remove(context).is_some();
+        if !variables_file.exists()
+            && !secrets_file.exists()
+            && !had_meta
+            && self.current_context != context
+        {
+            return Err(anyhow!("Context '{}' does not exist", context));
+        }
+
+        if variables_file.exists() {
+            fs::remove_file(&variables_file).context("Failed to delete variables file")?;
+        }
+        let backup_file = backup_path(&variables_file);
+        if backup_file.exists() {
+            fs::remove_file(&backup_file).context("Failed to delete variables backup")?;
+        }
+        if secrets_file.exists() {
+            fs::remove_file(&secrets_file).context("Failed to delete secrets file")?;
+        }
+        let history_file = history_file(&self.config_dir, context);
+        if history_file.exists() {
+            fs::remove_file(&history_file).context("Failed to delete history file")?;
+        }
+        if had_meta {
+            meta.save(&self.config_dir)?;
+        }
+        if self.current_context == context {
+            self.current_context = "default".to_string();
+        }
+        self.replace_persisted_context(context, "default")
+    }
+
+    /// Make `new` the persisted context if it is `old`
+    fn replace_persisted_context(&self, old: &str, new: &str) -> Result<()> {
+        if persisted_context(&self.config_dir)? == old {
+            atomic_write(&self.config_dir.join("current_context"), new)
+                .context("Failed to write current context file")?;
+        }
         Ok(())
     }
 
-    pub fn list_contexts(&self) -> Result<Vec<String>> {
-        let variables_dir = self.config_dir.join("variables");
-        if !variables_dir.exists() {
-            return Ok(vec!["default".to_string()]);
+    /// Copy the variables and secrets of `src`, and the context it inherits
+    /// from, to `dst`. An existing `dst` is only replaced with `force`.
+    pub fn clone_context(&self, src: &str, dst: &str, force: bool) -> Result<()> {
+        if src == dst {
+            return Err(anyhow!("Can't clone context '{}' onto itself", src));
         }
+        check_context_name(src)?;
+        check_context_name(dst)?;
+
+        let _lock = ConfigLock::acquire(&self.config_dir)?;
+        let mut meta = ContextMeta::load(&self.config_dir)?;
+        let files = |context: &str| {
+            [
+                self.config_dir
+                    .join("variables")
+                    .join(format!("{context}.yaml")),
+                secrets_file(&self.config_dir, context),
+            ]
+        };
+        if !self.exists(&meta, src) {
+            return Err(anyhow!("Context '{}' does not exist", src));
+        }
+        if self.exists(&meta, dst) && !force {
+            return Err(anyhow!(
+                "Context '{}' already exists; use --force to replace it",
+                dst
+            ));
+        }
+
+        for (from, to) in files(src).iter().zip(files(dst)) {
+            if from.exists() {
+                if let Some(dir) = to.parent() {
+                    fs::create_dir_all(dir).context("Failed to create variables directory")?;
+                }
+                fs::copy(from, &to)
+                    .with_context(|| format!("Failed to copy {}", from.display()))?;
+            } else if to.exists() {
+                fs::remove_file(&to)
+                    .with_context(|| format!("Failed to remove {}", to.display()))?;
+            }
+        }
+
+        match meta.parent(src).map(str::to_string) {
+            // The clone inherits like `src`, unless that would be from itself
+            Some(parent) if parent != dst => {
+                meta.contexts.entry(dst.to_string()).or_default().parent = Some(parent);
+            }
+            _ => {
+                meta.contexts.remove(dst);
+            }
+        }
+        meta.save(&self.config_dir)
+    }
+
+    /// Rename `old` to `new`, moving its variables, secrets and history along.
+    /// Contexts inheriting from `old`, the persisted context and the
+    /// `.doo-context` in effect follow the rename.
+    pub fn rename_context(&mut self, old: &str, new: &str) -> Result<()> {
+        if old == "default" {
+            return Err(anyhow!("The default context can't be renamed"));
+        }
+        check_context_name(old)?;
+        check_context_name(new)?;
+
+        let _lock = ConfigLock::acquire(&self.config_dir)?;
+        let mut meta = ContextMeta::load(&self.config_dir)?;
+        let files = |context: &str| {
+            let variables_file = self
+                .config_dir
+                .join("variables")
+                .join(format!("{context}.yaml"));
+            [
+                backup_path(&variables_file),
+                variables_file,
+                secrets_file(&self.config_dir, context),
+                history_file(&self.config_dir, context),
+            ]
+        };
+        if !self.exists(&meta, old) {
+            return Err(anyhow!("Context '{}' does not exist", old));
+        }
+        if old == new || self.exists(&meta, new) {
+            return Err(anyhow!("Context '{}' already exists", new));
+        }
+
+        for (from, to) in files(old).iter().zip(files(new)) {
+            if from.exists() {
+                fs::rename(from, &to)
+                    .with_context(|| format!("Failed to rename {}", from.display()))?;
+            }
+        }
+
+        if let Some(info) = meta.contexts.remove(old) {
+            meta.contexts.insert(new.to_string(), info);
+        }
+        for info in meta.contexts.values_mut() {
+            if info.parent.as_deref() == Some(old) {
+                info.parent = Some(new.to_string());
+            }
+        }
+        meta.save(&self.config_dir)?;
+
+        if self.current_context == old {
+            self.current_context = new.to_string();
+        }
+        if let Some(pin) = &self.pin {
+            if fs::read_to_string(pin).is_ok_and(|pinned| pinned.trim() == old) {
+                Self::pin_context(pin.parent().unwrap_or(Path::new(".")), new)?;
+            }
+        }
+        self.replace_persisted_context(old, new)
+    }
+
+    /// Whether `context` is active, is `default`, has metadata or has any
+    /// files
+    fn exists(&self, meta: &ContextMeta, context: &str) -> bool {
+        let variables_file = self
+            .config_dir
+            .join("variables")
+            .join(format!("{context}.yaml"));
+        context == "default"
+            || context == self.current_context
+            || meta.contexts.contains_key(context)
+            || [
+                backup_path(&variables_file),
+                variables_file,
+                secrets_file(&self.config_dir, context),
+                history_file(&self.config_dir, context),
+            ]
+            .iter()
+            .any(|file| file.exists())
+    }
+
+    /// Parent of `context`, if it inherits from another context
+    pub fn parent_of(&self, context: &str) -> Result<Option<String>> {
+        Ok(ContextMeta::load(&self.config_dir)?
+            .parent(context)
+            .map(str::to_string))
+    }
 
-        let mut contexts = vec!["default".to_string()];
+    /// All known contexts as a forest of parent-child relationships, sorted by
+    /// name at every level
+    pub fn list_contexts(&self) -> Result<Vec<ContextNode>> {
+        let meta = ContextMeta::load(&self.config_dir)?;
+        let mut names = BTreeSet::from(["default".to_string(), self.current_context.clone()]);
 
-        for entry in fs::read_dir(&variables_dir).context("Failed to read variables directory")? {
-            let entry = entry.context("Failed to read directory entry")?;
-            if let Some(name) = entry.file_name().to_str() {
-                if name.ends_with(".yaml") {
-                    let context_name = name.strip_suffix(".yaml").unwrap();
-                    if context_name != "default" {
-                        contexts.push(context_name.to_string());
+        let variables_dir = self.config_dir.join("variables");
+        if variables_dir.exists() {
+            for entry in
+                fs::read_dir(&variables_dir).context("Failed to read variables directory")?
+            {
+                let entry = entry.context("Failed to read directory entry")?;
+                if let Some(name) = entry.file_name().to_str() {
+                    let context_name = name
+                        .strip_suffix(SECRETS_SUFFIX)
+                        .or_else(|| name.strip_suffix(HISTORY_SUFFIX))
+                        .or_else(|| name.strip_suffix(".yaml"));
+                    if let Some(context_name) = context_name {
+                        names.insert(context_name.to_string());
                     }
                 }
             }
         }
+        for (name, info) in &meta.contexts {
+            names.insert(name.clone());
+            names.extend(info.parent.clone());
+        }
+        // Stray files and hand edits may not name a context that can be used
+        names.retain(|name| check_context_name(name).is_ok());
+
+        let mut children: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for name in &names {
+            if let Some(parent) = meta.parent(name) {
+                children.entry(parent).or_default().push(name);
+            }
+        }
+
+        fn build<'a>(
+            name: &'a str,
+            children: &BTreeMap<&str, Vec<&'a str>>,
+            visited: &mut BTreeSet<&'a str>,
+        ) -> ContextNode {
+            visited.insert(name);
+            let mut node = ContextNode {
+                name: name.to_string(),
+                children: Vec::new(),
+            };
+            for kid in children.get(name).into_iter().flatten() {
+                if !visited.contains(kid) {
+                    node.children.push(build(kid, children, visited));
+                }
+            }
+            node
+        }
+
+        let mut visited = BTreeSet::new();
+        let mut roots: Vec<ContextNode> = names
+            .iter()
+            .filter(|name| meta.parent(name).is_none())
+            .map(|name| build(name, &children, &mut visited))
+            .collect();
+        // Contexts caught in a hand-edited parent cycle are listed as roots
+        for name in &names {
+            if !visited.contains(name.as_str()) {
+                roots.push(build(name, &children, &mut visited));
+            }
+        }
+        Ok(roots)
+    }
+
+    /// [`list_contexts`](Self::list_contexts) flattened, every context
+    /// directly followed by its children
+    pub fn listed_contexts(&self) -> Result<Vec<ListedContext>> {
+        fn flatten(node: ContextNode, parent: Option<&str>, active: &str) -> Vec<ListedContext> {
+            let mut listed = vec![ListedContext {
+                active: node.name == active,
+                parent: parent.map(str::to_string),
+                name: node.name.clone(),
+                variables: None,
+            }];
+            for child in node.children {
+                listed.extend(flatten(child, Some(&node.name), active));
+            }
+            listed
+        }
+
+        self.list_contexts()?
+            .into_iter()
+            .flat_map(|node| flatten(node, None, &self.current_context))
+            .map(|context| {
+                Ok(ListedContext {
+                    variables: self.variable_count(&context.name)?,
+                    ..context
+                })
+            })
+            .collect()
+    }
+
+    /// Number of variables and secrets set in `context`, counted from its
+    /// files without resolving anything; `None` if they are encrypted
+    pub fn variable_count(&self, context: &str) -> Result<Option<usize>> {
+        #[derive(Deserialize)]
+        struct Names {
+            #[serde(default)]
+            vars: HashMap<String, serde::de::IgnoredAny>,
+        }
 
-        contexts.sort();
-        Ok(contexts)
+        let files = [
+            self.config_dir
+                .join("variables")
+                .join(format!("{context}.yaml")),
+            secrets_file(&self.config_dir, context),
+        ];
+        let mut count = 0;
+        for path in files.iter().filter(|path| path.exists()) {
+            let contents =
+                fs::read_to_string(path).with_context(|| format!("Failed to read {path:?}"))?;
+            if encryption::is_encrypted(&contents) {
+                return Ok(None);
+            }
+            if contents.trim().is_empty() {
+                continue;
+            }
+            let names: Names = serde_yaml::from_str(&contents)
+                .with_context(|| format!("Failed to parse {}", path.display()))?;
+            count += names.vars.len();
+        }
+        Ok(Some(count))
     }
 }
 
@@ -71,6 +716,7 @@ impl ContextManager {
 mod tests {
     use super::*;
     use crate::config::ConfigManager;
+    use crate::variables::VariableManager;
     use tempfile::TempDir;
 
     #[test]
@@ -83,7 +729,486 @@ mod tests {
 
         assert_eq!(context_manager.current_context(), "default");
 
-        context_manager.switch_context("test").unwrap();
+        context_manager.switch_context("test", None).unwrap();
         assert_eq!(context_manager.current_context(), "test");
     }
+
+    #[test]
+    fn test_delete_context() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+
+        let config_manager = ConfigManager::new_with_dir(config_dir.clone()).unwrap();
+        let mut context_manager = ContextManager::new(&config_manager).unwrap();
+        let variables_dir = config_dir.join("variables");
+        fs::create_dir_all(&variables_dir).unwrap();
+        fs::write(variables_dir.join("staging.yaml"), "vars: {}\n").unwrap();
+        fs::write(variables_dir.join("staging.secrets.yaml"), "vars: {}\n").unwrap();
+        fs::write(variables_dir.join("staging.history.yaml"), "vars: {}\n").unwrap();
+
+        context_manager
+            .switch_context("staging-eu", Some("staging"))
+            .unwrap();
+
+        assert!(context_manager.delete_context("default", true).is_err());
+        let err = context_manager
+            .delete_context("staging", false)
+            .unwrap_err();
+        assert!(err.to_string().contains("staging-eu"));
+
+        // Deleting the active context needs force and switches back to default
+        let err = context_manager
+            .delete_context("staging-eu", false)
+            .unwrap_err();
+        assert!(err.to_string().contains("--force"));
+        assert_eq!(context_manager.current_context(), "staging-eu");
+        context_manager.delete_context("staging-eu", true).unwrap();
+        assert_eq!(context_manager.current_context(), "default");
+        assert_eq!(
+            fs::read_to_string(config_dir.join("current_context")).unwrap(),
+            "default"
+        );
+
+        context_manager.delete_context("staging", false).unwrap();
+        assert!(!variables_dir.join("staging.yaml").exists());
+        assert!(!variables_dir.join("staging.secrets.yaml").exists());
+        assert!(!variables_dir.join("staging.history.yaml").exists());
+        assert!(context_manager.delete_context("staging", false).is_err());
+
+        let names: Vec<_> = context_manager
+            .list_contexts()
+            .unwrap()
+            .into_iter()
+            .map(|node| node.name)
+            .collect();
+        assert_eq!(names, vec!["default"]);
+    }
+
+    #[test]
+    fn test_context_pin() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir.clone()).unwrap();
+        let project = temp_dir.path().join("project");
+        let nested = project.join("src").join("api");
+        fs::create_dir_all(&nested).unwrap();
+        fs::create_dir(project.join(".git")).unwrap();
+
+        let mut context_manager = ContextManager::new_in(&config_manager, &nested).unwrap();
+        context_manager.switch_context("staging", None).unwrap();
+        assert_eq!(context_manager.pin_file(), None);
+
+        // A pin above the git root is ignored
+        ContextManager::pin_context(temp_dir.path(), "outside").unwrap();
+        let pin = ContextManager::pin_context(&project, "cluster-a").unwrap();
+        assert_eq!(fs::read_to_string(&pin).unwrap(), "cluster-a\n");
+        assert_eq!(find_context_pin(&nested), Some(pin.clone()));
+        fs::remove_file(&pin).unwrap();
+        assert_eq!(find_context_pin(&nested), None);
+        ContextManager::pin_context(&project, "cluster-a").unwrap();
+        assert!(ContextManager::pin_context(&project, "../a").is_err());
+
+        let mut context_manager = ContextManager::new_in(&config_manager, &nested).unwrap();
+        assert_eq!(context_manager.current_context(), "cluster-a");
+        assert_eq!(context_manager.pin_file(), Some(pin.as_path()));
+        let persisted = || fs::read_to_string(config_dir.join("current_context")).unwrap();
+        assert_eq!(persisted(), "staging");
+
+        // Renaming the pinned context updates the pin, not the persisted one
+        context_manager
+            .rename_context("cluster-a", "cluster-b")
+            .unwrap();
+        assert_eq!(fs::read_to_string(&pin).unwrap(), "cluster-b\n");
+        assert_eq!(persisted(), "staging");
+        context_manager.delete_context("cluster-b", true).unwrap();
+        assert_eq!(context_manager.current_context(), "default");
+        assert_eq!(persisted(), "staging");
+
+        let mut context_manager = ContextManager::new_in(&config_manager, &nested).unwrap();
+        assert_eq!(context_manager.unpin_context().unwrap(), Some(pin.clone()));
+        assert!(!pin.exists());
+        assert_eq!(context_manager.current_context(), "staging");
+        assert_eq!(context_manager.unpin_context().unwrap(), None);
+
+        fs::write(&pin, "not valid\n").unwrap();
+        let err = ContextManager::new_in(&config_manager, &nested)
+            .err()
+            .unwrap();
+        assert!(format!("{err:#}").contains("Invalid context name 'not valid'"));
+    }
+
+    #[test]
+    fn test_context_names() {
+        let message = |name: &str| check_context_name(name).unwrap_err().to_string();
+        assert_eq!(message(""), "Invalid context name '': it is empty");
+        assert!(message(&"a".repeat(65)).ends_with("it is longer than 64 characters"));
+        assert!(check_context_name(&"a".repeat(64)).is_ok());
+        assert!(message("../evil").ends_with("it can't contain path separators"));
+        assert!(message("a\\b").ends_with("it can't contain path separators"));
+        assert!(message(".hidden").ends_with("it can't start with '.'"));
+        assert!(message("a.secrets").ends_with("use letters, digits, '-' and '_'"));
+        assert!(check_context_name("staging-eu_2").is_ok());
+    }
+
+    #[test]
+    fn test_path_traversal_is_rejected() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir.clone()).unwrap();
+        let mut context_manager = ContextManager::new(&config_manager).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+        context_manager.switch_context("staging", None).unwrap();
+
+        // variables/../outside.yaml exists, but isn't a context
+        let outside = config_dir.join("outside.yaml");
+        fs::write(&outside, "vars:\n  '#1': kept\n").unwrap();
+        let config = fs::read_to_string(&outside).unwrap();
+        assert!(context_manager.switch_context("../outside", None).is_err());
+        assert!(context_manager
+            .switch_context("prod", Some("../outside"))
+            .is_err());
+        assert!(context_manager.delete_context("../outside", true).is_err());
+        assert!(context_manager
+            .clone_context("../outside", "copy", false)
+            .is_err());
+        assert!(context_manager
+            .rename_context("../outside", "moved")
+            .is_err());
+        assert!(variable_manager
+            .set_variable("../outside", "#1", "evil")
+            .is_err());
+        assert!(variable_manager.list_variables("../../etc/passwd").is_err());
+        assert_eq!(fs::read_to_string(&outside).unwrap(), config);
+        assert_eq!(context_manager.current_context(), "staging");
+        assert!(!temp_dir.path().join("evil.yaml").exists());
+
+        // Stray files don't show up as contexts that can't be switched to
+        let variables_dir = config_dir.join("variables");
+        fs::create_dir_all(&variables_dir).unwrap();
+        for file in ["bad name.yaml", ".hidden.yaml", "ok.yaml"] {
+            fs::write(variables_dir.join(file), "vars: {}\n").unwrap();
+        }
+        let names: Vec<_> = context_manager
+            .listed_contexts()
+            .unwrap()
+            .into_iter()
+            .map(|context| context.name)
+            .collect();
+        assert_eq!(names, vec!["default", "ok", "staging"]);
+
+        // A broken current_context falls back to default
+        fs::write(config_dir.join("current_context"), "../outside").unwrap();
+        let context_manager = ContextManager::new(&config_manager).unwrap();
+        assert_eq!(context_manager.current_context(), "default");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_switch_hooks() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        fs::create_dir_all(&config_dir).unwrap();
+        let marker = |name: &str| temp_dir.path().join(name);
+        fs::write(
+            config_dir.join("config.yaml"),
+            format!(
+                r##"commands: {{}}
+contexts:
+  prod:
+    on_switch: "touch {dir}/#name-#namespace"
+  strict:
+    on_switch:
+      command: "false"
+      strict: true
+  lenient:
+    on_switch: "false"
+"##,
+                dir = temp_dir.path().display()
+            ),
+        )
+        .unwrap();
+        let config_manager = ConfigManager::new_with_dir(config_dir.clone()).unwrap();
+        let mut context_manager = ContextManager::new(&config_manager).unwrap();
+        let persisted = || fs::read_to_string(config_dir.join("current_context")).unwrap();
+
+        // Only #name is replaced
+        context_manager.switch_context("prod", None).unwrap();
+        assert!(marker("prod-#namespace").exists());
+
+        let err = context_manager.switch_context("strict", None).unwrap_err();
+        assert!(err.to_string().contains("failed with exit code 1"));
+        assert_eq!(context_manager.current_context(), "prod");
+        assert_eq!(persisted(), "prod");
+
+        context_manager.switch_context("lenient", None).unwrap();
+        assert_eq!(persisted(), "lenient");
+
+        fs::remove_file(marker("prod-#namespace")).unwrap();
+        context_manager.set_run_hooks(false);
+        context_manager.switch_context("prod", None).unwrap();
+        context_manager.switch_context("strict", None).unwrap();
+        assert!(!marker("prod-#namespace").exists());
+        assert_eq!(persisted(), "strict");
+    }
+
+    #[test]
+    fn test_override_context() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir.clone()).unwrap();
+        ContextManager::pin_context(temp_dir.path(), "pinned").unwrap();
+
+        let mut context_manager = ContextManager::new_in(&config_manager, temp_dir.path()).unwrap();
+        context_manager.switch_context("staging", None).unwrap();
+        assert!(context_manager.override_context("not valid").is_err());
+
+        let mut context_manager = ContextManager::new_in(&config_manager, temp_dir.path()).unwrap();
+        assert_eq!(context_manager.current_context(), "pinned");
+        context_manager.override_context("prod").unwrap();
+        assert_eq!(context_manager.current_context(), "prod");
+        assert!(context_manager.is_one_off());
+        assert_eq!(context_manager.pin_file(), None);
+        assert_eq!(
+            fs::read_to_string(config_dir.join("current_context")).unwrap(),
+            "staging"
+        );
+
+        // Switching makes it permanent
+        context_manager.switch_context("default", None).unwrap();
+        assert!(!context_manager.is_one_off());
+    }
+
+    #[test]
+    fn test_clone_context() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+
+        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+        let mut context_manager = ContextManager::new(&config_manager).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+        context_manager
+            .switch_context("production", Some("default"))
+            .unwrap();
+        variable_manager
+            .set_variable("production", "#replicas", "5")
+            .unwrap();
+        variable_manager
+            .set_variable("production", "#region", "eu-west-1")
+            .unwrap();
+        variable_manager
+            .set_secret("production", "#token", "s3cr3t")
+            .unwrap();
+
+        context_manager
+            .clone_context("production", "staging", false)
+            .unwrap();
+        variable_manager
+            .set_variable("staging", "#replicas", "1")
+            .unwrap();
+
+        let get = |context: &str, name: &str| variable_manager.get_variable(context, name).unwrap();
+        assert_eq!(get("staging", "#replicas").as_deref(), Some("1"));
+        assert_eq!(get("staging", "#region").as_deref(), Some("eu-west-1"));
+        assert_eq!(get("staging", "#token").as_deref(), Some("s3cr3t"));
+        assert_eq!(get("production", "#replicas").as_deref(), Some("5"));
+        assert_eq!(
+            context_manager.parent_of("staging").unwrap().as_deref(),
+            Some("default")
+        );
+
+        let err = context_manager
+            .clone_context("production", "staging", false)
+            .unwrap_err();
+        assert!(err.to_string().contains("--force"));
+        context_manager
+            .clone_context("production", "staging", true)
+            .unwrap();
+        assert_eq!(get("staging", "#replicas").as_deref(), Some("5"));
+
+        let err = context_manager
+            .clone_context("missing", "other", false)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Context 'missing' does not exist");
+    }
+
+    #[test]
+    fn test_rename_context() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+
+        let config_manager = ConfigManager::new_with_dir(config_dir.clone()).unwrap();
+        let mut context_manager = ContextManager::new(&config_manager).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+        context_manager.switch_context("staging", None).unwrap();
+        context_manager
+            .switch_context("staging-eu", Some("staging"))
+            .unwrap();
+        context_manager.switch_context("staging", None).unwrap();
+        variable_manager
+            .set_variable("staging", "#namespace", "stage")
+            .unwrap();
+        variable_manager
+            .set_secret("staging", "#token", "s3cr3t")
+            .unwrap();
+
+        context_manager.rename_context("staging", "stage").unwrap();
+
+        let variables_dir = config_dir.join("variables");
+        assert!(!variables_dir.join("staging.yaml").exists());
+        assert!(variables_dir.join("stage.yaml").exists());
+        assert!(!secrets_file(&config_dir, "staging").exists());
+        assert_eq!(context_manager.current_context(), "stage");
+        assert_eq!(
+            ContextManager::new(&config_manager)
+                .unwrap()
+                .current_context(),
+            "stage"
+        );
+        assert_eq!(
+            context_manager.parent_of("staging-eu").unwrap().as_deref(),
+            Some("stage")
+        );
+
+        let get = |context: &str, name: &str| variable_manager.get_variable(context, name).unwrap();
+        assert_eq!(get("stage", "#namespace").as_deref(), Some("stage"));
+        assert_eq!(get("stage", "#token").as_deref(), Some("s3cr3t"));
+        assert_eq!(get("staging", "#namespace"), None);
+
+        let err = context_manager
+            .rename_context("default", "main")
+            .unwrap_err();
+        assert_eq!(err.to_string(), "The default context can't be renamed");
+        let err = context_manager
+            .rename_context("stage", "staging-eu")
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Context 'staging-eu' already exists");
+        let err = context_manager
+            .rename_context("missing", "other")
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Context 'missing' does not exist");
+        let err = context_manager
+            .rename_context("stage", "../stage")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid context name '../stage': it can't contain path separators"
+        );
+        assert!(context_manager.switch_context("a.secrets", None).is_err());
+
+        // A context that was only switched to has no files yet
+        context_manager.switch_context("fresh", None).unwrap();
+        context_manager.rename_context("fresh", "new").unwrap();
+        assert_eq!(context_manager.current_context(), "new");
+        assert!(!variables_dir.join("new.yaml").exists());
+    }
+
+    #[test]
+    fn test_context_tree() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+
+        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+        let mut context_manager = ContextManager::new(&config_manager).unwrap();
+
+        context_manager
+            .switch_context("staging", Some("default"))
+            .unwrap();
+        context_manager
+            .switch_context("staging-eu", Some("staging"))
+            .unwrap();
+        context_manager.switch_context("other", None).unwrap();
+        assert_eq!(
+            context_manager.parent_of("staging-eu").unwrap().as_deref(),
+            Some("staging")
+        );
+
+        let err = context_manager
+            .switch_context("default", Some("staging-eu"))
+            .unwrap_err();
+        assert!(err.to_string().contains("cycle"));
+
+        // A cycle in a hand-edited file is reported instead of followed
+        let mut meta = ContextMeta::default();
+        for (context, parent) in [("a", "b"), ("b", "c"), ("c", "a")] {
+            meta.contexts.entry(context.to_string()).or_default().parent = Some(parent.to_string());
+        }
+        let err = meta.chain("a").unwrap_err();
+        assert!(err.to_string().contains("a -> b -> c -> a"), "{err}");
+
+        // Chains are capped
+        let mut meta = ContextMeta::default();
+        for depth in 0..=MAX_CONTEXT_DEPTH {
+            meta.contexts.entry(format!("c{depth}")).or_default().parent =
+                Some(format!("c{}", depth + 1));
+        }
+        assert_eq!(meta.chain("c1").unwrap().len(), MAX_CONTEXT_DEPTH + 1);
+        let err = meta.chain("c0").unwrap_err();
+        assert!(err.to_string().contains("more than 16 ancestors"), "{err}");
+
+        // The active context is listed even without variables
+        let tree = context_manager.list_contexts().unwrap();
+        assert_eq!(
+            tree,
+            vec![
+                ContextNode {
+                    name: "default".to_string(),
+                    children: vec![ContextNode {
+                        name: "staging".to_string(),
+                        children: vec![ContextNode {
+                            name: "staging-eu".to_string(),
+                            children: vec![],
+                        }],
+                    }],
+                },
+                ContextNode {
+                    name: "other".to_string(),
+                    children: vec![],
+                },
+            ]
+        );
+
+        let listed = context_manager.listed_contexts().unwrap();
+        let names: Vec<_> = listed.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["default", "staging", "staging-eu", "other"]);
+        assert_eq!(
+            listed[2],
+            ListedContext {
+                name: "staging-eu".to_string(),
+                parent: Some("staging".to_string()),
+                active: false,
+                variables: Some(0),
+            }
+        );
+        assert!(listed[3].active);
+
+        // Counts come straight from the files, secrets included
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+        variable_manager
+            .set_variable("staging", "#ns", "shop")
+            .unwrap();
+        variable_manager
+            .set_secret("staging", "#token", "s3cr3t")
+            .unwrap();
+        variable_manager
+            .set_variable("other", "#ns", "misc")
+            .unwrap();
+        assert_eq!(context_manager.variable_count("staging").unwrap(), Some(2));
+        assert_eq!(
+            context_manager.variable_count("staging-eu").unwrap(),
+            Some(0)
+        );
+        variable_manager
+            .encrypt_context("other", "passphrase")
+            .unwrap();
+        assert_eq!(context_manager.variable_count("other").unwrap(), None);
+        let listed = context_manager.listed_contexts().unwrap();
+        assert_eq!(listed[1].variables, Some(2));
+        assert_eq!(listed[3].variables, None);
+
+        let json = serde_json::to_string(&listed).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Vec<ListedContext>>(&json).unwrap(),
+            listed
+        );
+    }
 }
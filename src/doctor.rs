@@ -0,0 +1,464 @@
+//! Health checks of the doo installation
+//!
+//! `doo doctor` runs every check and reports each as passed, a warning or
+//! failed. The checks of the config files run first and without a
+//! [`ConfigManager`], so they still work when a broken file keeps doo from
+//! loading its configs.
+
+use anyhow::Result;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+use crate::config::{
+    detect_format, github_api_url, main_config_file, ConfigManager, RepoManifest,
+    REPO_MANIFEST_FILE,
+};
+use crate::context::ContextManager;
+use crate::history::{ExecutionEntry, HISTORY_FILE};
+use crate::variables::VariableManager;
+
+/// How long the connectivity check waits for GitHub
+const CONNECTIVITY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    /// doo works, but some feature won't or a command may not do what's
+    /// expected
+    Warn,
+    Fail,
+}
+
+/// Outcome of one check
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DoctorResult {
+    pub check: &'static str,
+    pub status: CheckStatus,
+    pub message: String,
+    /// One line per problem found, e.g. per broken file
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<String>,
+}
+
+impl DoctorResult {
+    fn pass(check: &'static str, message: impl Into<String>) -> Self {
+        Self::new(check, CheckStatus::Pass, message)
+    }
+
+    fn warn(check: &'static str, message: impl Into<String>) -> Self {
+        Self::new(check, CheckStatus::Warn, message)
+    }
+
+    fn fail(check: &'static str, message: impl Into<String>) -> Self {
+        Self::new(check, CheckStatus::Fail, message)
+    }
+
+    fn new(check: &'static str, status: CheckStatus, message: impl Into<String>) -> Self {
+        Self {
+            check,
+            status,
+            message: message.into(),
+            details: Vec::new(),
+        }
+    }
+
+    fn with_details(mut self, details: Vec<String>) -> Self {
+        self.details = details;
+        self
+    }
+}
+
+/// Run all checks against `config_dir`. The checks of the commands are
+/// skipped while a config file fails, as the configs can't be loaded then.
+pub async fn run_checks(config_dir: &Path) -> Vec<DoctorResult> {
+    let mut results = vec![
+        check_config_dir(config_dir),
+        check_main_config(config_dir),
+        check_imported_configs(config_dir),
+        check_git(),
+        check_connectivity().await,
+    ];
+    if !results
+        .iter()
+        .any(|result| result.status == CheckStatus::Fail)
+    {
+        match load_managers(config_dir) {
+            Ok((config_manager, variable_manager, context)) => {
+                results.push(check_command_collisions(&config_manager));
+                results.push(check_placeholders(
+                    &config_manager,
+                    &variable_manager,
+                    &context,
+                ));
+            }
+            Err(e) => results.push(DoctorResult::fail(
+                "Commands",
+                format!("The configs can't be loaded: {e}"),
+            )),
+        }
+    }
+    results.push(check_history(config_dir));
+    results
+}
+
+fn load_managers(config_dir: &Path) -> Result<(ConfigManager, VariableManager, String)> {
+    let config_manager = ConfigManager::new_with_dir(config_dir.to_path_buf())?;
+    let context = ContextManager::new(&config_manager)?
+        .current_context()
+        .to_string();
+    let variable_manager = VariableManager::new(&config_manager)?;
+    Ok((config_manager, variable_manager, context))
+}
+
+/// The config directory exists and doo can create files in it
+pub fn check_config_dir(config_dir: &Path) -> DoctorResult {
+    const CHECK: &str = "Config directory";
+    if !config_dir.exists() {
+        return DoctorResult::pass(
+            CHECK,
+            format!("{} will be created on first use", config_dir.display()),
+        );
+    }
+    if !config_dir.is_dir() {
+        return DoctorResult::fail(
+            CHECK,
+            format!("{} is not a directory", config_dir.display()),
+        );
+    }
+    match tempfile::NamedTempFile::new_in(config_dir) {
+        Ok(_) => DoctorResult::pass(CHECK, format!("{} is writable", config_dir.display())),
+        Err(e) => DoctorResult::fail(
+            CHECK,
+            format!("{} is not writable: {e}", config_dir.display()),
+        ),
+    }
+}
+
+/// `config.yaml` (or `config.toml`) parses
+pub fn check_main_config(config_dir: &Path) -> DoctorResult {
+    const CHECK: &str = "Main config";
+    let path = main_config_file(config_dir);
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    if !path.exists() {
+        return DoctorResult::pass(CHECK, format!("{file_name} will be created on first use"));
+    }
+    match fs::read_to_string(&path)
+        .map_err(anyhow::Error::from)
+        .and_then(|contents| detect_format(&path).parse(&contents))
+    {
+        Ok(config) => DoctorResult::pass(
+            CHECK,
+            format!(
+                "{file_name} has {}",
+                plural(config.commands.len(), "command")
+            ),
+        ),
+        Err(e) => DoctorResult::fail(CHECK, format!("{file_name} can't be parsed: {e}")),
+    }
+}
+
+/// The imported config files and the manifest of imported repositories
+/// parse. Files in repository directories aren't checked, as doo skips
+/// those that aren't configs.
+pub fn check_imported_configs(config_dir: &Path) -> DoctorResult {
+    const CHECK: &str = "Imported configs";
+    let configs_dir = config_dir.join("configs");
+    let Ok(entries) = fs::read_dir(&configs_dir) else {
+        return DoctorResult::pass(CHECK, "No configs imported");
+    };
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.is_file()
+                && path.extension().is_some_and(|ext| {
+                    ext == "yaml" || ext == "yml" || ext == "toml" || ext == "json"
+                })
+        })
+        .collect();
+    paths.sort();
+
+    let mut checked = 0;
+    let mut broken = Vec::new();
+    for path in paths {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let parsed = fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|contents| {
+                if file_name == REPO_MANIFEST_FILE {
+                    serde_yaml::from_str::<RepoManifest>(&contents)?;
+                } else {
+                    detect_format(&path).parse(&contents)?;
+                }
+                Ok(())
+            });
+        if file_name != REPO_MANIFEST_FILE {
+            checked += 1;
+        }
+        if let Err(e) = parsed {
+            broken.push(format!("configs/{file_name}: {e}"));
+        }
+    }
+
+    if broken.is_empty() {
+        return DoctorResult::pass(
+            CHECK,
+            format!("{} without errors", plural(checked, "config file")),
+        );
+    }
+    DoctorResult::fail(
+        CHECK,
+        format!("{} can't be parsed", plural(broken.len(), "file")),
+    )
+    .with_details(broken)
+}
+
+/// git is installed, as imports of private repositories clone them
+pub fn check_git() -> DoctorResult {
+    const CHECK: &str = "Git";
+    match Command::new("git").arg("--version").output() {
+        Ok(output) if output.status.success() => DoctorResult::pass(
+            CHECK,
+            String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        ),
+        _ => DoctorResult::warn(
+            CHECK,
+            "git not found; it's needed to import and sync private repositories",
+        ),
+    }
+}
+
+/// The GitHub API answers, as public imports fetch configs from it
+pub async fn check_connectivity() -> DoctorResult {
+    const CHECK: &str = "Internet";
+    let url = github_api_url();
+    let response = reqwest::Client::builder()
+        .timeout(CONNECTIVITY_TIMEOUT)
+        .build()
+        .map(|client| client.get(&url).header("User-Agent", "doo-cli/0.1.0"));
+    let result = match response {
+        Ok(request) => request.send().await,
+        Err(e) => Err(e),
+    };
+    match result {
+        // Any answer will do, even a rate limit
+        Ok(_) => DoctorResult::pass(CHECK, format!("{url} is reachable")),
+        Err(_) => DoctorResult::warn(
+            CHECK,
+            format!("{url} is unreachable; importing and syncing from GitHub won't work"),
+        ),
+    }
+}
+
+/// No command name is defined by more than one config
+pub fn check_command_collisions(config_manager: &ConfigManager) -> DoctorResult {
+    const CHECK: &str = "Command names";
+    let conflicts = config_manager.conflicting_commands();
+    if conflicts.is_empty() {
+        return DoctorResult::pass(CHECK, "No command is defined in more than one config");
+    }
+    let count = conflicts.len();
+    DoctorResult::warn(
+        CHECK,
+        format!(
+            "{count} command{} defined in more than one config; doo asks which to run",
+            if count == 1 { " is" } else { "s are" }
+        ),
+    )
+    .with_details(
+        conflicts
+            .into_iter()
+            .map(|(name, sources)| format!("{name}: {}", sources.join(", ")))
+            .collect(),
+    )
+}
+
+/// Every `#` placeholder of every command has a value in `context` or is
+/// documented in the command's `params`, so running it doesn't leave the
+/// user guessing what to enter
+pub fn check_placeholders(
+    config_manager: &ConfigManager,
+    variable_manager: &VariableManager,
+    context: &str,
+) -> DoctorResult {
+    const CHECK: &str = "Placeholders";
+    let mut undocumented = Vec::new();
+    for source in config_manager.list_command_sources() {
+        let missing = match variable_manager.missing_placeholders(
+            context,
+            Some(&source.name),
+            &source.command,
+            &[],
+            &source.defaults,
+        ) {
+            Ok(missing) => missing,
+            Err(e) => return DoctorResult::warn(CHECK, format!("Not checked: {e}")),
+        };
+        let labels: Vec<String> = missing
+            .into_iter()
+            .filter(|label| label.starts_with('#') && source.param_spec(label).is_none())
+            .collect();
+        if !labels.is_empty() {
+            undocumented.push(format!("{}: {}", source.name, labels.join(", ")));
+        }
+    }
+
+    if undocumented.is_empty() {
+        return DoctorResult::pass(
+            CHECK,
+            format!("All variables of the commands are set in context {context} or documented"),
+        );
+    }
+    let count = undocumented.len();
+    DoctorResult::warn(
+        CHECK,
+        format!(
+            "{count} command{} variables that are neither set in context {context} nor documented in params",
+            if count == 1 { " uses" } else { "s use" }
+        ),
+    )
+    .with_details(undocumented)
+}
+
+/// Every line of the history file parses
+pub fn check_history(config_dir: &Path) -> DoctorResult {
+    const CHECK: &str = "History";
+    let path = config_dir.join(HISTORY_FILE);
+    if !path.exists() {
+        return DoctorResult::pass(CHECK, "No commands recorded yet");
+    }
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) => return DoctorResult::fail(CHECK, format!("{HISTORY_FILE} can't be read: {e}")),
+    };
+    let mut entries = 0;
+    let mut broken = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<ExecutionEntry>(line) {
+            Ok(_) => entries += 1,
+            Err(_) => broken.push(index + 1),
+        }
+    }
+    if broken.is_empty() {
+        return DoctorResult::pass(
+            CHECK,
+            format!(
+                "{entries} {}",
+                if entries == 1 { "entry" } else { "entries" }
+            ),
+        );
+    }
+    let lines: Vec<String> = broken.iter().map(usize::to_string).collect();
+    DoctorResult::fail(
+        CHECK,
+        format!(
+            "{HISTORY_FILE} has lines that can't be read (line {}); run `doo history clear` to start over",
+            lines.join(", ")
+        ),
+    )
+}
+
+/// `count` and `noun`, with an `s` unless `count` is 1
+fn plural(count: usize, noun: &str) -> String {
+    format!("{count} {noun}{}", if count == 1 { "" } else { "s" })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_config_file_checks() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path();
+        assert_eq!(check_main_config(config_dir).status, CheckStatus::Pass);
+
+        fs::write(config_dir.join("config.yaml"), "commands:\n  hi: echo hi\n").unwrap();
+        let result = check_main_config(config_dir);
+        assert_eq!(result.status, CheckStatus::Pass);
+        assert_eq!(result.message, "config.yaml has 1 command");
+        fs::write(config_dir.join("config.yaml"), "commands: [").unwrap();
+        assert_eq!(check_main_config(config_dir).status, CheckStatus::Fail);
+
+        let configs_dir = config_dir.join("configs");
+        fs::create_dir_all(configs_dir.join("team")).unwrap();
+        fs::write(configs_dir.join("good.yaml"), "commands:\n  a: echo a\n").unwrap();
+        fs::write(
+            configs_dir.join("team").join("notes.yaml"),
+            "- not a config",
+        )
+        .unwrap();
+        assert_eq!(check_imported_configs(config_dir).status, CheckStatus::Pass);
+        fs::write(configs_dir.join("bad.yaml"), "commands: 3").unwrap();
+        fs::write(configs_dir.join(REPO_MANIFEST_FILE), "repos: [").unwrap();
+        let result = check_imported_configs(config_dir);
+        assert_eq!(result.status, CheckStatus::Fail);
+        assert_eq!(result.message, "2 files can't be parsed");
+        assert!(result.details[0].starts_with("configs/bad.yaml: "));
+        assert!(result.details[1].starts_with("configs/repos.yaml: "));
+    }
+
+    #[test]
+    fn test_history_check() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path();
+        assert_eq!(check_history(config_dir).status, CheckStatus::Pass);
+
+        let entry = r#"{"timestamp":"2026-10-17T09:00:00Z","name":"hi","source":"main","command":"echo hi","exit_code":0,"duration_ms":3}"#;
+        fs::write(config_dir.join(HISTORY_FILE), format!("{entry}\n{entry}\n")).unwrap();
+        let result = check_history(config_dir);
+        assert_eq!(result.status, CheckStatus::Pass);
+        assert_eq!(result.message, "2 entries");
+
+        fs::write(
+            config_dir.join(HISTORY_FILE),
+            format!("{entry}\n{{\"timest\n{entry}\n"),
+        )
+        .unwrap();
+        let result = check_history(config_dir);
+        assert_eq!(result.status, CheckStatus::Fail);
+        assert!(result.message.contains("(line 2)"));
+    }
+
+    #[test]
+    fn test_command_checks() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path();
+        fs::create_dir_all(config_dir.join("configs")).unwrap();
+        fs::write(
+            config_dir.join("config.yaml"),
+            "commands:\n  logs: 'kubectl logs -n #1 #2'\n  pods:\n    command: 'kubectl get pods -n #ns'\n    params:\n      ns:\n        name: namespace\n",
+        )
+        .unwrap();
+        fs::write(
+            config_dir.join("configs").join("team.yaml"),
+            "commands:\n  logs: 'stern #1'\n",
+        )
+        .unwrap();
+        let (config_manager, mut variable_manager, context) = load_managers(config_dir).unwrap();
+
+        let result = check_command_collisions(&config_manager);
+        assert_eq!(result.status, CheckStatus::Warn);
+        assert_eq!(result.details, vec!["logs: main, team"]);
+
+        let result = check_placeholders(&config_manager, &variable_manager, &context);
+        assert_eq!(result.status, CheckStatus::Warn);
+        assert_eq!(result.details, vec!["logs: #1, #2", "logs: #1"]);
+
+        variable_manager
+            .set_variable(&context, "#1", "shop")
+            .unwrap();
+        variable_manager
+            .set_variable(&context, "#2", "web")
+            .unwrap();
+        let result = check_placeholders(&config_manager, &variable_manager, &context);
+        assert_eq!(result.status, CheckStatus::Pass);
+    }
+}
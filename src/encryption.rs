@@ -0,0 +1,235 @@
+//! Passphrase encryption of context files
+//!
+//! `doo context encrypt` replaces the variables, secrets and history files of
+//! a context with encrypted ones. The key is derived from a passphrase with
+//! Argon2id and the contents are sealed with ChaCha20-Poly1305, so a wrong
+//! passphrase or a modified file fails to decrypt instead of producing
+//! garbage. The passphrase comes from [`PASSPHRASE_ENV`] or is asked for, once
+//! per context and invocation.
+
+use anyhow::{anyhow, Result};
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::sync::Mutex;
+
+/// Environment variable with the passphrase of encrypted contexts, for
+/// non-interactive use
+pub const PASSPHRASE_ENV: &str = "DOO_PASSPHRASE";
+
+const BEGIN: &str = "-----BEGIN DOO ENCRYPTED FILE-----";
+const END: &str = "-----END DOO ENCRYPTED FILE-----";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// Whether `contents` is a file written by [`Vault::encrypt`]
+pub fn is_encrypted(contents: &str) -> bool {
+    contents.starts_with(BEGIN)
+}
+
+/// Keys of the encrypted contexts used so far, so the passphrase is only
+/// asked for and the key only derived once per context
+#[derive(Default)]
+pub struct Vault {
+    passphrases: Mutex<HashMap<String, String>>,
+    keys: Mutex<HashMap<String, ([u8; SALT_LEN], Key)>>,
+}
+
+impl Vault {
+    /// Use `passphrase` for `context` instead of asking for it
+    pub fn set_passphrase(&self, context: &str, passphrase: &str) {
+        let mut passphrases = self.passphrases.lock().unwrap();
+        passphrases.insert(context.to_string(), passphrase.to_string());
+    }
+
+    /// Whether the key of `context` is known, i.e. one of its files has been
+    /// decrypted or [`create_key`](Self::create_key) was called
+    pub fn is_unlocked(&self, context: &str) -> bool {
+        self.keys.lock().unwrap().contains_key(context)
+    }
+
+    /// Derive a key with a new salt from `passphrase`, which
+    /// [`encrypt`](Self::encrypt) then uses for `context`
+    pub fn create_key(&self, context: &str, passphrase: &str) -> Result<()> {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let key = derive_key(passphrase, &salt)?;
+        self.keys
+            .lock()
+            .unwrap()
+            .insert(context.to_string(), (salt, key));
+        Ok(())
+    }
+
+    /// Drop the key of `context`
+    pub fn forget(&self, context: &str) {
+        self.keys.lock().unwrap().remove(context);
+    }
+
+    /// Decrypt `contents`, an encrypted file of `context`
+    pub fn decrypt(&self, context: &str, contents: &str) -> Result<String> {
+        let (salt, nonce, ciphertext) = parse(contents)
+            .ok_or_else(|| anyhow!("The encrypted file of context '{context}' is damaged"))?;
+        let cached = self.keys.lock().unwrap().get(context).cloned();
+        let key = match cached {
+            Some((cached_salt, key)) if cached_salt == salt => key,
+            _ => derive_key(&self.passphrase(context)?, &salt)?,
+        };
+        let plaintext = ChaCha20Poly1305::new(&key)
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+            .map_err(|_| {
+                // Ask again next time rather than repeating the wrong one
+                self.passphrases.lock().unwrap().remove(context);
+                let source = match std::env::var(PASSPHRASE_ENV) {
+                    Ok(value) if !value.is_empty() => format!(" (from {PASSPHRASE_ENV})"),
+                    _ => String::new(),
+                };
+                anyhow!(
+                    "Wrong passphrase{source} for context '{context}', or its files were modified; nothing was changed"
+                )
+            })?;
+        self.keys
+            .lock()
+            .unwrap()
+            .insert(context.to_string(), (salt, key));
+        String::from_utf8(plaintext)
+            .map_err(|_| anyhow!("The encrypted file of context '{context}' is damaged"))
+    }
+
+    /// Encrypt `plaintext` with the key of `context`, which has to be
+    /// unlocked
+    pub fn encrypt(&self, context: &str, plaintext: &str) -> Result<String> {
+        let (salt, key) = self
+            .keys
+            .lock()
+            .unwrap()
+            .get(context)
+            .cloned()
+            .ok_or_else(|| anyhow!("Context '{context}' hasn't been unlocked"))?;
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = ChaCha20Poly1305::new(&key)
+            .encrypt(&nonce, plaintext.as_bytes())
+            .map_err(|_| anyhow!("Failed to encrypt the files of context '{context}'"))?;
+
+        let mut data = Vec::with_capacity(SALT_LEN + NONCE_LEN + ciphertext.len());
+        data.extend_from_slice(&salt);
+        data.extend_from_slice(&nonce);
+        data.extend_from_slice(&ciphertext);
+        let encoded = base64::encode(data);
+        let mut contents = format!("{BEGIN}\n");
+        for line in encoded.as_bytes().chunks(64) {
+            contents.push_str(std::str::from_utf8(line).unwrap_or_default());
+            contents.push('\n');
+        }
+        contents.push_str(END);
+        contents.push('\n');
+        Ok(contents)
+    }
+
+    /// Passphrase of `context`: set with [`set_passphrase`](Self::set_passphrase),
+    /// from [`PASSPHRASE_ENV`], or asked for
+    fn passphrase(&self, context: &str) -> Result<String> {
+        if let Some(passphrase) = self.passphrases.lock().unwrap().get(context) {
+            return Ok(passphrase.clone());
+        }
+        let passphrase = match std::env::var(PASSPHRASE_ENV) {
+            Ok(passphrase) if !passphrase.is_empty() => passphrase,
+            _ if std::io::stdin().is_terminal() && std::io::stderr().is_terminal() => {
+                dialoguer::Password::new()
+                    .with_prompt(format!("Passphrase for context {context}"))
+                    .interact()?
+            }
+            _ => {
+                return Err(anyhow!(
+                    "Context '{context}' is encrypted; set {PASSPHRASE_ENV} to its passphrase"
+                ))
+            }
+        };
+        self.set_passphrase(context, &passphrase);
+        Ok(passphrase)
+    }
+}
+
+/// A new passphrase for `context`, from [`PASSPHRASE_ENV`] or asked for
+/// twice
+pub fn new_passphrase(context: &str) -> Result<String> {
+    let passphrase = match std::env::var(PASSPHRASE_ENV) {
+        Ok(passphrase) => passphrase,
+        Err(_) if std::io::stdin().is_terminal() => dialoguer::Password::new()
+            .with_prompt(format!("New passphrase for context {context}"))
+            .with_confirmation("Repeat the passphrase", "The passphrases don't match")
+            .interact()?,
+        Err(_) => {
+            return Err(anyhow!(
+                "Set {PASSPHRASE_ENV} to the passphrase to encrypt context '{context}' with"
+            ))
+        }
+    };
+    if passphrase.is_empty() {
+        return Err(anyhow!("The passphrase can't be empty"));
+    }
+    Ok(passphrase)
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Key> {
+    let mut key = Key::default();
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow!("Failed to derive the encryption key: {e}"))?;
+    Ok(key)
+}
+
+/// Salt, nonce and ciphertext of an encrypted file
+fn parse(contents: &str) -> Option<([u8; SALT_LEN], [u8; NONCE_LEN], Vec<u8>)> {
+    let body = contents.trim().strip_prefix(BEGIN)?.strip_suffix(END)?;
+    let encoded: String = body.split_whitespace().collect();
+    let data = base64::decode(encoded).ok()?;
+    if data.len() < SALT_LEN + NONCE_LEN {
+        return None;
+    }
+    let (salt, rest) = data.split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    Some((
+        salt.try_into().ok()?,
+        nonce.try_into().ok()?,
+        ciphertext.to_vec(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let vault = Vault::default();
+        vault.create_key("staging", "correct horse").unwrap();
+        let encrypted = vault.encrypt("staging", "vars:\n  '#1': pods\n").unwrap();
+        assert!(is_encrypted(&encrypted));
+        assert!(!encrypted.contains("pods"));
+
+        // A new process asks for the passphrase again
+        let other = Vault::default();
+        other.set_passphrase("staging", "correct horse");
+        assert_eq!(
+            other.decrypt("staging", &encrypted).unwrap(),
+            "vars:\n  '#1': pods\n"
+        );
+        assert!(other.is_unlocked("staging"));
+
+        let wrong = Vault::default();
+        wrong.set_passphrase("staging", "battery staple");
+        let err = wrong.decrypt("staging", &encrypted).unwrap_err();
+        assert!(err.to_string().starts_with("Wrong passphrase"));
+        assert!(!wrong.is_unlocked("staging"));
+
+        let mut lines: Vec<String> = encrypted.lines().map(str::to_string).collect();
+        let flipped = if lines[1].starts_with('A') { "B" } else { "A" };
+        lines[1].replace_range(..1, flipped);
+        let tampered = lines.join("\n");
+        assert!(other.decrypt("staging", &tampered).is_err());
+    }
+}
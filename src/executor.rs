@@ -1,28 +1,149 @@
 use anyhow::{Context, Result};
 use colored::*;
-use std::process::{Command, Stdio};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
 
-pub struct CommandExecutor;
+use crate::history::Recording;
+
+/// How long a timed out command gets to exit after SIGTERM before it is killed
+const TERMINATE_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// Exit code of a command run by
+/// [`execute_parallel`](CommandExecutor::execute_parallel) that couldn't be
+/// started, like in shells
+pub const NOT_STARTED_EXIT_CODE: i32 = 127;
+
+/// Exit code of a command run by
+/// [`execute_parallel`](CommandExecutor::execute_parallel) that was
+/// terminated after its timeout, like with `timeout(1)`
+pub const TIMED_OUT_EXIT_CODE: i32 = 124;
+
+/// A resolved command for [`CommandExecutor::execute_parallel`]
+#[derive(Debug, Clone, Default)]
+pub struct ParallelCommand {
+    pub name: String,
+    pub command_line: String,
+    pub env: BTreeMap<String, String>,
+    pub cwd: Option<PathBuf>,
+    pub timeout: Option<Duration>,
+    /// Pass the command line to the shell instead of splitting it
+    pub shell: bool,
+}
+
+/// How a command run by [`CommandExecutor::execute_parallel`] went
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ExecutionResult {
+    pub name: String,
+    /// Like [`exit_code`], or [`NOT_STARTED_EXIT_CODE`] and
+    /// [`TIMED_OUT_EXIT_CODE`]
+    pub exit_code: i32,
+    pub stdout: String,
+    pub stderr: String,
+    pub duration_ms: u64,
+}
+
+impl ExecutionResult {
+    pub fn success(&self) -> bool {
+        self.exit_code == 0
+    }
+}
+
+pub struct CommandExecutor {
+    /// Report commands as successful without running them
+    dry_run: bool,
+}
 
 #[allow(dead_code)]
 impl CommandExecutor {
     pub fn new() -> Self {
-        Self
+        Self { dry_run: false }
+    }
+
+    /// In dry-run mode [`execute`](Self::execute),
+    /// [`execute_with_timeout`](Self::execute_with_timeout), their shell
+    /// variants, [`execute_streaming`](Self::execute_streaming) and
+    /// [`execute_parallel`](Self::execute_parallel) don't spawn anything and
+    /// report success. Output capturing with
+    /// [`execute_with_output`](Self::execute_with_output) is not affected.
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
     }
 
-    pub fn execute(&self, command_line: &str) -> Result<()> {
-        let parts: Vec<&str> = command_line.split_whitespace().collect();
-        if parts.is_empty() {
-            return Err(anyhow::anyhow!("Empty command"));
+    /// Printed in front of the resolved command before it is executed
+    pub fn label(&self) -> ColoredString {
+        if self.dry_run {
+            "Dry run:".yellow().bold()
+        } else {
+            "Executing:".green().bold()
         }
+    }
+
+    /// Run a command with inherited stdio, `env` added to its environment and
+    /// `cwd` as working directory (doo's own if `None`), and report how it
+    /// went. The exit status is returned so callers can forward it. With a
+    /// `recording`, the run is added to the history once the command has
+    /// exited; dry runs are not recorded.
+    pub fn execute(
+        &self,
+        command_line: &str,
+        env: &BTreeMap<String, String>,
+        cwd: Option<&Path>,
+        recording: Option<Recording<'_>>,
+    ) -> Result<ExitStatus> {
+        crate::timed_span!("execute", command = command_line);
+        self.run(split_command_line(command_line)?, env, cwd, recording)
+    }
+
+    /// Like [`execute`](Self::execute), but the whole command line is run by
+    /// the user's shell (`$SHELL -c`, `cmd /C` on Windows), so it may chain
+    /// commands with `&&`, `||` and `;`, use pipes, redirections and globs.
+    /// Values substituted into it are interpreted by the shell too.
+    pub fn execute_shell(
+        &self,
+        command_line: &str,
+        env: &BTreeMap<String, String>,
+        cwd: Option<&Path>,
+        recording: Option<Recording<'_>>,
+    ) -> Result<ExitStatus> {
+        crate::timed_span!("execute", command = command_line);
+        self.run(shell_command_line(command_line), env, cwd, recording)
+    }
 
-        let command = parts[0];
-        let args = &parts[1..];
+    /// Run `command` with `args` for [`execute`](Self::execute) and
+    /// [`execute_shell`](Self::execute_shell)
+    fn run(
+        &self,
+        (command, args): (String, Vec<String>),
+        env: &BTreeMap<String, String>,
+        cwd: Option<&Path>,
+        recording: Option<Recording<'_>>,
+    ) -> Result<ExitStatus> {
+        check_working_dir(cwd)?;
+        if self.dry_run {
+            return Ok(ExitStatus::default());
+        }
 
         println!("{}", "─".repeat(50).bright_black());
 
-        let mut child = Command::new(command)
-            .args(args)
+        let mut command_builder = Command::new(&command);
+        if let Some(cwd) = cwd {
+            command_builder.current_dir(cwd);
+        }
+        let started = Instant::now();
+        let mut child = command_builder
+            .args(&args)
+            .envs(env)
             .stdin(Stdio::inherit())
             .stdout(Stdio::inherit())
             .stderr(Stdio::inherit())
@@ -33,35 +154,316 @@ impl CommandExecutor {
             .with_context(|| format!("Failed to wait for command: {command}"))?;
 
         println!("{}", "─".repeat(50).bright_black());
+        report_status(status);
+        if let Some(recording) = recording {
+            recording.finish(Some(exit_code(status)), started.elapsed());
+        }
 
-        if !status.success() {
-            if let Some(code) = status.code() {
-                println!(
-                    "{} Command exited with code {}",
-                    "✗".red().bold(),
-                    code.to_string().red()
-                );
-            } else {
-                println!("{} Command was terminated by signal", "✗".red().bold());
+        Ok(status)
+    }
+
+    /// Like [`execute`](Self::execute), but terminates the command and fails
+    /// if it is still running after `timeout`. Terminated commands are
+    /// recorded without an exit code.
+    pub async fn execute_with_timeout(
+        &self,
+        command_line: &str,
+        timeout: Duration,
+        env: &BTreeMap<String, String>,
+        cwd: Option<&Path>,
+        recording: Option<Recording<'_>>,
+    ) -> Result<ExitStatus> {
+        crate::timed_span!("execute", command = command_line);
+        self.run_with_timeout(
+            split_command_line(command_line)?,
+            timeout,
+            env,
+            cwd,
+            recording,
+        )
+        .await
+    }
+
+    /// [`execute_shell`](Self::execute_shell) with the timeout of
+    /// [`execute_with_timeout`](Self::execute_with_timeout)
+    pub async fn execute_shell_with_timeout(
+        &self,
+        command_line: &str,
+        timeout: Duration,
+        env: &BTreeMap<String, String>,
+        cwd: Option<&Path>,
+        recording: Option<Recording<'_>>,
+    ) -> Result<ExitStatus> {
+        crate::timed_span!("execute", command = command_line);
+        self.run_with_timeout(
+            shell_command_line(command_line),
+            timeout,
+            env,
+            cwd,
+            recording,
+        )
+        .await
+    }
+
+    async fn run_with_timeout(
+        &self,
+        (command, args): (String, Vec<String>),
+        timeout: Duration,
+        env: &BTreeMap<String, String>,
+        cwd: Option<&Path>,
+        recording: Option<Recording<'_>>,
+    ) -> Result<ExitStatus> {
+        check_working_dir(cwd)?;
+        if self.dry_run {
+            return Ok(ExitStatus::default());
+        }
+
+        println!("{}", "─".repeat(50).bright_black());
+
+        let mut command_builder = tokio::process::Command::new(&command);
+        if let Some(cwd) = cwd {
+            command_builder.current_dir(cwd);
+        }
+        // Its own process group, so a timeout also stops what it started
+        #[cfg(unix)]
+        command_builder.process_group(0);
+        #[cfg(unix)]
+        let _foreground = TerminalForeground::hand_to_child(&mut command_builder);
+        let started = Instant::now();
+        let mut child = command_builder
+            .args(&args)
+            .envs(env)
+            .stdin(Stdio::inherit())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to execute command: {command}"))?;
+
+        let deadline = tokio::time::sleep(timeout);
+        tokio::pin!(deadline);
+        let finished = loop {
+            tokio::select! {
+                status = child.wait() => break Some(status),
+                _ = &mut deadline => break None,
+                // Outside the foreground process group, the child doesn't
+                // get the terminal's Ctrl-C itself
+                _ = tokio::signal::ctrl_c() => signal_group(child.id(), Signal::Interrupt),
             }
-        } else {
-            println!("{} Command completed successfully", "✓".green().bold());
+        };
+        let status = match finished {
+            Some(status) => {
+                status.with_context(|| format!("Failed to wait for command: {command}"))?
+            }
+            None => {
+                terminate(&mut child).await;
+                println!("{}", "─".repeat(50).bright_black());
+                if let Some(recording) = recording {
+                    recording.finish(None, started.elapsed());
+                }
+                return Err(anyhow::anyhow!(
+                    "Command '{}' timed out after {}s and was terminated",
+                    command,
+                    timeout.as_secs_f64()
+                ));
+            }
+        };
+
+        println!("{}", "─".repeat(50).bright_black());
+        report_status(status);
+        if let Some(recording) = recording {
+            recording.finish(Some(exit_code(status)), started.elapsed());
         }
 
-        Ok(())
+        Ok(status)
     }
 
-    pub fn execute_with_output(&self, command_line: &str) -> Result<String> {
-        let parts: Vec<&str> = command_line.split_whitespace().collect();
-        if parts.is_empty() {
-            return Err(anyhow::anyhow!("Empty command"));
+    /// Like [`execute`](Self::execute) without environment or working
+    /// directory, running the command again while it exits non-zero; see
+    /// [`retry`]
+    pub async fn execute_with_retry(
+        &self,
+        command_line: &str,
+        max_attempts: u32,
+        backoff: Duration,
+    ) -> Result<ExitStatus> {
+        retry(max_attempts, backoff, || async {
+            self.execute(command_line, &BTreeMap::new(), None, None)
+        })
+        .await
+    }
+
+    /// Run `commands` at the same time, at most `concurrency` of them at
+    /// once, and wait for all of them. Their output is captured instead of
+    /// printed, and the results are in the order of `commands`. A command
+    /// that can't be started or times out doesn't stop the others; its
+    /// result has the error as stderr. Dry runs spawn nothing and succeed.
+    pub async fn execute_parallel(
+        &self,
+        commands: &[ParallelCommand],
+        concurrency: usize,
+    ) -> Result<Vec<ExecutionResult>> {
+        let permits = Arc::new(Semaphore::new(concurrency.max(1)));
+        let tasks: Vec<_> = commands
+            .iter()
+            .cloned()
+            .map(|command| {
+                let permits = Arc::clone(&permits);
+                let dry_run = self.dry_run;
+                tokio::task::spawn(async move {
+                    let _permit = permits
+                        .acquire_owned()
+                        .await
+                        .expect("the semaphore is never closed");
+                    run_captured(command, dry_run).await
+                })
+            })
+            .collect();
+
+        let mut results = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            results.push(
+                task.await
+                    .context("Failed to wait for a parallel command")?,
+            );
+        }
+        Ok(results)
+    }
+
+    /// Like [`execute`](Self::execute), but in a pseudo-terminal connected to
+    /// ours, for commands that only work in a terminal, like `vim` or
+    /// `kubectl exec -it`. Our terminal is switched to raw mode while the
+    /// command runs, so keys like Ctrl-C go to the command. The size of the
+    /// pseudo-terminal is that of our terminal when the command starts.
+    #[cfg(feature = "pty")]
+    pub fn execute_pty(
+        &self,
+        command_line: &str,
+        env: &BTreeMap<String, String>,
+        cwd: Option<&Path>,
+    ) -> Result<ExitStatus> {
+        use portable_pty::{native_pty_system, CommandBuilder};
+        use std::io::Write;
+
+        crate::timed_span!("execute", command = command_line);
+        let (command, args) = split_command_line(command_line)?;
+        check_working_dir(cwd)?;
+        if self.dry_run {
+            return Ok(ExitStatus::default());
+        }
+
+        let pair = native_pty_system()
+            .openpty(pty::terminal_size())
+            .context("Failed to open a pseudo-terminal")?;
+        let mut command_builder = CommandBuilder::new(&command);
+        command_builder.args(&args);
+        for (key, value) in env {
+            command_builder.env(key, value);
+        }
+        // Commands in a pseudo-terminal start in the home directory otherwise
+        let cwd = match cwd {
+            Some(cwd) => cwd.to_path_buf(),
+            None => std::env::current_dir().context("Failed to read the working directory")?,
+        };
+        command_builder.cwd(cwd);
+        let mut child = pair
+            .slave
+            .spawn_command(command_builder)
+            .with_context(|| format!("Failed to execute command: {command}"))?;
+        // The output ends once the command and everything it started exit
+        drop(pair.slave);
+
+        let mut reader = pair
+            .master
+            .try_clone_reader()
+            .context("Failed to read from the pseudo-terminal")?;
+        let mut writer = pair
+            .master
+            .take_writer()
+            .context("Failed to write to the pseudo-terminal")?;
+        let raw_mode = pty::RawMode::enable();
+        let output = std::thread::spawn(move || {
+            let mut stdout = std::io::stdout();
+            let mut buffer = [0; 8192];
+            while let Ok(read @ 1..) = reader.read(&mut buffer) {
+                if stdout.write_all(&buffer[..read]).is_err() {
+                    break;
+                }
+                let _ = stdout.flush();
+            }
+        });
+        // Blocks reading stdin until the next key press even after the
+        // command exited, so it isn't joined
+        std::thread::spawn(move || {
+            let _ = std::io::copy(&mut std::io::stdin(), &mut writer);
+        });
+
+        let status = child
+            .wait()
+            .with_context(|| format!("Failed to wait for command: {command}"));
+        let _ = output.join();
+        drop(raw_mode);
+        Ok(pty::exit_status(status?.exit_code()))
+    }
+
+    /// Run a command without stdin and pass each line it prints to
+    /// `on_stdout` or `on_stderr` as soon as it arrives, without the line
+    /// break. Lines of the two streams are passed in the order they are
+    /// read. Returns the exit status once the command has exited and all of
+    /// its output was passed on.
+    pub fn execute_streaming(
+        &self,
+        command_line: &str,
+        on_stdout: impl Fn(&str),
+        on_stderr: impl Fn(&str),
+    ) -> Result<ExitStatus> {
+        crate::timed_span!("execute", command = command_line);
+        let (command, args) = split_command_line(command_line)?;
+        if self.dry_run {
+            return Ok(ExitStatus::default());
         }
 
-        let command = parts[0];
-        let args = &parts[1..];
+        let mut child = Command::new(&command)
+            .args(&args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to execute command: {command}"))?;
+
+        // The pipes are read on their own threads so neither can fill up
+        // while the other is waited on; the callbacks run on this one
+        let (sender, lines) = mpsc::channel();
+        let stdout = child
+            .stdout
+            .take()
+            .map(|pipe| forward_lines(pipe, false, sender.clone()));
+        let stderr = child
+            .stderr
+            .take()
+            .map(|pipe| forward_lines(pipe, true, sender));
+        for (is_stderr, line) in lines {
+            if is_stderr {
+                on_stderr(&line);
+            } else {
+                on_stdout(&line);
+            }
+        }
+        for reader in stdout.into_iter().chain(stderr) {
+            let _ = reader.join();
+        }
+
+        child
+            .wait()
+            .with_context(|| format!("Failed to wait for command: {command}"))
+    }
+
+    pub fn execute_with_output(&self, command_line: &str) -> Result<String> {
+        crate::timed_span!("execute", command = command_line);
+        let (command, args) = split_command_line(command_line)?;
 
-        let output = Command::new(command)
-            .args(args)
+        let output = Command::new(&command)
+            .args(&args)
             .output()
             .with_context(|| format!("Failed to execute command: {command}"))?;
 
@@ -75,6 +477,425 @@ impl CommandExecutor {
     }
 }
 
+/// Run `attempt` until it succeeds, at most `max_attempts` times, and return
+/// the status of the last run. Waits `backoff` before the first retry and
+/// twice as long before every further one. Errors are returned right away.
+pub async fn retry<F, Fut>(
+    max_attempts: u32,
+    backoff: Duration,
+    mut attempt: F,
+) -> Result<ExitStatus>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<ExitStatus>>,
+{
+    let max_attempts = max_attempts.max(1);
+    let mut delay = backoff;
+    let mut attempts = 1;
+    loop {
+        let status = attempt().await?;
+        if status.success() || attempts >= max_attempts {
+            return Ok(status);
+        }
+        eprintln!(
+            "{} Command failed (attempt {}/{}), retrying in {}s...",
+            "⚠".yellow(),
+            attempts,
+            max_attempts,
+            delay.as_secs_f64()
+        );
+        tokio::time::sleep(delay).await;
+        delay = delay.saturating_mul(2);
+        attempts += 1;
+    }
+}
+
+/// Exit code that forwards `status` to our own caller. Commands killed by a
+/// signal map to `128 + signal` like in shells.
+pub fn exit_code(status: ExitStatus) -> i32 {
+    if let Some(code) = status.code() {
+        return code;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    1
+}
+
+fn report_status(status: ExitStatus) {
+    if !status.success() {
+        if let Some(code) = status.code() {
+            println!(
+                "{} Command exited with code {}",
+                "✗".red().bold(),
+                code.to_string().red()
+            );
+        } else {
+            println!("{} Command was terminated by signal", "✗".red().bold());
+        }
+    } else {
+        println!("{} Command completed successfully", "✓".green().bold());
+    }
+}
+
+/// Signals sent to the process group of a child started with a timeout
+#[derive(Debug, Clone, Copy)]
+enum Signal {
+    Interrupt,
+    Terminate,
+    Kill,
+}
+
+/// Send `signal` to the process group `pgid` of a child; does nothing on
+/// Windows, where children don't get their own group
+fn signal_group(pgid: Option<u32>, signal: Signal) {
+    #[cfg(unix)]
+    if let Some(pgid) = pgid {
+        let signal = match signal {
+            Signal::Interrupt => libc::SIGINT,
+            Signal::Terminate => libc::SIGTERM,
+            Signal::Kill => libc::SIGKILL,
+        };
+        // SAFETY: plain syscall on the process group of a child we spawned
+        unsafe {
+            libc::kill(-(pgid as libc::pid_t), signal);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (pgid, signal);
+}
+
+/// Makes the process group of a child started with a timeout the foreground
+/// group of the terminal on stdin, so it can read from the terminal and gets
+/// Ctrl-C; our own group gets the terminal back on drop
+#[cfg(unix)]
+struct TerminalForeground {
+    previous: Option<libc::pid_t>,
+}
+
+#[cfg(unix)]
+impl TerminalForeground {
+    /// Set up `command`, which must start its own process group, to take
+    /// over the terminal. Does nothing if stdin isn't a terminal or we
+    /// aren't in the foreground ourselves.
+    fn hand_to_child(command: &mut tokio::process::Command) -> Self {
+        // SAFETY: both only read process group ids
+        let (previous, own) = unsafe { (libc::tcgetpgrp(libc::STDIN_FILENO), libc::getpgrp()) };
+        if previous < 0 || previous != own {
+            return Self { previous: None };
+        }
+        // SAFETY: only async-signal-safe calls between fork and exec. The
+        // child is in the background until it takes the terminal, which
+        // would stop it with SIGTTOU.
+        unsafe {
+            command.pre_exec(|| {
+                let handler = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+                libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpid());
+                libc::signal(libc::SIGTTOU, handler);
+                Ok(())
+            });
+        }
+        Self {
+            previous: Some(previous),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for TerminalForeground {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous {
+            // SAFETY: gives the terminal back to the group that had it,
+            // ignoring the SIGTTOU a background group gets for that
+            unsafe {
+                let handler = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+                libc::tcsetpgrp(libc::STDIN_FILENO, previous);
+                libc::signal(libc::SIGTTOU, handler);
+            }
+        }
+    }
+}
+
+/// Ask a timed out child and the processes it started to exit (SIGTERM to
+/// its process group on Unix, TerminateProcess on Windows) and kill them if
+/// the child is still running after a grace period
+async fn terminate(child: &mut tokio::process::Child) {
+    #[cfg(unix)]
+    {
+        // The group id is the child's pid, which is gone once it's reaped
+        let pgid = child.id();
+        signal_group(pgid, Signal::Terminate);
+        let exited = tokio::time::timeout(TERMINATE_GRACE_PERIOD, child.wait())
+            .await
+            .is_ok();
+        // Whatever is left of the group goes too, even if the child itself
+        // exited
+        signal_group(pgid, Signal::Kill);
+        if exited {
+            return;
+        }
+    }
+    let _ = child.start_kill();
+    let _ = child.wait().await;
+}
+
+/// Terminal handling for [`CommandExecutor::execute_pty`]
+#[cfg(feature = "pty")]
+mod pty {
+    use portable_pty::PtySize;
+    use std::process::ExitStatus;
+
+    /// Size of the terminal on stdout, 80x24 if it isn't one
+    pub fn terminal_size() -> PtySize {
+        let mut size = PtySize::default();
+        #[cfg(unix)]
+        {
+            // SAFETY: TIOCGWINSZ only writes to the winsize we pass
+            let mut winsize: libc::winsize = unsafe { std::mem::zeroed() };
+            if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut winsize) } == 0
+                && winsize.ws_row > 0
+                && winsize.ws_col > 0
+            {
+                size.rows = winsize.ws_row;
+                size.cols = winsize.ws_col;
+            }
+        }
+        size
+    }
+
+    /// `code` as the exit status of a process
+    pub fn exit_status(code: u32) -> ExitStatus {
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            ExitStatus::from_raw(((code & 0xff) as i32) << 8)
+        }
+        #[cfg(windows)]
+        {
+            use std::os::windows::process::ExitStatusExt;
+            ExitStatus::from_raw(code)
+        }
+    }
+
+    /// Raw mode of the terminal on stdin, which passes every key on as it is
+    /// typed; the previous mode is restored on drop. Does nothing if stdin
+    /// isn't a terminal, and on Windows.
+    pub struct RawMode {
+        #[cfg(unix)]
+        previous: Option<libc::termios>,
+    }
+
+    impl RawMode {
+        pub fn enable() -> Self {
+            #[cfg(unix)]
+            {
+                // SAFETY: termios calls on stdin with a termios we own
+                let previous = unsafe {
+                    let mut termios: libc::termios = std::mem::zeroed();
+                    if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                        return Self { previous: None };
+                    }
+                    let previous = termios;
+                    libc::cfmakeraw(&mut termios);
+                    libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios);
+                    previous
+                };
+                Self {
+                    previous: Some(previous),
+                }
+            }
+            #[cfg(not(unix))]
+            Self {}
+        }
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            #[cfg(unix)]
+            if let Some(previous) = &self.previous {
+                // SAFETY: restores the termios read from stdin in `enable`
+                unsafe {
+                    libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, previous);
+                }
+            }
+        }
+    }
+}
+
+/// Send each line read from `pipe` to `sender`, tagged with `is_stderr`, until
+/// the pipe is closed. Invalid UTF-8 is replaced rather than ending the output.
+fn forward_lines(
+    pipe: impl Read + Send + 'static,
+    is_stderr: bool,
+    sender: mpsc::Sender<(bool, String)>,
+) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        let mut reader = BufReader::new(pipe);
+        let mut line = Vec::new();
+        while matches!(reader.read_until(b'\n', &mut line), Ok(read) if read > 0) {
+            if line.ends_with(b"\n") {
+                line.pop();
+                if line.ends_with(b"\r") {
+                    line.pop();
+                }
+            }
+            let text = String::from_utf8_lossy(&line).into_owned();
+            if sender.send((is_stderr, text)).is_err() {
+                break;
+            }
+            line.clear();
+        }
+    })
+}
+
+/// Run `command` with its output captured, for
+/// [`CommandExecutor::execute_parallel`]
+async fn run_captured(command: ParallelCommand, dry_run: bool) -> ExecutionResult {
+    let started = Instant::now();
+    let (exit_code, stdout, stderr) = if dry_run {
+        (0, String::new(), String::new())
+    } else {
+        match spawn_captured(&command).await {
+            Ok(Some(output)) => (
+                exit_code(output.status),
+                String::from_utf8_lossy(&output.stdout).to_string(),
+                String::from_utf8_lossy(&output.stderr).to_string(),
+            ),
+            Ok(None) => (
+                TIMED_OUT_EXIT_CODE,
+                String::new(),
+                format!(
+                    "Command '{}' timed out after {}s and was terminated",
+                    command.name,
+                    command.timeout.unwrap_or_default().as_secs_f64()
+                ),
+            ),
+            Err(e) => (NOT_STARTED_EXIT_CODE, String::new(), format!("{e:#}")),
+        }
+    };
+    ExecutionResult {
+        name: command.name,
+        exit_code,
+        stdout,
+        stderr,
+        duration_ms: started.elapsed().as_millis().try_into().unwrap_or(u64::MAX),
+    }
+}
+
+/// Output of `command` without stdin, `None` if it timed out
+async fn spawn_captured(command: &ParallelCommand) -> Result<Option<std::process::Output>> {
+    let (program, args) = if command.shell {
+        shell_command_line(&command.command_line)
+    } else {
+        split_command_line(&command.command_line)?
+    };
+    check_working_dir(command.cwd.as_deref())?;
+    let mut command_builder = tokio::process::Command::new(&program);
+    if let Some(cwd) = &command.cwd {
+        command_builder.current_dir(cwd);
+    }
+    #[cfg(unix)]
+    command_builder.process_group(0);
+    // Dropping the output future on timeout kills the child
+    let child = command_builder
+        .args(&args)
+        .envs(&command.env)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to execute command: {program}"))?;
+    let pgid = child.id();
+    let output = child.wait_with_output();
+    let output = match command.timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, output).await {
+            Ok(output) => output,
+            Err(_) => {
+                signal_group(pgid, Signal::Kill);
+                return Ok(None);
+            }
+        },
+        None => output.await,
+    };
+    output
+        .map(Some)
+        .with_context(|| format!("Failed to wait for command: {program}"))
+}
+
+/// Fail early with a clear message instead of the spawn error of a missing
+/// working directory
+fn check_working_dir(cwd: Option<&Path>) -> Result<()> {
+    match cwd {
+        Some(cwd) if !cwd.is_dir() => Err(anyhow::anyhow!(
+            "Working directory '{}' does not exist",
+            cwd.display()
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Split a command line into program and arguments using shell quoting rules,
+/// so quoted values containing spaces stay a single argument
+fn split_command_line(command_line: &str) -> Result<(String, Vec<String>)> {
+    let mut parts = shell_words::split(&escape_comments(command_line))
+        .with_context(|| format!("Failed to parse command line: {command_line}"))?;
+    if parts.is_empty() {
+        return Err(anyhow::anyhow!("Empty command"));
+    }
+    let command = parts.remove(0);
+    Ok((command, parts))
+}
+
+/// The user's shell with the arguments to run `command_line`
+fn shell_command_line(command_line: &str) -> (String, Vec<String>) {
+    if cfg!(windows) {
+        let shell = std::env::var("COMSPEC").unwrap_or_else(|_| "cmd".to_string());
+        (shell, vec!["/C".to_string(), command_line.to_string()])
+    } else {
+        let shell = std::env::var("SHELL")
+            .ok()
+            .filter(|shell| !shell.is_empty())
+            .unwrap_or_else(|| "/bin/sh".to_string());
+        (shell, vec!["-c".to_string(), command_line.to_string()])
+    }
+}
+
+/// Command lines have no comments, but `shell_words` treats a `#` at the
+/// start of a word as one. Escape such a `#` so it stays a literal argument.
+fn escape_comments(command_line: &str) -> String {
+    let mut output = String::with_capacity(command_line.len());
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut word_start = true;
+    for c in command_line.chars() {
+        let mut separator = false;
+        if escaped {
+            escaped = false;
+        } else if let Some(open) = quote {
+            if c == open {
+                quote = None;
+            } else if c == '\\' && open == '"' {
+                escaped = true;
+            }
+        } else {
+            match c {
+                '\\' => escaped = true,
+                '\'' | '"' => quote = Some(c),
+                '#' if word_start => output.push('\\'),
+                _ => separator = c.is_whitespace(),
+            }
+        }
+        output.push(c);
+        word_start = separator;
+    }
+    output
+}
+
 impl Default for CommandExecutor {
     fn default() -> Self {
         Self::new()
@@ -84,6 +905,7 @@ impl Default for CommandExecutor {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::history::HistoryManager;
 
     #[test]
     fn test_execute_simple_command() {
@@ -99,4 +921,438 @@ mod tests {
         let result = executor.execute_with_output("nonexistent_command_12345");
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_split_command_line_respects_quotes() {
+        let (command, args) = split_command_line(r#"echo "hello world" 'a b' c\ d"#).unwrap();
+        assert_eq!(command, "echo");
+        assert_eq!(args, vec!["hello world", "a b", "c d"]);
+
+        assert!(split_command_line("   ").is_err());
+        assert!(split_command_line(r#"echo "unterminated"#).is_err());
+    }
+
+    #[test]
+    fn test_split_command_line_keeps_hashes() {
+        let (command, args) =
+            split_command_line(r##"grep #include 'a #b' x#y \#z "#q" # end"##).unwrap();
+        assert_eq!(command, "grep");
+        assert_eq!(
+            args,
+            vec!["#include", "a #b", "x#y", "#z", "#q", "#", "end"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_quoted_argument_is_passed_as_one() {
+        let executor = CommandExecutor::new();
+        let output = executor
+            .execute_with_output(r#"sh -c 'echo $#: $1' _ "hello world""#)
+            .unwrap();
+        assert_eq!(output.trim(), "1: hello world");
+
+        let output = executor
+            .execute_with_output(r#"echo "hello world""#)
+            .unwrap();
+        assert_eq!(output.trim(), "hello world");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_execute_returns_exit_status() {
+        let executor = CommandExecutor::new();
+        let no_env = BTreeMap::new();
+        let status = executor.execute("false", &no_env, None, None).unwrap();
+        assert_eq!(status.code(), Some(1));
+        assert_eq!(exit_code(status), 1);
+
+        let status = executor
+            .execute("sh -c 'exit 3'", &no_env, None, None)
+            .unwrap();
+        assert_eq!(exit_code(status), 3);
+        assert!(executor
+            .execute("true", &no_env, None, None)
+            .unwrap()
+            .success());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_execute_shell() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let executor = CommandExecutor::new();
+        let status = executor
+            .execute_shell(
+                "false || touch chained && test -f chained",
+                &BTreeMap::new(),
+                Some(temp_dir.path()),
+                None,
+            )
+            .unwrap();
+        assert!(status.success());
+        assert!(temp_dir.path().join("chained").exists());
+
+        let command = |shell| ParallelCommand {
+            name: "chain".to_string(),
+            command_line: "echo a && echo b; echo $((1 + 2))".to_string(),
+            shell,
+            ..Default::default()
+        };
+        let results = executor
+            .execute_parallel(&[command(true), command(false)], 2)
+            .await
+            .unwrap();
+        assert_eq!(results[0].stdout, "a\nb\n3\n");
+        // Without the shell, `&&` is an argument of echo
+        assert_eq!(results[1].stdout, "a && echo b; echo $((1 + 2))\n");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_execute_with_retry() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let counter = temp_dir.path().join("attempts");
+        // Fails the first two times it runs
+        let command = format!(
+            "sh -c 'echo x >> {counter}; [ $(wc -l < {counter}) -gt 2 ]'",
+            counter = counter.display()
+        );
+        let attempts = || std::fs::read_to_string(&counter).unwrap().lines().count();
+        let executor = CommandExecutor::new();
+
+        let started = Instant::now();
+        let status = executor
+            .execute_with_retry(&command, 3, Duration::from_millis(50))
+            .await
+            .unwrap();
+        assert!(status.success());
+        assert_eq!(attempts(), 3);
+        // 50ms before the second attempt, 100ms before the third
+        assert!(started.elapsed() >= Duration::from_millis(150));
+
+        std::fs::remove_file(&counter).unwrap();
+        let status = executor
+            .execute_with_retry(&command, 2, Duration::ZERO)
+            .await
+            .unwrap();
+        assert_eq!(status.code(), Some(1));
+        assert_eq!(attempts(), 2);
+
+        let status = executor
+            .execute_with_retry("true", 0, Duration::ZERO)
+            .await
+            .unwrap();
+        assert!(status.success());
+        assert!(executor
+            .execute_with_retry("nonexistent_command_12345", 3, Duration::ZERO)
+            .await
+            .is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_dry_run_does_not_spawn() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let marker = temp_dir.path().join("ran");
+        let mut executor = CommandExecutor::new();
+        executor.set_dry_run(true);
+
+        let command = format!("touch {}", marker.display());
+        assert!(executor
+            .execute(&command, &BTreeMap::new(), None, None)
+            .unwrap()
+            .success());
+        let status = executor
+            .execute_with_timeout(
+                &command,
+                Duration::from_secs(5),
+                &BTreeMap::new(),
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        assert!(status.success());
+        assert!(!marker.exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_env_is_passed_to_child() {
+        let executor = CommandExecutor::new();
+        let env = BTreeMap::from([("TEST_VAR".to_string(), "hello world".to_string())]);
+        let check = r#"sh -c 'test "$TEST_VAR" = "hello world"'"#;
+
+        assert!(executor.execute(check, &env, None, None).unwrap().success());
+        assert!(!executor
+            .execute(check, &BTreeMap::new(), None, None)
+            .unwrap()
+            .success());
+        let status = executor
+            .execute_with_timeout(check, Duration::from_secs(10), &env, None, None)
+            .await
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_working_directory() {
+        let executor = CommandExecutor::new();
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let check = format!(
+            "test \"$(pwd -P)\" = {}",
+            temp_dir.path().canonicalize().unwrap().display()
+        );
+
+        let status = executor
+            .execute(
+                &format!("sh -c '{check}'"),
+                &BTreeMap::new(),
+                Some(temp_dir.path()),
+                None,
+            )
+            .unwrap();
+        assert!(status.success());
+
+        let missing = temp_dir.path().join("missing");
+        let err = executor
+            .execute("true", &BTreeMap::new(), Some(&missing), None)
+            .unwrap_err();
+        assert!(err.to_string().contains("does not exist"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_timeout_terminates_the_whole_process_group() {
+        let executor = CommandExecutor::new();
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let marker = temp_dir.path().join("survived");
+        let command = format!("sh -c '(sleep 1.5; touch {}) & wait'", marker.display());
+        let err = executor
+            .execute_with_timeout(
+                &command,
+                Duration::from_millis(300),
+                &BTreeMap::new(),
+                None,
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert!(!marker.exists());
+    }
+
+    #[tokio::test]
+    async fn test_execute_with_timeout_finishes_in_time() {
+        let executor = CommandExecutor::new();
+        assert!(executor
+            .execute_with_timeout(
+                "echo fast",
+                Duration::from_secs(10),
+                &BTreeMap::new(),
+                None,
+                None
+            )
+            .await
+            .is_ok());
+    }
+
+    #[cfg(all(unix, feature = "pty"))]
+    #[test]
+    fn test_execute_pty() {
+        let executor = CommandExecutor::new();
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let no_env = BTreeMap::new();
+        // Commands see a terminal, unlike with execute_with_output
+        let status = executor
+            .execute_pty("sh -c 'test -t 0 && test -t 1'", &no_env, None)
+            .unwrap();
+        assert!(status.success());
+
+        let env = BTreeMap::from([("CODE".to_string(), "5".to_string())]);
+        let command = format!(
+            "sh -c 'test \"$(pwd -P)\" = \"$1\" && exit $CODE' _ {}",
+            temp_dir.path().canonicalize().unwrap().display()
+        );
+        let status = executor
+            .execute_pty(&command, &env, Some(temp_dir.path()))
+            .unwrap();
+        assert_eq!(exit_code(status), 5);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_execute_streaming() {
+        let executor = CommandExecutor::new();
+        let started = Instant::now();
+        let stdout = std::cell::RefCell::new(Vec::new());
+        let stderr = std::cell::RefCell::new(Vec::new());
+        let status = executor
+            .execute_streaming(
+                "sh -c 'echo one; sleep 0.3; echo two >&2; sleep 0.3; printf three; exit 4'",
+                |line| {
+                    stdout
+                        .borrow_mut()
+                        .push((line.to_string(), started.elapsed()))
+                },
+                |line| {
+                    stderr
+                        .borrow_mut()
+                        .push((line.to_string(), started.elapsed()))
+                },
+            )
+            .unwrap();
+        assert_eq!(exit_code(status), 4);
+
+        let stdout = stdout.into_inner();
+        let stderr = stderr.into_inner();
+        let lines: Vec<_> = stdout.iter().map(|(line, _)| line.as_str()).collect();
+        assert_eq!(lines, vec!["one", "three"]);
+        assert_eq!(stderr.len(), 1);
+        assert_eq!(stderr[0].0, "two");
+        // Each line arrived when it was printed, not when the command exited
+        assert!(stdout[0].1 < stderr[0].1 - Duration::from_millis(200));
+        assert!(stderr[0].1 < stdout[1].1 - Duration::from_millis(200));
+
+        let mut executor = CommandExecutor::new();
+        executor.set_dry_run(true);
+        let status = executor
+            .execute_streaming("echo hello", |_| panic!("ran"), |_| panic!("ran"))
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_execute_parallel() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let command = |name: &str, command_line: String| ParallelCommand {
+            name: name.to_string(),
+            command_line,
+            ..Default::default()
+        };
+        // Each command waits until all of them have started, so they only
+        // finish if they run at the same time
+        let barrier = |i: usize| {
+            format!(
+                "sh -c 'touch {dir}/{i}; while [ $(ls {dir} | wc -l) -lt 3 ]; do sleep 0.01; done; echo done {i}'",
+                dir = temp_dir.path().display()
+            )
+        };
+        let mut commands: Vec<_> = (0..3)
+            .map(|i| ParallelCommand {
+                timeout: Some(Duration::from_secs(10)),
+                ..command(&format!("wait{i}"), barrier(i))
+            })
+            .collect();
+        commands.push(command("fail", "sh -c 'echo oops >&2; exit 3'".into()));
+        commands.push(command("missing", "nonexistent_command_12345".into()));
+        commands.push(ParallelCommand {
+            timeout: Some(Duration::from_millis(100)),
+            ..command("slow", "sleep 5".into())
+        });
+
+        let executor = CommandExecutor::new();
+        let results = executor.execute_parallel(&commands, 8).await.unwrap();
+        let names: Vec<_> = results.iter().map(|result| result.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["wait0", "wait1", "wait2", "fail", "missing", "slow"]
+        );
+        for (i, result) in results[..3].iter().enumerate() {
+            assert!(result.success(), "{result:?}");
+            assert_eq!(result.stdout, format!("done {i}\n"));
+        }
+        assert_eq!(results[3].exit_code, 3);
+        assert_eq!(results[3].stderr, "oops\n");
+        assert_eq!(results[4].exit_code, NOT_STARTED_EXIT_CODE);
+        assert!(results[4].stderr.contains("nonexistent_command_12345"));
+        assert_eq!(results[5].exit_code, TIMED_OUT_EXIT_CODE);
+        assert!(results[5].duration_ms < 5000);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_execute_parallel_is_bounded() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        // Fails if another command is running at the same time
+        let exclusive = format!(
+            "sh -c 'mkdir {dir}/lock && sleep 0.05 && rmdir {dir}/lock'",
+            dir = temp_dir.path().display()
+        );
+        let commands: Vec<_> = (0..4)
+            .map(|i| ParallelCommand {
+                name: format!("job{i}"),
+                command_line: exclusive.clone(),
+                ..Default::default()
+            })
+            .collect();
+
+        let mut executor = CommandExecutor::new();
+        let results = executor.execute_parallel(&commands, 1).await.unwrap();
+        assert!(results.iter().all(ExecutionResult::success), "{results:?}");
+
+        executor.set_dry_run(true);
+        let marker = temp_dir.path().join("ran");
+        let touch = ParallelCommand {
+            name: "touch".to_string(),
+            command_line: format!("touch {}", marker.display()),
+            ..Default::default()
+        };
+        let results = executor.execute_parallel(&[touch], 1).await.unwrap();
+        assert!(results[0].success());
+        assert!(!marker.exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_runs_are_recorded() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut history = HistoryManager::new_with_limit(temp_dir.path().to_path_buf(), 10);
+        let mut executor = CommandExecutor::new();
+        let no_env = BTreeMap::new();
+        fn recording(history: &mut HistoryManager) -> Recording<'_> {
+            Recording {
+                history,
+                name: "fail",
+                source: "main",
+                command: "sh -c 'exit 3'",
+            }
+        }
+
+        executor
+            .execute(
+                "sh -c 'exit 3'",
+                &no_env,
+                None,
+                Some(recording(&mut history)),
+            )
+            .unwrap();
+        let result = executor
+            .execute_with_timeout(
+                "sleep 5",
+                Duration::from_millis(100),
+                &no_env,
+                None,
+                Some(recording(&mut history)),
+            )
+            .await;
+        assert!(result.is_err());
+        executor.set_dry_run(true);
+        executor
+            .execute("true", &no_env, None, Some(recording(&mut history)))
+            .unwrap();
+
+        let entries = history.list(None).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].name, "fail");
+        assert_eq!(entries[0].exit_code, Some(3));
+        // Terminated after the timeout
+        assert_eq!(entries[1].exit_code, None);
+        assert!(entries[1].duration_ms >= 100);
+    }
 }
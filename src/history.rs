@@ -0,0 +1,228 @@
+//! Log of executed commands
+//!
+//! Every command doo runs is appended to `history.jsonl` in the config
+//! directory, one JSON object per line, with the command as it was shown
+//! (secrets masked), its exit code and how long it took. Only the most recent
+//! entries are kept; the limit is `history_limit` in the main config.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::config::ConfigManager;
+use crate::lock::ConfigLock;
+use crate::util::atomic_write_private;
+
+/// File in the config directory holding the history
+pub const HISTORY_FILE: &str = "history.jsonl";
+
+/// Entries kept unless the main config sets `history_limit`
+pub const DEFAULT_HISTORY_LIMIT: usize = 1000;
+
+/// One executed command
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecutionEntry {
+    pub timestamp: DateTime<Utc>,
+    pub name: String,
+    /// Config file the command came from (`main` for the main config)
+    pub source: String,
+    /// The command as it ran, with the values of secrets masked
+    pub command: String,
+    /// `None` if the command was terminated after its timeout
+    pub exit_code: Option<i32>,
+    pub duration_ms: u64,
+}
+
+pub struct HistoryManager {
+    config_dir: PathBuf,
+    /// Entries to keep; `0` turns the history off
+    limit: usize,
+}
+
+impl HistoryManager {
+    pub fn new(config_manager: &ConfigManager) -> Self {
+        Self::new_with_limit(
+            config_manager.config_dir().clone(),
+            config_manager.history_limit(),
+        )
+    }
+
+    pub fn new_with_limit(config_dir: PathBuf, limit: usize) -> Self {
+        Self { config_dir, limit }
+    }
+
+    fn path(&self) -> PathBuf {
+        self.config_dir.join(HISTORY_FILE)
+    }
+
+    /// Add `entry`, dropping the oldest entries beyond the limit
+    pub fn append(&mut self, entry: ExecutionEntry) -> Result<()> {
+        if self.limit == 0 {
+            return Ok(());
+        }
+        let _lock = ConfigLock::acquire(&self.config_dir)?;
+        let mut entries = self.read()?;
+        entries.push(entry);
+        let excess = entries.len().saturating_sub(self.limit);
+        self.write(&entries[excess..])
+    }
+
+    /// The last `limit` entries (all with `None`), oldest first
+    pub fn list(&self, limit: Option<usize>) -> Result<Vec<ExecutionEntry>> {
+        let mut entries = self.read()?;
+        if let Some(limit) = limit {
+            entries.drain(..entries.len().saturating_sub(limit));
+        }
+        Ok(entries)
+    }
+
+    /// Remove all entries, returning how many there were
+    pub fn clear(&mut self) -> Result<usize> {
+        let _lock = ConfigLock::acquire(&self.config_dir)?;
+        let count = self.read()?.len();
+        let path = self.path();
+        if path.exists() {
+            fs::remove_file(&path).with_context(|| format!("Failed to remove {path:?}"))?;
+        }
+        Ok(count)
+    }
+
+    /// Entries in the file, skipping lines that don't parse (e.g. one cut
+    /// off by a full disk) so one bad line doesn't lose the rest
+    fn read(&self) -> Result<Vec<ExecutionEntry>> {
+        let path = self.path();
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let contents =
+            fs::read_to_string(&path).with_context(|| format!("Failed to read {path:?}"))?;
+        Ok(contents
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+
+    /// Commands may contain sensitive values, so the file is only readable
+    /// by the current user
+    fn write(&self, entries: &[ExecutionEntry]) -> Result<()> {
+        let mut contents = String::new();
+        for entry in entries {
+            contents
+                .push_str(&serde_json::to_string(entry).context("Failed to serialize history")?);
+            contents.push('\n');
+        }
+        atomic_write_private(&self.path(), &contents).context("Failed to write history file")
+    }
+}
+
+/// What [`CommandExecutor`](crate::executor::CommandExecutor) records in the
+/// history once a command has run
+pub struct Recording<'a> {
+    pub history: &'a mut HistoryManager,
+    pub name: &'a str,
+    pub source: &'a str,
+    /// The command as shown to the user, with secrets masked
+    pub command: &'a str,
+}
+
+impl Recording<'_> {
+    /// Append the entry for the finished command. A history that can't be
+    /// written is only worth a warning, the command ran either way.
+    pub fn finish(self, exit_code: Option<i32>, duration: Duration) {
+        let entry = ExecutionEntry {
+            timestamp: Utc::now(),
+            name: self.name.to_string(),
+            source: self.source.to_string(),
+            command: self.command.to_string(),
+            exit_code,
+            duration_ms: duration.as_millis().try_into().unwrap_or(u64::MAX),
+        };
+        if let Err(e) = self.history.append(entry) {
+            eprintln!("⚠ Failed to record the command in the history: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn entry(name: &str, exit_code: Option<i32>) -> ExecutionEntry {
+        ExecutionEntry {
+            timestamp: Utc::now(),
+            name: name.to_string(),
+            source: "main".to_string(),
+            command: format!("echo {name}"),
+            exit_code,
+            duration_ms: 12,
+        }
+    }
+
+    fn names(entries: Vec<ExecutionEntry>) -> Vec<String> {
+        entries.into_iter().map(|entry| entry.name).collect()
+    }
+
+    #[test]
+    fn test_append_and_list() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut history = HistoryManager::new_with_limit(temp_dir.path().to_path_buf(), 10);
+        assert!(history.list(None).unwrap().is_empty());
+
+        history.append(entry("pods", Some(0))).unwrap();
+        history.append(entry("deploy", Some(2))).unwrap();
+        history.append(entry("logs", None)).unwrap();
+
+        let entries = history.list(None).unwrap();
+        assert_eq!(entries[1].command, "echo deploy");
+        assert_eq!(entries[1].exit_code, Some(2));
+        assert_eq!(entries[2].exit_code, None);
+        assert_eq!(names(entries), vec!["pods", "deploy", "logs"]);
+        assert_eq!(
+            names(history.list(Some(2)).unwrap()),
+            vec!["deploy", "logs"]
+        );
+
+        let contents = fs::read_to_string(temp_dir.path().join(HISTORY_FILE)).unwrap();
+        assert_eq!(contents.lines().count(), 3);
+        assert!(contents.lines().all(|line| line.starts_with('{')));
+
+        assert_eq!(history.clear().unwrap(), 3);
+        assert!(history.list(None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_history_is_trimmed() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut history = HistoryManager::new_with_limit(temp_dir.path().to_path_buf(), 3);
+        for i in 0..5 {
+            history.append(entry(&format!("cmd{i}"), Some(0))).unwrap();
+        }
+        assert_eq!(
+            names(history.list(None).unwrap()),
+            vec!["cmd2", "cmd3", "cmd4"]
+        );
+
+        // A limit of 0 turns the history off
+        let mut off = HistoryManager::new_with_limit(temp_dir.path().join("off"), 0);
+        off.append(entry("pods", Some(0))).unwrap();
+        assert!(off.list(None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_broken_lines_are_skipped() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut history = HistoryManager::new_with_limit(temp_dir.path().to_path_buf(), 10);
+        history.append(entry("pods", Some(0))).unwrap();
+        let path = temp_dir.path().join(HISTORY_FILE);
+        let mut contents = fs::read_to_string(&path).unwrap();
+        contents.push_str("{\"timestamp\": \"2026-\n");
+        fs::write(&path, contents).unwrap();
+
+        history.append(entry("logs", Some(1))).unwrap();
+        assert_eq!(names(history.list(None).unwrap()), vec!["pods", "logs"]);
+    }
+}
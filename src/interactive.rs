@@ -1,23 +1,35 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use colored::*;
-use dialoguer::{theme::ColorfulTheme, FuzzySelect, Select};
+use dialoguer::{theme::ColorfulTheme, Confirm, FuzzySelect, Input, Select};
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::time::Duration;
 
-use crate::config::{CommandSearchResult, ConfigManager};
-use crate::context::ContextManager;
-use crate::executor::CommandExecutor;
-use crate::variables::VariableManager;
+use crate::config::{
+    display_command, project_config_in, CommandEntry, CommandSearchResult, CommandSource, Config,
+    ConfigManager, ConfigSource, ProjectInit,
+};
+use crate::context::{ContextManager, ContextNode};
+use crate::executor::{retry, CommandExecutor};
+use crate::history::{HistoryManager, Recording};
+use crate::variables::{VarValue, VariableManager, SECRET_MASK};
+
+/// Variables listed in the menu header before the rest are summed up
+const HEADER_VARIABLES: usize = 5;
 
 pub struct InteractiveMenu<'a> {
     config_manager: &'a ConfigManager,
-    variable_manager: &'a VariableManager,
-    context_manager: &'a ContextManager,
+    variable_manager: &'a mut VariableManager,
+    context_manager: &'a mut ContextManager,
 }
 
 impl<'a> InteractiveMenu<'a> {
     pub fn new(
         config_manager: &'a ConfigManager,
-        variable_manager: &'a VariableManager,
-        context_manager: &'a ContextManager,
+        variable_manager: &'a mut VariableManager,
+        context_manager: &'a mut ContextManager,
     ) -> Result<Self> {
         Ok(Self {
             config_manager,
@@ -26,14 +38,17 @@ impl<'a> InteractiveMenu<'a> {
         })
     }
 
-    #[allow(clippy::never_loop)]
-    pub fn run(&self, executor: &CommandExecutor) -> Result<()> {
+    /// Show the menu and run the selected command, returning its exit status
+    /// (`None` if the menu was closed without running anything). The first
+    /// two entries open the variable editor and the context picker, which
+    /// return to the menu.
+    pub async fn run(&mut self, executor: &CommandExecutor) -> Result<Option<ExitStatus>> {
         loop {
             // Get all commands
             let commands = self.config_manager.search_commands("");
             if commands.is_empty() {
                 println!("{}", "No commands available.".red());
-                return Ok(());
+                return Ok(None);
             }
 
             // Prepare command list with better formatting for better visual distinction
@@ -44,65 +59,109 @@ impl<'a> InteractiveMenu<'a> {
                          name,
                          command,
                          description,
+                         tags,
+                         params,
+                         examples,
                      }| {
-                        let header = format!("[{name}]  =>  {command}");
+                        let command = display_command(command, params);
+                        let mut header = format!("[{name}]  =>  {command}");
+                        // Listed in the item so the fuzzy search matches them
+                        if !tags.is_empty() {
+                            let tags = format!("({})", tags.join(", "));
+                            header = format!("{header}  {}", tags.truecolor(140, 140, 140));
+                        }
                         if let Some(desc) = description {
                             // Put description on next line, slightly gray
-                            format!("{header}\n   {}", desc.truecolor(140, 140, 140))
-                        } else {
-                            header
+                            header = format!("{header}\n   {}", desc.truecolor(140, 140, 140));
+                        }
+                        for example in examples {
+                            let example = format!("e.g. {example}");
+                            header = format!("{header}\n   {}", example.truecolor(140, 140, 140));
                         }
+                        header
                     },
                 )
                 .collect();
 
+            let context = self.context_manager.current_context().to_string();
+            let mut items = vec![
+                format!(
+                    "{}",
+                    format!("⚙ Edit the variables of context {context}").yellow()
+                ),
+                format!("{}", "⇄ Switch to another context".yellow()),
+            ];
+            items.extend(command_items);
+
             // Show context information
             println!();
             println!("{}", "┌─ DOO Command Browser ─┐".cyan().bold());
+            let one_off = if self.context_manager.is_one_off() {
+                " (one-off)"
+            } else {
+                ""
+            };
+            println!("│ Context: {}{} │", context.blue().bold(), one_off);
             println!(
-                "│ Context: {} │",
-                self.context_manager.current_context().blue().bold()
+                "│ Variables: {}",
+                variables_summary(self.variable_manager, &context)?
             );
             println!("{}", "└─ ─ ─ ─ ─ ─ ─ ─ ─ ─ ─ ─┘".cyan());
 
             // Use dialoguer's FuzzySelect for the interactive menu
             let selection = FuzzySelect::with_theme(&ColorfulTheme::default())
                 .with_prompt("Search and select command")
-                .default(0)
-                .items(&command_items)
+                .default(2)
+                .items(&items)
                 .interact_opt()?;
 
             match selection {
+                Some(0) => {
+                    edit_variables(&mut TerminalPrompter, self.variable_manager, &context)?;
+                    print!("\x1B[2J\x1B[1;1H");
+                }
+                Some(1) => {
+                    // The header and the variables shown are read again for
+                    // the new context when the menu comes back
+                    match select_context(&mut TerminalPrompter, self.context_manager) {
+                        Ok(_) => print!("\x1B[2J\x1B[1;1H"),
+                        // E.g. a strict on_switch hook that failed; the
+                        // message stays above the menu
+                        Err(e) => eprintln!("{} {e:#}", "✗".red().bold()),
+                    }
+                }
                 Some(index) => {
                     let CommandSearchResult {
                         name: cmd_name,
                         command: cmd_template,
                         ..
-                    } = &commands[index];
+                    } = &commands[index - 2];
 
                     // Clear screen for cleaner output
                     print!("\x1B[2J\x1B[1;1H");
 
                     // Execute the selected command
-                    self.execute_selected_command(cmd_name, cmd_template, executor)?;
+                    let status = self
+                        .execute_selected_command(cmd_name, cmd_template, executor)
+                        .await?;
 
-                    return Ok(());
+                    return Ok(Some(status));
                 }
                 None => {
                     // User pressed Escape - clear screen and exit
                     print!("\x1B[2J\x1B[1;1H");
-                    return Ok(());
+                    return Ok(None);
                 }
             }
         }
     }
 
-    fn execute_selected_command(
-        &self,
+    async fn execute_selected_command(
+        &mut self,
         cmd_name: &str,
         cmd_template: &str,
         executor: &CommandExecutor,
-    ) -> Result<()> {
+    ) -> Result<ExitStatus> {
         println!(
             "{} Selected command: {}",
             "✓".green().bold(),
@@ -112,7 +171,8 @@ impl<'a> InteractiveMenu<'a> {
         // Check for conflicts before executing
         let conflicts = self.config_manager.get_command_conflicts(cmd_name);
 
-        let final_template = if conflicts.len() > 1 {
+        // The project config overrides the others without asking
+        let selected = if conflicts.len() > 1 && conflicts[0].kind != ConfigSource::ProjectLocal {
             // Multiple definitions found, ask user to choose
             println!(
                 "{} Command '{}' found in multiple config files:",
@@ -122,7 +182,9 @@ impl<'a> InteractiveMenu<'a> {
 
             let options: Vec<String> = conflicts
                 .iter()
-                .map(|conflict| format!("{} ({})", conflict.source_file, conflict.command))
+                .map(|conflict| {
+                    format!("{} ({})", conflict.source_file, conflict.display_command())
+                })
                 .collect();
 
             let selection = Select::with_theme(&ColorfulTheme::default())
@@ -131,30 +193,586 @@ impl<'a> InteractiveMenu<'a> {
                 .items(&options)
                 .interact()?;
 
-            conflicts[selection].command.clone()
+            conflicts[selection].clone()
         } else {
-            cmd_template.to_string()
+            conflicts
+                .into_iter()
+                .next()
+                .unwrap_or_else(|| CommandSource {
+                    name: cmd_name.to_string(),
+                    command: cmd_template.to_string(),
+                    description: None,
+                    defaults: BTreeMap::new(),
+                    timeout_secs: None,
+                    retry: None,
+                    env: BTreeMap::new(),
+                    cwd: None,
+                    args: BTreeMap::new(),
+                    quote_args: false,
+                    shell: false,
+                    params: BTreeMap::new(),
+                    examples: Vec::new(),
+                    source_file: String::new(),
+                    kind: ConfigSource::Main,
+                })
         };
 
-        // Resolve variables in the command template
-        let resolved_command = self.variable_manager.resolve_variables(
-            self.context_manager.current_context(),
-            &final_template,
+        // Point out templates that likely won't do what they look like, but
+        // run them anyway
+        for warning in VariableManager::validate_template(&selected.command) {
+            println!("{} {}", "⚠".yellow().bold(), warning);
+        }
+
+        // Resolve variables in the command template, asking for missing values
+        let context = self.context_manager.current_context();
+        let defaults = prompt_missing_placeholders(
+            self.variable_manager,
+            context,
+            &selected,
+            &[],
+            PromptMode::new(false, false),
+        )?;
+        let (resolved_command, values) = self.variable_manager.resolve_variables_with_values(
+            context,
+            Some(&selected.name),
+            &selected.command,
             &[],
+            &defaults,
+            selected.quote_args,
         )?;
+        selected.check_args(&values)?;
 
-        println!(
-            "{} {}",
-            "Executing:".green().bold(),
-            resolved_command.bright_white()
-        );
+        let shown = self
+            .variable_manager
+            .mask_secrets(context, &resolved_command)?;
+        println!("{} {}", executor.label(), shown.bright_white());
+
+        let env = self.variable_manager.resolve_env(
+            context,
+            Some(&selected.name),
+            &self.config_manager.command_env(context, &selected),
+            &[],
+            &defaults,
+        )?;
+
+        let cwd = selected
+            .cwd
+            .as_deref()
+            .map(|cwd| {
+                self.variable_manager.resolve_variables_with_defaults(
+                    context,
+                    Some(&selected.name),
+                    cwd,
+                    &[],
+                    &defaults,
+                )
+            })
+            .transpose()?
+            .map(PathBuf::from);
+
+        let (max_attempts, backoff) = selected
+            .retry
+            .as_ref()
+            .map_or((1, Duration::ZERO), |retry| {
+                (retry.max_attempts, retry.backoff())
+            });
+        let (config_manager, selected) = (self.config_manager, &selected);
+        let (resolved_command, shown, env, cwd) = (&resolved_command, &shown, &env, cwd.as_deref());
+        retry(max_attempts, backoff, move || async move {
+            let mut history = HistoryManager::new(config_manager);
+            let recording = Recording {
+                history: &mut history,
+                name: &selected.name,
+                source: &selected.source_file,
+                command: shown,
+            };
+            match selected.timeout_secs {
+                Some(secs) if selected.shell => {
+                    executor
+                        .execute_shell_with_timeout(
+                            resolved_command,
+                            Duration::from_secs(secs),
+                            env,
+                            cwd,
+                            Some(recording),
+                        )
+                        .await
+                }
+                Some(secs) => {
+                    executor
+                        .execute_with_timeout(
+                            resolved_command,
+                            Duration::from_secs(secs),
+                            env,
+                            cwd,
+                            Some(recording),
+                        )
+                        .await
+                }
+                None if selected.shell => {
+                    executor.execute_shell(resolved_command, env, cwd, Some(recording))
+                }
+                None => executor.execute(resolved_command, env, cwd, Some(recording)),
+            }
+        })
+        .await
+    }
+}
+
+/// The variables and secrets of `context` for the menu header, e.g.
+/// `#1=shop, #token=*****`, secrets masked
+fn variables_summary(variable_manager: &VariableManager, context: &str) -> Result<String> {
+    let variables = context_variables(variable_manager, context)?;
+    if variables.is_empty() {
+        return Ok("none".truecolor(140, 140, 140).to_string());
+    }
+    let mut shown: Vec<String> = variables
+        .iter()
+        .take(HEADER_VARIABLES)
+        .map(|(name, value, secret)| {
+            let value = if *secret {
+                SECRET_MASK.to_string()
+            } else {
+                value.to_string()
+            };
+            format!("{}={}", name.cyan(), value)
+        })
+        .collect();
+    if variables.len() > HEADER_VARIABLES {
+        shown.push(format!("{} more", variables.len() - HEADER_VARIABLES));
+    }
+    Ok(shown.join(", "))
+}
+
+/// Context-wide variables and secrets of `context` by name, with whether
+/// each is a secret
+fn context_variables(
+    variable_manager: &VariableManager,
+    context: &str,
+) -> Result<Vec<(String, VarValue, bool)>> {
+    let mut variables: Vec<_> = variable_manager
+        .list_variables(context)?
+        .into_iter()
+        .map(|(name, value)| (name, value, false))
+        .chain(
+            variable_manager
+                .list_secrets(context)?
+                .into_iter()
+                .map(|(name, value)| (name, value, true)),
+        )
+        .collect();
+    variables.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(variables)
+}
+
+/// Let `prompter` pick one of the contexts to switch to, children indented
+/// below their parent. Returns the new context, `None` if it went back or
+/// picked the active one.
+pub fn select_context(
+    prompter: &mut dyn Prompter,
+    context_manager: &mut ContextManager,
+) -> Result<Option<String>> {
+    fn flatten(node: ContextNode, depth: usize, names: &mut Vec<(String, usize)>) {
+        names.push((node.name, depth));
+        for child in node.children {
+            flatten(child, depth + 1, names);
+        }
+    }
+
+    let mut names = Vec::new();
+    for node in context_manager.list_contexts()? {
+        flatten(node, 0, &mut names);
+    }
+    let active = context_manager.current_context();
+    let mut items: Vec<String> = names
+        .iter()
+        .map(|(name, depth)| {
+            let marker = if name == active { " (active)" } else { "" };
+            format!("{}{name}{marker}", "  ".repeat(*depth))
+        })
+        .collect();
+    items.push("← Back to the commands".to_string());
+
+    let selection = prompter.select("Switch to context", &items)?;
+    let Some((name, _)) = names.get(selection).filter(|(name, _)| name != active) else {
+        return Ok(None);
+    };
+    context_manager.switch_context(name, None)?;
+    Ok(Some(name.clone()))
+}
+
+/// Let `prompter` change, add and delete the context-wide variables of
+/// `context` until it picks "back"
+pub fn edit_variables(
+    prompter: &mut dyn Prompter,
+    variable_manager: &mut VariableManager,
+    context: &str,
+) -> Result<()> {
+    loop {
+        let variables = context_variables(variable_manager, context)?;
+        let mut items: Vec<String> = variables
+            .iter()
+            .map(|(name, value, secret)| {
+                if *secret {
+                    format!("{name} = {SECRET_MASK} (secret)")
+                } else {
+                    format!("{name} = {value}")
+                }
+            })
+            .collect();
+        let add = items.len();
+        items.push("+ Add a variable".to_string());
+        let delete = (!variables.is_empty()).then(|| {
+            items.push("- Delete a variable".to_string());
+            items.len() - 1
+        });
+        items.push("← Back to the commands".to_string());
+
+        let selection = prompter.select(&format!("Variables of context {context}"), &items)?;
+        if let Some((name, value, secret)) = variables.get(selection) {
+            let (name, secret) = (name.clone(), *secret);
+            match value {
+                VarValue::List(_) => {
+                    let items = prompter.input(
+                        &format!("New items of {name}, separated by commas"),
+                        &|_| Ok(()),
+                    )?;
+                    let items = items.split(',').map(|item| item.trim().to_string());
+                    variable_manager.set_list(context, &name, items.collect(), secret)?;
+                }
+                VarValue::Single(_) => {
+                    let value = prompter.input(&format!("New value of {name}"), &|_| Ok(()))?;
+                    store_variable(variable_manager, context, &name, &value, secret)?;
+                }
+            }
+            println!(
+                "{} Set {} in context {}",
+                "✓".green().bold(),
+                name.cyan(),
+                context.blue().bold()
+            );
+        } else if selection == add {
+            let name =
+                prompter.input("Name of the new variable (e.g. #1 or namespace)", &|name| {
+                    if name.trim().is_empty() || name.contains(char::is_whitespace) {
+                        return Err(anyhow!("Variable names can't be empty or contain spaces"));
+                    }
+                    Ok(())
+                })?;
+            let value = prompter.input(&format!("Value of {name}"), &|_| Ok(()))?;
+            let secret = prompter.confirm("Keep it secret?", false)?;
+            store_variable(variable_manager, context, &name, &value, secret)?;
+            println!(
+                "{} Set {} in context {}",
+                "✓".green().bold(),
+                name.cyan(),
+                context.blue().bold()
+            );
+        } else if Some(selection) == delete {
+            let names: Vec<String> = variables.iter().map(|(name, ..)| name.clone()).collect();
+            let name = &names[prompter.select("Variable to delete", &names)?];
+            if prompter.confirm(&format!("Delete {name} from context {context}?"), false)? {
+                variable_manager.remove_variable(context, name)?;
+                println!(
+                    "{} Removed {} from context {}",
+                    "✓".green().bold(),
+                    name.cyan(),
+                    context.blue().bold()
+                );
+            }
+        } else {
+            return Ok(());
+        }
+    }
+}
 
-        executor.execute(&resolved_command)?;
+fn store_variable(
+    variable_manager: &mut VariableManager,
+    context: &str,
+    name: &str,
+    value: &str,
+    secret: bool,
+) -> Result<()> {
+    if secret {
+        variable_manager.set_secret(context, name, value)
+    } else {
+        variable_manager.set_variable(context, name, value)
+    }
+}
+
+/// Project config written by [`init_wizard`]
+#[derive(Debug)]
+pub struct InitResult {
+    pub name: String,
+    pub path: PathBuf,
+    /// All commands in the file, including merged ones
+    pub config: Config,
+    pub merged: bool,
+}
 
+/// Ask `prompter` for the name, commands and schema comment of a project
+/// config in `dir` and write it. If `dir` already has one, the new commands
+/// are merged into it or replace it, as chosen. `None` if cancelled.
+pub fn init_wizard(prompter: &mut dyn Prompter, dir: &Path) -> Result<Option<InitResult>> {
+    let mut merge = false;
+    if let Some(existing) = project_config_in(dir) {
+        let items = [
+            "Merge the new commands into it".to_string(),
+            "Overwrite it".to_string(),
+            "Cancel".to_string(),
+        ];
+        match prompter.select(&format!("{} already exists", existing.display()), &items)? {
+            0 => merge = true,
+            1 => {}
+            _ => return Ok(None),
+        }
+    }
+
+    let default_name = dir
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("project")
+        .to_string();
+    let name = prompter.input(&format!("Project name (empty for {default_name})"), &|_| {
         Ok(())
+    })?;
+    let name = match name.trim() {
+        "" => default_name,
+        name => name.to_string(),
+    };
+
+    let mut commands: Vec<(String, CommandEntry)> = Vec::new();
+    loop {
+        let command_name = prompter.input("Name of a command (empty when done)", &|name| {
+            if name.trim().contains(char::is_whitespace) {
+                return Err(anyhow!("Command names can't contain spaces"));
+            }
+            Ok(())
+        })?;
+        let command_name = command_name.trim().to_string();
+        if command_name.is_empty() {
+            break;
+        }
+        let template = prompter.input(
+            &format!("Template of {command_name} (e.g. cargo test #1)"),
+            &|template| {
+                if template.trim().is_empty() {
+                    return Err(anyhow!("The template can't be empty"));
+                }
+                Ok(())
+            },
+        )?;
+        let description = prompter.input(
+            &format!("Description of {command_name} (optional)"),
+            &|_| Ok(()),
+        )?;
+        let description = Some(description.trim()).filter(|description| !description.is_empty());
+        commands.retain(|(name, _)| *name != command_name);
+        commands.push((
+            command_name,
+            CommandEntry::new(template.trim(), description, &[]),
+        ));
+    }
+    let schema_comment = prompter.confirm("Add the schema comment for editor support?", true)?;
+
+    let init = ProjectInit {
+        name,
+        commands,
+        schema_comment,
+    };
+    let (path, config) = ConfigManager::write_project_config(dir, &init, merge)?;
+    Ok(Some(InitResult {
+        name: init.name,
+        path,
+        config,
+        merged: merge,
+    }))
+}
+
+/// What happens to placeholders without a value before a command runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptMode {
+    /// Ask for values on the terminal, failing without one
+    Ask,
+    /// Fail right away, since nobody can answer in a CI pipeline
+    Fail,
+    /// Leave the placeholders in the command (`--no-prompt`)
+    Skip,
+}
+
+impl PromptMode {
+    /// `Skip` with `--no-prompt`, otherwise `Fail` with `--ci` or when the
+    /// `CI` environment variable is `true` or `1`, and `Ask` if neither
+    pub fn new(no_prompt: bool, ci: bool) -> Self {
+        let ci_env = std::env::var("CI")
+            .is_ok_and(|value| matches!(value.to_lowercase().as_str(), "true" | "1"));
+        if no_prompt {
+            PromptMode::Skip
+        } else if ci || ci_env {
+            PromptMode::Fail
+        } else {
+            PromptMode::Ask
+        }
+    }
+}
+
+/// Where answers to questions about missing placeholder values come from:
+/// the terminal, or scripted answers in tests
+pub trait Prompter {
+    /// Free text, accepted once `validate` passes
+    fn input(&mut self, prompt: &str, validate: &dyn Fn(&str) -> Result<()>) -> Result<String>;
+    /// Index of one of `items`
+    fn select(&mut self, prompt: &str, items: &[String]) -> Result<usize>;
+    /// Yes or no, `default` when just confirmed
+    fn confirm(&mut self, prompt: &str, default: bool) -> Result<bool>;
+}
+
+/// [`Prompter`] asking on the terminal
+pub struct TerminalPrompter;
+
+impl Prompter for TerminalPrompter {
+    fn input(&mut self, prompt: &str, validate: &dyn Fn(&str) -> Result<()>) -> Result<String> {
+        Ok(Input::with_theme(&ColorfulTheme::default())
+            .with_prompt(prompt)
+            .validate_with(|value: &String| validate(value).map_err(|e| e.to_string()))
+            .interact_text()?)
+    }
+
+    fn select(&mut self, prompt: &str, items: &[String]) -> Result<usize> {
+        Ok(Select::with_theme(&ColorfulTheme::default())
+            .with_prompt(prompt)
+            .default(0)
+            .items(items)
+            .interact()?)
+    }
+
+    fn confirm(&mut self, prompt: &str, default: bool) -> Result<bool> {
+        Ok(Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt(prompt)
+            .default(default)
+            .interact()?)
     }
 }
 
+/// Ask for a value for every placeholder of `source` that would otherwise
+/// stay unresolved, returning the command's defaults extended with the answers.
+/// The answers for variables can be saved in `context` right away.
+///
+/// Fails with the list of missing placeholders when stdin is not a terminal
+/// or `mode` is [`PromptMode::Fail`], and asks nothing with [`PromptMode::Skip`].
+pub fn prompt_missing_placeholders(
+    variable_manager: &mut VariableManager,
+    context: &str,
+    source: &CommandSource,
+    args: &[String],
+    mode: PromptMode,
+) -> Result<BTreeMap<String, String>> {
+    if mode == PromptMode::Skip {
+        return Ok(source.defaults.clone());
+    }
+    let missing = variable_manager.missing_placeholders(
+        context,
+        Some(&source.name),
+        &source.command,
+        args,
+        &source.defaults,
+    )?;
+    if missing.is_empty() {
+        return Ok(source.defaults.clone());
+    }
+    if mode == PromptMode::Fail {
+        return Err(anyhow!(
+            "Command '{}' has placeholders without a value: {}. Pass them as arguments or set them with 'doo var'; doo doesn't ask for values in CI",
+            source.name,
+            missing.join(", ")
+        ));
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(anyhow!(
+            "Command '{}' has placeholders without a value: {}. Pass them as arguments, set them with 'doo var' or run with --no-prompt",
+            source.name,
+            missing.join(", ")
+        ));
+    }
+    ask_for_placeholders(
+        &mut TerminalPrompter,
+        variable_manager,
+        context,
+        source,
+        &missing,
+    )
+}
+
+/// Ask `prompter` for a value for each of the `missing` placeholders of
+/// `source`, then offer to save those of variables in `context`
+pub fn ask_for_placeholders(
+    prompter: &mut dyn Prompter,
+    variable_manager: &mut VariableManager,
+    context: &str,
+    source: &CommandSource,
+    missing: &[String],
+) -> Result<BTreeMap<String, String>> {
+    let mut defaults = source.defaults.clone();
+    for (i, placeholder) in missing.iter().enumerate() {
+        // Named parameters are asked for by name, with their description
+        let param = source.param_spec(placeholder);
+        let label = match param.and_then(|spec| spec.name.as_ref()) {
+            Some(name) => format!("{name} ({placeholder})"),
+            None => placeholder.clone(),
+        };
+        let mut prompt = format!(
+            "{}: value for {} ({} of {})",
+            source.name,
+            label,
+            i + 1,
+            missing.len()
+        );
+        if let Some(description) = param.and_then(|spec| spec.description.as_ref()) {
+            prompt.push_str(&format!(" - {description}"));
+        }
+        let value = match source.arg_spec(placeholder) {
+            Some(spec) if !spec.choices.is_empty() => {
+                spec.choices[prompter.select(&prompt, &spec.choices)?].clone()
+            }
+            Some(spec) => prompter.input(&prompt, &|value| {
+                spec.check(&source.name, placeholder, value)
+            })?,
+            None => prompter.input(&prompt, &|_| Ok(()))?,
+        };
+        defaults.insert(placeholder.clone(), value);
+    }
+
+    // Only `#N` and `#name` placeholders can be stored with `doo var`
+    let savable: Vec<(String, VarValue)> = missing
+        .iter()
+        .filter(|placeholder| placeholder.starts_with('#'))
+        .map(|placeholder| (placeholder.clone(), defaults[placeholder].as_str().into()))
+        .collect();
+    if !savable.is_empty() {
+        let names = savable
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let prompt = format!(
+            "Save {} as variable{} in context {}?",
+            names,
+            if savable.len() == 1 { "" } else { "s" },
+            context
+        );
+        if prompter.confirm(&prompt, false)? {
+            variable_manager.set_variables(context, savable, false, None)?;
+            println!(
+                "{} Saved {} in context {}",
+                "✓".green().bold(),
+                names,
+                context.blue().bold()
+            );
+        }
+    }
+    Ok(defaults)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -169,10 +787,338 @@ mod tests {
         let config_dir = temp_dir.path().join(".config").join("doo");
 
         let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
-        let context_manager = ContextManager::new(&config_manager).unwrap();
-        let variable_manager = VariableManager::new(&config_manager).unwrap();
+        let mut context_manager = ContextManager::new(&config_manager).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
 
-        let menu = InteractiveMenu::new(&config_manager, &variable_manager, &context_manager);
+        let menu =
+            InteractiveMenu::new(&config_manager, &mut variable_manager, &mut context_manager);
         assert!(menu.is_ok());
     }
+
+    /// Answers prompts from a script, recording the prompts
+    #[derive(Default)]
+    struct ScriptedPrompter {
+        inputs: Vec<&'static str>,
+        selections: Vec<usize>,
+        confirm: bool,
+        prompts: Vec<String>,
+    }
+
+    impl Prompter for ScriptedPrompter {
+        fn input(&mut self, prompt: &str, validate: &dyn Fn(&str) -> Result<()>) -> Result<String> {
+            self.prompts.push(prompt.to_string());
+            let value = self.inputs.remove(0);
+            validate(value)?;
+            Ok(value.to_string())
+        }
+
+        fn select(&mut self, prompt: &str, _items: &[String]) -> Result<usize> {
+            self.prompts.push(prompt.to_string());
+            Ok(self.selections.remove(0))
+        }
+
+        fn confirm(&mut self, prompt: &str, _default: bool) -> Result<bool> {
+            self.prompts.push(prompt.to_string());
+            Ok(self.confirm)
+        }
+    }
+
+    fn watch_command(config_dir: &std::path::Path) -> (ConfigManager, CommandSource) {
+        std::fs::create_dir_all(config_dir).unwrap();
+        std::fs::write(
+            config_dir.join("config.yaml"),
+            r##"commands:
+  watch:
+    command: "watch kubectl -n #1 get #kind $1"
+    params:
+      "#1": { name: namespace, description: Kubernetes namespace }
+    args:
+      kind:
+        choices: [pods, deployments]
+"##,
+        )
+        .unwrap();
+        let config_manager = ConfigManager::new_with_dir(config_dir.to_path_buf()).unwrap();
+        let source = config_manager
+            .get_command_with_source("watch")
+            .unwrap()
+            .unwrap();
+        (config_manager, source)
+    }
+
+    #[test]
+    fn test_prompted_values_resolve_the_command() {
+        let temp_dir = TempDir::new().unwrap();
+        let (config_manager, source) = watch_command(&temp_dir.path().join("doo"));
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+
+        let missing = variable_manager
+            .missing_placeholders(
+                "default",
+                Some("watch"),
+                &source.command,
+                &[],
+                &source.defaults,
+            )
+            .unwrap();
+        assert_eq!(missing, vec!["#1", "#kind", "$1"]);
+        let mut prompter = ScriptedPrompter {
+            inputs: vec!["web", "-w"],
+            selections: vec![1],
+            ..Default::default()
+        };
+        let defaults = ask_for_placeholders(
+            &mut prompter,
+            &mut variable_manager,
+            "default",
+            &source,
+            &missing,
+        )
+        .unwrap();
+        assert_eq!(
+            prompter.prompts,
+            vec![
+                "watch: value for namespace (#1) (1 of 3) - Kubernetes namespace",
+                "watch: value for #kind (2 of 3)",
+                "watch: value for $1 (3 of 3)",
+                "Save #1, #kind as variables in context default?",
+            ]
+        );
+
+        let resolved = variable_manager
+            .resolve_variables_with_defaults(
+                "default",
+                Some("watch"),
+                &source.command,
+                &[],
+                &defaults,
+            )
+            .unwrap();
+        assert_eq!(resolved, "watch kubectl -n web get deployments -w");
+        // Declined, so nothing was saved
+        assert!(variable_manager
+            .list_variables("default")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_prompted_values_can_be_saved() {
+        let temp_dir = TempDir::new().unwrap();
+        let (config_manager, source) = watch_command(&temp_dir.path().join("doo"));
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+
+        let mut prompter = ScriptedPrompter {
+            inputs: vec!["web"],
+            confirm: true,
+            ..Default::default()
+        };
+        let missing = vec!["#1".to_string()];
+        ask_for_placeholders(
+            &mut prompter,
+            &mut variable_manager,
+            "default",
+            &source,
+            &missing,
+        )
+        .unwrap();
+        let variables = variable_manager.list_variables("default").unwrap();
+        assert_eq!(variables["#1"], "web");
+
+        // Placeholders with choices are picked from them
+        let mut prompter = ScriptedPrompter {
+            selections: vec![0],
+            ..Default::default()
+        };
+        let kind = vec!["#kind".to_string()];
+        let defaults = ask_for_placeholders(
+            &mut prompter,
+            &mut variable_manager,
+            "default",
+            &source,
+            &kind,
+        )
+        .unwrap();
+        assert_eq!(defaults["#kind"], "pods");
+    }
+
+    #[test]
+    fn test_select_context() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+        let mut context_manager = ContextManager::new(&config_manager).unwrap();
+        context_manager
+            .switch_context("staging", Some("default"))
+            .unwrap();
+        context_manager.switch_context("other", None).unwrap();
+
+        // Items: default, staging (indented), other (active), back
+        let mut prompter = ScriptedPrompter {
+            selections: vec![1],
+            ..Default::default()
+        };
+        let switched = select_context(&mut prompter, &mut context_manager).unwrap();
+        assert_eq!(switched.as_deref(), Some("staging"));
+        assert_eq!(context_manager.current_context(), "staging");
+        assert_eq!(prompter.prompts, vec!["Switch to context"]);
+        // The switch is persisted like with 'doo context staging'
+        let reloaded = ContextManager::new(&config_manager).unwrap();
+        assert_eq!(reloaded.current_context(), "staging");
+
+        // Picking the active context or going back changes nothing
+        for selection in [1, 3] {
+            let mut prompter = ScriptedPrompter {
+                selections: vec![selection],
+                ..Default::default()
+            };
+            assert_eq!(
+                select_context(&mut prompter, &mut context_manager).unwrap(),
+                None
+            );
+            assert_eq!(context_manager.current_context(), "staging");
+        }
+    }
+
+    #[test]
+    fn test_edit_variables() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+        variable_manager
+            .set_variable("staging", "ns", "shop")
+            .unwrap();
+        variable_manager
+            .set_list("staging", "hosts", vec!["a".to_string()], false)
+            .unwrap();
+
+        // Items: #hosts, #ns, add, delete, back; then #token before add
+        let mut prompter = ScriptedPrompter {
+            inputs: vec!["web", "a, b", "token", "s3cr3t"],
+            selections: vec![1, 0, 2, 5],
+            confirm: true,
+            ..Default::default()
+        };
+        edit_variables(&mut prompter, &mut variable_manager, "staging").unwrap();
+        assert_eq!(
+            variable_manager
+                .get_variable("staging", "ns")
+                .unwrap()
+                .as_deref(),
+            Some("web")
+        );
+        assert_eq!(
+            variable_manager.list_variables("staging").unwrap()["#hosts"],
+            VarValue::List(vec!["a".to_string(), "b".to_string()])
+        );
+        assert!(variable_manager
+            .list_secrets("staging")
+            .unwrap()
+            .contains_key("#token"));
+        assert_eq!(prompter.prompts[0], "Variables of context staging");
+        assert!(variables_summary(&variable_manager, "staging")
+            .unwrap()
+            .contains(&format!("={SECRET_MASK}")));
+
+        // Items: #hosts, #ns, #token, add, delete, back
+        let mut prompter = ScriptedPrompter {
+            selections: vec![4, 1, 5],
+            confirm: true,
+            ..Default::default()
+        };
+        edit_variables(&mut prompter, &mut variable_manager, "staging").unwrap();
+        assert_eq!(
+            variable_manager.get_variable("staging", "ns").unwrap(),
+            None
+        );
+        assert!(prompter
+            .prompts
+            .contains(&"Delete #ns from context staging?".to_string()));
+    }
+
+    #[test]
+    fn test_prompt_modes() {
+        let temp_dir = TempDir::new().unwrap();
+        let (config_manager, source) = watch_command(&temp_dir.path().join("doo"));
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+
+        let error = prompt_missing_placeholders(
+            &mut variable_manager,
+            "default",
+            &source,
+            &[],
+            PromptMode::Fail,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Command 'watch' has placeholders without a value: #1, #kind, $1. Pass them as arguments or set them with 'doo var'; doo doesn't ask for values in CI"
+        );
+        let defaults = prompt_missing_placeholders(
+            &mut variable_manager,
+            "default",
+            &source,
+            &[],
+            PromptMode::Skip,
+        )
+        .unwrap();
+        assert_eq!(defaults, source.defaults);
+
+        assert_eq!(PromptMode::new(true, true), PromptMode::Skip);
+        assert_eq!(PromptMode::new(false, true), PromptMode::Fail);
+    }
+
+    #[test]
+    fn test_init_wizard() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut prompter = ScriptedPrompter {
+            inputs: vec!["", "test", "cargo test #1", "Run the tests", ""],
+            confirm: true,
+            ..Default::default()
+        };
+        let result = init_wizard(&mut prompter, temp_dir.path())
+            .unwrap()
+            .unwrap();
+        let dir_name = temp_dir.path().file_name().unwrap().to_str().unwrap();
+        assert_eq!(result.name, dir_name);
+        assert_eq!(result.path, temp_dir.path().join(".doo.yaml"));
+        assert!(!result.merged);
+        let test = &result.config.commands["test"];
+        assert_eq!(test.command_str(), "cargo test #1");
+        assert_eq!(test.description(), Some("Run the tests"));
+        let content = std::fs::read_to_string(&result.path).unwrap();
+        assert!(content.contains(&format!("# Commands of the {dir_name} project.")));
+        assert!(content.contains("yaml-language-server"));
+
+        // A second run asks what to do with the existing file
+        let mut prompter = ScriptedPrompter {
+            inputs: vec!["shop", "build", "cargo build", "", ""],
+            selections: vec![0],
+            ..Default::default()
+        };
+        let result = init_wizard(&mut prompter, temp_dir.path())
+            .unwrap()
+            .unwrap();
+        assert!(result.merged);
+        assert_eq!(result.name, "shop");
+        assert!(result.config.commands.contains_key("test"));
+        assert_eq!(result.config.commands["build"].description(), None);
+
+        let mut prompter = ScriptedPrompter {
+            selections: vec![2],
+            ..Default::default()
+        };
+        assert!(init_wizard(&mut prompter, temp_dir.path())
+            .unwrap()
+            .is_none());
+
+        let mut prompter = ScriptedPrompter {
+            inputs: vec!["", "my test"],
+            selections: vec![1],
+            ..Default::default()
+        };
+        let error = init_wizard(&mut prompter, temp_dir.path()).unwrap_err();
+        assert_eq!(error.to_string(), "Command names can't contain spaces");
+    }
 }
@@ -0,0 +1,167 @@
+//! Secret variables in the OS keychain
+//!
+//! With `secret_store: keyring` in the main config, secret values go to the
+//! macOS Keychain, the Secret Service on Linux or the Windows Credential
+//! Manager, and the secrets file only holds a reference such as
+//! `keyring:staging/token`. References are resolved whenever a value is
+//! needed, whatever the configured store, so switching back to files keeps
+//! existing secrets working.
+//!
+//! Talking to the keychain needs the `keyring` cargo feature. Without it,
+//! [`SystemKeychain`] fails with a message explaining how to get it.
+
+use anyhow::Result;
+
+use crate::variables::VarValue;
+
+/// Prefix of values in secrets files that refer to a keychain entry
+pub const REFERENCE_PREFIX: &str = "keyring:";
+
+/// Service name doo's keychain entries are stored under
+#[cfg(feature = "keyring")]
+const SERVICE: &str = "doo";
+
+/// Storage for secret values, keyed by account (`<context>/<name>`)
+pub trait Keychain: Send + Sync {
+    /// The secret stored for `account`, `None` if there is none
+    fn get(&self, account: &str) -> Result<Option<String>>;
+    fn set(&self, account: &str, secret: &str) -> Result<()>;
+    /// Remove the entry of `account`; a missing entry is not an error
+    fn delete(&self, account: &str) -> Result<()>;
+}
+
+/// The keychain of the operating system
+#[derive(Debug, Default)]
+pub struct SystemKeychain;
+
+#[cfg(feature = "keyring")]
+impl SystemKeychain {
+    fn entry(account: &str) -> Result<keyring::Entry> {
+        keyring::Entry::new(SERVICE, account).map_err(keyring_error)
+    }
+}
+
+#[cfg(feature = "keyring")]
+impl Keychain for SystemKeychain {
+    fn get(&self, account: &str) -> Result<Option<String>> {
+        match Self::entry(account)?.get_password() {
+            Ok(secret) => Ok(Some(secret)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(keyring_error(e)),
+        }
+    }
+
+    fn set(&self, account: &str, secret: &str) -> Result<()> {
+        Self::entry(account)?
+            .set_password(secret)
+            .map_err(keyring_error)
+    }
+
+    fn delete(&self, account: &str) -> Result<()> {
+        match Self::entry(account)?.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(keyring_error(e)),
+        }
+    }
+}
+
+/// Turn the errors users can do something about into instructions
+#[cfg(feature = "keyring")]
+fn keyring_error(error: keyring::Error) -> anyhow::Error {
+    match error {
+        keyring::Error::NoStorageAccess(_) => anyhow::anyhow!(
+            "access to the system keychain was denied ({error}); unlock it or allow doo to use it and try again"
+        ),
+        keyring::Error::PlatformFailure(_) => anyhow::anyhow!(
+            "the system keychain isn't available ({error}); on Linux a Secret Service such as GNOME Keyring or KWallet has to be running"
+        ),
+        error => anyhow::anyhow!("system keychain error: {error}"),
+    }
+}
+
+#[cfg(not(feature = "keyring"))]
+impl Keychain for SystemKeychain {
+    fn get(&self, _account: &str) -> Result<Option<String>> {
+        Err(unsupported())
+    }
+
+    fn set(&self, _account: &str, _secret: &str) -> Result<()> {
+        Err(unsupported())
+    }
+
+    fn delete(&self, _account: &str) -> Result<()> {
+        Err(unsupported())
+    }
+}
+
+#[cfg(not(feature = "keyring"))]
+fn unsupported() -> anyhow::Error {
+    anyhow::anyhow!(
+        "this build of doo can't use the system keychain; reinstall it with `cargo install doo --features keyring`"
+    )
+}
+
+/// Account of the secret `key` (`#token`) of `context`, scoped to `command`
+/// if given: `staging/token` or `staging/deploy/token`
+pub fn account(context: &str, command: Option<&str>, key: &str) -> String {
+    let name = key.trim_start_matches('#');
+    match command {
+        Some(command) => format!("{context}/{command}/{name}"),
+        None => format!("{context}/{name}"),
+    }
+}
+
+/// Value stored in the secrets file in place of the secret of `account`
+pub fn reference(account: &str) -> VarValue {
+    VarValue::Single(format!("{REFERENCE_PREFIX}{account}"))
+}
+
+/// Account `value` refers to, if it is a keychain reference
+pub fn referenced_account(value: &VarValue) -> Option<&str> {
+    match value {
+        VarValue::Single(value) => value.strip_prefix(REFERENCE_PREFIX),
+        VarValue::List(_) => None,
+    }
+}
+
+/// Keychain kept in memory, for tests
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct MemoryKeychain {
+    pub entries: std::sync::Arc<std::sync::Mutex<std::collections::BTreeMap<String, String>>>,
+}
+
+#[cfg(test)]
+impl Keychain for MemoryKeychain {
+    fn get(&self, account: &str) -> Result<Option<String>> {
+        Ok(self.entries.lock().unwrap().get(account).cloned())
+    }
+
+    fn set(&self, account: &str, secret: &str) -> Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert(account.to_string(), secret.to_string());
+        Ok(())
+    }
+
+    fn delete(&self, account: &str) -> Result<()> {
+        self.entries.lock().unwrap().remove(account);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_references() {
+        assert_eq!(account("staging", None, "#token"), "staging/token");
+        assert_eq!(account("staging", Some("deploy"), "#1"), "staging/deploy/1");
+        let value = reference("staging/token");
+        assert_eq!(value, VarValue::from("keyring:staging/token"));
+        assert_eq!(referenced_account(&value), Some("staging/token"));
+        assert_eq!(referenced_account(&VarValue::from("s3cr3t")), None);
+        let list = VarValue::List(vec!["keyring:staging/token".to_string()]);
+        assert_eq!(referenced_account(&list), None);
+    }
+}
@@ -27,10 +27,21 @@
 //! # }
 //! ```
 
+pub mod completions;
 pub mod config;
 pub mod context;
+pub mod doctor;
+pub mod encryption;
 pub mod executor;
+pub mod history;
 pub mod interactive;
+pub mod keychain;
+pub mod lock;
+pub mod providers;
+pub mod suggest;
+pub mod telemetry;
+pub mod template;
+pub mod util;
 pub mod variables;
 
 pub use config::{Config, ConfigManager};
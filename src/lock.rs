@@ -0,0 +1,90 @@
+//! Advisory locking of the config directory
+//!
+//! Every read-modify-write of files in the config directory holds a
+//! [`ConfigLock`], so concurrent doo processes can't lose each other's updates.
+
+use anyhow::{anyhow, Context, Result};
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const LOCK_FILE: &str = ".lock";
+
+/// How long to wait for another doo process before giving up
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Exclusive lock on a config directory, released on drop
+#[derive(Debug)]
+pub struct ConfigLock {
+    file: File,
+}
+
+impl ConfigLock {
+    /// Lock the config directory, waiting a few seconds for other processes
+    pub fn acquire(config_dir: &Path) -> Result<Self> {
+        Self::acquire_with_timeout(config_dir, LOCK_TIMEOUT)
+    }
+
+    pub fn acquire_with_timeout(config_dir: &Path, timeout: Duration) -> Result<Self> {
+        fs::create_dir_all(config_dir).context("Failed to create config directory")?;
+        let lock_path = lock_path(config_dir);
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)
+            .with_context(|| format!("Failed to open lock file: {lock_path:?}"))?;
+
+        let started = Instant::now();
+        loop {
+            match file.try_lock() {
+                Ok(()) => return Ok(Self { file }),
+                Err(TryLockError::WouldBlock) if started.elapsed() < timeout => {
+                    thread::sleep(LOCK_RETRY_INTERVAL);
+                }
+                Err(TryLockError::WouldBlock) => {
+                    return Err(anyhow!(
+                        "Another doo process holds the lock on {}. Try again once it has finished",
+                        lock_path.display()
+                    ));
+                }
+                Err(TryLockError::Error(e)) => {
+                    return Err(e).with_context(|| format!("Failed to lock {lock_path:?}"));
+                }
+            }
+        }
+    }
+}
+
+impl Drop for ConfigLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+fn lock_path(config_dir: &Path) -> PathBuf {
+    config_dir.join(LOCK_FILE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_lock_is_exclusive_until_dropped() {
+        let temp_dir = TempDir::new().unwrap();
+
+        let lock = ConfigLock::acquire(temp_dir.path()).unwrap();
+        let err = ConfigLock::acquire_with_timeout(temp_dir.path(), Duration::from_millis(50))
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Another doo process holds the lock"));
+
+        drop(lock);
+        assert!(ConfigLock::acquire_with_timeout(temp_dir.path(), Duration::ZERO).is_ok());
+    }
+}
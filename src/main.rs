@@ -1,43 +1,287 @@
-use anyhow::Result;
-use clap::{Arg, ArgMatches, Command};
+use anyhow::{Context, Result};
+use chrono::Utc;
+use clap::{Arg, ArgAction, ArgMatches, Command};
 use colored::*;
-use std::process;
+use dialoguer::Confirm;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::process::{self, ExitStatus};
+use std::time::Duration;
 
+mod completions;
 mod config;
 mod context;
+mod doctor;
+mod encryption;
 mod executor;
+mod history;
 mod interactive;
+mod keychain;
+mod lock;
+mod providers;
+mod suggest;
+mod telemetry;
+mod template;
+mod util;
 mod variables;
 
-use config::ConfigManager;
-use context::ContextManager;
-use executor::CommandExecutor;
-use interactive::InteractiveMenu;
-use variables::VariableManager;
+use config::{
+    is_url, CommandSource, ConfigFormat, ConfigManager, ConfigSource, MergeOutcome, SecretStore,
+};
+use context::{ContextManager, ContextNode};
+use doctor::{CheckStatus, DoctorResult};
+use executor::{exit_code, retry, CommandExecutor, ExecutionResult, ParallelCommand};
+use history::{HistoryManager, Recording};
+use interactive::{prompt_missing_placeholders, InteractiveMenu, PromptMode, TerminalPrompter};
+use variables::{
+    parse_ttl, variable_key, HistoryEntry, ListedVariable, PlaceholderStatus, ShellFormat,
+    ValueSource, VarValue, VariableListing, VariableManager, SECRET_MASK,
+};
 
 #[tokio::main]
 async fn main() {
-    if let Err(e) = run().await {
-        eprintln!("{} {}", "Error:".red().bold(), e);
-        process::exit(1);
+    match run().await {
+        Ok(0) => {}
+        // Forward the exit code of the wrapped command
+        Ok(code) => process::exit(code),
+        // Already reported where it happened
+        Err(e) if e.is::<Exit>() => process::exit(e.downcast::<Exit>().map_or(1, |exit| exit.0)),
+        Err(e) => {
+            eprintln!("{} {}", "Error:".red().bold(), e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Error for failures that were already reported to the user; `main` exits
+/// with its code without printing anything else. Returned instead of calling
+/// [`process::exit`] so the `--timings` summary is still printed.
+#[derive(Debug)]
+struct Exit(i32);
+
+impl std::fmt::Display for Exit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "exit code {}", self.0)
     }
 }
 
-async fn run() -> Result<()> {
+impl std::error::Error for Exit {}
+
+async fn run() -> Result<i32> {
     let matches = build_cli().get_matches();
 
+    #[cfg(feature = "telemetry")]
+    let timings = matches
+        .get_flag("timings")
+        .then(telemetry::Timings::install);
+
+    let result = dispatch(&matches).await;
+
+    #[cfg(feature = "telemetry")]
+    if let Some(timings) = timings {
+        timings.print_summary();
+    }
+
+    result
+}
+
+/// Load `./.env` into the variable manager, warning about malformed lines.
+/// A missing file is only reported when it was asked for explicitly.
+fn load_dotenv(variable_manager: &mut VariableManager, explicit: bool) -> Result<()> {
+    let path = Path::new(".env");
+    if !path.is_file() {
+        if explicit {
+            eprintln!("{} No .env file in the current directory", "⚠".yellow());
+        }
+        return Ok(());
+    }
+    let skipped = variable_manager.load_dotenv(path)?;
+    if !skipped.is_empty() {
+        let lines: Vec<String> = skipped.iter().map(usize::to_string).collect();
+        eprintln!(
+            "{} Skipped malformed lines in .env: {}",
+            "⚠".yellow(),
+            lines.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Create a project config: with the wizard in a terminal, otherwise with
+/// example commands
+fn handle_init_command() -> Result<()> {
+    let dir = std::env::current_dir()?;
+    if !std::io::stdin().is_terminal() {
+        ConfigManager::init_project_config(&dir)?;
+        println!(
+            "{} Created {} with example commands",
+            "✓".green().bold(),
+            dir.join(".doo.yaml").display().to_string().cyan()
+        );
+        println!(
+            "  They take precedence over your other configs here; try {}",
+            "doo hello".cyan()
+        );
+        return Ok(());
+    }
+
+    let Some(result) = interactive::init_wizard(&mut TerminalPrompter, &dir)? else {
+        println!("{} Left the project config alone", "✗".red().bold());
+        return Ok(());
+    };
+    println!(
+        "{} {} {} for project {}",
+        "✓".green().bold(),
+        if result.merged { "Updated" } else { "Wrote" },
+        result.path.display().to_string().cyan(),
+        result.name.bold()
+    );
+    let mut commands: Vec<_> = result.config.commands.iter().collect();
+    commands.sort_by_key(|(name, _)| *name);
+    let width = commands
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    for (name, entry) in &commands {
+        println!(
+            "  {}  {}",
+            format!("{name:<width$}").cyan(),
+            entry.command_str()
+        );
+    }
+    match commands.first() {
+        Some((name, _)) => println!(
+            "  In this directory and below, they take precedence over your other configs; try {}",
+            format!("doo {name}").cyan()
+        ),
+        None => println!(
+            "  No commands yet; add them to the file or run {} again",
+            "doo init".cyan()
+        ),
+    }
+    Ok(())
+}
+
+/// Point out that the config `config_name` is stored as JSON and how to
+/// convert it
+fn warn_json_config(config_name: &str) {
+    eprintln!(
+        "{} Config '{}' is JSON, which is deprecated; convert it with {}",
+        "⚠".yellow(),
+        config_name,
+        format!("doo config convert {config_name} --format yaml").cyan()
+    );
+}
+
+/// Run the requested subcommand, returning the exit code for doo
+async fn dispatch(matches: &ArgMatches) -> Result<i32> {
+    // Runs before the configs are loaded, so it can report broken ones
+    if let Some(("doctor", _)) = matches.subcommand() {
+        return handle_doctor_command(OutputFormat::from_matches(matches)).await;
+    }
+    // Loading would fail on a broken project config that init can replace
+    if let Some(("init", _)) = matches.subcommand() {
+        handle_init_command()?;
+        return Ok(0);
+    }
+
     // Initialize managers
     let mut config_manager = ConfigManager::new()?;
+    let quiet = match matches.subcommand() {
+        Some(("config", sub_matches)) => matches!(sub_matches.subcommand(), Some(("convert", _))),
+        Some((name, _)) => name == "completions" || name == completions::COMPLETE_COMMANDS,
+        None => false,
+    };
+    if !quiet {
+        for config_name in config_manager.json_configs() {
+            warn_json_config(&config_name);
+        }
+    }
     let mut context_manager = ContextManager::new(&config_manager)?;
+    if let Some(context) = matches.get_one::<String>("context") {
+        context_manager.override_context(context)?;
+    }
     let mut variable_manager = VariableManager::new(&config_manager)?;
-    let executor = CommandExecutor::new();
+    variable_manager.set_strict_env(matches.get_flag("strict-env"));
+    if matches.get_flag("dotenv") || config_manager.load_dotenv() {
+        load_dotenv(&mut variable_manager, matches.get_flag("dotenv"))?;
+    }
+    let mut executor = CommandExecutor::new();
+    executor.set_dry_run(matches.get_flag("dry-run"));
+    let format = OutputFormat::from_matches(matches);
 
     match matches.subcommand() {
         Some(("var", sub_matches)) => {
-            handle_variable_command(sub_matches, &mut variable_manager, &context_manager)?;
+            if let Some(("diff", diff_matches)) = sub_matches.subcommand() {
+                return handle_variable_diff_command(
+                    diff_matches,
+                    &variable_manager,
+                    &context_manager,
+                    format.or_from(diff_matches),
+                );
+            }
+            handle_variable_command(
+                sub_matches,
+                &config_manager,
+                &mut variable_manager,
+                &context_manager,
+                format,
+            )?;
         }
         Some(("context", sub_matches)) => {
-            handle_context_command(sub_matches, &mut context_manager)?;
+            if let Some(("show", show_matches)) = sub_matches.subcommand() {
+                handle_context_show_command(
+                    &config_manager,
+                    &variable_manager,
+                    &context_manager,
+                    format.or_from(show_matches),
+                )?;
+                return Ok(0);
+            }
+            handle_context_command(
+                sub_matches,
+                &mut context_manager,
+                &mut variable_manager,
+                format,
+            )?;
+        }
+        Some(("search", sub_matches)) => {
+            handle_search_command(sub_matches, &config_manager, format)?;
+        }
+        Some(("history", sub_matches)) => {
+            handle_history_command(sub_matches, &config_manager, format)?;
+        }
+        Some(("status", _)) => {
+            handle_status_command(&config_manager, &variable_manager, &context_manager)?;
+        }
+        Some(("eval", sub_matches)) => {
+            handle_eval_command(sub_matches, &variable_manager, &context_manager)?;
+        }
+        Some(("which", sub_matches)) => {
+            handle_which_command(
+                sub_matches,
+                &config_manager,
+                &variable_manager,
+                &context_manager,
+                format.or_from(sub_matches),
+            )?;
+        }
+        Some(("where", sub_matches)) => {
+            handle_where_command(sub_matches, &config_manager, format.or_from(sub_matches))?;
+        }
+        Some(("command", sub_matches)) => {
+            handle_command_command(sub_matches, &mut config_manager, format)?;
+        }
+        Some(("config", sub_matches)) => {
+            handle_config_command(
+                sub_matches,
+                &mut config_manager,
+                &variable_manager,
+                &context_manager,
+            )?;
         }
         Some(("import", sub_matches)) => {
             handle_import_command(sub_matches, &mut config_manager).await?;
@@ -45,125 +289,3218 @@ async fn run() -> Result<()> {
         Some(("import-repo", sub_matches)) => {
             handle_import_repo_command(sub_matches, &mut config_manager).await?;
         }
-        Some(("sync", _)) => {
-            handle_sync_command(&mut config_manager).await?;
-        }
-        Some((cmd_name, _)) => {
-            // For external subcommands, collect all trailing arguments
-            let raw_args = std::env::args().collect::<Vec<_>>();
-            let mut args = Vec::new();
-
-            // Find the position after the command name and collect all following args
-            if let Some(cmd_pos) = raw_args.iter().position(|arg| arg == cmd_name) {
-                args = raw_args.into_iter().skip(cmd_pos + 1).collect();
+        Some(("sync", sub_matches)) => {
+            if matches.get_flag("dry-run") || sub_matches.get_flag("dry-run") {
+                let config_name = sub_matches.get_one::<String>("config");
+                return handle_sync_preview_command(config_name, &mut config_manager).await;
             }
+            match sub_matches.get_one::<String>("config") {
+                Some(config_name) => {
+                    handle_sync_single_command(config_name, &mut config_manager).await?;
+                }
+                None => handle_sync_command(&mut config_manager).await?,
+            }
+        }
+        Some(("run-all", sub_matches)) => {
+            return handle_run_all_command(
+                sub_matches,
+                PromptMode::new(matches.get_flag("no-prompt"), matches.get_flag("ci")),
+                &config_manager,
+                &mut variable_manager,
+                &context_manager,
+                &executor,
+                format.or_from(sub_matches),
+            )
+            .await;
+        }
+        Some(("backup", sub_matches)) => {
+            handle_backup_command(sub_matches, &config_manager)?;
+        }
+        Some(("restore", sub_matches)) => {
+            handle_restore_command(sub_matches, &config_manager)?;
+        }
+        Some(("completions", sub_matches)) => {
+            let shell = sub_matches.get_one::<String>("shell").unwrap();
+            completions::write_completions(shell, &mut build_cli(), &mut std::io::stdout())?;
+        }
+        Some((completions::COMPLETE_COMMANDS, _)) => {
+            handle_complete_commands(&config_manager);
+        }
+        Some((cmd_name, sub_matches)) => {
+            // For external subcommands, clap collects all trailing arguments
+            let args = sub_matches
+                .get_many::<OsString>("")
+                .into_iter()
+                .flatten()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect();
 
-            handle_command_execution(
+            let status = handle_command_execution(
                 cmd_name,
                 args,
+                PromptMode::new(matches.get_flag("no-prompt"), matches.get_flag("ci")),
                 &mut config_manager,
-                &variable_manager,
+                &mut variable_manager,
                 &context_manager,
                 &executor,
-            )?;
+            )
+            .await?;
+            return Ok(exit_code(status));
+        }
+        None => {
+            // No subcommand provided, show interactive menu
+            let mut menu =
+                InteractiveMenu::new(&config_manager, &mut variable_manager, &mut context_manager)?;
+            if let Some(status) = menu.run(&executor).await? {
+                return Ok(exit_code(status));
+            }
+        }
+    }
+
+    Ok(0)
+}
+
+/// `--token` of the GitHub import subcommands
+fn token_arg() -> Arg {
+    Arg::new("token")
+        .help("GitHub personal access token for a private repository (not stored)")
+        .long("token")
+        .value_name("PAT")
+}
+
+fn build_cli() -> Command {
+    let cli = Command::new("doo")
+        .about("A CLI wrapper for other commands with persistent variables and contexts")
+        .version("0.1.0")
+        .author("Your Name")
+        .arg_required_else_help(false)
+        .subcommand(
+            Command::new("var")
+                .about("Manage variables")
+                .args_conflicts_with_subcommands(true)
+                .subcommand_negates_reqs(true)
+                .arg(
+                    Arg::new("name")
+                        .help("Variable name (e.g., #1 or namespace)")
+                        .required_unless_present("from-pairs")
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("value")
+                        .help("Variable value, or several to store a list that expands to one argument each. To set several variables, alternate names and values: #1 prod #2 api")
+                        .required_unless_present("from-pairs")
+                        .num_args(1..)
+                        .index(2),
+                )
+                .arg(
+                    Arg::new("from-pairs")
+                        .help("Set several variables at once from comma-separated name=value pairs")
+                        .long("from-pairs")
+                        .value_name("PAIRS")
+                        .conflicts_with_all(["name", "command"]),
+                )
+                .arg(
+                    Arg::new("secret")
+                        .help("Store the value as a secret that is masked in output")
+                        .long("secret")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("ttl")
+                        .help("Treat the variable as unset after this long (e.g. 30m, 2h, 1d)")
+                        .long("ttl")
+                        .value_name("DURATION"),
+                )
+                .arg(
+                    Arg::new("command")
+                        .help("Only use the value when running this command")
+                        .long("command")
+                        .value_name("COMMAND")
+                        .conflicts_with("ttl"),
+                )
+                .arg(
+                    Arg::new("desc")
+                        .help("Describe what the variable is for; shown by 'doo var list'")
+                        .long("desc")
+                        .value_name("TEXT")
+                        .conflicts_with_all(["from-pairs", "command"]),
+                )
+                .arg(
+                    Arg::new("quiet")
+                        .help("Don't warn when no command refers to the variable")
+                        .short('q')
+                        .long("quiet")
+                        .action(ArgAction::SetTrue),
+                )
+                .subcommand(
+                    Command::new("list")
+                        .about("List the variables of a context and the commands using them")
+                        .arg(
+                            Arg::new("context")
+                                .help("Context to list (default: current context)")
+                                .long("context")
+                                .value_name("NAME"),
+                        )
+                        .arg(
+                            Arg::new("reveal")
+                                .help("Show the values of secret variables")
+                                .long("reveal")
+                                .action(ArgAction::SetTrue),
+                        )
+                        .arg(output_arg()),
+                )
+                .subcommand(
+                    Command::new("get")
+                        .about("Print the bare value of a variable, e.g. for NS=$(doo var get #1)")
+                        .arg(
+                            Arg::new("name")
+                                .help("Variable name (e.g., #1 or namespace)")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("context")
+                                .help("Context to read from (default: current context)")
+                                .long("context")
+                                .value_name("NAME"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("remove")
+                        .about("Remove a variable")
+                        .arg(
+                            Arg::new("name")
+                                .help("Variable name (e.g., #1 or namespace)")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("command")
+                                .help("Remove the value that only applies to this command")
+                                .long("command")
+                                .value_name("COMMAND"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("history")
+                        .about("List the previous values of a variable, most recent first")
+                        .arg(
+                            Arg::new("name")
+                                .help("Variable name (e.g., #1 or namespace)")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("reveal")
+                                .help("Show previous values of secret variables")
+                                .long("reveal")
+                                .action(ArgAction::SetTrue),
+                        ),
+                )
+                .subcommand(
+                    Command::new("undo")
+                        .about("Restore the previous value of a variable")
+                        .arg(
+                            Arg::new("name")
+                                .help("Variable name (e.g., #1 or namespace)")
+                                .required(true)
+                                .index(1),
+                        ),
+                )
+                .subcommand(
+                    Command::new("export")
+                        .about(
+                            "Write the variables of a context to a YAML or JSON file, or print them as shell export statements",
+                        )
+                        .arg(
+                            Arg::new("file")
+                                .help("Output file (.json for JSON, YAML otherwise); without it, print statements for eval \"$(doo var export)\"")
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("format")
+                                .help("Shell syntax of the printed statements")
+                                .long("format")
+                                .value_name("SHELL")
+                                .value_parser(ShellFormat::NAMES.to_vec())
+                                .default_value("sh")
+                                .conflicts_with("file"),
+                        )
+                        .arg(
+                            Arg::new("context")
+                                .help("Context to export (default: current context)")
+                                .long("context")
+                                .value_name("NAME"),
+                        )
+                        .arg(
+                            Arg::new("include-secrets")
+                                .help("Also export secret variables")
+                                .long("include-secrets")
+                                .action(ArgAction::SetTrue),
+                        ),
+                )
+                .subcommand(
+                    Command::new("import")
+                        .about("Merge variables from a file written by 'doo var export' or a .env file")
+                        .arg(
+                            Arg::new("file")
+                                .help("File to import (.json for JSON, .env or *.env for dotenv, YAML otherwise)")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("context")
+                                .help("Context to import into (default: current context)")
+                                .long("context")
+                                .value_name("NAME"),
+                        )
+                        .arg(
+                            Arg::new("replace")
+                                .help("Remove all variables of the context before importing")
+                                .long("replace")
+                                .action(ArgAction::SetTrue),
+                        ),
+                )
+                .subcommand(
+                    Command::new("copy")
+                        .about("Copy variables from one context to another")
+                        .arg(
+                            Arg::new("from")
+                                .help("Context to copy from")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("to")
+                                .help("Context to copy to")
+                                .required(true)
+                                .index(2),
+                        )
+                        .arg(
+                            Arg::new("only")
+                                .help("Only copy this variable (repeatable)")
+                                .long("only")
+                                .value_name("NAME")
+                                .action(ArgAction::Append),
+                        )
+                        .arg(
+                            Arg::new("force")
+                                .help("Overwrite variables that are already set without asking")
+                                .long("force")
+                                .action(ArgAction::SetTrue),
+                        ),
+                )
+                .subcommand(
+                    Command::new("diff")
+                        .about("Compare the variables of two contexts; exits with 1 if they differ")
+                        .arg(
+                            Arg::new("a")
+                                .help("First context")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("b")
+                                .help("Second context")
+                                .required(true)
+                                .index(2),
+                        )
+                        .arg(output_arg()),
+                )
+                .subcommand(
+                    Command::new("migrate-secrets")
+                        .about("Move secrets from the secrets files to the system keychain")
+                        .arg(
+                            Arg::new("context")
+                                .help("Only migrate this context (default: all contexts)")
+                                .long("context")
+                                .value_name("NAME"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("context")
+                .about("Switch, list, show, clone, rename, delete or encrypt contexts")
+                .args_conflicts_with_subcommands(true)
+                .subcommand_negates_reqs(true)
+                .arg(
+                    Arg::new("name")
+                        .help("Context name")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("parent")
+                        .help("Inherit variables that aren't set in this context from PARENT")
+                        .long("parent")
+                        .value_name("PARENT"),
+                )
+                .arg(
+                    Arg::new("no-hooks")
+                        .help("Don't run the on_switch hook of the context")
+                        .long("no-hooks")
+                        .action(ArgAction::SetTrue),
+                )
+                .subcommand(
+                    Command::new("list")
+                        .about("List all contexts")
+                        .arg(output_arg()),
+                )
+                .subcommand(
+                    Command::new("show")
+                        .about("Summarize the active context and the values its commands are missing")
+                        .arg(output_arg()),
+                )
+                .subcommand(
+                    Command::new("delete")
+                        .about("Delete a context and its variables")
+                        .arg(
+                            Arg::new("name")
+                                .help("Context name")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("force")
+                                .help("Delete the active context and switch back to default")
+                                .long("force")
+                                .action(ArgAction::SetTrue),
+                        )
+                        .arg(
+                            Arg::new("yes")
+                                .help("Delete without asking for confirmation")
+                                .short('y')
+                                .long("yes")
+                                .action(ArgAction::SetTrue),
+                        ),
+                )
+                .subcommand(
+                    Command::new("clone")
+                        .about("Copy the variables of a context to a new context")
+                        .arg(
+                            Arg::new("src")
+                                .help("Context to copy")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("dst")
+                                .help("Name of the new context")
+                                .required(true)
+                                .index(2),
+                        )
+                        .arg(
+                            Arg::new("force")
+                                .help("Replace DST if it already exists")
+                                .long("force")
+                                .action(ArgAction::SetTrue),
+                        ),
+                )
+                .subcommand(
+                    Command::new("pin")
+                        .about("Use a context in the current directory and below, via a .doo-context file")
+                        .arg(
+                            Arg::new("name")
+                                .help("Context name")
+                                .required(true)
+                                .index(1),
+                        ),
+                )
+                .subcommand(
+                    Command::new("unpin")
+                        .about("Remove the .doo-context file that chooses the active context"),
+                )
+                .subcommand(
+                    Command::new("rename")
+                        .about("Rename a context, keeping its variables")
+                        .arg(
+                            Arg::new("old")
+                                .help("Current name of the context")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("new")
+                                .help("New name of the context")
+                                .required(true)
+                                .index(2),
+                        ),
+                )
+                .subcommand(
+                    Command::new("encrypt")
+                        .about("Encrypt the variables of a context with a passphrase")
+                        .arg(
+                            Arg::new("name")
+                                .help("Context name")
+                                .required(true)
+                                .index(1),
+                        ),
+                )
+                .subcommand(
+                    Command::new("decrypt")
+                        .about("Store the variables of an encrypted context in plaintext again")
+                        .arg(
+                            Arg::new("name")
+                                .help("Context name")
+                                .required(true)
+                                .index(1),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("command")
+                .about("Add, remove or list commands")
+                .subcommand_required(true)
+                .arg_required_else_help(true)
+                .subcommand(
+                    Command::new("add")
+                        .about("Add a command to the main config or replace it")
+                        .arg(
+                            Arg::new("name")
+                                .help("Command name")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("template")
+                                .help("Command template (e.g., \"kubectl logs -n #1 #2\")")
+                                .required(true)
+                                .index(2),
+                        )
+                        .arg(
+                            Arg::new("description")
+                                .help("Description shown in search and the interactive menu")
+                                .long("description")
+                                .value_name("TEXT"),
+                        )
+                        .arg(
+                            Arg::new("tag")
+                                .help("Tag the command for 'doo search --tags' (repeatable)")
+                                .long("tag")
+                                .value_name("TAG")
+                                .action(ArgAction::Append),
+                        ),
+                )
+                .subcommand(
+                    Command::new("remove")
+                        .about("Remove a command from the main config")
+                        .arg(
+                            Arg::new("name")
+                                .help("Command name")
+                                .required(true)
+                                .index(1),
+                        ),
+                )
+                .subcommand(
+                    Command::new("list")
+                        .about("List the commands of all configs and where they are defined"),
+                )
+                .subcommand(
+                    Command::new("show")
+                        .about("Show a command with its description, parameters, examples and source")
+                        .arg(
+                            Arg::new("name")
+                                .help("Command name")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(output_arg()),
+                ),
+        )
+        .subcommand(
+            Command::new("config")
+                .about("Validate, show, convert, merge or export the config files")
+                .subcommand_required(true)
+                .arg_required_else_help(true)
+                .subcommand(
+                    Command::new("validate")
+                        .about("Check the main and imported configs, failing on any problem"),
+                )
+                .subcommand(
+                    Command::new("merge")
+                        .about("Combine two imported configs into a new one")
+                        .arg(
+                            Arg::new("a")
+                                .help("First imported config")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("b")
+                                .help("Second imported config")
+                                .required(true)
+                                .index(2),
+                        )
+                        .arg(
+                            Arg::new("output")
+                                .help("Name of the merged config")
+                                .required(true)
+                                .index(3),
+                        ),
+                )
+                .subcommand(
+                    Command::new("show")
+                        .about("Show an imported config (or main) with its origin and commands")
+                        .arg(
+                            Arg::new("name")
+                                .help("Imported config, or main for the main config")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("raw")
+                                .help("Print the config file as it is")
+                                .long("raw")
+                                .action(ArgAction::SetTrue),
+                        ),
+                )
+                .subcommand(
+                    Command::new("convert")
+                        .about("Rewrite an imported config (or main) in another format")
+                        .arg(
+                            Arg::new("name")
+                                .help("Imported config, or main for the main config")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("format")
+                                .help("Format to convert to")
+                                .long("format")
+                                .value_name("FORMAT")
+                                .value_parser(["yaml", "toml"])
+                                .default_value("yaml"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("export")
+                        .about("Print a config as YAML, by default all commands merged into one")
+                        .arg(
+                            Arg::new("config")
+                                .help("Export only this imported config, or main for the main config")
+                                .long("config")
+                                .value_name("NAME"),
+                        )
+                        .arg(
+                            Arg::new("merged")
+                                .help("Export all commands as one config (the default)")
+                                .long("merged")
+                                .action(ArgAction::SetTrue)
+                                .conflicts_with("config"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("import")
+                .about("Import a config file from a local path, a URL or a GitHub repository")
+                .arg(
+                    Arg::new("file")
+                        .help("Path to config file, http(s) URL or GitHub repository (owner/repo)")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("as")
+                        .help("Store the config as PREFIX and run its commands as PREFIX:<command>")
+                        .long("as")
+                        .value_name("PREFIX"),
+                )
+                .arg(token_arg()),
+        )
+        .subcommand(
+            Command::new("import-repo")
+                .about("Import all YAML config files from a GitHub repository")
+                .arg(
+                    Arg::new("repo")
+                        .help("GitHub repository (owner/repo)")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(token_arg()),
+        )
+        .subcommand(
+            Command::new("sync")
+                .about("Sync all imported configs with their remote origins")
+                .arg(
+                    Arg::new("config")
+                        .help("Sync only this imported config, without asking for confirmation")
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("dry-run")
+                        .help("Show what would change without writing anything; exits with 1 if anything would")
+                        .long("dry-run")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("backup")
+                .about("Write all configs, variables and contexts to a tar.gz archive")
+                .arg(
+                    Arg::new("output")
+                        .help("Archive path (default: doo-backup-<timestamp>.tar.gz)")
+                        .short('o')
+                        .long("output"),
+                )
+                .arg(
+                    Arg::new("exclude-git")
+                        .help("Skip .git directories of imported repositories")
+                        .long("exclude-git")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("init")
+                .about("Set up a .doo.yaml with the commands of the current project"),
+        )
+        .subcommand(
+            Command::new("status").about(
+                "Show the active context, configs, conflicts and commands missing values",
+            ),
+        )
+        .subcommand(
+            Command::new("which")
+                .visible_alias("info")
+                .about("Show where a command comes from and the values its placeholders would get")
+                .arg(
+                    Arg::new("name")
+                        .help("Command name")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(output_arg()),
+        )
+        .subcommand(
+            Command::new("where")
+                .about("Show every config that defines a command")
+                .arg(
+                    Arg::new("name")
+                        .help("Command name")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(output_arg()),
+        )
+        .subcommand(
+            Command::new("eval")
+                .about("Resolve a template in the current context and print the result")
+                .arg(
+                    Arg::new("template")
+                        .help("Template to resolve, e.g. \"kubectl -n #1 get pods\"")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("args")
+                        .help("Arguments for the positional placeholders; put -- before them")
+                        .num_args(0..)
+                        .index(2),
+                )
+                .arg(
+                    Arg::new("reveal")
+                        .help("Show the values of secret variables")
+                        .long("reveal")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("run-all")
+                .about("Run all commands whose names match a pattern at the same time")
+                .arg(
+                    Arg::new("pattern")
+                        .help("Command names to run, with * for any text and ? for one character")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("concurrency")
+                        .help("Number of commands to run at once (default: number of CPUs)")
+                        .short('j')
+                        .long("concurrency")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(u64).range(1..)),
+                )
+                .arg(output_arg()),
+        )
+        .subcommand(
+            Command::new("doctor")
+                .about("Check the config files, git, internet access, commands and history"),
+        )
+        .subcommand(
+            Command::new("history")
+                .about("Show the commands doo ran recently, or clear the history")
+                .args_conflicts_with_subcommands(true)
+                .arg(
+                    Arg::new("limit")
+                        .help("Number of entries to show")
+                        .short('n')
+                        .long("limit")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("20"),
+                )
+                .subcommand(Command::new("clear").about("Remove all entries")),
+        )
+        .subcommand(
+            Command::new("search")
+                .about("Search commands by name, template, description or tag")
+                .arg(
+                    Arg::new("query")
+                        .help("Text to search for (default: list all commands)")
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("tags")
+                        .help("Only list commands with this tag")
+                        .long("tags")
+                        .value_name("TAG"),
+                ),
+        )
+        .subcommand(
+            Command::new("completions")
+                .about("Print a shell completion script")
+                .arg(
+                    Arg::new("shell")
+                        .help("Shell to generate completions for")
+                        .required(true)
+                        .value_parser(completions::SHELLS.to_vec())
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            Command::new(completions::COMPLETE_COMMANDS)
+                .about("List command names for shell completion")
+                .hide(true),
+        )
+        .subcommand(
+            Command::new("restore")
+                .about("Restore configs, variables and contexts from a backup archive")
+                .arg(
+                    Arg::new("file")
+                        .help("Path to a backup archive created with 'doo backup'")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("merge")
+                        .help("Only restore files that don't exist yet instead of replacing everything")
+                        .long("merge")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .arg(
+            Arg::new("no-prompt")
+                .help("Leave placeholders without a value in the command instead of asking for them")
+                .long("no-prompt")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("ci")
+                .help("Fail on placeholders without a value instead of asking (default when CI=true)")
+                .long("ci")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("context")
+                .help("Use this context for this run only, without switching to it")
+                .short('c')
+                .long("context")
+                .value_name("NAME"),
+        )
+        .arg(
+            Arg::new("strict-env")
+                .help("Fail if a ${NAME} environment variable in a command is not set")
+                .long("strict-env")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(output_arg().default_value("text"))
+        .arg(
+            Arg::new("dry-run")
+                .help("Print the resolved command instead of running it")
+                .long("dry-run")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("dotenv")
+                .help("Provide the entries of ./.env as #KEY variables")
+                .long("dotenv")
+                .action(ArgAction::SetTrue),
+        )
+        .allow_external_subcommands(true);
+
+    #[cfg(feature = "telemetry")]
+    let cli = cli.arg(
+        Arg::new("timings")
+            .help("Print how long each phase took after the run")
+            .long("timings")
+            .global(true)
+            .action(ArgAction::SetTrue),
+    );
+
+    cli
+}
+
+/// `--output FORMAT`, given before the subcommand or to `var list`,
+/// `var diff` and `context list` directly
+fn output_arg() -> Arg {
+    Arg::new("output")
+        .help("Output format of list and search commands")
+        .short('o')
+        .long("output")
+        .value_name("FORMAT")
+        .value_parser(["text", "json"])
+}
+
+/// How list and search commands print their results
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    /// Colored tables for humans
+    Text,
+    /// JSON, one object per line or a single document for `var list` and
+    /// `context list`
+    Json,
+}
+
+impl OutputFormat {
+    fn from_matches(matches: &ArgMatches) -> Self {
+        match matches.get_one::<String>("output").map(String::as_str) {
+            Some("json") => Self::Json,
+            _ => Self::Text,
+        }
+    }
+
+    /// The format given to the subcommand itself, falling back to `self`
+    fn or_from(self, sub_matches: &ArgMatches) -> Self {
+        match sub_matches.get_one::<String>("output") {
+            Some(_) => Self::from_matches(sub_matches),
+            None => self,
+        }
+    }
+}
+
+/// Print `value` as a single line of JSON
+fn print_json(value: &impl serde::Serialize) -> Result<()> {
+    println!("{}", serde_json::to_string(value)?);
+    Ok(())
+}
+
+/// Time left until a variable expires in its two largest units, e.g. `1h 5m`
+fn format_remaining(remaining: chrono::Duration) -> String {
+    let secs = remaining.num_seconds().max(0);
+    let units = [("d", 86_400), ("h", 3_600), ("m", 60), ("s", 1)];
+    let parts: Vec<String> = units
+        .iter()
+        .scan(secs, |left, (unit, size)| {
+            let amount = *left / size;
+            *left %= size;
+            Some((amount, unit))
+        })
+        .skip_while(|(amount, _)| *amount == 0)
+        .take(2)
+        .filter(|(amount, _)| *amount > 0)
+        .map(|(amount, unit)| format!("{amount}{unit}"))
+        .collect();
+    if parts.is_empty() {
+        "0s".to_string()
+    } else {
+        parts.join(" ")
+    }
+}
+
+/// How long ago something happened, e.g. "3 days ago"
+fn format_age(age: chrono::Duration) -> String {
+    let units = [("day", 86_400), ("hour", 3_600), ("minute", 60)];
+    let secs = age.num_seconds().max(0);
+    units
+        .iter()
+        .find(|(_, size)| secs >= *size)
+        .map(|(unit, size)| {
+            let amount = secs / size;
+            let plural = if amount == 1 { "" } else { "s" };
+            format!("{amount} {unit}{plural} ago")
+        })
+        .unwrap_or_else(|| "just now".to_string())
+}
+
+fn handle_command_command(
+    matches: &ArgMatches,
+    config_manager: &mut ConfigManager,
+    format: OutputFormat,
+) -> Result<()> {
+    match matches.subcommand() {
+        Some(("add", sub_matches)) => {
+            let name = sub_matches.get_one::<String>("name").unwrap();
+            let template = sub_matches.get_one::<String>("template").unwrap();
+            let description = sub_matches.get_one::<String>("description");
+            let tags: Vec<String> = sub_matches
+                .get_many::<String>("tag")
+                .unwrap_or_default()
+                .cloned()
+                .collect();
+            if build_cli().find_subcommand(name).is_some() {
+                return Err(anyhow::anyhow!(
+                    "'{}' is a reserved command and can't be used as a command name",
+                    name
+                ));
+            }
+            let replaced = config_manager.main_commands().contains_key(name.as_str());
+            config_manager.add_command_with_tags(
+                name,
+                template,
+                description.map(String::as_str),
+                &tags,
+            )?;
+            println!(
+                "{} Command {} {}: {}",
+                "✓".green().bold(),
+                name.cyan().bold(),
+                if replaced { "updated" } else { "added" },
+                template.bright_white()
+            );
+        }
+        Some(("remove", sub_matches)) => {
+            let name = sub_matches.get_one::<String>("name").unwrap();
+            if !config_manager.remove_command(name)? {
+                return Err(anyhow::anyhow!(
+                    "Command '{}' is not defined in the main config",
+                    name
+                ));
+            }
+            println!(
+                "{} Command {} removed",
+                "✓".green().bold(),
+                name.cyan().bold()
+            );
+        }
+        Some(("list", _)) => handle_command_list_command(config_manager, format)?,
+        Some(("show", sub_matches)) => {
+            handle_command_show_command(sub_matches, config_manager, format.or_from(sub_matches))?
+        }
+        _ => unreachable!("clap requires a subcommand"),
+    }
+    Ok(())
+}
+
+/// Print everything the config says about a command
+fn handle_command_show_command(
+    matches: &ArgMatches,
+    config_manager: &ConfigManager,
+    format: OutputFormat,
+) -> Result<()> {
+    let name = matches.get_one::<String>("name").unwrap();
+    let source = config_manager
+        .get_command_with_source(name)?
+        .ok_or_else(|| anyhow::anyhow!("Command '{}' not found", name))?;
+    let path = config_manager.config_file(&source.source_file);
+
+    if format == OutputFormat::Json {
+        return print_json(&serde_json::json!({
+            "name": source.name,
+            "command": source.command,
+            "description": source.description,
+            "source": source.source_file,
+            "path": path,
+            "params": source.params,
+            "defaults": source.defaults,
+            "examples": source.examples,
+            "timeout_secs": source.timeout_secs,
+            "retry": source.retry,
+            "env": source.env,
+            "cwd": source.cwd,
+            "quote_args": source.quote_args,
+            "shell": source.shell,
+        }));
+    }
+
+    let grey = |text: &str| text.truecolor(140, 140, 140);
+    println!("{}", source.name.cyan().bold());
+    if let Some(description) = &source.description {
+        println!("  {}", grey(description));
+    }
+    println!("  Source:   {}", source.source_file);
+    if let Some(path) = &path {
+        println!("  Path:     {}", path.display());
+    }
+    println!("  Command:  {}", source.command.bright_white());
+    if let Some(secs) = source.timeout_secs {
+        println!("  Timeout:  {secs}s");
+    }
+    if let Some(retry) = &source.retry {
+        println!(
+            "  Retry:    {} attempts, {}s backoff",
+            retry.max_attempts, retry.backoff_secs
+        );
+    }
+    if let Some(cwd) = &source.cwd {
+        println!("  Cwd:      {cwd}");
+    }
+    for (key, value) in &source.env {
+        println!("  Env:      {key}={value}");
+    }
+    if source.shell {
+        println!("  Shell:    runs with the user's shell");
+    }
+    if source.quote_args {
+        println!("  Quoting:  substituted values are quoted");
+    }
+
+    // Parameters, and defaults of placeholders without a parameter spec
+    let mut keys: Vec<&String> = source.params.keys().collect();
+    keys.extend(
+        source
+            .defaults
+            .keys()
+            .filter(|key| !source.params.contains_key(*key)),
+    );
+    if !keys.is_empty() {
+        println!();
+        println!("{}", "Parameters:".bold());
+        let width = keys.iter().map(|key| key.len()).max().unwrap_or(0);
+        for key in keys {
+            let mut notes = Vec::new();
+            let spec = source.params.get(key);
+            if let Some(param_name) = spec.and_then(|spec| spec.name.as_ref()) {
+                notes.push(format!("<{param_name}>"));
+            }
+            if let Some(description) = spec.and_then(|spec| spec.description.as_ref()) {
+                notes.push(description.clone());
+            }
+            if let Some(default) = source.defaults.get(key) {
+                notes.push(format!("default: {default}"));
+            }
+            if spec.is_some_and(|spec| spec.required) {
+                notes.push("required".to_string());
+            }
+            println!(
+                "  {}  {}",
+                format!("{key:<width$}").cyan(),
+                grey(&notes.join(", "))
+            );
+        }
+    }
+
+    if !source.examples.is_empty() {
+        println!();
+        println!("{}", "Examples:".bold());
+        for example in &source.examples {
+            println!("  {example}");
+        }
+    }
+    Ok(())
+}
+
+fn handle_command_list_command(config_manager: &ConfigManager, format: OutputFormat) -> Result<()> {
+    let sources = config_manager.list_command_sources();
+
+    if format == OutputFormat::Json {
+        for source in &sources {
+            print_json(&serde_json::json!({
+                "name": source.name,
+                "command": source.command,
+                "description": source.description,
+                "source": source.source_file,
+            }))?;
+        }
+        return Ok(());
+    }
+
+    if sources.is_empty() {
+        println!("No commands defined. Add one with 'doo command add <name> <template>'.");
+        return Ok(());
+    }
+    let name_width = sources
+        .iter()
+        .map(|s| s.name.len())
+        .max()
+        .unwrap_or(0)
+        .max(4);
+    let source_width = sources
+        .iter()
+        .map(|s| s.source_file.len())
+        .max()
+        .unwrap_or(0)
+        .max(6);
+    println!(
+        "  {}  {}  {}",
+        format!("{:<name_width$}", "NAME").bold(),
+        format!("{:<source_width$}", "SOURCE").bold(),
+        "COMMAND".bold()
+    );
+    for source in &sources {
+        let command = if source.command.is_empty() {
+            "(resolved when run)".to_string()
+        } else {
+            source.command.clone()
+        };
+        println!(
+            "  {}  {}  {}",
+            format!("{:<name_width$}", source.name).cyan(),
+            format!("{:<source_width$}", source.source_file).truecolor(140, 140, 140),
+            command
+        );
+    }
+    Ok(())
+}
+
+fn handle_config_command(
+    matches: &ArgMatches,
+    config_manager: &mut ConfigManager,
+    variable_manager: &VariableManager,
+    context_manager: &ContextManager,
+) -> Result<()> {
+    match matches.subcommand() {
+        Some(("validate", _)) => {
+            let mut errors = config_manager.validate();
+            errors.extend(
+                config_manager.validate_required_params(
+                    variable_manager,
+                    context_manager.current_context(),
+                )?,
+            );
+            if errors.is_empty() {
+                println!("{} No problems found in the configs", "✓".green().bold());
+                return Ok(());
+            }
+            for error in &errors {
+                eprintln!("{} {}", "✗".red().bold(), error);
+            }
+            Err(anyhow::anyhow!(
+                "Found {} problem{} in the configs",
+                errors.len(),
+                if errors.len() == 1 { "" } else { "s" }
+            ))
+        }
+        Some(("show", sub_matches)) => handle_config_show_command(sub_matches, config_manager),
+        Some(("convert", sub_matches)) => {
+            let name = sub_matches.get_one::<String>("name").unwrap();
+            let format = ConfigFormat::from_name(sub_matches.get_one::<String>("format").unwrap())?;
+            let path = config_manager.convert_config(name, format)?;
+            println!(
+                "{} Converted {} to {}",
+                "✓".green().bold(),
+                name.cyan().bold(),
+                path.display()
+            );
+            Ok(())
+        }
+        Some(("export", sub_matches)) => {
+            let config_name = sub_matches.get_one::<String>("config");
+            print!(
+                "{}",
+                config_manager.export_to_yaml(config_name.map(String::as_str))?
+            );
+            Ok(())
+        }
+        Some(("merge", sub_matches)) => {
+            let a = sub_matches.get_one::<String>("a").unwrap();
+            let b = sub_matches.get_one::<String>("b").unwrap();
+            let output = sub_matches.get_one::<String>("output").unwrap();
+            match config_manager.merge_configs(a, b, output)? {
+                MergeOutcome::Merged { commands } => {
+                    println!(
+                        "{} Merged {} and {} into {} ({} commands)",
+                        "✓".green().bold(),
+                        a.cyan().bold(),
+                        b.cyan().bold(),
+                        output.cyan().bold(),
+                        commands
+                    );
+                    Ok(())
+                }
+                MergeOutcome::Conflicts(conflicts) => {
+                    for conflict in &conflicts {
+                        eprintln!(
+                            "{} {}\n    {}: {}\n    {}: {}",
+                            "✗".red().bold(),
+                            conflict.name.bold(),
+                            a,
+                            conflict.first.command_str(),
+                            b,
+                            conflict.second.command_str()
+                        );
+                    }
+                    Err(anyhow::anyhow!(
+                        "{} command{} defined differently in {} and {}; nothing was written",
+                        conflicts.len(),
+                        if conflicts.len() == 1 { " is" } else { "s are" },
+                        a,
+                        b
+                    ))
+                }
+            }
+        }
+        _ => unreachable!("clap requires a subcommand"),
+    }
+}
+
+fn handle_config_show_command(matches: &ArgMatches, config_manager: &ConfigManager) -> Result<()> {
+    let name = matches.get_one::<String>("name").unwrap();
+    let config = match name.as_str() {
+        "main" => Some(config_manager.main_config()),
+        _ => config_manager.get_imported_config(name),
+    }
+    .ok_or_else(|| anyhow::anyhow!("Config '{}' not found", name))?;
+    let path = config_manager.config_file(name);
+
+    if matches.get_flag("raw") {
+        let path = path.context("The config has no file")?;
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        print!("{contents}");
+        return Ok(());
+    }
+
+    println!("{}", name.cyan().bold());
+    if let Some(path) = &path {
+        println!("  File:      {}", path.display());
+    }
+    match &config.origin {
+        Some(origin) => println!("  Origin:    {} ({:?})", origin.repo, origin.import_type),
+        None => println!("  Origin:    {}", "local".truecolor(140, 140, 140)),
+    }
+    if let Some(namespace) = &config.namespace {
+        println!("  Namespace: {namespace} (run as {namespace}:<command>)");
+    }
+    println!("  Commands:  {}", config.commands.len());
+
+    let mut commands: Vec<_> = config.commands.iter().collect();
+    commands.sort_by_key(|(command_name, _)| *command_name);
+    let name_width = commands
+        .iter()
+        .map(|(command_name, _)| command_name.len())
+        .max()
+        .unwrap_or(0);
+    if !commands.is_empty() {
+        println!();
+    }
+    for (command_name, entry) in commands {
+        println!(
+            "  {}  {}",
+            format!("{command_name:<name_width$}").cyan(),
+            entry.command_str().white()
+        );
+        if let Some(description) = entry.description() {
+            println!(
+                "  {:name_width$}  {}",
+                "",
+                description.truecolor(140, 140, 140)
+            );
+        }
+    }
+    Ok(())
+}
+
+fn handle_search_command(
+    matches: &ArgMatches,
+    config_manager: &ConfigManager,
+    format: OutputFormat,
+) -> Result<()> {
+    let query = matches
+        .get_one::<String>("query")
+        .map(String::as_str)
+        .unwrap_or("");
+    let tag = matches.get_one::<String>("tags");
+    let results = match tag {
+        Some(tag) => config_manager
+            .search_commands_by_tag(tag)
+            .into_iter()
+            .filter(|result| result.matches(query))
+            .collect(),
+        None => config_manager.search_commands(query),
+    };
+
+    if format == OutputFormat::Json {
+        for result in &results {
+            print_json(result)?;
+        }
+        return Ok(());
+    }
+
+    if results.is_empty() {
+        match tag {
+            Some(tag) if query.is_empty() => println!("No commands are tagged '{tag}'"),
+            Some(tag) => println!("No commands tagged '{tag}' match '{query}'"),
+            None => println!("No commands match '{}'", query),
+        }
+        return Ok(());
+    }
+    let name_width = results.iter().map(|r| r.name.len()).max().unwrap_or(0);
+    for result in &results {
+        let tags = if result.tags.is_empty() {
+            String::new()
+        } else {
+            format!("  ({})", result.tags.join(", "))
+        };
+        println!(
+            "  {}  {}{}",
+            format!("{:<name_width$}", result.name).cyan().bold(),
+            result.command.bright_white(),
+            tags.truecolor(140, 140, 140)
+        );
+        if let Some(description) = &result.description {
+            println!(
+                "  {:<name_width$}  {}",
+                "",
+                description.truecolor(140, 140, 140)
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Recently run commands `doo status` checks for placeholders without values
+const STATUS_RECENT_COMMANDS: usize = 10;
+
+fn handle_status_command(
+    config_manager: &ConfigManager,
+    variable_manager: &VariableManager,
+    context_manager: &ContextManager,
+) -> Result<()> {
+    let grey = |text: &str| text.truecolor(140, 140, 140);
+    let label = |text: &str| format!("{text:<10}").bold();
+    let context = context_manager.current_context();
+
+    let mut notes = Vec::new();
+    if let Some(parent) = context_manager.parent_of(context)? {
+        notes.push(format!("inherits from {parent}"));
+    }
+    if let Some(pin) = context_manager.pin_file() {
+        notes.push(format!("pinned by {}", pin.display()));
+    }
+    if context_manager.is_one_off() {
+        notes.push("one-off".to_string());
+    }
+    let notes = if notes.is_empty() {
+        String::new()
+    } else {
+        format!(" ({})", notes.join(", "))
+    };
+    println!(
+        "{}{}{}",
+        label("Context"),
+        context.blue().bold(),
+        grey(&notes)
+    );
+
+    let variables = variable_manager.list_variables(context)?.len();
+    let secrets = variable_manager.list_secrets(context)?.len();
+    println!(
+        "{}{} set, {} secret",
+        label("Variables"),
+        (variables + secrets).to_string().yellow(),
+        secrets.to_string().yellow()
+    );
+
+    let imported = config_manager.imported_config_names();
+    let configs = if imported.is_empty() {
+        "main".to_string()
+    } else {
+        format!("main, {}", imported.join(", "))
+    };
+    println!(
+        "{}{} {}",
+        label("Configs"),
+        (imported.len() + 1).to_string().cyan(),
+        grey(&format!("({configs})"))
+    );
+    if let Some(path) = config_manager.project_config_file() {
+        println!("{}{}", label("Project"), path.display().to_string().cyan());
+    }
+    println!(
+        "{}{}",
+        label("Commands"),
+        config_manager.list_commands().len().to_string().cyan()
+    );
+
+    let origins = config_manager.remote_origins();
+    if !origins.is_empty() {
+        println!("{}", "Remote".bold());
+        let width = origins
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0);
+        for (name, origin) in &origins {
+            let synced = match origin.synced_at {
+                Some(time) => format!("synced {}", format_age(Utc::now() - time)),
+                None => "sync time unknown".to_string(),
+            };
+            println!(
+                "  {}  {}  {}",
+                format!("{name:<width$}").cyan(),
+                origin.repo,
+                grey(&synced)
+            );
+        }
+    }
+
+    let conflicts = config_manager.conflicting_commands();
+    if !conflicts.is_empty() {
+        println!("{}", "Conflicts".bold());
+        for (name, sources) in &conflicts {
+            println!(
+                "  {} {} {}",
+                "⚠".yellow().bold(),
+                name.cyan(),
+                grey(&format!("defined in {}", sources.join(", ")))
+            );
+        }
+    }
+    let overrides = config_manager.project_overrides();
+    if !overrides.is_empty() {
+        println!("{}", "Overridden by the project".bold());
+        for (name, sources) in &overrides {
+            println!(
+                "  {} {}",
+                name.cyan(),
+                grey(&format!("also in {}", sources.join(", ")))
+            );
+        }
+    }
+
+    let mut recent: Vec<String> = Vec::new();
+    for entry in HistoryManager::new(config_manager)
+        .list(None)?
+        .into_iter()
+        .rev()
+    {
+        if recent.len() == STATUS_RECENT_COMMANDS {
+            break;
+        }
+        if !recent.contains(&entry.name) {
+            recent.push(entry.name);
+        }
+    }
+    let mut unresolved = Vec::new();
+    for name in recent {
+        let Some(source) = config_manager.get_command_with_source(&name)? else {
+            continue;
+        };
+        let missing: Vec<String> = variable_manager
+            .missing_placeholders(context, Some(&name), &source.command, &[], &source.defaults)?
+            .into_iter()
+            .filter(|label| label.starts_with('#'))
+            .collect();
+        if !missing.is_empty() {
+            unresolved.push((name, missing));
+        }
+    }
+    if !unresolved.is_empty() {
+        println!("{}", "Missing values in recently run commands".bold());
+        for (name, missing) in unresolved {
+            println!(
+                "  {} {} {}",
+                "⚠".yellow().bold(),
+                name.cyan(),
+                missing.join(", ").yellow()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Print `template` resolved in the current context, warning about the
+/// placeholders that have no value
+fn handle_eval_command(
+    matches: &ArgMatches,
+    variable_manager: &VariableManager,
+    context_manager: &ContextManager,
+) -> Result<()> {
+    let template = matches.get_one::<String>("template").unwrap();
+    let args: Vec<String> = matches
+        .get_many::<String>("args")
+        .unwrap_or_default()
+        .cloned()
+        .collect();
+    let context = context_manager.current_context();
+
+    let missing =
+        variable_manager.missing_placeholders(context, None, template, &args, &BTreeMap::new())?;
+    // Placeholders without a value are printed as they are
+    let mut resolved = variable_manager.preview_variables(context, template, &args)?;
+    if !matches.get_flag("reveal") {
+        resolved = variable_manager.mask_secrets(context, &resolved)?;
+    }
+    if !missing.is_empty() {
+        eprintln!(
+            "{} No value for {} in context {}",
+            "⚠".yellow(),
+            missing.join(", "),
+            context
+        );
+    }
+    println!("{resolved}");
+    Ok(())
+}
+
+fn handle_which_command(
+    matches: &ArgMatches,
+    config_manager: &ConfigManager,
+    variable_manager: &VariableManager,
+    context_manager: &ContextManager,
+    format: OutputFormat,
+) -> Result<()> {
+    let name = matches.get_one::<String>("name").unwrap();
+    let source = config_manager
+        .get_command_with_source(name)?
+        .ok_or_else(|| anyhow::anyhow!("Command '{}' not found", name))?;
+    let context = context_manager.current_context();
+    let placeholders = variable_manager.inspect_placeholders(
+        context,
+        Some(&source.name),
+        &source.command,
+        &source.defaults,
+    )?;
+    let param = |status: &PlaceholderStatus| source.param_spec(&status.label);
+
+    if format == OutputFormat::Json {
+        let placeholders: Vec<serde_json::Value> = placeholders
+            .iter()
+            .map(|status| {
+                let mut value = serde_json::to_value(status)?;
+                if let Some(spec) = param(status) {
+                    value["param"] = serde_json::to_value(spec)?;
+                }
+                Ok(value)
+            })
+            .collect::<Result<_>>()?;
+        return print_json(&serde_json::json!({
+            "name": source.name,
+            "command": source.command,
+            "description": source.description,
+            "source": source.source_file,
+            "context": context,
+            "placeholders": placeholders,
+        }));
+    }
+
+    let grey = |text: &str| text.truecolor(140, 140, 140);
+    println!("{}", source.name.cyan().bold());
+    if let Some(description) = &source.description {
+        println!("  {}", grey(description));
+    }
+    println!("  Source:   {}", source.source_file);
+    println!("  Command:  {}", source.command.bright_white());
+    println!("  Context:  {}", context.blue());
+    if placeholders.is_empty() {
+        println!();
+        println!("No placeholders; the command runs as it is");
+        return Ok(());
+    }
+
+    println!();
+    let width = placeholders
+        .iter()
+        .map(|status| status.label.len())
+        .max()
+        .unwrap_or(0);
+    let value_width = placeholders
+        .iter()
+        .filter_map(|status| status.value.as_ref())
+        .map(|value| value.to_string().chars().count())
+        .max()
+        .unwrap_or(0)
+        .max("argument".len());
+    for status in &placeholders {
+        let value = match &status.value {
+            Some(value) => format!("{:<value_width$}", value.to_string()).green(),
+            None if status.source == ValueSource::Argument => {
+                format!("{:<value_width$}", "argument").normal()
+            }
+            None if status.optional => format!("{:<value_width$}", "unset").normal(),
+            None => format!("{:<value_width$}", "missing").yellow().bold(),
+        };
+        let mut notes = Vec::new();
+        match (status.source, &status.context) {
+            (ValueSource::Variable, Some(set_in)) => notes.push(format!("set in {set_in}")),
+            (ValueSource::CommandVariable, Some(set_in)) => {
+                notes.push(format!("set for {} in {set_in}", source.name))
+            }
+            (ValueSource::Dotenv, _) => notes.push("from .env".to_string()),
+            (ValueSource::Default, _) => notes.push("default".to_string()),
+            (ValueSource::Environment, _) => notes.push("environment".to_string()),
+            (ValueSource::Argument, _) => notes.push("given when running".to_string()),
+            (ValueSource::Missing, _) if status.optional => {
+                notes.push("optional, expands to nothing".to_string())
+            }
+            // `#N` falls back to the runtime arguments
+            (ValueSource::Missing, _)
+                if status
+                    .label
+                    .strip_prefix('#')
+                    .is_some_and(|n| n.parse::<usize>().is_ok()) =>
+            {
+                notes.push("taken from the arguments if given".to_string())
+            }
+            _ => {}
+        }
+        if status.secret {
+            notes.push("secret".to_string());
+        }
+        if let Some(spec) = param(status) {
+            if let Some(param_name) = &spec.name {
+                notes.push(format!("<{param_name}>"));
+            }
+            if let Some(description) = &spec.description {
+                notes.push(description.clone());
+            }
+        }
+        println!(
+            "  {}  {}  {}",
+            format!("{:<width$}", status.label).cyan(),
+            value,
+            grey(&notes.join(", "))
+        );
+    }
+    Ok(())
+}
+
+/// List the configs defining a command, in the order running it offers them
+fn handle_where_command(
+    matches: &ArgMatches,
+    config_manager: &ConfigManager,
+    format: OutputFormat,
+) -> Result<()> {
+    let name = matches.get_one::<String>("name").unwrap();
+    let sources = config_manager.get_command_conflicts(name);
+    if sources.is_empty() {
+        return Err(anyhow::anyhow!("Command '{}' not found", name));
+    }
+    let origin = |source: &CommandSource| {
+        config_manager
+            .get_imported_config(&source.source_file)
+            .and_then(|config| config.origin.as_ref())
+            .map(|origin| origin.url())
+    };
+
+    if format == OutputFormat::Json {
+        let sources: Vec<serde_json::Value> = sources
+            .iter()
+            .map(|source| {
+                serde_json::json!({
+                    "source": source.source_file,
+                    "path": config_manager.config_file(&source.source_file),
+                    "command": source.command,
+                    "description": source.description,
+                    "origin": origin(source),
+                })
+            })
+            .collect();
+        return print_json(&serde_json::json!({ "name": name, "sources": sources }));
+    }
+
+    let grey = |text: &str| text.truecolor(140, 140, 140);
+    if sources.len() > 1 {
+        println!(
+            "{} is defined in {} configs:",
+            name.cyan().bold(),
+            sources.len()
+        );
+    }
+    for (i, source) in sources.iter().enumerate() {
+        if sources.len() > 1 {
+            println!();
+            println!("{}. {}", i + 1, source.source_file.bold());
+        } else {
+            println!("{}", name.cyan().bold());
+            println!("  Source:   {}", source.source_file);
+            if let Some(path) = config_manager.config_file(&source.source_file) {
+                println!("  Path:     {}", path.display());
+            }
+        }
+        println!("  Command:  {}", source.command.bright_white());
+        if let Some(description) = &source.description {
+            println!("  About:    {}", grey(description));
+        }
+        if let Some(url) = origin(source) {
+            println!("  Origin:   {url}");
+        }
+    }
+    Ok(())
+}
+
+async fn handle_doctor_command(format: OutputFormat) -> Result<i32> {
+    let config_dir = ConfigManager::default_config_dir()?;
+    let results = doctor::run_checks(&config_dir).await;
+    let failed = results
+        .iter()
+        .filter(|result| result.status == CheckStatus::Fail)
+        .count();
+    let exit_code = if failed > 0 { 1 } else { 0 };
+    if format == OutputFormat::Json {
+        for result in &results {
+            print_json(result)?;
+        }
+        return Ok(exit_code);
+    }
+
+    let width = results
+        .iter()
+        .map(|result| result.check.len())
+        .max()
+        .unwrap_or(0);
+    for DoctorResult {
+        check,
+        status,
+        message,
+        details,
+    } in &results
+    {
+        let symbol = match status {
+            CheckStatus::Pass => "✓".green().bold(),
+            CheckStatus::Warn => "⚠".yellow().bold(),
+            CheckStatus::Fail => "✗".red().bold(),
+        };
+        println!(
+            "{} {}  {}",
+            symbol,
+            format!("{check:<width$}").bold(),
+            message
+        );
+        for detail in details {
+            println!("    {}", detail.truecolor(140, 140, 140));
+        }
+    }
+
+    let warnings = results
+        .iter()
+        .filter(|result| result.status == CheckStatus::Warn)
+        .count();
+    let plural =
+        |count: usize, word: &str| format!("{count} {word}{}", if count == 1 { "" } else { "s" });
+    println!();
+    match (failed, warnings) {
+        (0, 0) => println!("{} All checks passed", "✓".green().bold()),
+        (0, _) => println!(
+            "{} No problems, {}",
+            "✓".green().bold(),
+            plural(warnings, "warning")
+        ),
+        _ => println!(
+            "{} {} failed, {}",
+            "✗".red().bold(),
+            plural(failed, "check"),
+            plural(warnings, "warning")
+        ),
+    }
+    Ok(exit_code)
+}
+
+fn handle_history_command(
+    matches: &ArgMatches,
+    config_manager: &ConfigManager,
+    format: OutputFormat,
+) -> Result<()> {
+    let mut history = HistoryManager::new(config_manager);
+    if let Some(("clear", _)) = matches.subcommand() {
+        let count = history.clear()?;
+        println!(
+            "{} Removed {} {} from the history",
+            "✓".green().bold(),
+            count,
+            if count == 1 { "entry" } else { "entries" }
+        );
+        return Ok(());
+    }
+
+    let limit = matches.get_one::<usize>("limit").copied();
+    let entries = history.list(limit)?;
+    if format == OutputFormat::Json {
+        for entry in &entries {
+            print_json(entry)?;
+        }
+        return Ok(());
+    }
+
+    if entries.is_empty() {
+        println!("No commands in the history yet");
+        return Ok(());
+    }
+    let name_width = entries.iter().map(|e| e.name.len()).max().unwrap_or(0);
+    for entry in &entries {
+        let timestamp = entry
+            .timestamp
+            .with_timezone(&chrono::Local)
+            .format("%Y-%m-%d %H:%M:%S");
+        let status = match entry.exit_code {
+            Some(0) => format!("{:<9}", "✓").green(),
+            Some(code) => format!("{:<9}", format!("✗ {code}")).red(),
+            None => format!("{:<9}", "timed out").red(),
+        };
+        let duration = format_duration(entry.duration_ms);
+        println!(
+            "  {}  {}  {:>6}  {}  {}",
+            timestamp.to_string().truecolor(140, 140, 140),
+            status,
+            duration,
+            format!("{:<name_width$}", entry.name).cyan(),
+            entry.command.bright_white()
+        );
+    }
+    Ok(())
+}
+
+/// `850ms`, `12.3s` or `4m 10s`
+fn format_duration(millis: u64) -> String {
+    match millis {
+        0..=999 => format!("{millis}ms"),
+        1_000..=59_999 => format!("{:.1}s", millis as f64 / 1000.0),
+        _ => format_remaining(chrono::Duration::seconds((millis / 1000) as i64)),
+    }
+}
+
+/// Print the names of all available commands, one per line
+fn handle_complete_commands(config_manager: &ConfigManager) {
+    let mut names: Vec<String> = config_manager
+        .search_commands("")
+        .into_iter()
+        .map(|result| result.name)
+        .collect();
+    names.sort();
+    names.dedup();
+    for name in names {
+        println!("{name}");
+    }
+}
+
+fn handle_variable_command(
+    matches: &ArgMatches,
+    config_manager: &ConfigManager,
+    variable_manager: &mut VariableManager,
+    context_manager: &ContextManager,
+    format: OutputFormat,
+) -> Result<()> {
+    match matches.subcommand() {
+        Some(("list", sub_matches)) => {
+            return handle_variable_list_command(
+                sub_matches,
+                config_manager,
+                variable_manager,
+                context_manager,
+                format.or_from(sub_matches),
+            );
+        }
+        Some(("get", sub_matches)) => {
+            return handle_variable_get_command(sub_matches, variable_manager, context_manager);
+        }
+        Some(("remove", sub_matches)) => {
+            return handle_variable_remove_command(sub_matches, variable_manager, context_manager);
+        }
+        Some(("history", sub_matches)) => {
+            return handle_variable_history_command(
+                sub_matches,
+                variable_manager,
+                context_manager,
+                format,
+            );
+        }
+        Some(("undo", sub_matches)) => {
+            return handle_variable_undo_command(sub_matches, variable_manager, context_manager);
+        }
+        Some(("copy", sub_matches)) => {
+            return handle_variable_copy_command(sub_matches, variable_manager);
+        }
+        Some(("migrate-secrets", sub_matches)) => {
+            return handle_migrate_secrets_command(
+                sub_matches,
+                config_manager,
+                variable_manager,
+                context_manager,
+            );
+        }
+        Some(("export", sub_matches)) => {
+            return handle_variable_export_command(sub_matches, variable_manager, context_manager);
+        }
+        Some(("import", sub_matches)) => {
+            return handle_variable_import_command(sub_matches, variable_manager, context_manager);
+        }
+        _ => {}
+    }
+
+    if let Some(pairs) = variable_pairs(matches)? {
+        if matches.contains_id("desc") {
+            return Err(anyhow::anyhow!(
+                "--desc describes a single variable; set the description of each one separately"
+            ));
+        }
+        return handle_variable_pairs(
+            matches,
+            pairs,
+            config_manager,
+            variable_manager,
+            context_manager,
+        );
+    }
+
+    let name = variable_key(matches.get_one::<String>("name").unwrap());
+    let mut values: Vec<String> = matches
+        .get_many::<String>("value")
+        .unwrap()
+        .cloned()
+        .collect();
+    let value = if values.len() == 1 {
+        VarValue::Single(values.remove(0))
+    } else {
+        VarValue::List(values)
+    };
+    let context = context_manager.current_context();
+    let secret = matches.get_flag("secret");
+    let shown = value.to_string();
+
+    if let Some(command) = matches.get_one::<String>("command") {
+        variable_manager.set_command_variable(context, command, &name, value, secret)?;
+        println!(
+            "{} {} {} set to {} for command {} in context {}",
+            "✓".green().bold(),
+            if secret { "Secret" } else { "Variable" },
+            name.cyan().bold(),
+            if secret { SECRET_MASK } else { &shown }.yellow(),
+            command.cyan(),
+            context.blue().bold()
+        );
+        return warn_unreferenced_variables(
+            matches,
+            config_manager,
+            variable_manager,
+            context,
+            &[&name],
+        );
+    }
+
+    let expires = match matches.get_one::<String>("ttl") {
+        Some(ttl) => {
+            let ttl = parse_ttl(ttl)?;
+            Some(variable_manager.set_with_ttl(context, &name, value, secret, ttl)?)
+        }
+        None => {
+            match value {
+                VarValue::Single(value) if secret => {
+                    variable_manager.set_secret(context, &name, &value)?
+                }
+                VarValue::Single(value) => variable_manager.set_variable(context, &name, &value)?,
+                VarValue::List(items) => {
+                    variable_manager.set_list(context, &name, items, secret)?
+                }
+            }
+            None
+        }
+    };
+    let description = matches.get_one::<String>("desc");
+    if let Some(description) = description {
+        variable_manager.describe_variable(context, &name, Some(description))?;
+    }
+
+    let (kind, shown) = if secret {
+        ("Secret", SECRET_MASK)
+    } else {
+        ("Variable", shown.as_str())
+    };
+    let expiry = expires
+        .map(|at| format!(" (expires in {})", format_remaining(at - Utc::now())))
+        .unwrap_or_default();
+    println!(
+        "{} {} {} set to {} in context {}{}",
+        "✓".green().bold(),
+        kind,
+        name.cyan().bold(),
+        shown.yellow(),
+        context.blue().bold(),
+        expiry
+    );
+    if let Some(description) = description {
+        println!("  {}", description.truecolor(140, 140, 140));
+    }
+
+    warn_unreferenced_variables(matches, config_manager, variable_manager, context, &[&name])
+}
+
+/// Warn about variables no command refers to, which usually means a typo in
+/// the name, and suggest the placeholder that was probably meant. With
+/// `--command`, only the placeholders of that command count.
+fn warn_unreferenced_variables(
+    matches: &ArgMatches,
+    config_manager: &ConfigManager,
+    variable_manager: &VariableManager,
+    context: &str,
+    names: &[&str],
+) -> Result<()> {
+    if matches.get_flag("quiet") {
+        return Ok(());
+    }
+    let command = matches.get_one::<String>("command").map(String::as_str);
+    let mut referenced = config_manager.referenced_variables(command);
+    // The context's env applies to every command
+    for value in config_manager.context_env(context).values() {
+        let tokens = template::parse(value);
+        referenced.extend(
+            template::placeholders(&tokens).filter_map(template::Placeholder::reference_key),
+        );
+    }
+    if command.is_none() {
+        // Variables can also be used in the values of other variables
+        for value in variable_manager.list_variables(context)?.values() {
+            let text = value.to_string();
+            let tokens = template::parse(&text);
+            referenced.extend(
+                template::placeholders(&tokens).filter_map(template::Placeholder::reference_key),
+            );
+        }
+    }
+
+    for name in names {
+        if referenced.contains(*name) {
+            continue;
+        }
+        let problem = match command {
+            Some(command) => format!("Command {command} doesn't reference {name}"),
+            None => format!("No command references {name}"),
+        };
+        let suggestion = suggest::closest_match(name, referenced.iter().map(String::as_str))
+            .map(|closest| format!("; did you mean {closest}?"))
+            .unwrap_or_default();
+        eprintln!(
+            "{} {}",
+            "⚠".yellow(),
+            format!("{problem}{suggestion}").yellow()
+        );
+    }
+    Ok(())
+}
+
+/// Name/value pairs to set at once, from `--from-pairs` or from alternating
+/// names and values like `#1 prod #2 api`. `None` for a single variable.
+fn variable_pairs(matches: &ArgMatches) -> Result<Option<Vec<(String, String)>>> {
+    if let Some(spec) = matches.get_one::<String>("from-pairs") {
+        let pairs = spec
+            .split(',')
+            .map(|pair| {
+                pair.split_once('=')
+                    .filter(|(name, _)| !name.trim().is_empty())
+                    .map(|(name, value)| (name.trim().to_string(), value.to_string()))
+                    .ok_or_else(|| anyhow::anyhow!("Invalid pair '{pair}'; expected name=value"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        return Ok(Some(pairs));
+    }
+
+    let args: Vec<&String> = matches
+        .get_one::<String>("name")
+        .into_iter()
+        .chain(matches.get_many::<String>("value").into_iter().flatten())
+        .collect();
+    // A second name is what tells pairs apart from a list value
+    if args.len() < 3 || !args[2].starts_with('#') {
+        return Ok(None);
+    }
+    if !args.len().is_multiple_of(2) {
+        return Err(anyhow::anyhow!(
+            "Missing a value for '{}'; give variables as name/value pairs",
+            args[args.len() - 1]
+        ));
+    }
+    if matches.get_one::<String>("command").is_some() {
+        return Err(anyhow::anyhow!("--command sets one variable at a time"));
+    }
+    Ok(Some(
+        args.chunks(2)
+            .map(|pair| (pair[0].clone(), pair[1].clone()))
+            .collect(),
+    ))
+}
+
+fn handle_variable_pairs(
+    matches: &ArgMatches,
+    pairs: Vec<(String, String)>,
+    config_manager: &ConfigManager,
+    variable_manager: &mut VariableManager,
+    context_manager: &ContextManager,
+) -> Result<()> {
+    let context = context_manager.current_context();
+    let secret = matches.get_flag("secret");
+    let ttl = matches
+        .get_one::<String>("ttl")
+        .map(|ttl| parse_ttl(ttl))
+        .transpose()?;
+    let pairs: Vec<(String, String)> = pairs
+        .into_iter()
+        .map(|(name, value)| (variable_key(&name), value))
+        .collect();
+    let expires = variable_manager.set_variables(
+        context,
+        pairs
+            .iter()
+            .map(|(name, value)| (name.clone(), VarValue::Single(value.clone())))
+            .collect(),
+        secret,
+        ttl,
+    )?;
+
+    let expiry = expires
+        .map(|at| format!(" (expire in {})", format_remaining(at - Utc::now())))
+        .unwrap_or_default();
+    println!(
+        "{} Set {} {} in context {}{}",
+        "✓".green().bold(),
+        pairs.len(),
+        if secret { "secrets" } else { "variables" },
+        context.blue().bold(),
+        expiry
+    );
+    for (name, value) in &pairs {
+        let shown = if secret { SECRET_MASK } else { value.as_str() };
+        println!("  {} = {}", name.cyan().bold(), shown.yellow());
+    }
+    let names: Vec<&str> = pairs.iter().map(|(name, _)| name.as_str()).collect();
+    warn_unreferenced_variables(matches, config_manager, variable_manager, context, &names)
+}
+
+fn handle_variable_list_command(
+    matches: &ArgMatches,
+    config_manager: &ConfigManager,
+    variable_manager: &VariableManager,
+    context_manager: &ContextManager,
+    format: OutputFormat,
+) -> Result<()> {
+    let context = matches
+        .get_one::<String>("context")
+        .map(String::as_str)
+        .unwrap_or(context_manager.current_context());
+
+    // Which main config commands use each placeholder
+    let mut used_by: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+    for (command_name, entry) in config_manager.main_commands() {
+        let tokens = template::parse(entry.command_str());
+        let mut names: Vec<String> = template::placeholders(&tokens)
+            .filter_map(|placeholder| placeholder.variable_name())
+            .collect();
+        names.sort();
+        names.dedup();
+        for name in names {
+            used_by.entry(name).or_default().push(command_name);
+        }
+    }
+    for commands in used_by.values_mut() {
+        commands.sort();
+    }
+
+    let expires = variable_manager.expiry(context)?;
+    let meta = variable_manager.metadata(context)?;
+    let now = Utc::now();
+
+    // Variable name to (shown value, secret, context it is inherited from)
+    let reveal = matches.get_flag("reveal");
+    let shown = |name: &str, value: VarValue| -> Result<VarValue> {
+        if reveal {
+            variable_manager.reveal(name, value)
+        } else {
+            Ok(VarValue::from(SECRET_MASK))
+        }
+    };
+    let mut variables: BTreeMap<String, (VarValue, bool, Option<String>)> = variable_manager
+        .list_variables(context)?
+        .into_iter()
+        .map(|(name, value)| (name, (value, false, None)))
+        .collect();
+    for (name, value) in variable_manager.list_secrets(context)? {
+        let value = shown(&name, value)?;
+        variables.insert(name, (value, true, None));
+    }
+    for (name, inherited) in variable_manager.list_inherited(context)? {
+        if variables.contains_key(&name) {
+            continue;
+        }
+        let value = if inherited.secret {
+            shown(&name, inherited.value)?
+        } else {
+            inherited.value
+        };
+        variables.insert(name, (value, inherited.secret, Some(inherited.context)));
+    }
+
+    // Command name to variable name to (shown value, secret)
+    let mut scoped = variable_manager.list_command_variables(context)?;
+    for (name, (value, secret)) in scoped.values_mut().flat_map(BTreeMap::iter_mut) {
+        if *secret && !reveal {
+            *value = VarValue::from(SECRET_MASK);
+        } else if *secret {
+            *value = variable_manager.reveal(name, value.clone())?;
+        }
+    }
+
+    // Placeholders without a value, with the commands that have no
+    // command-scoped value for them either
+    let pending: BTreeMap<&String, Vec<&str>> = used_by
+        .iter()
+        .filter(|(name, _)| !variables.contains_key(*name))
+        .map(|(name, commands)| {
+            let commands = commands
+                .iter()
+                .copied()
+                .filter(|command| {
+                    !scoped
+                        .get(*command)
+                        .is_some_and(|vars| vars.contains_key(name))
+                })
+                .collect::<Vec<_>>();
+            (name, commands)
+        })
+        .filter(|(_, commands)| !commands.is_empty())
+        .collect();
+
+    if format == OutputFormat::Json {
+        let listed = |value: Option<VarValue>, secret: bool, used_by: Vec<String>| ListedVariable {
+            value,
+            secret,
+            expires_at: None,
+            expired: false,
+            description: None,
+            updated_at: None,
+            used_by,
+            inherited_from: None,
+        };
+        let mut vars = BTreeMap::new();
+        for (name, (value, secret, inherited_from)) in &variables {
+            let used_by = used_by.get(name).into_iter().flatten();
+            let mut entry = listed(
+                Some(value.clone()),
+                *secret,
+                used_by.map(|command| command.to_string()).collect(),
+            );
+            if inherited_from.is_some() {
+                entry.inherited_from = inherited_from.clone();
+            } else {
+                entry.expires_at = expires.get(name).copied();
+                entry.expired = entry.expires_at.is_some_and(|at| at <= now);
+                if let Some(meta) = meta.get(name) {
+                    entry.description = meta.description.clone();
+                    entry.updated_at = meta.updated_at;
+                }
+            }
+            vars.insert(name.clone(), entry);
+        }
+        // Placeholders without a value are listed with a null value
+        for (name, commands) in &pending {
+            let commands = commands.iter().map(|command| command.to_string()).collect();
+            vars.insert(name.to_string(), listed(None, false, commands));
+        }
+        let commands = scoped
+            .iter()
+            .map(|(command, scoped_vars)| {
+                let scoped_vars = scoped_vars
+                    .iter()
+                    .map(|(name, (value, secret))| {
+                        let entry = listed(Some(value.clone()), *secret, vec![command.clone()]);
+                        (name.clone(), entry)
+                    })
+                    .collect();
+                (command.clone(), scoped_vars)
+            })
+            .collect();
+        return print_json(&VariableListing {
+            context: context.to_string(),
+            vars,
+            commands,
+        });
+    }
+
+    let variables: BTreeMap<String, String> = variables
+        .into_iter()
+        .map(|(name, (value, secret, inherited_from))| {
+            let mut notes = Vec::new();
+            if value.is_list() {
+                notes.push(format!("list of {}", value.items().len()));
+            }
+            if secret {
+                notes.push("secret".to_string());
+            }
+            if let Some(parent) = inherited_from {
+                notes.push(format!("from {parent}"));
+                let value = format!("{value} ({})", notes.join(", "));
+                return (name, value);
+            }
+            match expires.get(&name) {
+                Some(at) if *at <= now => notes.push("expired".to_string()),
+                Some(at) => notes.push(format!("expires in {}", format_remaining(*at - now))),
+                None => {}
+            }
+            if let Some(at) = meta.get(&name).and_then(|meta| meta.updated_at) {
+                notes.push(format!("updated {}", format_age(now - at)));
+            }
+            let value = if notes.is_empty() {
+                value.to_string()
+            } else {
+                format!("{value} ({})", notes.join(", "))
+            };
+            (name, value)
+        })
+        .collect();
+    if variables.is_empty() && scoped.is_empty() {
+        println!("No variables set in context {}", context.blue().bold());
+    } else if variables.is_empty() {
+        println!(
+            "No context-wide variables set in context {}",
+            context.blue().bold()
+        );
+    } else {
+        println!("Variables in context {}:", context.blue().bold());
+        let name_width = variables
+            .keys()
+            .map(|name| name.len())
+            .max()
+            .unwrap_or(0)
+            .max(4);
+        let value_width = variables
+            .values()
+            .map(|v| v.len())
+            .max()
+            .unwrap_or(0)
+            .max(5);
+        println!(
+            "  {}  {}  {}",
+            format!("{:<name_width$}", "NAME").bold(),
+            format!("{:<value_width$}", "VALUE").bold(),
+            "USED BY".bold()
+        );
+        for (name, value) in &variables {
+            let commands = used_by
+                .get(name)
+                .map(|commands| commands.join(", "))
+                .unwrap_or_else(|| "-".to_string());
+            println!(
+                "  {}  {}  {}",
+                format!("{name:<name_width$}").cyan(),
+                format!("{value:<value_width$}").yellow(),
+                commands
+            );
+            if let Some(description) = meta.get(name).and_then(|meta| meta.description.as_ref()) {
+                println!(
+                    "  {:name_width$}  {}",
+                    "",
+                    description.truecolor(140, 140, 140)
+                );
+            }
+        }
+    }
+
+    if !scoped.is_empty() {
+        println!("\nCommand-scoped variables:");
+        for (command, vars) in &scoped {
+            println!("  {}", command.bold());
+            for (name, (value, secret)) in vars {
+                let note = if *secret { " (secret)" } else { "" };
+                println!(
+                    "    {}  {}{}",
+                    name.cyan(),
+                    value.to_string().yellow(),
+                    note
+                );
+            }
+        }
+    }
+
+    if !pending.is_empty() {
+        println!("\nPlaceholders without a value in this context:");
+        for (name, commands) in pending {
+            println!(
+                "  {}  {}",
+                name.cyan(),
+                commands.join(", ").truecolor(140, 140, 140)
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Print only the value, so the output can be used in command substitution
+fn handle_variable_get_command(
+    matches: &ArgMatches,
+    variable_manager: &VariableManager,
+    context_manager: &ContextManager,
+) -> Result<()> {
+    let name = variable_key(matches.get_one::<String>("name").unwrap());
+    let context = matches
+        .get_one::<String>("context")
+        .map(String::as_str)
+        .unwrap_or(context_manager.current_context());
+
+    match variable_manager.effective_variable(context, &name)? {
+        Some(value) => {
+            println!("{value}");
+            Ok(())
+        }
+        None => Err(anyhow::anyhow!(
+            "Variable '{}' is not set in context '{}'",
+            name,
+            context
+        )),
+    }
+}
+
+fn handle_variable_remove_command(
+    matches: &ArgMatches,
+    variable_manager: &mut VariableManager,
+    context_manager: &ContextManager,
+) -> Result<()> {
+    let name = variable_key(matches.get_one::<String>("name").unwrap());
+    let context = context_manager.current_context();
+
+    if let Some(command) = matches.get_one::<String>("command") {
+        if !variable_manager.remove_command_variable(context, command, &name)? {
+            return Err(anyhow::anyhow!(
+                "Variable '{}' is not set for command '{}' in context '{}'",
+                name,
+                command,
+                context
+            ));
+        }
+        println!(
+            "{} Variable {} for command {} removed from context {}",
+            "✓".green().bold(),
+            name.cyan().bold(),
+            command.cyan(),
+            context.blue().bold()
+        );
+        return Ok(());
+    }
+
+    // Only the context's own value is removed; an inherited one stays
+    let removed = variable_manager.remove_variable(context, &name)?;
+    let inherited = variable_manager.list_inherited(context)?.remove(&name);
+    match (removed, inherited) {
+        (true, inherited) => {
+            println!(
+                "{} Variable {} removed from context {}",
+                "✓".green().bold(),
+                name.cyan().bold(),
+                context.blue().bold()
+            );
+            if let Some(inherited) = inherited {
+                println!(
+                    "  The value from context {} applies again",
+                    inherited.context.blue().bold()
+                );
+            }
+            Ok(())
+        }
+        (false, Some(inherited)) => Err(anyhow::anyhow!(
+            "Variable '{}' is not set in context '{}', it is inherited from '{}'; remove it there to drop it",
+            name,
+            context,
+            inherited.context
+        )),
+        (false, None) => Err(anyhow::anyhow!(
+            "Variable '{}' is not set in context '{}'",
+            name,
+            context
+        )),
+    }
+}
+
+fn handle_variable_history_command(
+    matches: &ArgMatches,
+    variable_manager: &VariableManager,
+    context_manager: &ContextManager,
+    format: OutputFormat,
+) -> Result<()> {
+    let name = variable_key(matches.get_one::<String>("name").unwrap());
+    let context = context_manager.current_context();
+    let reveal = matches.get_flag("reveal");
+    let history = variable_manager.history(context, &name)?;
+    let shown = |entry: &HistoryEntry| {
+        if entry.secret && !reveal {
+            SECRET_MASK.to_string()
+        } else {
+            entry.value.to_string()
+        }
+    };
+
+    if format == OutputFormat::Json {
+        for entry in &history {
+            print_json(&serde_json::json!({
+                "context": context,
+                "name": name,
+                "value": shown(entry),
+                "secret": entry.secret,
+                "replaced_at": entry.replaced_at.to_rfc3339(),
+            }))?;
+        }
+        return Ok(());
+    }
+
+    if history.is_empty() {
+        println!(
+            "No previous values of {} in context {}",
+            name.cyan().bold(),
+            context.blue().bold()
+        );
+        return Ok(());
+    }
+    println!(
+        "Previous values of {} in context {}:",
+        name.cyan().bold(),
+        context.blue().bold()
+    );
+    for entry in &history {
+        let replaced_at = entry
+            .replaced_at
+            .with_timezone(&chrono::Local)
+            .format("%Y-%m-%d %H:%M:%S");
+        let secret = if entry.secret { " (secret)" } else { "" };
+        println!(
+            "  {}  {}{}",
+            replaced_at.to_string().truecolor(140, 140, 140),
+            shown(entry).yellow(),
+            secret
+        );
+    }
+    Ok(())
+}
+
+fn handle_variable_undo_command(
+    matches: &ArgMatches,
+    variable_manager: &mut VariableManager,
+    context_manager: &ContextManager,
+) -> Result<()> {
+    let name = variable_key(matches.get_one::<String>("name").unwrap());
+    let context = context_manager.current_context();
+
+    let Some(entry) = variable_manager.undo_variable(context, &name)? else {
+        return Err(anyhow::anyhow!(
+            "Variable '{}' has no previous value in context '{}'",
+            name,
+            context
+        ));
+    };
+    let value = if entry.secret {
+        SECRET_MASK.to_string()
+    } else {
+        entry.value.to_string()
+    };
+    println!(
+        "{} Variable {} restored to {} in context {}",
+        "✓".green().bold(),
+        name.cyan().bold(),
+        value.yellow(),
+        context.blue().bold()
+    );
+    Ok(())
+}
+
+fn handle_variable_export_command(
+    matches: &ArgMatches,
+    variable_manager: &VariableManager,
+    context_manager: &ContextManager,
+) -> Result<()> {
+    let context = matches
+        .get_one::<String>("context")
+        .map(String::as_str)
+        .unwrap_or(context_manager.current_context());
+
+    let Some(file) = matches.get_one::<String>("file").map(PathBuf::from) else {
+        let shell = ShellFormat::from_name(matches.get_one::<String>("format").unwrap())?;
+        print!(
+            "{}",
+            variable_manager.export_as_shell(
+                context,
+                shell,
+                matches.get_flag("include-secrets")
+            )?
+        );
+        return Ok(());
+    };
+    let count =
+        variable_manager.export_variables(context, &file, matches.get_flag("include-secrets"))?;
+    println!(
+        "{} Exported {} variables of context {} to {}",
+        "✓".green().bold(),
+        count,
+        context.blue().bold(),
+        file.display().to_string().cyan()
+    );
+    Ok(())
+}
+
+fn handle_variable_import_command(
+    matches: &ArgMatches,
+    variable_manager: &mut VariableManager,
+    context_manager: &ContextManager,
+) -> Result<()> {
+    let file = PathBuf::from(matches.get_one::<String>("file").unwrap());
+    let context = matches
+        .get_one::<String>("context")
+        .map(String::as_str)
+        .unwrap_or(context_manager.current_context());
+
+    let outcome = variable_manager.import_variables(context, &file, matches.get_flag("replace"))?;
+    println!(
+        "{} Imported {} into context {}",
+        "✓".green().bold(),
+        file.display().to_string().cyan(),
+        context.blue().bold()
+    );
+    for (label, names) in [
+        ("Added", &outcome.added),
+        ("Overwritten", &outcome.overwritten),
+        ("Unchanged", &outcome.unchanged),
+    ] {
+        if !names.is_empty() {
+            println!("  {:<12} {}", format!("{label}:"), names.join(", "));
+        }
+    }
+    Ok(())
+}
+
+fn handle_migrate_secrets_command(
+    matches: &ArgMatches,
+    config_manager: &ConfigManager,
+    variable_manager: &mut VariableManager,
+    context_manager: &ContextManager,
+) -> Result<()> {
+    let contexts = match matches.get_one::<String>("context") {
+        Some(context) => vec![context.clone()],
+        None => context_manager
+            .listed_contexts()?
+            .into_iter()
+            .map(|context| context.name)
+            .collect(),
+    };
+
+    let mut total = 0;
+    for context in &contexts {
+        let moved = variable_manager.migrate_secrets(context)?;
+        if moved.is_empty() {
+            continue;
+        }
+        println!(
+            "{} Moved to the system keychain from context {}:",
+            "✓".green().bold(),
+            context.blue().bold()
+        );
+        for name in &moved {
+            println!("  {}", name.cyan());
+        }
+        total += moved.len();
+    }
+    if total == 0 {
+        println!("No secrets left to migrate; they are all in the system keychain already");
+    }
+    if config_manager.secret_store() != SecretStore::Keyring {
+        println!(
+            "  Set {} in the main config so new secrets go to the keychain too",
+            "secret_store: keyring".bright_white()
+        );
+    }
+    Ok(())
+}
+
+fn ensure_context_exists(context_manager: &ContextManager, context: &str) -> Result<()> {
+    if context_manager
+        .listed_contexts()?
+        .iter()
+        .any(|listed| listed.name == context)
+    {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!("Context '{}' does not exist", context))
+    }
+}
+
+/// "1 variable" or "N variables"
+fn variable_count_label(count: usize) -> String {
+    match count {
+        1 => "1 variable".to_string(),
+        count => format!("{count} variables"),
+    }
+}
+
+/// Print the differences between two contexts. Returns the exit code: 1 if
+/// they differ, so scripts can check it.
+fn handle_variable_diff_command(
+    matches: &ArgMatches,
+    variable_manager: &VariableManager,
+    context_manager: &ContextManager,
+    format: OutputFormat,
+) -> Result<i32> {
+    let a = matches.get_one::<String>("a").unwrap();
+    let b = matches.get_one::<String>("b").unwrap();
+    ensure_context_exists(context_manager, a)?;
+    ensure_context_exists(context_manager, b)?;
+
+    let diff = variable_manager.diff_contexts(a, b)?;
+    let code = if diff.is_empty() { 0 } else { 1 };
+    if format == OutputFormat::Json {
+        print_json(&diff)?;
+        return Ok(code);
+    }
+
+    if diff.is_empty() {
+        println!(
+            "{} Contexts {} and {} have the same variables",
+            "✓".green().bold(),
+            a.blue().bold(),
+            b.blue().bold()
+        );
+        return Ok(code);
+    }
+    let width = diff
+        .only_in_a
+        .keys()
+        .chain(diff.only_in_b.keys())
+        .chain(diff.changed.keys())
+        .map(String::len)
+        .max()
+        .unwrap_or(0);
+    for (context, only) in [(a, &diff.only_in_a), (b, &diff.only_in_b)] {
+        if only.is_empty() {
+            continue;
+        }
+        println!("Only in {}:", context.blue().bold());
+        for (key, variable) in only {
+            println!(
+                "  {}  {}",
+                format!("{key:<width$}").cyan(),
+                variable.value.to_string().yellow()
+            );
+        }
+    }
+    if !diff.changed.is_empty() {
+        println!("Different in {} and {}:", a.blue().bold(), b.blue().bold());
+        for (key, changed) in &diff.changed {
+            println!(
+                "  {}  {} {} {}",
+                format!("{key:<width$}").cyan(),
+                changed.a.value.to_string().yellow(),
+                "→".truecolor(140, 140, 140),
+                changed.b.value.to_string().yellow()
+            );
+        }
+    }
+    Ok(code)
+}
+
+fn handle_variable_copy_command(
+    matches: &ArgMatches,
+    variable_manager: &mut VariableManager,
+) -> Result<()> {
+    let from = matches.get_one::<String>("from").unwrap();
+    let to = matches.get_one::<String>("to").unwrap();
+    let only: Vec<String> = matches
+        .get_many::<String>("only")
+        .map(|names| names.cloned().collect())
+        .unwrap_or_default();
+    let force = matches.get_flag("force");
+    let interactive = std::io::stdin().is_terminal();
+
+    let outcome = variable_manager.copy_variables(from, to, &only, |name| {
+        if force {
+            return Ok(true);
+        }
+        // Without a terminal there is nobody to ask, keep the target's value
+        if !interactive {
+            return Ok(false);
+        }
+        Ok(Confirm::new()
+            .with_prompt(format!("{name} is already set in context {to}. Overwrite?"))
+            .default(false)
+            .interact()?)
+    })?;
+
+    if outcome.copied.is_empty() {
+        println!(
+            "{} No variables copied from {} to {}",
+            "⚠".yellow().bold(),
+            from.blue().bold(),
+            to.blue().bold()
+        );
+    } else {
+        println!(
+            "{} Copied from {} to {}:",
+            "✓".green().bold(),
+            from.blue().bold(),
+            to.blue().bold()
+        );
+        for name in &outcome.copied {
+            println!("  {}", name.cyan());
+        }
+    }
+    if !outcome.skipped.is_empty() {
+        println!("Skipped, already set in {}:", to.blue().bold());
+        for name in &outcome.skipped {
+            println!("  {}", name.cyan());
+        }
+        if !force {
+            println!("Use --force to overwrite them.");
+        }
+    }
+
+    Ok(())
+}
+
+fn handle_context_command(
+    matches: &ArgMatches,
+    context_manager: &mut ContextManager,
+    variable_manager: &mut VariableManager,
+    format: OutputFormat,
+) -> Result<()> {
+    match matches.subcommand() {
+        Some(("list", sub_matches)) => {
+            return handle_context_list_command(context_manager, format.or_from(sub_matches))
+        }
+        Some(("delete", sub_matches)) => {
+            let context_name = sub_matches.get_one::<String>("name").unwrap();
+            let force = sub_matches.get_flag("force");
+            ensure_context_exists(context_manager, context_name)?;
+            if context_name == context_manager.current_context() && !force {
+                // Fails with the hint to use --force before asking anything
+                context_manager.delete_context(context_name, false)?;
+            }
+            let discarded = context_manager.variable_count(context_name)?;
+            if !sub_matches.get_flag("yes") {
+                if !std::io::stdin().is_terminal() {
+                    return Err(anyhow::anyhow!(
+                        "Deleting context '{}' needs confirmation; pass --yes to delete it without asking",
+                        context_name
+                    ));
+                }
+                let what = match discarded {
+                    Some(count) => format!(" and its {}", variable_count_label(count)),
+                    None => " and its encrypted variables".to_string(),
+                };
+                let confirmed = Confirm::new()
+                    .with_prompt(format!("Delete context {context_name}{what}?"))
+                    .default(false)
+                    .interact()?;
+                if !confirmed {
+                    println!("{} Kept context {}", "✗".red().bold(), context_name);
+                    return Ok(());
+                }
+            }
+            let accounts = variable_manager.keychain_accounts(context_name)?;
+            context_manager.delete_context(context_name, force)?;
+            variable_manager.delete_from_keychain(&accounts);
+            println!(
+                "{} Deleted context {}",
+                "✓".green().bold(),
+                context_name.blue().bold()
+            );
+            match discarded {
+                Some(count) => println!("  Discarded {}", variable_count_label(count)),
+                None => println!("  Discarded its encrypted variables"),
+            }
+            println!(
+                "  Active context: {}",
+                context_manager.current_context().blue().bold()
+            );
+            return Ok(());
+        }
+        Some(("encrypt", sub_matches)) => {
+            let context_name = sub_matches.get_one::<String>("name").unwrap();
+            ensure_context_exists(context_manager, context_name)?;
+            if variable_manager.is_encrypted(context_name)? {
+                return Err(anyhow::anyhow!(
+                    "Context '{}' is already encrypted",
+                    context_name
+                ));
+            }
+            let passphrase = encryption::new_passphrase(context_name)?;
+            variable_manager.encrypt_context(context_name, &passphrase)?;
+            println!(
+                "{} Encrypted context {}",
+                "✓".green().bold(),
+                context_name.blue().bold()
+            );
+            println!(
+                "  doo asks for the passphrase when it needs the variables, or reads it from {}",
+                encryption::PASSPHRASE_ENV
+            );
+            return Ok(());
+        }
+        Some(("decrypt", sub_matches)) => {
+            let context_name = sub_matches.get_one::<String>("name").unwrap();
+            ensure_context_exists(context_manager, context_name)?;
+            variable_manager.decrypt_context(context_name)?;
+            println!(
+                "{} Decrypted context {}",
+                "✓".green().bold(),
+                context_name.blue().bold()
+            );
+            return Ok(());
+        }
+        Some(("clone", sub_matches)) => {
+            let src = sub_matches.get_one::<String>("src").unwrap();
+            let dst = sub_matches.get_one::<String>("dst").unwrap();
+            context_manager.clone_context(src, dst, sub_matches.get_flag("force"))?;
+            variable_manager.adopt_secrets(dst)?;
+            println!(
+                "{} Cloned context {} to {}",
+                "✓".green().bold(),
+                src.blue().bold(),
+                dst.blue().bold()
+            );
+            println!("  Switch to it with: doo context {dst}");
+            return Ok(());
+        }
+        Some(("rename", sub_matches)) => {
+            let old = sub_matches.get_one::<String>("old").unwrap();
+            let new = sub_matches.get_one::<String>("new").unwrap();
+            context_manager.rename_context(old, new)?;
+            variable_manager.adopt_secrets(new)?;
+            println!(
+                "{} Renamed context {} to {}",
+                "✓".green().bold(),
+                old.blue().bold(),
+                new.blue().bold()
+            );
+            println!(
+                "  Active context: {}",
+                context_manager.current_context().blue().bold()
+            );
+            return Ok(());
+        }
+        Some(("pin", sub_matches)) => {
+            let context_name = sub_matches.get_one::<String>("name").unwrap();
+            let pin = ContextManager::pin_context(&std::env::current_dir()?, context_name)?;
+            println!(
+                "{} Pinned context {} in {}",
+                "✓".green().bold(),
+                context_name.blue().bold(),
+                pin.display().to_string().cyan()
+            );
+            println!("  doo uses it in this directory and below; undo with doo context unpin");
+            return Ok(());
         }
-        None => {
-            // No subcommand provided, show interactive menu
-            let menu = InteractiveMenu::new(&config_manager, &variable_manager, &context_manager)?;
-            menu.run(&executor)?;
+        Some(("unpin", _)) => {
+            let Some(pin) = context_manager.unpin_context()? else {
+                return Err(anyhow::anyhow!(
+                    "No {} here or in a parent directory pins the context",
+                    context::CONTEXT_PIN_FILE
+                ));
+            };
+            println!(
+                "{} Removed {}",
+                "✓".green().bold(),
+                pin.display().to_string().cyan()
+            );
+            println!(
+                "  Active context: {}",
+                context_manager.current_context().blue().bold()
+            );
+            return Ok(());
         }
+        _ => {}
     }
 
-    Ok(())
-}
-
-fn build_cli() -> Command {
-    Command::new("doo")
-        .about("A CLI wrapper for other commands with persistent variables and contexts")
-        .version("0.1.0")
-        .author("Your Name")
-        .arg_required_else_help(false)
-        .subcommand(
-            Command::new("var")
-                .about("Manage variables")
-                .arg(
-                    Arg::new("name")
-                        .help("Variable name (e.g., #1)")
-                        .required(true)
-                        .index(1),
-                )
-                .arg(
-                    Arg::new("value")
-                        .help("Variable value")
-                        .required(true)
-                        .index(2),
-                ),
-        )
-        .subcommand(
-            Command::new("context").about("Switch context").arg(
-                Arg::new("name")
-                    .help("Context name")
-                    .required(true)
-                    .index(1),
-            ),
-        )
-        .subcommand(
-            Command::new("import")
-                .about("Import a config file from local path or GitHub repository")
-                .arg(
-                    Arg::new("file")
-                        .help("Path to config file or GitHub repository (owner/repo)")
-                        .required(true)
-                        .index(1),
-                ),
-        )
-        .subcommand(
-            Command::new("import-repo")
-                .about("Import all YAML config files from a GitHub repository")
-                .arg(
-                    Arg::new("repo")
-                        .help("GitHub repository (owner/repo)")
-                        .required(true)
-                        .index(1),
-                ),
-        )
-        .subcommand(
-            Command::new("sync").about("Sync all imported configs with their remote origins"),
+    let context_name = matches.get_one::<String>("name").unwrap();
+    let parent = matches.get_one::<String>("parent");
+    let pinned = context_manager.pin_file().map(|pin| {
+        (
+            pin.to_path_buf(),
+            context_manager.current_context().to_string(),
         )
-        .allow_external_subcommands(true)
+    });
+    context_manager.set_run_hooks(!matches.get_flag("no-hooks"));
+    context_manager.switch_context(context_name, parent.map(String::as_str))?;
+    println!(
+        "{} Switched to context {}",
+        "✓".green().bold(),
+        context_name.blue().bold()
+    );
+    if let Some(parent) = context_manager.parent_of(context_name)? {
+        println!("  Inherits variables from {}", parent.blue());
+    }
+    if let Some((pin, pinned)) = pinned.filter(|(_, pinned)| pinned != context_name) {
+        eprintln!(
+            "{} {} pins context {} here, so doo keeps using it in this directory; run doo context unpin to remove it",
+            "⚠".yellow(),
+            pin.display(),
+            pinned
+        );
+    }
+
+    Ok(())
 }
 
-fn handle_variable_command(
-    matches: &ArgMatches,
-    variable_manager: &mut VariableManager,
+/// Print the active context, its variables file and counts, the variable
+/// changed last and the commands with `#` placeholders it has no value for
+fn handle_context_show_command(
+    config_manager: &ConfigManager,
+    variable_manager: &VariableManager,
     context_manager: &ContextManager,
+    format: OutputFormat,
 ) -> Result<()> {
-    let name = matches.get_one::<String>("name").unwrap();
-    let value = matches.get_one::<String>("value").unwrap();
+    let context = context_manager.current_context();
+    let parent = context_manager.parent_of(context)?;
+    let file = variable_manager.variables_file(context);
+    let variables = variable_manager.list_variables(context)?.len();
+    let secrets = variable_manager.list_secrets(context)?.len();
+    let last_changed = variable_manager
+        .metadata(context)?
+        .into_iter()
+        .filter_map(|(name, meta)| Some((meta.updated_at?, name)))
+        .max();
+    let env = config_manager.context_env(context);
+
+    let mut names: Vec<String> = config_manager.list_commands().into_keys().collect();
+    names.sort();
+    let mut unset = Vec::new();
+    for name in names {
+        let Some(source) = config_manager.get_command_with_source(&name)? else {
+            continue;
+        };
+        let missing: Vec<String> = variable_manager
+            .missing_placeholders(context, Some(&name), &source.command, &[], &source.defaults)?
+            .into_iter()
+            .filter(|label| label.starts_with('#'))
+            .collect();
+        if !missing.is_empty() {
+            unset.push((name, missing));
+        }
+    }
+
+    let pin = context_manager.pin_file();
+    if format == OutputFormat::Json {
+        return print_json(&serde_json::json!({
+            "context": context,
+            "pinned_by": pin,
+            "parent": parent,
+            "variables_file": file,
+            "variables_file_exists": file.exists(),
+            "variables": variables + secrets,
+            "secrets": secrets,
+            "env": env,
+            "last_changed": last_changed.as_ref().map(|(updated_at, name)| serde_json::json!({
+                "name": name,
+                "updated_at": updated_at,
+            })),
+            "unset": unset
+                .iter()
+                .map(|(command, placeholders)| serde_json::json!({
+                    "command": command,
+                    "placeholders": placeholders,
+                }))
+                .collect::<Vec<_>>(),
+        }));
+    }
 
-    variable_manager.set_variable(context_manager.current_context(), name, value)?;
+    let grey = |text: &str| text.truecolor(140, 140, 140);
+    let label = |text: &str| format!("{text:<10}").bold();
+    let parent = parent
+        .map(|parent| format!(" (inherits from {parent})"))
+        .unwrap_or_default();
     println!(
-        "{} Variable {} set to {} in context {}",
-        "✓".green().bold(),
-        name.cyan().bold(),
-        value.yellow(),
-        context_manager.current_context().blue().bold()
+        "{}{}{}",
+        label("Context"),
+        context.blue().bold(),
+        grey(&parent)
+    );
+    if let Some(pin) = pin {
+        println!("{}{}", label("Pinned by"), pin.display().to_string().cyan());
+    }
+    let missing_file = if file.exists() {
+        ""
+    } else {
+        " (not created yet)"
+    };
+    println!(
+        "{}{}{}",
+        label("File"),
+        file.display().to_string().cyan(),
+        grey(missing_file)
+    );
+    println!(
+        "{}{} set, {} secret",
+        label("Variables"),
+        (variables + secrets).to_string().yellow(),
+        secrets.to_string().yellow()
     );
+    if let Some((updated_at, name)) = &last_changed {
+        println!(
+            "{}{} {}",
+            label("Changed"),
+            name.cyan(),
+            grey(&format_age(Utc::now() - *updated_at))
+        );
+    }
+    // Shown as configured; placeholders are resolved when a command runs
+    for (i, (key, value)) in env.iter().enumerate() {
+        let name = if i == 0 { "Env" } else { "" };
+        println!("{}{}={}", label(name), key.cyan(), value);
+    }
 
+    if unset.is_empty() {
+        println!(
+            "{} Every command has values for its variables",
+            "✓".green().bold()
+        );
+        return Ok(());
+    }
+    println!("{}", "Commands missing values".bold());
+    let width = unset.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    for (name, missing) in &unset {
+        println!(
+            "  {} {}  {}",
+            "⚠".yellow().bold(),
+            format!("{name:<width$}").cyan(),
+            missing.join(", ").yellow()
+        );
+    }
     Ok(())
 }
 
-fn handle_context_command(
-    matches: &ArgMatches,
-    context_manager: &mut ContextManager,
+fn handle_context_list_command(
+    context_manager: &ContextManager,
+    format: OutputFormat,
 ) -> Result<()> {
-    let context_name = matches.get_one::<String>("name").unwrap();
-    context_manager.switch_context(context_name)?;
-    println!(
-        "{} Switched to context {}",
-        "✓".green().bold(),
-        context_name.blue().bold()
-    );
+    if format == OutputFormat::Json {
+        return print_json(&context_manager.listed_contexts()?);
+    }
+
+    fn width(node: &ContextNode, depth: usize) -> usize {
+        node.children
+            .iter()
+            .map(|child| width(child, depth + 1))
+            .fold(depth * 2 + node.name.len(), usize::max)
+    }
+
+    fn print_node(
+        node: &ContextNode,
+        depth: usize,
+        width: usize,
+        context_manager: &ContextManager,
+    ) -> Result<()> {
+        let indent = "  ".repeat(depth);
+        let padding = " ".repeat(width - depth * 2 - node.name.len());
+        let variables = match context_manager.variable_count(&node.name)? {
+            Some(count) => variable_count_label(count),
+            None => "encrypted".to_string(),
+        };
+        let variables = variables.truecolor(140, 140, 140);
+        if node.name == context_manager.current_context() {
+            println!(
+                "{indent}{} {}{padding}  {variables}",
+                "*".green().bold(),
+                node.name.green().bold()
+            );
+        } else {
+            println!("{indent}  {}{padding}  {variables}", node.name);
+        }
+        for child in &node.children {
+            print_node(child, depth + 1, width, context_manager)?;
+        }
+        Ok(())
+    }
+
+    let contexts = context_manager.list_contexts()?;
+    let width = contexts
+        .iter()
+        .map(|node| width(node, 0))
+        .max()
+        .unwrap_or(0);
+    println!("{}", "Contexts:".bold());
+    for node in &contexts {
+        print_node(node, 0, width, context_manager)?;
+    }
 
     Ok(())
 }
@@ -173,11 +3510,51 @@ async fn handle_import_command(
     config_manager: &mut ConfigManager,
 ) -> Result<()> {
     let file_path = matches.get_one::<String>("file").unwrap();
+    let token = matches.get_one::<String>("token");
+    let is_github_repo =
+        file_path.contains('/') && !file_path.contains('.') && !file_path.starts_with('/');
+    if token.is_some() && (is_url(file_path) || !is_github_repo) {
+        return Err(anyhow::anyhow!(
+            "--token only applies to GitHub repositories (owner/repo)"
+        ));
+    }
+    let namespace = matches.get_one::<String>("as");
+    if let Some(namespace) = namespace {
+        config_manager.check_namespace(namespace)?;
+    }
 
-    // Check if it's a GitHub repository (contains /)
-    if file_path.contains('/') && !file_path.contains('.') && !file_path.starts_with('/') {
+    if is_url(file_path) {
+        let imported = config_manager.import_config_from_url(file_path).await;
+        match with_namespace(config_manager, imported, namespace) {
+            Ok(imported_name) => {
+                println!(
+                    "{} Successfully imported config from '{}' as '{}'",
+                    "✓".green().bold(),
+                    file_path.cyan().bold(),
+                    imported_name.cyan().bold()
+                );
+            }
+            Err(e) => {
+                println!(
+                    "{} Failed to import from '{}': {}",
+                    "✗".red().bold(),
+                    file_path.yellow(),
+                    e.to_string().red()
+                );
+                return Err(Exit(1).into());
+            }
+        }
+    } else if is_github_repo {
         // GitHub repository format: owner/repo
-        match config_manager.import_config_from_github(file_path).await {
+        let imported = match token {
+            Some(token) => {
+                config_manager
+                    .import_config_from_github_with_token(file_path, token)
+                    .await
+            }
+            None => config_manager.import_config_from_github(file_path).await,
+        };
+        match with_namespace(config_manager, imported, namespace) {
             Ok(imported_name) => {
                 println!(
                     "{} Successfully imported config from GitHub repository '{}' as '{}'",
@@ -193,18 +3570,22 @@ async fn handle_import_command(
                     file_path.yellow(),
                     e.to_string().red()
                 );
-                process::exit(1);
+                return Err(Exit(1).into());
             }
         }
     } else {
         // Local file import
-        match config_manager.import_config(file_path) {
+        let imported = config_manager.import_config(file_path);
+        match with_namespace(config_manager, imported, namespace) {
             Ok(imported_name) => {
                 println!(
                     "{} Successfully imported config file as '{}'",
                     "✓".green().bold(),
                     imported_name.cyan().bold()
                 );
+                if config_manager.json_configs().contains(&imported_name) {
+                    warn_json_config(&imported_name);
+                }
             }
             Err(e) => {
                 println!(
@@ -212,21 +3593,42 @@ async fn handle_import_command(
                     "✗".red().bold(),
                     e.to_string().red()
                 );
-                process::exit(1);
+                return Err(Exit(1).into());
             }
         }
     }
+    if let Some(namespace) = namespace {
+        println!(
+            "  Run its commands as {}",
+            format!("{namespace}:<command>").cyan()
+        );
+    }
 
     Ok(())
 }
 
+/// Store a freshly imported config under `namespace`, if one was given
+fn with_namespace(
+    config_manager: &mut ConfigManager,
+    imported: Result<String>,
+    namespace: Option<&String>,
+) -> Result<String> {
+    let imported_name = imported?;
+    match namespace {
+        Some(namespace) => config_manager.namespace_config(&imported_name, namespace),
+        None => Ok(imported_name),
+    }
+}
+
 async fn handle_import_repo_command(
     matches: &ArgMatches,
     config_manager: &mut ConfigManager,
 ) -> Result<()> {
     let repo = matches.get_one::<String>("repo").unwrap();
 
-    match config_manager.import_repo_configs(repo).await {
+    let token = matches.get_one::<String>("token").map(String::as_str);
+
+    match config_manager.import_repo_configs(repo, token).await {
         Ok(imported_configs) => {
             println!(
                 "{} Successfully imported {} config file(s) from repository '{}':",
@@ -245,7 +3647,7 @@ async fn handle_import_repo_command(
                 repo.yellow(),
                 e.to_string().red()
             );
-            process::exit(1);
+            return Err(Exit(1).into());
         }
     }
 
@@ -263,36 +3665,373 @@ async fn handle_sync_command(config_manager: &mut ConfigManager) -> Result<()> {
                 "✗".red().bold(),
                 e.to_string().red()
             );
-            process::exit(1);
+            return Err(Exit(1).into());
+        }
+    }
+
+    Ok(())
+}
+
+async fn handle_sync_single_command(
+    config_name: &str,
+    config_manager: &mut ConfigManager,
+) -> Result<()> {
+    let origin = config_manager.sync_origin(config_name)?;
+    let before = config_manager.config_checksum(config_name)?;
+
+    println!("🔄 Syncing {config_name} from {}...", origin.repo);
+    config_manager
+        .sync_single_config(config_name, &origin)
+        .await
+        .with_context(|| format!("Failed to sync config '{config_name}'"))?;
+    let after = config_manager.config_checksum(config_name)?;
+
+    println!("   Before: {}", &before[..12]);
+    println!("   After:  {}", &after[..12]);
+    if before == after {
+        println!(
+            "{} {} is already up to date",
+            "✓".green().bold(),
+            config_name
+        );
+    } else {
+        println!(
+            "{} Updated {} from its origin",
+            "✓".green().bold(),
+            config_name.cyan()
+        );
+    }
+
+    Ok(())
+}
+
+/// Print how the configs differ from their origins, returning 1 if any do
+async fn handle_sync_preview_command(
+    config_name: Option<&String>,
+    config_manager: &mut ConfigManager,
+) -> Result<i32> {
+    let diffs = match config_name {
+        Some(config_name) => {
+            let origin = config_manager.sync_origin(config_name)?;
+            vec![
+                config_manager
+                    .preview_sync_config(config_name, &origin)
+                    .await?,
+            ]
+        }
+        None => config_manager.preview_sync_all().await?,
+    };
+
+    if diffs.is_empty() {
+        println!("📦 No imported configs with remote origins found. Nothing to sync.");
+        return Ok(0);
+    }
+
+    let mut drifted = 0;
+    for diff in &diffs {
+        if diff.is_empty() {
+            println!("{} {} is up to date", "✓".green().bold(), diff.name);
+            continue;
+        }
+        drifted += 1;
+        println!(
+            "{} {} differs from its origin: {} added, {} removed, {} changed",
+            "≠".yellow().bold(),
+            diff.name.cyan(),
+            diff.added.len(),
+            diff.removed.len(),
+            diff.changed.len()
+        );
+        for name in &diff.added {
+            println!("  {} {}", "+".green(), name);
+        }
+        for name in &diff.removed {
+            println!("  {} {}", "-".red(), name);
+        }
+        for name in &diff.changed {
+            println!("  {} {}", "~".yellow(), name);
         }
+        for line in diff.unified.lines() {
+            if line.starts_with("+++") || line.starts_with("---") {
+                println!("{}", line.bold());
+            } else if line.starts_with('+') {
+                println!("{}", line.green());
+            } else if line.starts_with('-') {
+                println!("{}", line.red());
+            } else if line.starts_with("@@") {
+                println!("{}", line.cyan());
+            } else {
+                println!("{line}");
+            }
+        }
+    }
+
+    if drifted == 0 {
+        println!("\n✅ All configs are up to date.");
+        Ok(0)
+    } else {
+        println!("\n{drifted} config(s) would change. Run 'doo sync' to update them.");
+        Ok(1)
+    }
+}
+
+fn handle_backup_command(matches: &ArgMatches, config_manager: &ConfigManager) -> Result<()> {
+    let archive_path = match matches.get_one::<String>("output") {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(format!(
+            "doo-backup-{}.tar.gz",
+            chrono::Local::now().format("%Y%m%d-%H%M%S")
+        )),
+    };
+    let include_git = !matches.get_flag("exclude-git");
+
+    let file_count = config_manager.create_backup(&archive_path, include_git)?;
+    println!(
+        "{} Backed up {} file(s) to {}",
+        "✓".green().bold(),
+        file_count,
+        archive_path.display().to_string().cyan().bold()
+    );
+
+    Ok(())
+}
+
+fn handle_restore_command(matches: &ArgMatches, config_manager: &ConfigManager) -> Result<()> {
+    let archive_path = PathBuf::from(matches.get_one::<String>("file").unwrap());
+    let merge = matches.get_flag("merge");
+
+    if !archive_path.exists() {
+        return Err(anyhow::anyhow!(
+            "Backup file does not exist: {}",
+            archive_path.display()
+        ));
+    }
+
+    if merge {
+        println!("Files missing from your config directory will be restored from the backup.");
+    } else {
+        println!("\n⚠️  WARNING: This will replace all configs, variables and contexts!");
+        println!("   Current state will be lost and replaced with the backup content.");
+    }
+
+    let confirmed = Confirm::new()
+        .with_prompt("Do you want to continue with the restore?")
+        .default(false)
+        .interact()?;
+
+    if !confirmed {
+        println!("❌ Restore cancelled by user.");
+        return Ok(());
     }
 
+    let file_count = config_manager.restore_backup(&archive_path, merge)?;
+    println!(
+        "{} Restored {} file(s) from {}",
+        "✓".green().bold(),
+        file_count,
+        archive_path.display().to_string().cyan().bold()
+    );
+
     Ok(())
 }
 
-fn handle_command_execution(
+/// Working directory of `source` with its placeholders resolved
+fn resolve_cwd(
+    variable_manager: &VariableManager,
+    context: &str,
+    source: &config::CommandSource,
+    args: &[String],
+    defaults: &BTreeMap<String, String>,
+) -> Result<Option<PathBuf>> {
+    let cwd = source
+        .cwd
+        .as_deref()
+        .map(|cwd| {
+            variable_manager.resolve_variables_with_defaults(
+                context,
+                Some(&source.name),
+                cwd,
+                args,
+                defaults,
+            )
+        })
+        .transpose()?;
+    Ok(cwd.map(PathBuf::from))
+}
+
+/// Run the commands matching the pattern of `run-all` at the same time and
+/// print their output one after another. Missing values are asked for
+/// before anything runs. Returns 1 if any of them failed.
+async fn handle_run_all_command(
+    matches: &ArgMatches,
+    prompt: PromptMode,
+    config_manager: &ConfigManager,
+    variable_manager: &mut VariableManager,
+    context_manager: &ContextManager,
+    executor: &CommandExecutor,
+    format: OutputFormat,
+) -> Result<i32> {
+    let pattern = matches.get_one::<String>("pattern").unwrap();
+    let concurrency = matches
+        .get_one::<u64>("concurrency")
+        .map(|&n| n as usize)
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(4, |n| n.get()));
+    let names = config_manager.commands_matching(pattern);
+    if names.is_empty() {
+        return Err(anyhow::anyhow!("No command matches '{}'", pattern));
+    }
+
+    let context = context_manager.current_context();
+    let mut commands = Vec::new();
+    let mut sources = Vec::new();
+    for name in &names {
+        let source = config_manager
+            .get_command_with_source(name)?
+            .with_context(|| format!("Command '{name}' not found"))?;
+        let defaults =
+            prompt_missing_placeholders(variable_manager, context, &source, &[], prompt)?;
+        let (command_line, values) = variable_manager.resolve_variables_with_values(
+            context,
+            Some(&source.name),
+            &source.command,
+            &[],
+            &defaults,
+            source.quote_args,
+        )?;
+        source.check_args(&values)?;
+        let shown = variable_manager.mask_secrets(context, &command_line)?;
+        if format == OutputFormat::Text {
+            println!(
+                "{} {} {}",
+                executor.label(),
+                format!("{name}:").cyan(),
+                shown.bright_white()
+            );
+        }
+        commands.push(ParallelCommand {
+            name: name.clone(),
+            env: variable_manager.resolve_env(
+                context,
+                Some(&source.name),
+                &config_manager.command_env(context, &source),
+                &[],
+                &defaults,
+            )?,
+            cwd: resolve_cwd(variable_manager, context, &source, &[], &defaults)?,
+            timeout: source.timeout_secs.map(Duration::from_secs),
+            shell: source.shell,
+            command_line,
+        });
+        sources.push((source.source_file, shown));
+    }
+    if executor.is_dry_run() {
+        return Ok(0);
+    }
+
+    let results = executor.execute_parallel(&commands, concurrency).await?;
+    let mut history = HistoryManager::new(config_manager);
+    for (result, (source, shown)) in results.iter().zip(&sources) {
+        Recording {
+            history: &mut history,
+            name: &result.name,
+            source,
+            command: shown,
+        }
+        .finish(
+            Some(result.exit_code),
+            Duration::from_millis(result.duration_ms),
+        );
+    }
+
+    match format {
+        OutputFormat::Json => {
+            for result in &results {
+                print_json(result)?;
+            }
+        }
+        OutputFormat::Text => print_execution_results(&results),
+    }
+    Ok(i32::from(results.iter().any(|result| !result.success())))
+}
+
+/// Output of each command run by `run-all` under a line with how it went,
+/// then which ones failed
+fn print_execution_results(results: &[ExecutionResult]) {
+    for result in results {
+        let outcome = if result.success() {
+            "✓".green().bold()
+        } else {
+            "✗".red().bold()
+        };
+        let details = match result.exit_code {
+            0 => format!("{:.1}s", result.duration_ms as f64 / 1000.0),
+            code => format!(
+                "exit code {code}, {:.1}s",
+                result.duration_ms as f64 / 1000.0
+            ),
+        };
+        println!(
+            "{} {} {} {}",
+            "──".bright_black(),
+            outcome,
+            result.name.cyan().bold(),
+            details.truecolor(140, 140, 140)
+        );
+        print!("{}", result.stdout);
+        eprint!("{}", result.stderr);
+    }
+
+    let failed: Vec<&str> = results
+        .iter()
+        .filter(|result| !result.success())
+        .map(|result| result.name.as_str())
+        .collect();
+    if failed.is_empty() {
+        println!(
+            "{} All {} commands completed successfully",
+            "✓".green().bold(),
+            results.len()
+        );
+    } else {
+        println!(
+            "{} {} of {} commands failed: {}",
+            "✗".red().bold(),
+            failed.len(),
+            results.len(),
+            failed.join(", ")
+        );
+    }
+}
+
+async fn handle_command_execution(
     cmd_name: &str,
     args: Vec<String>,
+    prompt: PromptMode,
     config_manager: &mut ConfigManager,
-    variable_manager: &VariableManager,
+    variable_manager: &mut VariableManager,
     context_manager: &ContextManager,
     executor: &CommandExecutor,
-) -> Result<()> {
+) -> Result<ExitStatus> {
     // Check for conflicts first
     let conflicts = config_manager.get_command_conflicts(cmd_name);
 
     if conflicts.is_empty() {
+        let commands = config_manager.list_commands();
+        let suggestion = suggest::closest_match(cmd_name, commands.keys().map(String::as_str))
+            .map(|closest| format!(" Did you mean '{}'?", closest.cyan()))
+            .unwrap_or_default();
         println!(
-            "{} Command '{}' not found. Use 'doo' without arguments to browse available commands.",
+            "{} Command '{}' not found.{} Use 'doo' without arguments to browse available commands.",
             "✗".red().bold(),
-            cmd_name.yellow()
+            cmd_name.yellow(),
+            suggestion
         );
-        process::exit(1);
+        return Err(Exit(1).into());
     }
 
-    let command_template = if conflicts.len() == 1 {
-        // No conflict, use the single command
-        conflicts[0].command.clone()
+    let source = if conflicts.len() == 1 || conflicts[0].kind == ConfigSource::ProjectLocal {
+        // No conflict, or the project config overrides the others
+        &conflicts[0]
     } else {
         // Multiple definitions found, ask user to choose
         println!(
@@ -325,29 +4064,79 @@ fn handle_command_execution(
         io::stdin().read_line(&mut input)?;
 
         match input.trim().parse::<usize>() {
-            Ok(choice) if choice >= 1 && choice <= conflicts.len() => {
-                conflicts[choice - 1].command.clone()
-            }
+            Ok(choice) if choice >= 1 && choice <= conflicts.len() => &conflicts[choice - 1],
             _ => {
                 println!("{} Invalid choice", "✗".red().bold());
-                process::exit(1);
+                return Err(Exit(1).into());
             }
         }
     };
 
-    let resolved_command = variable_manager.resolve_variables(
-        context_manager.current_context(),
-        &command_template,
+    let context = context_manager.current_context();
+    let defaults = prompt_missing_placeholders(variable_manager, context, source, &args, prompt)?;
+    let (resolved_command, values) = variable_manager.resolve_variables_with_values(
+        context,
+        Some(&source.name),
+        &source.command,
         &args,
+        &defaults,
+        source.quote_args,
     )?;
+    source.check_args(&values)?;
 
-    println!(
-        "{} {}",
-        "Executing:".green().bold(),
-        resolved_command.bright_white()
-    );
+    let shown = variable_manager.mask_secrets(context, &resolved_command)?;
+    println!("{} {}", executor.label(), shown.bright_white());
 
-    executor.execute(&resolved_command)?;
+    let env = variable_manager.resolve_env(
+        context,
+        Some(&source.name),
+        &config_manager.command_env(context, source),
+        &args,
+        &defaults,
+    )?;
+    let cwd = resolve_cwd(variable_manager, context, source, &args, &defaults)?;
 
-    Ok(())
+    let (max_attempts, backoff) = source.retry.as_ref().map_or((1, Duration::ZERO), |retry| {
+        (retry.max_attempts, retry.backoff())
+    });
+    let (config_manager, source) = (&*config_manager, &source);
+    let (resolved_command, shown, env, cwd) = (&resolved_command, &shown, &env, cwd.as_deref());
+    retry(max_attempts, backoff, move || async move {
+        let mut history = HistoryManager::new(config_manager);
+        let recording = Recording {
+            history: &mut history,
+            name: &source.name,
+            source: &source.source_file,
+            command: shown,
+        };
+        match source.timeout_secs {
+            Some(secs) if source.shell => {
+                executor
+                    .execute_shell_with_timeout(
+                        resolved_command,
+                        Duration::from_secs(secs),
+                        env,
+                        cwd,
+                        Some(recording),
+                    )
+                    .await
+            }
+            Some(secs) => {
+                executor
+                    .execute_with_timeout(
+                        resolved_command,
+                        Duration::from_secs(secs),
+                        env,
+                        cwd,
+                        Some(recording),
+                    )
+                    .await
+            }
+            None if source.shell => {
+                executor.execute_shell(resolved_command, env, cwd, Some(recording))
+            }
+            None => executor.execute(resolved_command, env, cwd, Some(recording)),
+        }
+    })
+    .await
 }
@@ -0,0 +1,272 @@
+//! External command providers
+//!
+//! Any executable listed under `providers:` in the main config, or named
+//! `doo-provider-<name>` on `PATH` when the main config sets
+//! `discover_providers: true`, can contribute commands. doo runs
+//! `<provider> list`, which must print a JSON array of commands:
+//!
+//! ```json
+//! [{ "name": "deploy", "template": "kubectl apply -f #1", "description": "...", "tags": ["k8s"] }]
+//! ```
+//!
+//! Commands without a `template` are dynamic: their template is fetched with
+//! `<provider> resolve <name>` right before execution. Every provider call is
+//! bounded by a timeout so a slow provider can't hang doo.
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::ffi::OsStr;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// File name prefix of provider executables on PATH
+pub const PROVIDER_PREFIX: &str = "doo-provider-";
+
+/// Maximum time a provider may take to answer
+pub const PROVIDER_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// A command reported by a provider's `list` output
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProviderCommand {
+    pub name: String,
+    /// Missing for dynamic commands resolved with `resolve <name>`
+    #[serde(default)]
+    pub template: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Provider {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+impl Provider {
+    fn from_path(path: PathBuf) -> Self {
+        let stem = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let name = stem
+            .strip_prefix(PROVIDER_PREFIX)
+            .map(str::to_string)
+            .unwrap_or(stem);
+        Self { name, path }
+    }
+
+    /// Label used as the source of the provider's commands
+    pub fn source_label(&self) -> String {
+        format!("plugin:{}", self.name)
+    }
+
+    /// Run `<provider> list` and parse its commands
+    pub fn list(&self, timeout: Duration) -> Result<Vec<ProviderCommand>> {
+        let stdout = self.run(&["list"], timeout)?;
+        serde_json::from_str(&stdout)
+            .with_context(|| format!("Provider '{}' returned invalid JSON", self.name))
+    }
+
+    /// Run `<provider> resolve <name>` to get the template of a dynamic command
+    pub fn resolve(&self, command_name: &str, timeout: Duration) -> Result<String> {
+        let template = self.run(&["resolve", command_name], timeout)?;
+        let template = template.trim();
+        if template.is_empty() {
+            return Err(anyhow!(
+                "Provider '{}' returned no template for '{}'",
+                self.name,
+                command_name
+            ));
+        }
+        Ok(template.to_string())
+    }
+
+    fn run(&self, args: &[&str], timeout: Duration) -> Result<String> {
+        crate::timed_span!("provider", name = self.name, action = args[0]);
+
+        let mut child = Command::new(&self.path)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run provider '{}'", self.name))?;
+
+        // Read pipes on separate threads so a chatty provider can't block on a full pipe
+        let mut stdout_pipe = child.stdout.take().context("Provider stdout unavailable")?;
+        let mut stderr_pipe = child.stderr.take().context("Provider stderr unavailable")?;
+        let stdout_reader = thread::spawn(move || {
+            let mut output = String::new();
+            stdout_pipe.read_to_string(&mut output).map(|_| output)
+        });
+        let stderr_reader = thread::spawn(move || {
+            let mut output = String::new();
+            let _ = stderr_pipe.read_to_string(&mut output);
+            output
+        });
+
+        let started = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if started.elapsed() >= timeout {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(anyhow!(
+                    "Provider '{}' did not answer within {:?}",
+                    self.name,
+                    timeout
+                ));
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+
+        let stdout = stdout_reader
+            .join()
+            .map_err(|_| anyhow!("Failed to read output of provider '{}'", self.name))?
+            .with_context(|| format!("Provider '{}' wrote invalid UTF-8", self.name))?;
+        let stderr = stderr_reader.join().unwrap_or_default();
+
+        if !status.success() {
+            return Err(anyhow!(
+                "Provider '{}' failed ({}): {}",
+                self.name,
+                status,
+                stderr.trim()
+            ));
+        }
+        Ok(stdout)
+    }
+}
+
+/// Find providers listed in the config plus, with `search_path`, all
+/// `doo-provider-*` executables on `PATH`. The first provider with a given
+/// name wins.
+pub fn discover(configured: &[String], search_path: bool) -> Vec<Provider> {
+    let path_var = search_path
+        .then(|| std::env::var_os("PATH"))
+        .flatten()
+        .unwrap_or_default();
+    discover_in(&path_var, configured)
+}
+
+fn discover_in(path_var: &OsStr, configured: &[String]) -> Vec<Provider> {
+    let mut providers: Vec<Provider> = configured
+        .iter()
+        .map(|path| Provider::from_path(PathBuf::from(path)))
+        .collect();
+
+    for dir in std::env::split_paths(path_var) {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut found: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with(PROVIDER_PREFIX))
+                    && is_executable(path)
+            })
+            .collect();
+        found.sort();
+
+        for path in found {
+            let provider = Provider::from_path(path);
+            if !providers.iter().any(|p| p.name == provider.name) {
+                providers.push(provider);
+            }
+        }
+    }
+
+    providers
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(windows)]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+        && path.extension().is_some_and(|ext| {
+            ["exe", "bat", "cmd"]
+                .iter()
+                .any(|candidate| ext.eq_ignore_ascii_case(candidate))
+        })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    fn write_script(dir: &Path, name: &str, body: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, format!("#!/bin/sh\n{body}\n")).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_discover_on_path() {
+        let temp_dir = TempDir::new().unwrap();
+        write_script(temp_dir.path(), "doo-provider-catalog", "exit 0");
+        fs::write(temp_dir.path().join("doo-provider-noexec"), "").unwrap();
+        write_script(temp_dir.path(), "unrelated", "exit 0");
+
+        let providers = discover_in(temp_dir.path().as_os_str(), &[]);
+        let names: Vec<_> = providers.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["catalog"]);
+        assert_eq!(providers[0].source_label(), "plugin:catalog");
+    }
+
+    #[test]
+    fn test_list_and_resolve() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = write_script(
+            temp_dir.path(),
+            "doo-provider-svc",
+            r#"case "$1" in
+  list) echo '[{"name":"deploy","template":"kubectl apply -n #1","tags":["k8s"]},{"name":"dyn"}]' ;;
+  resolve) echo "echo resolved $2" ;;
+esac"#,
+        );
+        let provider = Provider::from_path(path);
+
+        let commands = provider.list(PROVIDER_TIMEOUT).unwrap();
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[0].template.as_deref(), Some("kubectl apply -n #1"));
+        assert_eq!(commands[0].tags, vec!["k8s"]);
+        assert!(commands[1].template.is_none());
+
+        assert_eq!(
+            provider.resolve("dyn", PROVIDER_TIMEOUT).unwrap(),
+            "echo resolved dyn"
+        );
+    }
+
+    #[test]
+    fn test_slow_provider_times_out() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = write_script(temp_dir.path(), "doo-provider-slow", "sleep 5");
+        let provider = Provider::from_path(path);
+
+        let started = Instant::now();
+        let err = provider.list(Duration::from_millis(100)).unwrap_err();
+        assert!(err.to_string().contains("did not answer"));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
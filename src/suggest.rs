@@ -0,0 +1,92 @@
+//! Suggestions for mistyped names
+//!
+//! Used to point at the command or placeholder that was probably meant when a
+//! name matches nothing, like `#ns` for `#namespace`.
+
+/// Number of single character insertions, deletions, substitutions and
+/// swaps of neighbours that turn `a` into `b`
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, distance) in distances[0].iter_mut().enumerate() {
+        *distance = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+    distances[a.len()][b.len()]
+}
+
+/// The candidate `name` was most likely meant to be: one a few typos away, or
+/// one it abbreviates (`#ns` for `#namespace`). Closer candidates win, ties go
+/// to the alphabetically first.
+pub fn closest_match<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    let limit = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, candidate)| *distance <= limit || abbreviates(name, candidate))
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// Whether `short` is made of letters of `long` in order, starting with the
+/// same one, ignoring a leading `#` or `$`
+fn abbreviates(short: &str, long: &str) -> bool {
+    let short = short.trim_start_matches(['#', '$']);
+    let long = long.trim_start_matches(['#', '$']);
+    if short.chars().count() < 2 || short.chars().next() != long.chars().next() {
+        return false;
+    }
+    let mut remaining = long.chars();
+    short
+        .chars()
+        .all(|wanted| remaining.any(|found| found == wanted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("deploy", "deploy"), 0);
+        assert_eq!(edit_distance("dpeloy", "deploy"), 1);
+        assert_eq!(edit_distance("", "ps"), 2);
+        assert_eq!(edit_distance("logs", "log"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn test_closest_match() {
+        let placeholders = ["#namespace", "#name", "#pod", "#1"];
+        assert_eq!(closest_match("#ns", placeholders), Some("#namespace"));
+        assert_eq!(closest_match("#nmae", placeholders), Some("#name"));
+        assert_eq!(closest_match("#pdo", placeholders), Some("#pod"));
+        assert_eq!(closest_match("#2", placeholders), Some("#1"));
+        assert_eq!(closest_match("#region", placeholders), None);
+        assert_eq!(closest_match("#pod", placeholders), None);
+
+        let commands = ["deploy", "logs", "pods"];
+        assert_eq!(closest_match("dpeloy", commands), Some("deploy"));
+        assert_eq!(closest_match("log", commands), Some("logs"));
+        assert_eq!(closest_match("build", commands), None);
+    }
+}
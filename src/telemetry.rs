@@ -0,0 +1,146 @@
+//! Optional tracing instrumentation
+//!
+//! With the `telemetry` cargo feature enabled, expensive operations (config
+//! scans, YAML parsing, GitHub fetches, git calls, variable resolution and
+//! command execution) are wrapped in `tracing` spans. Embedders can install
+//! their own subscriber; the CLI offers `doo --timings` which installs
+//! [`Timings`] and prints a per-phase summary after the run.
+//!
+//! Without the feature, [`timed_span!`](crate::timed_span) expands to nothing,
+//! [`timed_future!`](crate::timed_future) to the bare future, and no tracing
+//! dependencies are compiled in.
+
+/// Enter a span named `$name` with the given display fields until the end of
+/// the enclosing scope
+#[cfg(feature = "telemetry")]
+#[macro_export]
+macro_rules! timed_span {
+    ($name:literal $(, $field:ident = $value:expr)* $(,)?) => {
+        let _span = ::tracing::info_span!(
+            $name $(, $field = ::tracing::field::display(&$value))*
+        )
+        .entered();
+    };
+}
+
+/// Enter a span named `$name` with the given display fields until the end of
+/// the enclosing scope
+#[cfg(not(feature = "telemetry"))]
+#[macro_export]
+macro_rules! timed_span {
+    ($name:literal $(, $field:ident = $value:expr)* $(,)?) => {
+        $(let _ = &$value;)*
+    };
+}
+
+/// Run `$future` inside a span named `$name` with the given display fields.
+/// For async code, where the guard of [`timed_span!`](crate::timed_span)
+/// must not be held across an `.await`.
+#[cfg(feature = "telemetry")]
+#[macro_export]
+macro_rules! timed_future {
+    ($name:literal $(, $field:ident = $value:expr)*; $future:expr) => {
+        ::tracing::Instrument::instrument(
+            $future,
+            ::tracing::info_span!($name $(, $field = ::tracing::field::display(&$value))*),
+        )
+    };
+}
+
+/// Run `$future` inside a span named `$name` with the given display fields.
+/// For async code, where the guard of [`timed_span!`](crate::timed_span)
+/// must not be held across an `.await`.
+#[cfg(not(feature = "telemetry"))]
+#[macro_export]
+macro_rules! timed_future {
+    ($name:literal $(, $field:ident = $value:expr)*; $future:expr) => {{
+        $(let _ = &$value;)*
+        $future
+    }};
+}
+
+#[cfg(feature = "telemetry")]
+pub use timings::Timings;
+
+#[cfg(feature = "telemetry")]
+mod timings {
+    use colored::*;
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+    use tracing::span::{Attributes, Id};
+    use tracing::Subscriber;
+    use tracing_subscriber::layer::{Context, SubscriberExt};
+    use tracing_subscriber::registry::LookupSpan;
+    use tracing_subscriber::Layer;
+
+    #[derive(Debug, Default, Clone, Copy)]
+    struct PhaseStats {
+        count: u32,
+        total: Duration,
+    }
+
+    type Stats = Arc<Mutex<BTreeMap<&'static str, PhaseStats>>>;
+
+    /// Collects the total time spent per span name
+    pub struct Timings {
+        stats: Stats,
+    }
+
+    struct TimingsLayer {
+        stats: Stats,
+    }
+
+    impl<S> Layer<S> for TimingsLayer
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn on_new_span(&self, _attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+            if let Some(span) = ctx.span(id) {
+                span.extensions_mut().insert(Instant::now());
+            }
+        }
+
+        fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+            let Some(span) = ctx.span(&id) else {
+                return;
+            };
+            let Some(started) = span.extensions().get::<Instant>().copied() else {
+                return;
+            };
+            if let Ok(mut stats) = self.stats.lock() {
+                let phase = stats.entry(span.name()).or_default();
+                phase.count += 1;
+                phase.total += started.elapsed();
+            }
+        }
+    }
+
+    impl Timings {
+        /// Install a global subscriber that records span durations
+        pub fn install() -> Self {
+            let stats = Stats::default();
+            let subscriber = tracing_subscriber::registry().with(TimingsLayer {
+                stats: stats.clone(),
+            });
+            // Another subscriber may already be installed by an embedder
+            let _ = tracing::subscriber::set_global_default(subscriber);
+            Self { stats }
+        }
+
+        /// Print the per-phase summary to stderr
+        pub fn print_summary(&self) {
+            let Ok(stats) = self.stats.lock() else {
+                return;
+            };
+            eprintln!("\n{}", "⏱  Timings".cyan().bold());
+            if stats.is_empty() {
+                eprintln!("  No instrumented phases ran");
+                return;
+            }
+            for (phase, PhaseStats { count, total }) in stats.iter() {
+                eprintln!("  {:<24} {:>4}×  {:>10.2?}", phase, count, total);
+            }
+        }
+    }
+}
@@ -0,0 +1,607 @@
+//! Command template parsing
+//!
+//! Templates are split into literal text and placeholder tokens once, so that
+//! resolution never has to search and replace inside already substituted text.
+//! `##` and `$$` stand for a literal `#` and `$`. A placeholder may be
+//! followed by filters that transform its value, e.g. `#1|upper|trim`, and
+//! marked optional with a trailing `?`, e.g. `#2?`.
+
+use anyhow::{anyhow, Result};
+
+/// Names of the filters a placeholder value can be piped through
+pub const FILTERS: &[&str] = &["upper", "lower", "trim", "basename"];
+
+/// The kind of a placeholder found in a template
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlaceholderKind {
+    /// `$N`: always taken from the N-th runtime argument
+    Argument(usize),
+    /// `#N`: stored variable, falling back to runtime arguments
+    Variable(usize),
+    /// `#name`: stored variable that is never filled from runtime arguments
+    Named(String),
+    /// `${NAME}`: environment variable, unless a doo variable `NAME` is set
+    Env(String),
+    /// `#*`, `#@` or `$@`: all runtime arguments after those used by
+    /// numbered placeholders
+    Rest,
+}
+
+/// A placeholder token with the exact text it was parsed from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placeholder {
+    pub kind: PlaceholderKind,
+    /// Inline default from `#N:-value` or `#N:=value`, used when nothing else
+    /// provides a value. A quoted default keeps its quotes.
+    pub default: Option<String>,
+    /// Filters from `#N|name`, applied to the value in order
+    pub filters: Vec<String>,
+    /// Marked with `?` (`#N?`): expands to nothing when no value is found
+    pub optional: bool,
+    /// Original text of the placeholder in the template
+    pub raw: String,
+}
+
+impl Placeholder {
+    /// The placeholder as written, without an inline default (`$1`, `#2`, `#name`)
+    pub fn label(&self) -> String {
+        match self.kind {
+            PlaceholderKind::Argument(n) => format!("${n}"),
+            PlaceholderKind::Env(ref name) => format!("${{{name}}}"),
+            PlaceholderKind::Rest => self.raw[..2].to_string(),
+            _ => self.variable_name().unwrap_or_default(),
+        }
+    }
+
+    /// Name under which a value for this placeholder is stored with `doo var`
+    pub fn variable_name(&self) -> Option<String> {
+        match self.kind {
+            PlaceholderKind::Argument(_) | PlaceholderKind::Env(_) | PlaceholderKind::Rest => None,
+            PlaceholderKind::Variable(n) => Some(format!("#{n}")),
+            PlaceholderKind::Named(ref name) => Some(format!("#{name}")),
+        }
+    }
+
+    /// Key of the stored variable the placeholder takes its value from; for
+    /// `${NAME}` that's the doo variable `#NAME` that overrides the
+    /// environment
+    pub fn reference_key(&self) -> Option<String> {
+        match &self.kind {
+            PlaceholderKind::Env(name) => Some(format!("#{name}")),
+            _ => self.variable_name(),
+        }
+    }
+
+    /// Fail if a filter of the placeholder is not one of [`FILTERS`]
+    pub fn check_filters(&self) -> Result<()> {
+        match self.filters.iter().find(|f| !FILTERS.contains(&f.as_str())) {
+            Some(filter) => Err(anyhow!(
+                "Unknown filter '{}' in placeholder {}; supported filters: {}",
+                filter,
+                self.raw,
+                FILTERS.join(", ")
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Pass `value` through the filters of the placeholder; unknown filters
+    /// leave it unchanged
+    pub fn apply_filters(&self, value: String) -> String {
+        self.filters
+            .iter()
+            .fold(value, |value, filter| match filter.as_str() {
+                "upper" => value.to_uppercase(),
+                "lower" => value.to_lowercase(),
+                "trim" => value.trim().to_string(),
+                "basename" => {
+                    let trimmed = value.trim_end_matches('/');
+                    match trimmed.rsplit('/').next() {
+                        Some(name) if !trimmed.is_empty() => name.to_string(),
+                        _ => value,
+                    }
+                }
+                _ => value,
+            })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+/// Split a template into literal segments and placeholder tokens
+pub fn parse(template: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut literal = String::new();
+    let mut rest = template;
+
+    while let Some(c) = rest.chars().next() {
+        // `##` and `$$` are an escaped, literal `#` or `$` (so `$${` is a
+        // literal `${`)
+        if rest.starts_with("##") || rest.starts_with("$$") {
+            literal.push(c);
+            rest = &rest[2..];
+            continue;
+        }
+        if let Some(placeholder) = env_placeholder(rest) {
+            if !literal.is_empty() {
+                tokens.push(Token::Literal(std::mem::take(&mut literal)));
+            }
+            rest = &rest[placeholder.raw.len()..];
+            tokens.push(Token::Placeholder(placeholder));
+            continue;
+        }
+        if let Some((kind, len)) = placeholder_kind(c, &rest[c.len_utf8()..]) {
+            if !literal.is_empty() {
+                tokens.push(Token::Literal(std::mem::take(&mut literal)));
+            }
+            let mut end = 1 + len;
+            let optional = kind != PlaceholderKind::Rest && rest[end..].starts_with('?');
+            if optional {
+                end += 1;
+            }
+            let filters_len = filters_len(&rest[end..]);
+            let filters = parse_filters(&rest[end..end + filters_len]);
+            end += filters_len;
+            let mut default = None;
+            if rest[end..].starts_with(":=") || rest[end..].starts_with(":-") {
+                let value_len = default_len(&rest[end + 2..]);
+                default = Some(rest[end + 2..end + 2 + value_len].to_string());
+                end += 2 + value_len;
+            }
+            tokens.push(Token::Placeholder(Placeholder {
+                kind,
+                default,
+                filters,
+                optional,
+                raw: rest[..end].to_string(),
+            }));
+            rest = &rest[end..];
+            continue;
+        }
+        literal.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+
+    if !literal.is_empty() {
+        tokens.push(Token::Literal(literal));
+    }
+    tokens
+}
+
+/// Text in `template` that looks like a placeholder but is left as literal
+/// text when parsing: an unclosed or invalid `${...}`, `#0` or `$0`, or a
+/// number too large to be an argument position
+pub fn malformed(template: &str) -> Vec<String> {
+    let mut found = Vec::new();
+    let mut rest = template;
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("##") || rest.starts_with("$$") {
+            rest = &rest[2..];
+            continue;
+        }
+        if let Some(placeholder) = env_placeholder(rest) {
+            rest = &rest[placeholder.raw.len()..];
+            continue;
+        }
+        if rest.starts_with("${") {
+            let len = match rest.find(|c: char| c == '}' || c.is_whitespace()) {
+                Some(end) if rest[end..].starts_with('}') => end + 1,
+                Some(end) => end,
+                None => rest.len(),
+            };
+            found.push(rest[..len].to_string());
+            rest = &rest[len..];
+            continue;
+        }
+        let after = &rest[c.len_utf8()..];
+        match placeholder_kind(c, after) {
+            Some((PlaceholderKind::Variable(0) | PlaceholderKind::Argument(0), len)) => {
+                found.push(rest[..1 + len].to_string());
+                rest = &after[len..];
+                continue;
+            }
+            None if (c == '#' || c == '$') && after.starts_with(|d: char| d.is_ascii_digit()) => {
+                let digits = after.bytes().take_while(|b| b.is_ascii_digit()).count();
+                found.push(rest[..1 + digits].to_string());
+                rest = &after[digits..];
+                continue;
+            }
+            _ => {}
+        }
+        rest = after;
+    }
+    found
+}
+
+/// Parse `${NAME}`, `${NAME|filter}` or `${NAME:-default}`, optionally
+/// followed by `?`, at the start of `text`
+fn env_placeholder(text: &str) -> Option<Placeholder> {
+    let inner = text.strip_prefix("${")?;
+    let close = inner.find('}')?;
+    let body = &inner[..close];
+    let (name, default) = match body.find(":-").or_else(|| body.find(":=")) {
+        Some(i) => (&body[..i], Some(body[i + 2..].to_string())),
+        None => (body, None),
+    };
+    let (name, filters) = match name.find('|') {
+        Some(i) if filters_len(&name[i..]) == name.len() - i => {
+            (&name[..i], parse_filters(&name[i..]))
+        }
+        Some(_) => return None,
+        None => (name, Vec::new()),
+    };
+    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    let optional = text[close + 3..].starts_with('?');
+    valid.then(|| Placeholder {
+        kind: PlaceholderKind::Env(name.to_string()),
+        default,
+        filters,
+        optional,
+        raw: text[..close + 3 + usize::from(optional)].to_string(),
+    })
+}
+
+/// Length of the `|name|name` filters at the start of `text`; a `|` that is
+/// not directly followed by a name is not part of them
+fn filters_len(text: &str) -> usize {
+    let mut len = 0;
+    while let Some(after) = text[len..].strip_prefix('|') {
+        let name_len = after
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(after.len());
+        if name_len == 0 {
+            break;
+        }
+        len += 1 + name_len;
+    }
+    len
+}
+
+fn parse_filters(text: &str) -> Vec<String> {
+    text.split('|')
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Recognize the placeholder starting with `sigil` followed by `rest`,
+/// returning its kind and the length of the text after the sigil
+fn placeholder_kind(sigil: char, rest: &str) -> Option<(PlaceholderKind, usize)> {
+    if sigil != '#' && sigil != '$' {
+        return None;
+    }
+    if (sigil == '#' && rest.starts_with('*')) || rest.starts_with('@') {
+        return Some((PlaceholderKind::Rest, 1));
+    }
+    let digits = rest.bytes().take_while(|b| b.is_ascii_digit()).count();
+    if digits > 0 {
+        // Numbers too large for usize are treated as literal text
+        let n = rest[..digits].parse::<usize>().ok()?;
+        let kind = if sigil == '#' {
+            PlaceholderKind::Variable(n)
+        } else {
+            PlaceholderKind::Argument(n)
+        };
+        return Some((kind, digits));
+    }
+
+    // Named placeholders start with a letter or underscore; a trailing dash
+    // belongs to the surrounding text
+    if sigil == '#' && rest.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+            .unwrap_or(rest.len());
+        let name = rest[..len].trim_end_matches('-');
+        return Some((PlaceholderKind::Named(name.to_string()), name.len()));
+    }
+    None
+}
+
+/// Length of an inline default at the start of `text`: a quoted string
+/// including its quotes, or everything up to whitespace or a quote
+fn default_len(text: &str) -> usize {
+    if let Some(quote) = text.chars().next().filter(|c| *c == '"' || *c == '\'') {
+        if let Some(close) = text[1..].find(quote) {
+            return close + 2;
+        }
+    }
+    text.find(|c: char| c.is_whitespace() || c == '\'' || c == '"')
+        .unwrap_or(text.len())
+}
+
+/// Render tokens back into a string, substituting placeholders with the value
+/// returned by `resolve`. Unresolved placeholders keep their original text.
+/// A rest or optional placeholder that resolves to nothing takes the spaces
+/// before it along, so no stray whitespace is left behind; an optional one
+/// also takes the flag it belongs to (`-n #2?`, `--namespace=#2?`).
+pub fn render<F>(tokens: &[Token], mut resolve: F) -> String
+where
+    F: FnMut(&Placeholder) -> Option<String>,
+{
+    let mut output = String::new();
+    for token in tokens {
+        match token {
+            Token::Literal(text) => output.push_str(text),
+            Token::Placeholder(placeholder) => match resolve(placeholder) {
+                Some(value) if value.is_empty() && placeholder.optional => {
+                    drop_flag_before(&mut output);
+                }
+                Some(value) if value.is_empty() && placeholder.kind == PlaceholderKind::Rest => {
+                    output.truncate(output.trim_end_matches([' ', '\t']).len());
+                }
+                Some(value) => output.push_str(&value),
+                None => output.push_str(&placeholder.raw),
+            },
+        }
+    }
+    output
+}
+
+/// Remove the spaces at the end of `output`, or the flag waiting for its
+/// value (`-n=`, `--namespace=`) together with the spaces before it. A flag
+/// separated by a space is kept, since it may not take a value at all
+/// (`ls -la #dir?`).
+fn drop_flag_before(output: &mut String) {
+    let word_start = output.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+    let word = &output[word_start..];
+    let is_flag = word.starts_with('-')
+        && word[1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '-')
+        && word.ends_with('=');
+    let end = if is_flag { word_start } else { output.len() };
+    let end = output[..end].trim_end_matches([' ', '\t']).len();
+    output.truncate(end);
+}
+
+/// All placeholders of a template in order of appearance
+pub fn placeholders(tokens: &[Token]) -> impl Iterator<Item = &Placeholder> {
+    tokens.iter().filter_map(|token| match token {
+        Token::Placeholder(placeholder) => Some(placeholder),
+        Token::Literal(_) => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_parse_placeholders() {
+        let tokens = parse("kubectl logs -n #1 $2 #10");
+        let kinds: Vec<_> = placeholders(&tokens).map(|p| p.kind.clone()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                PlaceholderKind::Variable(1),
+                PlaceholderKind::Argument(2),
+                PlaceholderKind::Variable(10),
+            ]
+        );
+        assert_eq!(tokens[0], Token::Literal("kubectl logs -n ".to_string()));
+    }
+
+    #[test]
+    fn test_parse_rest_placeholder() {
+        let tokens = parse("kubectl -n #1 #*");
+        let found: Vec<_> = placeholders(&tokens).collect();
+        assert_eq!(found[1].kind, PlaceholderKind::Rest);
+        assert_eq!(found[1].label(), "#*");
+        assert_eq!(found[1].variable_name(), None);
+        // `$*` is not a placeholder
+        assert_eq!(placeholders(&parse("echo $*")).count(), 0);
+
+        for template in ["terraform -chdir=#1 #@", "terraform -chdir=#1 $@"] {
+            let tokens = parse(template);
+            let found: Vec<_> = placeholders(&tokens).collect();
+            assert_eq!(found[1].kind, PlaceholderKind::Rest);
+            assert_eq!(found[1].label(), &template[template.len() - 2..]);
+        }
+        assert_eq!(placeholders(&parse("echo $$@")).count(), 0);
+    }
+
+    #[test]
+    fn test_parse_optional_placeholders() {
+        let tokens = parse("kubectl logs #1 #2? -n #ns?|lower ${HOME}? #*?");
+        let parsed: Vec<_> = placeholders(&tokens)
+            .map(|p| (p.raw.as_str(), p.label(), p.optional))
+            .collect();
+        assert_eq!(
+            parsed,
+            vec![
+                ("#1", "#1".to_string(), false),
+                ("#2?", "#2".to_string(), true),
+                ("#ns?|lower", "#ns".to_string(), true),
+                ("${HOME}?", "${HOME}".to_string(), true),
+                ("#*", "#*".to_string(), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_filters() {
+        let tokens =
+            parse("echo #1|upper|trim #name|basename:-/tmp/x ${HOME|lower} #2 | grep x #3|");
+        let parsed: Vec<_> = placeholders(&tokens)
+            .map(|p| (p.raw.as_str(), p.filters.clone(), p.default.clone()))
+            .collect();
+        assert_eq!(
+            parsed,
+            vec![
+                ("#1|upper|trim", vec!["upper".into(), "trim".into()], None),
+                (
+                    "#name|basename:-/tmp/x",
+                    vec!["basename".into()],
+                    Some("/tmp/x".into())
+                ),
+                ("${HOME|lower}", vec!["lower".into()], None),
+                // A pipe with whitespace around it or without a name is literal
+                ("#2", vec![], None),
+                ("#3", vec![], None),
+            ]
+        );
+
+        let filtered = |raw: &str, value: &str| {
+            let tokens = parse(raw);
+            let placeholder = placeholders(&tokens).next().unwrap().clone();
+            placeholder.apply_filters(value.to_string())
+        };
+        assert_eq!(filtered("#1|upper", "eu-west-1"), "EU-WEST-1");
+        assert_eq!(filtered("#1|lower", "EU"), "eu");
+        assert_eq!(filtered("#1|trim|upper", "  eu \n"), "EU");
+        assert_eq!(filtered("#1|basename", "/srv/app/"), "app");
+        assert_eq!(filtered("#1|basename", "file.txt"), "file.txt");
+
+        let tokens = parse("#1|shout");
+        let error = placeholders(&tokens).next().unwrap().check_filters();
+        assert_eq!(
+            error.unwrap_err().to_string(),
+            "Unknown filter 'shout' in placeholder #1|shout; supported filters: upper, lower, trim, basename"
+        );
+    }
+
+    #[test]
+    fn test_malformed_placeholders() {
+        assert!(malformed("kubectl -n #1 ${HOME} #name:-x $2 ##0 $${").is_empty());
+        assert_eq!(
+            malformed("echo ${HOME ${1X} #0 $0 #99999999999999999999999 ok"),
+            vec!["${HOME", "${1X}", "#0", "$0", "#99999999999999999999999"]
+        );
+    }
+
+    #[test]
+    fn test_doubled_sigils_are_escapes() {
+        let tokens = parse("grep ##include #1 $$HOME $1 ##1 $$2 ###2");
+        let found: Vec<_> = placeholders(&tokens).map(|p| p.label()).collect();
+        assert_eq!(found, vec!["#1", "$1", "#2"]);
+        assert_eq!(
+            render(&tokens, |_| None),
+            "grep #include #1 $HOME $1 #1 $2 ##2"
+        );
+        assert_eq!(
+            render(&tokens, |p| Some(format!("<{}>", p.label()))),
+            "grep #include <#1> $HOME <$1> #1 $2 #<#2>"
+        );
+    }
+
+    #[test]
+    fn test_lone_sigils_are_literal() {
+        let tokens = parse("echo # $ ##x $HOME");
+        assert_eq!(
+            tokens,
+            vec![Token::Literal("echo # $ #x $HOME".to_string())]
+        );
+        assert_eq!(parse("#-"), vec![Token::Literal("#-".to_string())]);
+
+        // A word after `#` is a named placeholder, so text like `#x` needs
+        // the escape above to stay literal
+        let tokens = parse("echo # $ #x $HOME");
+        let found: Vec<_> = placeholders(&tokens).collect();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].kind, PlaceholderKind::Named("x".to_string()));
+    }
+
+    #[test]
+    fn test_parse_fallback_default() {
+        let tokens =
+            parse(r#"kubectl get pods -n #1:-default-ns -l #labels:-"app=web tier=front" x"#);
+        let found: Vec<_> = placeholders(&tokens).collect();
+        assert_eq!(found[0].default.as_deref(), Some("default-ns"));
+        assert_eq!(found[0].raw, "#1:-default-ns");
+        assert_eq!(found[1].default.as_deref(), Some(r#""app=web tier=front""#));
+        assert_eq!(tokens.last(), Some(&Token::Literal(" x".to_string())));
+
+        // An unterminated quote is not a quoted default
+        let tokens = parse("echo #1:-'abc");
+        let found: Vec<_> = placeholders(&tokens).collect();
+        assert_eq!(found[0].default.as_deref(), Some(""));
+    }
+
+    #[test]
+    fn test_parse_env_placeholders() {
+        let tokens =
+            parse("aws s3 ls s3://${BUCKET}/#1 ${REGION:-eu-west-1} ${not valid} $${HOME}");
+        let found: Vec<_> = placeholders(&tokens).collect();
+        assert_eq!(found[0].kind, PlaceholderKind::Env("BUCKET".to_string()));
+        assert_eq!(found[0].label(), "${BUCKET}");
+        assert_eq!(found[1].kind, PlaceholderKind::Variable(1));
+        assert_eq!(found[2].kind, PlaceholderKind::Env("REGION".to_string()));
+        assert_eq!(found[2].default.as_deref(), Some("eu-west-1"));
+        assert_eq!(found.len(), 3);
+        // The escape renders as a literal `${`
+        assert_eq!(
+            render(&tokens, |_| None),
+            "aws s3 ls s3://${BUCKET}/#1 ${REGION:-eu-west-1} ${not valid} ${HOME}"
+        );
+    }
+
+    #[test]
+    fn test_parse_named_placeholders() {
+        let tokens = parse("kubectl -n #namespace --context=#kube_ctx-2- #1");
+        let found: Vec<_> = placeholders(&tokens).collect();
+        assert_eq!(
+            found[0].kind,
+            PlaceholderKind::Named("namespace".to_string())
+        );
+        assert_eq!(found[0].variable_name().as_deref(), Some("#namespace"));
+        assert_eq!(
+            found[1].kind,
+            PlaceholderKind::Named("kube_ctx-2".to_string())
+        );
+        assert_eq!(found[2].kind, PlaceholderKind::Variable(1));
+        assert_eq!(
+            render(&tokens, |_| None),
+            "kubectl -n #namespace --context=#kube_ctx-2- #1"
+        );
+    }
+
+    #[test]
+    fn test_parse_inline_default() {
+        let tokens = parse("kubectl get pods -n #1:=default-ns 'x $2:=a b'");
+        let found: Vec<_> = placeholders(&tokens).collect();
+        assert_eq!(found[0].kind, PlaceholderKind::Variable(1));
+        assert_eq!(found[0].default.as_deref(), Some("default-ns"));
+        assert_eq!(found[0].raw, "#1:=default-ns");
+        assert_eq!(found[1].default.as_deref(), Some("a"));
+        assert_eq!(
+            render(&tokens, |_| None),
+            "kubectl get pods -n #1:=default-ns 'x $2:=a b'"
+        );
+
+        // The default stops at quotes
+        let tokens = parse("echo '#1:=x'");
+        let found: Vec<_> = placeholders(&tokens).collect();
+        assert_eq!(found[0].default.as_deref(), Some("x"));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_render_without_values_is_identity(template in "[a-z #$0-9é-]{0,40}") {
+            // Doubled sigils are escapes and render as one
+            prop_assume!(!template.contains("##") && !template.contains("$$"));
+            let tokens = parse(&template);
+            prop_assert_eq!(render(&tokens, |_| None), template);
+        }
+
+        #[test]
+        fn prop_escaped_text_is_literal(text in "[a-z #$0-9{}*é-]{0,40}") {
+            let template = text.replace('#', "##").replace('$', "$$");
+            let tokens = parse(&template);
+            prop_assert_eq!(placeholders(&tokens).count(), 0);
+            prop_assert_eq!(render(&tokens, |_| Some("x".to_string())), text);
+        }
+
+        #[test]
+        fn prop_placeholders_round_trip(n in 0usize..1000, prefix in "[a-z ]{0,10}") {
+            let template = format!("{prefix}#{n}");
+            let tokens = parse(&template);
+            let found: Vec<_> = placeholders(&tokens).collect();
+            prop_assert_eq!(found.len(), 1);
+            prop_assert_eq!(&found[0].kind, &PlaceholderKind::Variable(n));
+        }
+    }
+}
@@ -0,0 +1,273 @@
+//! Filesystem helpers shared by the managers
+//!
+//! Files in the config directory are replaced atomically: the new contents go
+//! to a temporary file next to the destination, which is flushed to disk and
+//! then renamed over it. A process killed halfway leaves either the old or the
+//! new file behind, never a truncated one. The main config and the variables
+//! additionally keep the previous version as `<file>.bak`, which
+//! [`read_yaml_or_recover`] offers to restore when a file no longer parses.
+
+use anyhow::{anyhow, Context, Result};
+use dialoguer::Confirm;
+use serde::de::DeserializeOwned;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Replace `path` with `contents` atomically
+pub fn atomic_write(path: &Path, contents: &str) -> Result<()> {
+    let temp = write_temp(path, contents, false)?;
+    replace(&temp, path)
+}
+
+/// Like [`atomic_write`], but the file is only readable by the current user
+/// (mode 0600 on Unix), for secrets and history
+pub fn atomic_write_private(path: &Path, contents: &str) -> Result<()> {
+    let temp = write_temp(path, contents, true)?;
+    replace(&temp, path)
+}
+
+/// Like [`atomic_write`], but the current contents of `path` are kept as
+/// `<file>.bak` first
+pub fn atomic_write_with_backup(path: &Path, contents: &str) -> Result<()> {
+    if path.exists() {
+        let backup = backup_path(path);
+        fs::copy(path, &backup).with_context(|| format!("Failed to back up {path:?}"))?;
+    }
+    atomic_write(path, contents)
+}
+
+/// Parse the YAML file at `path`. If it doesn't parse but its backup or a
+/// temporary file left by an interrupted write does, restore that one after
+/// asking when `interactive`, and otherwise fail with a message pointing at it.
+pub fn read_yaml_or_recover<T: DeserializeOwned>(path: &Path, interactive: bool) -> Result<T> {
+    read_or_recover(path, interactive, |contents| {
+        serde_yaml::from_str(contents).map_err(anyhow::Error::from)
+    })
+}
+
+/// Like [`read_yaml_or_recover`], for files in another format read with
+/// `parse`
+pub fn read_or_recover<T>(
+    path: &Path,
+    interactive: bool,
+    parse: impl Fn(&str) -> Result<T>,
+) -> Result<T> {
+    let contents = fs::read_to_string(path).with_context(|| format!("Failed to read {path:?}"))?;
+    let error = match parse(&contents) {
+        Ok(value) => return Ok(value),
+        Err(error) => error,
+    };
+
+    let recoverable = [backup_path(path), temp_path(path)]
+        .into_iter()
+        .find_map(|candidate| {
+            let contents = fs::read_to_string(&candidate).ok()?;
+            let value = parse(&contents).ok()?;
+            Some((candidate, contents, value))
+        });
+    let Some((candidate, contents, value)) = recoverable else {
+        return Err(error.context(format!("Failed to parse {}", path.display())));
+    };
+
+    if interactive
+        && Confirm::new()
+            .with_prompt(format!(
+                "{} can't be parsed ({error}). Restore the previous version from {}?",
+                path.display(),
+                candidate.display()
+            ))
+            .default(true)
+            .interact()?
+    {
+        atomic_write(path, &contents)?;
+        eprintln!("Restored {} from {}", path.display(), candidate.display());
+        return Ok(value);
+    }
+    Err(anyhow!(
+        "{} can't be parsed: {error}\nThe previous version is in {}; restore it with\n  cp {} {}",
+        path.display(),
+        candidate.display(),
+        shell_words::quote(&candidate.to_string_lossy()),
+        shell_words::quote(&path.to_string_lossy())
+    ))
+}
+
+/// `<file>.bak` next to `path`
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".bak");
+    path.with_file_name(name)
+}
+
+/// `<file>.tmp` next to `path`
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+/// Write `contents` to the temporary file for `path` and flush it to disk
+fn write_temp(path: &Path, contents: &str, private: bool) -> Result<PathBuf> {
+    let temp = temp_path(path);
+    let mut file = create(&temp, private)
+        .with_context(|| format!("Failed to create temporary file: {temp:?}"))?;
+    file.write_all(contents.as_bytes())
+        .and_then(|_| file.sync_all())
+        .with_context(|| format!("Failed to write temporary file: {temp:?}"))?;
+    Ok(temp)
+}
+
+#[cfg(unix)]
+fn create(path: &Path, private: bool) -> std::io::Result<File> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    if !private {
+        return File::create(path);
+    }
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    // The mode only applies to new files, and a stale temporary file may be
+    // left over from an interrupted write
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    Ok(file)
+}
+
+#[cfg(not(unix))]
+fn create(path: &Path, _private: bool) -> std::io::Result<File> {
+    File::create(path)
+}
+
+/// Rename `temp` over `path`, then sync the directory so the rename itself
+/// survives a crash
+#[cfg(unix)]
+fn replace(temp: &Path, path: &Path) -> Result<()> {
+    fs::rename(temp, path).with_context(|| format!("Failed to replace {path:?}"))?;
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        // Not every filesystem supports syncing directories
+        let _ = File::open(dir).and_then(|dir| dir.sync_all());
+    }
+    Ok(())
+}
+
+/// Rename `temp` over `path`. `fs::rename` uses `MoveFileExW` with
+/// `MOVEFILE_REPLACE_EXISTING` here, which fails while another process (a
+/// virus scanner, an editor) briefly holds the destination open, so it is
+/// retried for a moment before giving up.
+#[cfg(windows)]
+fn replace(temp: &Path, path: &Path) -> Result<()> {
+    use std::io::ErrorKind;
+    use std::thread;
+    use std::time::Duration;
+
+    let mut attempts = 0;
+    loop {
+        match fs::rename(temp, path) {
+            Err(e) if e.kind() == ErrorKind::PermissionDenied && attempts < 10 => {
+                attempts += 1;
+                thread::sleep(Duration::from_millis(50));
+            }
+            result => return result.with_context(|| format!("Failed to replace {path:?}")),
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn replace(temp: &Path, path: &Path) -> Result<()> {
+    fs::rename(temp, path).with_context(|| format!("Failed to replace {path:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_atomic_write() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("config.yaml");
+
+        atomic_write(&path, "commands: {}\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "commands: {}\n");
+        atomic_write(&path, "commands:\n  ps: ps aux\n").unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "commands:\n  ps: ps aux\n"
+        );
+        assert!(!temp_path(&path).exists());
+    }
+
+    #[test]
+    fn test_interrupted_write_keeps_old_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("default.yaml");
+        atomic_write(&path, "vars:\n  '#1': old\n").unwrap();
+
+        // A crash between writing the temporary file and renaming it leaves
+        // the destination untouched
+        let temp = write_temp(&path, "vars:\n  '#1': new\n", false).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "vars:\n  '#1': old\n");
+        assert_eq!(temp, temp_dir.path().join("default.yaml.tmp"));
+
+        // The stale temporary file doesn't get in the way of the next write
+        atomic_write(&path, "vars:\n  '#1': newer\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "vars:\n  '#1': newer\n");
+        assert!(!temp.exists());
+    }
+
+    #[test]
+    fn test_recover_from_backup() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("config.yaml");
+        atomic_write_with_backup(&path, "commands:\n  a: echo a\n").unwrap();
+        assert!(!backup_path(&path).exists());
+        atomic_write_with_backup(&path, "commands:\n  b: echo b\n").unwrap();
+        assert_eq!(
+            fs::read_to_string(backup_path(&path)).unwrap(),
+            "commands:\n  a: echo a\n"
+        );
+
+        type Commands =
+            std::collections::BTreeMap<String, std::collections::BTreeMap<String, String>>;
+        let read = |interactive| read_yaml_or_recover::<Commands>(&path, interactive);
+        assert_eq!(read(false).unwrap()["commands"]["b"], "echo b");
+
+        // A broken file points at the backup instead of just failing
+        fs::write(&path, "commands: [unterminated\n").unwrap();
+        let message = read(false).unwrap_err().to_string();
+        assert!(message.starts_with(&format!("{} can't be parsed", path.display())));
+        assert!(message.contains(&format!(
+            "The previous version is in {}",
+            backup_path(&path).display()
+        )));
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "commands: [unterminated\n"
+        );
+
+        // Without a usable backup it is a plain parse error
+        fs::write(backup_path(&path), "also: [broken\n").unwrap();
+        let error = read(false).unwrap_err();
+        assert!(error.to_string().starts_with("Failed to parse"));
+        assert!(!format!("{error:#}").contains("previous version"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_atomic_write_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("default.secrets.yaml");
+        fs::write(&path, "vars: {}\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        atomic_write_private(&path, "vars:\n  token: abc\n").unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
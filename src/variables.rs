@@ -1,222 +1,4908 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use crate::config::ConfigManager;
+use crate::config::{ConfigManager, SecretStore};
+use crate::context::{check_context_name, ContextMeta};
+use crate::encryption::{self, Vault};
+use crate::executor::CommandExecutor;
+use crate::keychain::{self, Keychain, SystemKeychain};
+use crate::lock::ConfigLock;
+use crate::template::{self, PlaceholderKind};
+use crate::util::{
+    atomic_write, atomic_write_private, atomic_write_with_backup, backup_path, read_yaml_or_recover,
+};
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+/// Shown instead of the value of a secret variable
+pub const SECRET_MASK: &str = "*****";
+
+/// File name suffix of secrets files in the variables directory
+pub const SECRETS_SUFFIX: &str = ".secrets.yaml";
+
+/// File name suffix of variable history files in the variables directory
+pub const HISTORY_SUFFIX: &str = ".history.yaml";
+
+/// How many previous values are kept per variable
+pub const HISTORY_LIMIT: usize = 20;
+
+/// How many variables deep references like `#url` -> `#host` may go
+pub const MAX_REFERENCE_DEPTH: usize = 16;
+
+/// Prefix of variable values that are the output of a command, e.g.
+/// `!cmd:kubectl config current-context`
+pub const DYNAMIC_PREFIX: &str = "!cmd:";
+
+/// Variables scoped to one command, by name, with whether they are secret
+pub type ScopedVariables = BTreeMap<String, (VarValue, bool)>;
+
+/// Variables of a context as printed by `doo var list --output json`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VariableListing {
+    pub context: String,
+    /// Context-wide variables, plus placeholders of main config commands that
+    /// have no value yet
+    pub vars: BTreeMap<String, ListedVariable>,
+    /// Command-scoped variables by command name
+    pub commands: BTreeMap<String, BTreeMap<String, ListedVariable>>,
+}
+
+/// One entry of a [`VariableListing`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListedVariable {
+    /// `None` for placeholders without a value. Secrets are masked unless
+    /// they were revealed.
+    pub value: Option<VarValue>,
+    pub secret: bool,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub expired: bool,
+    pub description: Option<String>,
+    /// When the value was last set; `None` for values written before doo
+    /// kept track of it
+    pub updated_at: Option<DateTime<Utc>>,
+    /// Main config commands using the variable
+    pub used_by: Vec<String>,
+    /// Ancestor context the value comes from; `None` if it is set in the
+    /// listed context itself
+    pub inherited_from: Option<String>,
+}
+
+/// A variable a context inherits from one of its ancestors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InheritedVariable {
+    /// Stored value; secrets kept in the keychain are their `keyring:`
+    /// reference
+    pub value: VarValue,
+    pub secret: bool,
+    /// Nearest ancestor setting the variable
+    pub context: String,
+}
+
+/// Value of a variable: a single string, or a list whose items are passed to
+/// commands as separate arguments. Files without lists keep their flat
+/// `name: value` layout.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum VarValue {
+    Single(String),
+    List(Vec<String>),
+}
+
+impl VarValue {
+    pub fn is_list(&self) -> bool {
+        matches!(self, VarValue::List(_))
+    }
+
+    /// The individual strings of the value, one for a single value
+    pub fn items(&self) -> &[String] {
+        match self {
+            VarValue::Single(value) => std::slice::from_ref(value),
+            VarValue::List(items) => items,
+        }
+    }
+}
+
+impl From<&str> for VarValue {
+    fn from(value: &str) -> Self {
+        VarValue::Single(value.to_string())
+    }
+}
+
+impl From<String> for VarValue {
+    fn from(value: String) -> Self {
+        VarValue::Single(value)
+    }
+}
+
+impl PartialEq<&str> for VarValue {
+    fn eq(&self, other: &&str) -> bool {
+        matches!(self, VarValue::Single(value) if value == other)
+    }
+}
+
+impl PartialEq<String> for VarValue {
+    fn eq(&self, other: &String) -> bool {
+        matches!(self, VarValue::Single(value) if value == other)
+    }
+}
+
+impl From<Vec<String>> for VarValue {
+    fn from(items: Vec<String>) -> Self {
+        VarValue::List(items)
+    }
+}
+
+/// A single value as is; list items quoted where needed and separated by
+/// spaces, which is how they are substituted into commands
+impl std::fmt::Display for VarValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VarValue::Single(value) => f.write_str(value),
+            VarValue::List(items) => {
+                let quoted: Vec<_> = items.iter().map(|item| shell_words::quote(item)).collect();
+                f.write_str(&quoted.join(" "))
+            }
+        }
+    }
+}
+
+/// Description and modification time of a variable
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VarMeta {
+    pub description: Option<String>,
+    /// When the value was last written; unknown for variables set before
+    /// doo recorded it
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl VarMeta {
+    fn is_empty(&self) -> bool {
+        self.description.is_none() && self.updated_at.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(from = "VariablesFile", into = "VariablesFile")]
 pub struct Variables {
-    pub vars: HashMap<String, String>,
+    pub vars: HashMap<String, VarValue>,
+    /// When variables set with a TTL stop being used
+    pub expires: HashMap<String, DateTime<Utc>>,
+    /// Values that only apply to one command, keyed by command name
+    pub commands: BTreeMap<String, HashMap<String, VarValue>>,
+    /// Descriptions, stored next to the values, and modification times,
+    /// stored in a map of their own like `expires`
+    pub meta: HashMap<String, VarMeta>,
+}
+
+/// On-disk layout of [`Variables`]
+#[derive(Serialize, Deserialize)]
+struct VariablesFile {
+    vars: HashMap<String, StoredVar>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    expires: HashMap<String, DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    updated: HashMap<String, DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    commands: BTreeMap<String, HashMap<String, VarValue>>,
+}
+
+/// A variable in a file: just the value (`name: value`) unless it has a
+/// description, so files without any keep their flat layout
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum StoredVar {
+    Plain(VarValue),
+    Detailed {
+        value: VarValue,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        description: Option<String>,
+        /// Only read, from files written before the `updated` map
+        #[serde(default, skip_serializing)]
+        updated_at: Option<DateTime<Utc>>,
+    },
+}
+
+impl From<VariablesFile> for Variables {
+    fn from(mut file: VariablesFile) -> Self {
+        let mut vars = HashMap::new();
+        let mut meta = HashMap::new();
+        for (name, stored) in file.vars {
+            let (value, description, updated_at) = match stored {
+                StoredVar::Plain(value) => (value, None, None),
+                StoredVar::Detailed {
+                    value,
+                    description,
+                    updated_at,
+                } => (value, description, updated_at),
+            };
+            let entry = VarMeta {
+                description,
+                updated_at: file.updated.remove(&name).or(updated_at),
+            };
+            if !entry.is_empty() {
+                meta.insert(name.clone(), entry);
+            }
+            vars.insert(name, value);
+        }
+        Variables {
+            vars,
+            expires: file.expires,
+            commands: file.commands,
+            meta,
+        }
+    }
+}
+
+impl From<Variables> for VariablesFile {
+    fn from(variables: Variables) -> Self {
+        let Variables {
+            vars,
+            expires,
+            commands,
+            mut meta,
+        } = variables;
+        let mut updated = HashMap::new();
+        let vars = vars
+            .into_iter()
+            .map(|(name, value)| {
+                let VarMeta {
+                    description,
+                    updated_at,
+                } = meta.remove(&name).unwrap_or_default();
+                if let Some(at) = updated_at {
+                    updated.insert(name.clone(), at);
+                }
+                let stored = match description {
+                    Some(description) => StoredVar::Detailed {
+                        value,
+                        description: Some(description),
+                        updated_at: None,
+                    },
+                    None => StoredVar::Plain(value),
+                };
+                (name, stored)
+            })
+            .collect();
+        VariablesFile {
+            vars,
+            expires,
+            updated,
+            commands,
+        }
+    }
+}
+
+impl Variables {
+    /// Variables that haven't expired at `now`
+    pub fn active(self, now: DateTime<Utc>) -> HashMap<String, VarValue> {
+        let Variables {
+            mut vars, expires, ..
+        } = self;
+        vars.retain(|name, _| expires.get(name).is_none_or(|at| *at > now));
+        vars
+    }
+
+    fn remove(&mut self, name: &str) -> Option<VarValue> {
+        self.expires.remove(name);
+        self.meta.remove(name);
+        self.vars.remove(name)
+    }
+
+    /// Set `name` to `value`, recording when it was written
+    fn insert(&mut self, name: &str, value: VarValue) {
+        self.vars.insert(name.to_string(), value);
+        self.meta.entry(name.to_string()).or_default().updated_at = Some(Utc::now());
+    }
+
+    fn set_expiry(&mut self, name: &str, expires: Option<DateTime<Utc>>) {
+        match expires {
+            Some(at) => self.expires.insert(name.to_string(), at),
+            None => self.expires.remove(name),
+        };
+    }
+}
+
+/// Standalone file written by [`VariableManager::export_variables`]
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ExportedVariables {
+    #[serde(default)]
+    pub vars: BTreeMap<String, VarValue>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub secrets: BTreeMap<String, VarValue>,
+}
+
+/// Shell syntax of [`VariableManager::export_as_shell`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellFormat {
+    /// `export KEY='value'` for sh, bash and zsh
+    Sh,
+    /// `set -gx KEY 'value'`
+    Fish,
+    /// `$env:KEY = 'value'`
+    PowerShell,
+}
+
+impl ShellFormat {
+    pub const NAMES: &'static [&'static str] = &["sh", "fish", "powershell"];
+
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "sh" => Ok(ShellFormat::Sh),
+            "fish" => Ok(ShellFormat::Fish),
+            "powershell" => Ok(ShellFormat::PowerShell),
+            _ => Err(anyhow!("Unsupported shell format '{}'", name)),
+        }
+    }
+
+    /// Statement that sets environment variable `name` to `value`
+    fn statement(self, name: &str, value: &str) -> String {
+        match self {
+            ShellFormat::Sh => format!("export {}={}", name, shell_words::quote(value)),
+            ShellFormat::Fish => format!(
+                "set -gx {} '{}'",
+                name,
+                value.replace('\\', "\\\\").replace('\'', "\\'")
+            ),
+            ShellFormat::PowerShell => {
+                format!("$env:{} = '{}'", name, value.replace('\'', "''"))
+            }
+        }
+    }
+}
+
+/// Result of [`VariableManager::import_variables`], variable names in order
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ImportOutcome {
+    pub added: Vec<String>,
+    /// Already set in the context with a different value
+    pub overwritten: Vec<String>,
+    pub unchanged: Vec<String>,
+}
+
+/// A value a variable had before it was overwritten
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub value: VarValue,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub secret: bool,
+    /// When the value was replaced
+    pub replaced_at: DateTime<Utc>,
+}
+
+/// Previous values of the variables of a context, oldest first
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct VariableHistory {
+    vars: HashMap<String, Vec<HistoryEntry>>,
+}
+
+impl VariableHistory {
+    fn record(&mut self, name: &str, entry: HistoryEntry) {
+        let values = self.vars.entry(name.to_string()).or_default();
+        values.push(entry);
+        if values.len() > HISTORY_LIMIT {
+            values.drain(..values.len() - HISTORY_LIMIT);
+        }
+    }
+}
+
+/// Result of [`VariableManager::copy_variables`], variable names in order
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CopyOutcome {
+    pub copied: Vec<String>,
+    /// Already set in the target context and not overwritten
+    pub skipped: Vec<String>,
+}
+
+/// Variables and secrets of a context captured by
+/// [`VariableManager::snapshot`], to put back with
+/// [`VariableManager::restore`]
+#[derive(Debug, Clone)]
+pub struct VariableSnapshot {
+    context: String,
+    /// `None` if the context had no such file
+    variables: Option<Variables>,
+    secrets: Option<Variables>,
+}
+
+#[allow(dead_code)]
+impl VariableSnapshot {
+    pub fn context(&self) -> &str {
+        &self.context
+    }
+}
+
+/// Result of [`VariableManager::diff_contexts`], as printed by
+/// `doo var diff --output json`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContextDiff {
+    pub only_in_a: BTreeMap<String, DiffedVariable>,
+    pub only_in_b: BTreeMap<String, DiffedVariable>,
+    /// Set in both contexts, to different values
+    pub changed: BTreeMap<String, ChangedVariable>,
+}
+
+impl ContextDiff {
+    pub fn is_empty(&self) -> bool {
+        self.only_in_a.is_empty() && self.only_in_b.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Value of a variable in a [`ContextDiff`], [`SECRET_MASK`] for secrets
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiffedVariable {
+    pub value: VarValue,
+    pub secret: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangedVariable {
+    pub a: DiffedVariable,
+    pub b: DiffedVariable,
 }
 
-pub struct VariableManager {
-    config_dir: PathBuf,
-}
+/// A placeholder of a command and the value it would get, as reported by
+/// [`VariableManager::inspect_placeholders`] and printed by `doo which`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlaceholderStatus {
+    /// The placeholder without default and filters (`#1`, `$1`, `#name`,
+    /// `${NAME}`)
+    pub label: String,
+    /// The placeholder as written in the template
+    pub raw: String,
+    /// [`SECRET_MASK`] for secrets; `None` if the value is missing or comes
+    /// from the runtime arguments
+    pub value: Option<VarValue>,
+    pub source: ValueSource,
+    /// Context the stored value is set in, the active one or a parent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context: Option<String>,
+    pub secret: bool,
+    pub optional: bool,
+}
+
+/// Where a [`PlaceholderStatus`] gets its value from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValueSource {
+    /// Set with `doo var`
+    Variable,
+    /// Set with `doo var --command` for this command
+    CommandVariable,
+    /// An entry of a loaded `.env` file
+    Dotenv,
+    /// Inline default or one from the command's `defaults` or `params`
+    Default,
+    Environment,
+    /// Given on the command line (`$N`, `#*`)
+    Argument,
+    /// Nothing provides a value; `#N` then takes the next runtime argument
+    Missing,
+}
+
+/// Problem in a command template found by
+/// [`VariableManager::validate_template`], before anything runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateWarning {
+    /// Text that looks like a placeholder but is left as it is, e.g. `${HOME`
+    UnknownSyntax(String),
+    /// A placeholder with a filter that doesn't exist
+    UnknownFilter(String),
+    /// A numbered placeholder while a lower position is never used, so the
+    /// arguments don't line up with the numbers (`#3` without `#2`)
+    SkippedPosition {
+        placeholder: String,
+        missing: String,
+    },
+    /// A numbered placeholder after `#*`; it still takes its argument before
+    /// the ones `#*` forwards
+    AfterRest { placeholder: String, rest: String },
+}
+
+impl std::fmt::Display for TemplateWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TemplateWarning::UnknownSyntax(text) => write!(f, "malformed placeholder '{text}'"),
+            TemplateWarning::UnknownFilter(message) => write!(f, "{message}"),
+            TemplateWarning::SkippedPosition {
+                placeholder,
+                missing,
+            } => write!(
+                f,
+                "{placeholder} is used but {missing} isn't, so arguments won't line up with the numbers"
+            ),
+            TemplateWarning::AfterRest { placeholder, rest } => write!(
+                f,
+                "{placeholder} comes after {rest} but still takes its argument before the ones {rest} forwards"
+            ),
+        }
+    }
+}
+
+pub struct VariableManager {
+    config_dir: PathBuf,
+    /// Fail on unset `${NAME}` environment variables instead of leaving them
+    strict_env: bool,
+    /// Entries of a loaded `.env` file, keyed like variables (`#KEY`)
+    dotenv: HashMap<String, String>,
+    /// Holds the secrets that the secrets files refer to with `keyring:`
+    keychain: Box<dyn Keychain>,
+    /// Store new secret values in `keychain` instead of the secrets file
+    keychain_secrets: bool,
+    /// Keys of the encrypted contexts opened so far
+    vault: Vault,
+}
+
+#[allow(dead_code)]
+impl VariableManager {
+    pub fn new(config_manager: &ConfigManager) -> Result<Self> {
+        let config_dir = config_manager.config_dir().clone();
+        let variables_dir = config_dir.join("variables");
+
+        // Create variables directory if it doesn't exist
+        fs::create_dir_all(&variables_dir).context("Failed to create variables directory")?;
+
+        Ok(Self {
+            config_dir,
+            strict_env: false,
+            dotenv: HashMap::new(),
+            keychain: Box::new(SystemKeychain),
+            keychain_secrets: config_manager.secret_store() == SecretStore::Keyring,
+            vault: Vault::default(),
+        })
+    }
+
+    /// Use `passphrase` for the encrypted context `context` instead of
+    /// asking for it
+    pub fn set_passphrase(&self, context: &str, passphrase: &str) {
+        self.vault.set_passphrase(context, passphrase);
+    }
+
+    /// Whether the files of `context` are encrypted
+    pub fn is_encrypted(&self, context: &str) -> Result<bool> {
+        let path = self.variables_file(context);
+        if !path.exists() {
+            return Ok(false);
+        }
+        let contents =
+            fs::read_to_string(&path).with_context(|| format!("Failed to read {path:?}"))?;
+        Ok(encryption::is_encrypted(&contents))
+    }
+
+    /// Encrypt the variables, secrets and history files of `context` with
+    /// `passphrase`. The plaintext backup of the variables file is removed.
+    pub fn encrypt_context(&mut self, context: &str, passphrase: &str) -> Result<()> {
+        let _lock = ConfigLock::acquire(&self.config_dir)?;
+        if self.is_encrypted(context)? {
+            return Err(anyhow!("Context '{}' is already encrypted", context));
+        }
+        // Make sure the files parse before they can no longer be read
+        let variables = serde_yaml::to_string(&self.load_variables(context)?)
+            .context("Failed to serialize variables")?;
+        self.load_secrets(context)?;
+        self.load_history(context)?;
+
+        self.vault.create_key(context, passphrase)?;
+        // The variables file marks the context as encrypted, so it goes
+        // first; the other files are encrypted on their next write otherwise
+        let mut files = vec![(self.variables_file(context), variables)];
+        for path in [
+            secrets_file(&self.config_dir, context),
+            history_file(&self.config_dir, context),
+        ] {
+            if path.exists() {
+                let contents = fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read {path:?}"))?;
+                files.push((path, contents));
+            }
+        }
+        for (path, contents) in files {
+            if !encryption::is_encrypted(&contents) {
+                let encrypted = self.vault.encrypt(context, &contents)?;
+                atomic_write_private(&path, &encrypted)
+                    .with_context(|| format!("Failed to write {path:?}"))?;
+            }
+        }
+        remove_backup(&self.variables_file(context))
+    }
+
+    /// Turn the encrypted files of `context` back into plaintext. Nothing is
+    /// written unless all of them could be decrypted.
+    pub fn decrypt_context(&mut self, context: &str) -> Result<()> {
+        let _lock = self.lock_context(context)?;
+        if !self.is_encrypted(context)? {
+            return Err(anyhow!("Context '{}' is not encrypted", context));
+        }
+        let mut files = Vec::new();
+        for path in [
+            self.variables_file(context),
+            secrets_file(&self.config_dir, context),
+            history_file(&self.config_dir, context),
+        ] {
+            if let Some(contents) = self.read_encrypted(context, &path)? {
+                files.push((path, contents));
+            }
+        }
+        for (index, (path, contents)) in files.iter().enumerate() {
+            // Secrets and history stay readable only by the current user
+            let write = if index == 0 {
+                atomic_write
+            } else {
+                atomic_write_private
+            };
+            write(path, contents).with_context(|| format!("Failed to write {path:?}"))?;
+        }
+        remove_backup(&self.variables_file(context))?;
+        self.vault.forget(context);
+        Ok(())
+    }
+
+    /// Use `keychain` for secrets instead of the one of the operating
+    /// system, storing new secret values in it if `store_secrets` is set
+    pub fn set_keychain(&mut self, keychain: Box<dyn Keychain>, store_secrets: bool) {
+        self.keychain = keychain;
+        self.keychain_secrets = store_secrets;
+    }
+
+    /// Make unset environment variables in templates an error instead of
+    /// leaving `${NAME}` untouched
+    pub fn set_strict_env(&mut self, strict_env: bool) {
+        self.strict_env = strict_env;
+    }
+
+    /// Provide the entries of the dotenv file at `path` as `#KEY`
+    /// placeholders in every context, below the variables set with `doo var`.
+    /// Malformed lines are skipped; their line numbers are returned.
+    pub fn load_dotenv(&mut self, path: &Path) -> Result<Vec<usize>> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let (vars, skipped) = parse_env_lenient(&contents);
+        self.dotenv = vars
+            .into_iter()
+            .map(|(key, value)| (variable_key(&key), value))
+            .collect();
+        Ok(skipped)
+    }
+
+    pub fn set_variable(&mut self, context: &str, name: &str, value: &str) -> Result<()> {
+        self.store(context, name, value.into(), false, None)
+    }
+
+    /// Store a variable in the context's secrets file, which is only readable
+    /// by the current user. Secret values are masked wherever doo prints them.
+    pub fn set_secret(&mut self, context: &str, name: &str, value: &str) -> Result<()> {
+        self.store(context, name, value.into(), true, None)
+    }
+
+    /// Store a list variable, whose items `#name` expands to as separate
+    /// arguments
+    pub fn set_list(
+        &mut self,
+        context: &str,
+        name: &str,
+        items: Vec<String>,
+        secret: bool,
+    ) -> Result<()> {
+        self.store(context, name, VarValue::List(items), secret, None)
+    }
+
+    /// Like [`set_variable`](Self::set_variable) or
+    /// [`set_secret`](Self::set_secret), but the variable counts as unset once
+    /// `ttl` has passed. Returns the expiry time.
+    pub fn set_with_ttl(
+        &mut self,
+        context: &str,
+        name: &str,
+        value: impl Into<VarValue>,
+        secret: bool,
+        ttl: Duration,
+    ) -> Result<DateTime<Utc>> {
+        let ttl = chrono::Duration::from_std(ttl).context("TTL is too long")?;
+        let expires = Utc::now()
+            .checked_add_signed(ttl)
+            .ok_or_else(|| anyhow!("TTL is too long"))?;
+        self.store(context, name, value.into(), secret, Some(expires))?;
+        Ok(expires)
+    }
+
+    /// Set several variables at once, writing each file only once. With a
+    /// `ttl`, all of them expire together; the expiry time is returned.
+    pub fn set_variables(
+        &mut self,
+        context: &str,
+        values: Vec<(String, VarValue)>,
+        secret: bool,
+        ttl: Option<Duration>,
+    ) -> Result<Option<DateTime<Utc>>> {
+        let expires = match ttl {
+            Some(ttl) => {
+                let ttl = chrono::Duration::from_std(ttl).context("TTL is too long")?;
+                Some(
+                    Utc::now()
+                        .checked_add_signed(ttl)
+                        .ok_or_else(|| anyhow!("TTL is too long"))?,
+                )
+            }
+            None => None,
+        };
+        self.store_all(context, values, secret, expires)?;
+        Ok(expires)
+    }
+
+    /// Write `name` to the variables or secrets file of `context`, dropping it
+    /// from the other one so a name is either plain or secret. A different
+    /// previous value is added to the history of `context`.
+    fn store(
+        &mut self,
+        context: &str,
+        name: &str,
+        value: VarValue,
+        secret: bool,
+        expires: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        self.store_all(context, vec![(name.to_string(), value)], secret, expires)
+    }
+
+    /// [`store`](Self::store) for several variables, reading and writing
+    /// each file once
+    fn store_all(
+        &mut self,
+        context: &str,
+        values: Vec<(String, VarValue)>,
+        secret: bool,
+        expires: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        let _lock = self.lock_context(context)?;
+        let mut variables = self.load_variables(context)?;
+        let mut secrets = self.load_secrets(context)?;
+        let mut replaced = Vec::new();
+        let mut moved = false;
+        for (name, value) in values {
+            let key = variable_key(&name);
+            let previous = match secrets.vars.get(&key) {
+                Some(value) => Some((value.clone(), true)),
+                None => variables.vars.get(&key).map(|value| (value.clone(), false)),
+            };
+            // The keychain only holds the current value of a secret, so its
+            // previous values can't be restored
+            let previous =
+                previous.filter(|(previous, _)| keychain::referenced_account(previous).is_none());
+            if let Some((previous, was_secret)) = previous {
+                if previous != value || was_secret != secret {
+                    replaced.push((
+                        key.clone(),
+                        HistoryEntry {
+                            value: previous,
+                            secret: was_secret,
+                            replaced_at: Utc::now(),
+                        },
+                    ));
+                }
+            }
+
+            let (target, other) = if secret {
+                (&mut secrets, &mut variables)
+            } else {
+                (&mut variables, &mut secrets)
+            };
+            target.insert(&key, value);
+            target.set_expiry(&key, expires);
+            // The description stays with the variable when it becomes secret
+            // or plain
+            if let Some(description) = other.meta.remove(&key).and_then(|meta| meta.description) {
+                target.meta.entry(key.clone()).or_default().description = Some(description);
+            }
+            moved |= other.remove(&key).is_some();
+        }
+
+        if !replaced.is_empty() {
+            let mut history = self.load_history(context)?;
+            for (key, entry) in replaced {
+                history.record(&key, entry);
+            }
+            self.save_history(context, &history)?;
+        }
+        if secret || moved {
+            self.save_secrets(context, &secrets)?;
+        }
+        if !secret || moved {
+            self.save_variables(context, &variables)?;
+        }
+        Ok(())
+    }
+
+    /// Previous values of `name` in `context`, most recent first
+    pub fn history(&self, context: &str, name: &str) -> Result<Vec<HistoryEntry>> {
+        let mut values = self
+            .load_history(context)?
+            .vars
+            .remove(&variable_key(name))
+            .unwrap_or_default();
+        values.reverse();
+        Ok(values)
+    }
+
+    /// Set `name` in `context` back to its most recent previous value and
+    /// drop that value from the history. Returns the restored entry, `None`
+    /// if there is no previous value.
+    pub fn undo_variable(&mut self, context: &str, name: &str) -> Result<Option<HistoryEntry>> {
+        let _lock = self.lock_context(context)?;
+        let key = variable_key(name);
+        let mut history = self.load_history(context)?;
+        let Some(entry) = history.vars.get_mut(&key).and_then(Vec::pop) else {
+            return Ok(None);
+        };
+        if history.vars.get(&key).is_some_and(Vec::is_empty) {
+            history.vars.remove(&key);
+        }
+
+        let mut variables = self.load_variables(context)?;
+        let mut secrets = self.load_secrets(context)?;
+        let (target, other) = if entry.secret {
+            (&mut secrets, &mut variables)
+        } else {
+            (&mut variables, &mut secrets)
+        };
+        target.insert(&key, entry.value.clone());
+        target.set_expiry(&key, None);
+        let moved = other.remove(&key).is_some();
+        if entry.secret || moved {
+            self.save_secrets(context, &secrets)?;
+        }
+        if !entry.secret || moved {
+            self.save_variables(context, &variables)?;
+        }
+        self.save_history(context, &history)?;
+        Ok(Some(entry))
+    }
+
+    /// Capture the variables and secrets of `context`, including
+    /// command-scoped values, expiry times and descriptions, so changes made
+    /// afterwards can be reverted with [`restore`](Self::restore)
+    pub fn snapshot(&self, context: &str) -> Result<VariableSnapshot> {
+        let variables_file = self.variables_file(context);
+        let secrets_file = secrets_file(&self.config_dir, context);
+        Ok(VariableSnapshot {
+            context: context.to_string(),
+            variables: variables_file
+                .exists()
+                .then(|| self.load_variables(context))
+                .transpose()?,
+            secrets: secrets_file
+                .exists()
+                .then(|| self.load_secrets(context))
+                .transpose()?,
+        })
+    }
+
+    /// Put the variables and secrets of a snapshot back, discarding whatever
+    /// was set in its context since. The history isn't touched, and secrets
+    /// kept in the keychain keep their current value.
+    pub fn restore(&mut self, snapshot: VariableSnapshot) -> Result<()> {
+        let VariableSnapshot {
+            context,
+            variables,
+            secrets,
+        } = snapshot;
+        let _lock = self.lock_context(&context)?;
+        match secrets {
+            Some(secrets) => self.save_secrets(&context, &secrets)?,
+            None => {
+                let secrets_file = secrets_file(&self.config_dir, &context);
+                if secrets_file.exists() {
+                    // Drops the keychain entries of secrets set since
+                    self.save_secrets(&context, &Variables::default())?;
+                    fs::remove_file(&secrets_file).context("Failed to delete secrets file")?;
+                }
+            }
+        }
+        match variables {
+            Some(variables) => self.save_variables(&context, &variables)?,
+            None => {
+                let variables_file = self.variables_file(&context);
+                if variables_file.exists() {
+                    fs::remove_file(&variables_file).context("Failed to delete variables file")?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Value of `name` in `context`, `None` if it is unset or expired
+    pub fn get_variable(&self, context: &str, name: &str) -> Result<Option<String>> {
+        let key = variable_key(name);
+        let now = Utc::now();
+        let value = match self.load_variables(context)?.active(now).remove(&key) {
+            Some(value) => Some(value),
+            None => self
+                .load_revealed_secrets(context)?
+                .active(now)
+                .remove(&key),
+        };
+        Ok(value.map(|value| value.to_string()))
+    }
+
+    /// Value of `name` as commands in `context` see it: set in `context` or
+    /// inherited from its parents, `None` if unset or expired
+    pub fn effective_variable(&self, context: &str, name: &str) -> Result<Option<String>> {
+        Ok(self
+            .load_inherited_variables(context, None)?
+            .remove(&variable_key(name)))
+    }
+
+    /// Expiry times of the variables and secrets of `context` that have a TTL
+    pub fn expiry(&self, context: &str) -> Result<HashMap<String, DateTime<Utc>>> {
+        let mut expires = self.load_variables(context)?.expires;
+        expires.extend(self.load_secrets(context)?.expires);
+        Ok(expires)
+    }
+
+    /// Plain variables of `context`, without secrets, including expired ones
+    pub fn list_variables(&self, context: &str) -> Result<HashMap<String, VarValue>> {
+        let variables = self.load_variables(context)?;
+        Ok(variables.vars)
+    }
+
+    /// Secret variables of `context`, including expired ones. Secrets kept
+    /// in the keychain are listed as their `keyring:` reference; see
+    /// [`reveal`](Self::reveal).
+    pub fn list_secrets(&self, context: &str) -> Result<HashMap<String, VarValue>> {
+        Ok(self.load_secrets(context)?.vars)
+    }
+
+    /// Unexpired context-wide variables and secrets that `context` takes
+    /// from its ancestors because it doesn't set them itself
+    pub fn list_inherited(&self, context: &str) -> Result<BTreeMap<String, InheritedVariable>> {
+        let chain = ContextMeta::load(&self.config_dir)?.chain(context)?;
+        let now = Utc::now();
+        let mut own: HashSet<String> = self
+            .load_variables(context)?
+            .active(now)
+            .into_keys()
+            .collect();
+        own.extend(self.load_secrets(context)?.active(now).into_keys());
+        let mut inherited = BTreeMap::new();
+        for ancestor in &chain[1..] {
+            let secrets = self.load_secrets(ancestor)?.active(now);
+            let variables = self.load_variables(ancestor)?.active(now);
+            let entries = secrets.into_iter().map(|entry| (entry, true));
+            for ((name, value), secret) in
+                entries.chain(variables.into_iter().map(|entry| (entry, false)))
+            {
+                if own.contains(&name) || inherited.contains_key(&name) {
+                    continue;
+                }
+                inherited.insert(
+                    name,
+                    InheritedVariable {
+                        value,
+                        secret,
+                        context: ancestor.clone(),
+                    },
+                );
+            }
+        }
+        Ok(inherited)
+    }
+
+    /// The secret `value` of `key` refers to if it is a `keyring:`
+    /// reference, otherwise `value` itself
+    pub fn reveal(&self, key: &str, value: VarValue) -> Result<VarValue> {
+        let Some(account) = keychain::referenced_account(&value) else {
+            return Ok(value);
+        };
+        match self.keychain.get(account) {
+            Ok(Some(secret)) => Ok(VarValue::Single(secret)),
+            Ok(None) => Err(anyhow!(
+                "{key} refers to {value}, which is missing from the system keychain; set it again with 'doo var --secret {} <value>'",
+                key.trim_start_matches('#')
+            )),
+            Err(e) => Err(anyhow!("Can't read {key} from the system keychain: {e}")),
+        }
+    }
+
+    /// Compare the context-wide variables set in `a` and `b`, without
+    /// inherited and expired ones. Secrets are compared by their actual
+    /// values but returned masked.
+    pub fn diff_contexts(&self, a: &str, b: &str) -> Result<ContextDiff> {
+        let now = Utc::now();
+        let load = |context: &str| -> Result<BTreeMap<String, DiffedVariable>> {
+            let secrets = self.load_revealed_secrets(context)?.active(now);
+            let vars = self.load_variables(context)?.active(now);
+            let entries = secrets.into_iter().map(|entry| (entry, true));
+            let entries = entries.chain(vars.into_iter().map(|entry| (entry, false)));
+            Ok(entries
+                .map(|((key, value), secret)| (key, DiffedVariable { value, secret }))
+                .collect())
+        };
+        let mask = |mut variable: DiffedVariable| {
+            if variable.secret {
+                variable.value = VarValue::from(SECRET_MASK);
+            }
+            variable
+        };
+
+        let mut diff = ContextDiff::default();
+        let mut in_b = load(b)?;
+        for (key, in_a) in load(a)? {
+            match in_b.remove(&key) {
+                Some(in_b) if in_b.value == in_a.value => {}
+                Some(in_b) => {
+                    let changed = ChangedVariable {
+                        a: mask(in_a),
+                        b: mask(in_b),
+                    };
+                    diff.changed.insert(key, changed);
+                }
+                None => {
+                    diff.only_in_a.insert(key, mask(in_a));
+                }
+            }
+        }
+        diff.only_in_b = in_b
+            .into_iter()
+            .map(|(key, in_b)| (key, mask(in_b)))
+            .collect();
+        Ok(diff)
+    }
+
+    /// Move the secrets of `context` that are stored in its secrets file to
+    /// the keychain, leaving `keyring:` references behind, and drop previous
+    /// secret values from its history. Returns the moved variables; list
+    /// values stay in the file.
+    pub fn migrate_secrets(&mut self, context: &str) -> Result<Vec<String>> {
+        let _lock = self.lock_context(context)?;
+        let secrets = self.load_secrets(context)?;
+        let plain =
+            |value: &VarValue| !value.is_list() && keychain::referenced_account(value).is_none();
+        let mut moved: Vec<String> = secrets
+            .vars
+            .iter()
+            .filter(|(_, value)| plain(value))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for (command, vars) in &secrets.commands {
+            let scoped = vars.iter().filter(|(_, value)| plain(value));
+            moved.extend(scoped.map(|(key, _)| format!("{key} (--command {command})")));
+        }
+        moved.sort();
+        if !moved.is_empty() {
+            self.write_secrets(context, &secrets, true)?;
+        }
+
+        let mut history = self.load_history(context)?;
+        let before = history.vars.values().map(Vec::len).sum::<usize>();
+        for entries in history.vars.values_mut() {
+            entries.retain(|entry| !entry.secret);
+        }
+        history.vars.retain(|_, entries| !entries.is_empty());
+        if history.vars.values().map(Vec::len).sum::<usize>() != before {
+            self.save_history(context, &history)?;
+        }
+        Ok(moved)
+    }
+
+    /// Give the secrets of `context` that refer to another context's
+    /// keychain entries (after cloning or renaming a context) entries of
+    /// their own
+    pub fn adopt_secrets(&mut self, context: &str) -> Result<()> {
+        let _lock = self.lock_context(context)?;
+        let secrets = self.load_secrets(context)?;
+        let foreign = referenced_accounts(&secrets)
+            .any(|account| !account.starts_with(&format!("{context}/")));
+        if foreign {
+            self.write_secrets(context, &secrets, false)?;
+        }
+        Ok(())
+    }
+
+    /// Keychain entries the secrets of `context` refer to
+    pub fn keychain_accounts(&self, context: &str) -> Result<Vec<String>> {
+        let secrets = self.load_secrets(context)?;
+        Ok(referenced_accounts(&secrets).map(str::to_string).collect())
+    }
+
+    /// Delete keychain entries, e.g. those of a deleted context, warning
+    /// about the ones that can't be deleted
+    pub fn delete_from_keychain(&self, accounts: &[String]) {
+        for account in accounts {
+            if let Err(e) = self.keychain.delete(account) {
+                eprintln!("⚠ Failed to delete {account} from the system keychain: {e}");
+            }
+        }
+    }
+
+    /// Set the description of `name` in `context`, or remove it with `None`.
+    /// Returns `false` if the variable isn't set.
+    pub fn describe_variable(
+        &mut self,
+        context: &str,
+        name: &str,
+        description: Option<&str>,
+    ) -> Result<bool> {
+        let _lock = self.lock_context(context)?;
+        let key = variable_key(name);
+        let describe = |variables: &mut Variables| {
+            let meta = variables.meta.entry(key.clone()).or_default();
+            meta.description = description.map(str::to_string);
+        };
+        let mut variables = self.load_variables(context)?;
+        if variables.vars.contains_key(&key) {
+            describe(&mut variables);
+            self.save_variables(context, &variables)?;
+            return Ok(true);
+        }
+        let mut secrets = self.load_secrets(context)?;
+        if secrets.vars.contains_key(&key) {
+            describe(&mut secrets);
+            self.save_secrets(context, &secrets)?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Descriptions and modification times of the variables and secrets of
+    /// `context`, for those that have any
+    pub fn metadata(&self, context: &str) -> Result<HashMap<String, VarMeta>> {
+        let mut meta = self.load_variables(context)?.meta;
+        meta.extend(self.load_secrets(context)?.meta);
+        Ok(meta)
+    }
+
+    pub fn remove_variable(&mut self, context: &str, name: &str) -> Result<bool> {
+        let _lock = self.lock_context(context)?;
+        let key = variable_key(name);
+        let mut variables = self.load_variables(context)?;
+        if variables.remove(&key).is_some() {
+            self.save_variables(context, &variables)?;
+            return Ok(true);
+        }
+        let mut secrets = self.load_secrets(context)?;
+        if secrets.remove(&key).is_some() {
+            self.save_secrets(context, &secrets)?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Set `name` in `context` for `command` only; when the command runs, it
+    /// takes precedence over the context-wide value. Command-scoped values
+    /// have no history or TTL.
+    pub fn set_command_variable(
+        &mut self,
+        context: &str,
+        command: &str,
+        name: &str,
+        value: VarValue,
+        secret: bool,
+    ) -> Result<()> {
+        let _lock = self.lock_context(context)?;
+        let key = variable_key(name);
+        let mut variables = self.load_variables(context)?;
+        let mut secrets = self.load_secrets(context)?;
+        let (target, other) = if secret {
+            (&mut secrets, &mut variables)
+        } else {
+            (&mut variables, &mut secrets)
+        };
+        target
+            .commands
+            .entry(command.to_string())
+            .or_default()
+            .insert(key.clone(), value);
+        let moved = remove_scoped(other, command, &key).is_some();
+
+        if secret || moved {
+            self.save_secrets(context, &secrets)?;
+        }
+        if !secret || moved {
+            self.save_variables(context, &variables)?;
+        }
+        Ok(())
+    }
+
+    /// Remove the value of `name` that only applies to `command`
+    pub fn remove_command_variable(
+        &mut self,
+        context: &str,
+        command: &str,
+        name: &str,
+    ) -> Result<bool> {
+        let _lock = self.lock_context(context)?;
+        let key = variable_key(name);
+        let mut variables = self.load_variables(context)?;
+        if remove_scoped(&mut variables, command, &key).is_some() {
+            self.save_variables(context, &variables)?;
+            return Ok(true);
+        }
+        let mut secrets = self.load_secrets(context)?;
+        if remove_scoped(&mut secrets, command, &key).is_some() {
+            self.save_secrets(context, &secrets)?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Command-scoped values set in `context` by command, then variable
+    /// name, with whether they are secret
+    pub fn list_command_variables(
+        &self,
+        context: &str,
+    ) -> Result<BTreeMap<String, ScopedVariables>> {
+        let mut scoped: BTreeMap<String, ScopedVariables> = BTreeMap::new();
+        let files = [
+            (self.load_variables(context)?, false),
+            (self.load_secrets(context)?, true),
+        ];
+        for (variables, secret) in files {
+            for (command, vars) in variables.commands {
+                let entries = scoped.entry(command).or_default();
+                entries.extend(vars.into_iter().map(|(key, value)| (key, (value, secret))));
+            }
+        }
+        Ok(scoped)
+    }
+
+    /// Copy the variables of `from` to `to`, or only those in `only` if it is
+    /// not empty. Secrets stay secrets and expiry times are kept; expired
+    /// variables are not copied. For variables already set in `to`,
+    /// `overwrite` is asked with the variable name whether to replace them.
+    pub fn copy_variables(
+        &mut self,
+        from: &str,
+        to: &str,
+        only: &[String],
+        mut overwrite: impl FnMut(&str) -> Result<bool>,
+    ) -> Result<CopyOutcome> {
+        let now = Utc::now();
+        let source_expires = self.expiry(from)?;
+        let source_vars = self.load_variables(from)?.active(now);
+        let source_secrets = self.load_secrets(from)?.active(now);
+        let mut names: Vec<String> = if only.is_empty() {
+            source_vars
+                .keys()
+                .chain(source_secrets.keys())
+                .cloned()
+                .collect()
+        } else {
+            only.iter().map(|name| variable_key(name)).collect()
+        };
+        names.sort();
+        names.dedup();
+        if names.is_empty() {
+            return Err(anyhow!("Context '{}' has no variables to copy", from));
+        }
+        if let Some(name) = names
+            .iter()
+            .find(|name| !source_vars.contains_key(*name) && !source_secrets.contains_key(*name))
+        {
+            return Err(anyhow!(
+                "Variable '{}' is not set in context '{}'",
+                name,
+                from
+            ));
+        }
+
+        // Decide before locking so a prompt doesn't block other doo processes
+        let target_vars = self.load_variables(to)?.vars;
+        let target_secrets = self.load_secrets(to)?.vars;
+        let mut outcome = CopyOutcome::default();
+        for name in names {
+            let exists = target_vars.contains_key(&name) || target_secrets.contains_key(&name);
+            if exists && !overwrite(&name)? {
+                outcome.skipped.push(name);
+            } else {
+                outcome.copied.push(name);
+            }
+        }
+
+        let _lock = self.lock_context(to)?;
+        let mut variables = self.load_variables(to)?;
+        let mut secrets = self.load_secrets(to)?;
+        for name in &outcome.copied {
+            let (target, other, value) = match source_secrets.get(name) {
+                Some(value) => (&mut secrets, &mut variables, value),
+                None => (&mut variables, &mut secrets, &source_vars[name]),
+            };
+            other.remove(name);
+            target.insert(name, value.clone());
+            target.set_expiry(name, source_expires.get(name).copied());
+        }
+        if !outcome.copied.is_empty() {
+            self.save_variables(to, &variables)?;
+            if !secrets.vars.is_empty() || secrets_file(&self.config_dir, to).exists() {
+                self.save_secrets(to, &secrets)?;
+            }
+        }
+        Ok(outcome)
+    }
+
+    /// Statements that set the variables of `context` as environment
+    /// variables in `shell`, one per line, e.g. `export NAMESPACE=prod`.
+    /// Only variables whose name is a valid environment variable name are
+    /// included (so `#namespace` but not `#1`), and secrets only on request.
+    pub fn export_as_shell(
+        &self,
+        context: &str,
+        shell: ShellFormat,
+        include_secrets: bool,
+    ) -> Result<String> {
+        let now = Utc::now();
+        let mut vars: BTreeMap<String, VarValue> = self
+            .load_variables(context)?
+            .active(now)
+            .into_iter()
+            .collect();
+        if include_secrets {
+            vars.extend(self.load_revealed_secrets(context)?.active(now));
+        }
+        let mut script = String::new();
+        for (key, value) in &vars {
+            let name = key.trim_start_matches('#');
+            if is_env_name(name) {
+                script.push_str(&shell.statement(name, &value.to_string()));
+                script.push('\n');
+            }
+        }
+        Ok(script)
+    }
+
+    /// Write the variables of `context` to `path`, as JSON if it ends in
+    /// `.json` and YAML otherwise. Secrets are only included on request.
+    /// Returns the number of exported variables.
+    pub fn export_variables(
+        &self,
+        context: &str,
+        path: &Path,
+        include_secrets: bool,
+    ) -> Result<usize> {
+        let now = Utc::now();
+        let mut exported = ExportedVariables {
+            vars: self
+                .load_variables(context)?
+                .active(now)
+                .into_iter()
+                .collect(),
+            secrets: BTreeMap::new(),
+        };
+        if include_secrets {
+            exported.secrets = self
+                .load_revealed_secrets(context)?
+                .active(now)
+                .into_iter()
+                .collect();
+        }
+        let count = exported.vars.len() + exported.secrets.len();
+
+        let contents = if is_json(path) {
+            serde_json::to_string_pretty(&exported).context("Failed to serialize variables")? + "\n"
+        } else {
+            serde_yaml::to_string(&exported).context("Failed to serialize variables")?
+        };
+        if exported.secrets.is_empty() {
+            atomic_write(path, &contents)
+        } else {
+            atomic_write_private(path, &contents)
+        }
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(count)
+    }
+
+    /// Merge the variables of a file written by
+    /// [`export_variables`](Self::export_variables) or of a dotenv file
+    /// (`.env`, `*.env`) into `context`, or replace all variables of
+    /// `context` with them if `replace` is set. The file is validated before
+    /// anything is changed.
+    pub fn import_variables(
+        &mut self,
+        context: &str,
+        path: &Path,
+        replace: bool,
+    ) -> Result<ImportOutcome> {
+        if is_env_file(path) {
+            let vars = read_env_file(path)?;
+            return self.merge_variables(
+                context,
+                path,
+                ExportedVariables {
+                    vars: vars.into_iter().map(|(k, v)| (k, v.into())).collect(),
+                    ..Default::default()
+                },
+                replace,
+            );
+        }
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let parsed = if is_json(path) {
+            serde_json::from_str(&contents).map_err(anyhow::Error::from)
+        } else {
+            serde_yaml::from_str(&contents).map_err(anyhow::Error::from)
+        };
+        let imported: ExportedVariables =
+            parsed.map_err(|e| anyhow!("Invalid variables file {}: {}", path.display(), e))?;
+        self.merge_variables(context, path, imported, replace)
+    }
+
+    /// Set every `KEY=value` entry of a dotenv file as variable `KEY` of
+    /// `context`, returning the number of entries. See [`parse_env`] for the
+    /// format.
+    pub fn load_from_env_file(&mut self, context: &str, path: &Path) -> Result<usize> {
+        let vars = read_env_file(path)?;
+        let count = vars.len();
+        self.merge_variables(
+            context,
+            path,
+            ExportedVariables {
+                vars: vars.into_iter().map(|(k, v)| (k, v.into())).collect(),
+                ..Default::default()
+            },
+            false,
+        )?;
+        Ok(count)
+    }
+
+    fn merge_variables(
+        &mut self,
+        context: &str,
+        path: &Path,
+        imported: ExportedVariables,
+        replace: bool,
+    ) -> Result<ImportOutcome> {
+        if let Some(name) = imported
+            .vars
+            .keys()
+            .chain(imported.secrets.keys())
+            .find(|name| name.trim_start_matches('#').is_empty())
+        {
+            return Err(anyhow!(
+                "Invalid variable name '{}' in {}",
+                name,
+                path.display()
+            ));
+        }
+
+        let _lock = self.lock_context(context)?;
+        let mut variables = self.load_variables(context)?;
+        let mut secrets = self.load_secrets(context)?;
+        if replace {
+            variables = Variables::default();
+            secrets = Variables::default();
+        }
+
+        let mut outcome = ImportOutcome::default();
+        let entries = imported
+            .vars
+            .iter()
+            .map(|entry| (entry, false))
+            .chain(imported.secrets.iter().map(|entry| (entry, true)));
+        for ((name, value), secret) in entries {
+            let key = variable_key(name);
+            let (target, other) = if secret {
+                (&mut secrets, &mut variables)
+            } else {
+                (&mut variables, &mut secrets)
+            };
+            let previous = other
+                .remove(&key)
+                .or_else(|| target.vars.get(&key).cloned());
+            match previous {
+                None => outcome.added.push(key.clone()),
+                Some(previous) if previous == *value => outcome.unchanged.push(key.clone()),
+                Some(_) => outcome.overwritten.push(key.clone()),
+            }
+            target.set_expiry(&key, None);
+            target.insert(&key, value.clone());
+        }
+
+        self.save_variables(context, &variables)?;
+        if !secrets.vars.is_empty() || secrets_file(&self.config_dir, context).exists() {
+            self.save_secrets(context, &secrets)?;
+        }
+        for names in [
+            &mut outcome.added,
+            &mut outcome.overwritten,
+            &mut outcome.unchanged,
+        ] {
+            names.sort();
+        }
+        Ok(outcome)
+    }
+
+    /// Replace the values of all secrets visible in `context` (including
+    /// inherited ones) with [`SECRET_MASK`] so `text` can be printed
+    pub fn mask_secrets(&self, context: &str, text: &str) -> Result<String> {
+        let chain = ContextMeta::load(&self.config_dir)?.chain(context)?;
+        let mut values = Vec::new();
+        for name in &chain {
+            let secrets = self.load_revealed_secrets(name)?;
+            let scoped = secrets
+                .commands
+                .into_values()
+                .flat_map(HashMap::into_values);
+            for value in secrets.vars.into_values().chain(scoped) {
+                if value.is_list() {
+                    values.extend(value.items().iter().cloned());
+                }
+                values.push(value.to_string());
+            }
+        }
+        Ok(mask_values(text, values))
+    }
+
+    /// Substitute the placeholders of `template` using the variables of `context`
+    /// and the runtime `args`.
+    ///
+    /// Placeholders are resolved with the following precedence:
+    /// 1. `$N` is always the N-th runtime argument
+    /// 2. `#N` and `#name` are the stored variable of the context, or of the
+    ///    nearest parent context that sets it
+    /// 3. `#N` without a stored value takes the next unused runtime argument,
+    ///    in ascending placeholder order
+    /// 4. the inline default of `#N:-value` / `$N:-value` (`:=` works the same)
+    ///
+    /// `${NAME}` takes the stored variable `#NAME` if set, else the environment
+    /// variable `NAME`, else the default of `${NAME:-value}`. `$${` is a
+    /// literal `${`.
+    ///
+    /// Stored values may refer to other stored variables (`#host`,
+    /// `${HOST}`), which are expanded first; a cycle is an error naming the
+    /// chain of variables.
+    ///
+    /// List variables expand to their items as separate arguments, quoted
+    /// where needed.
+    ///
+    /// Stored values starting with [`DYNAMIC_PREFIX`] are replaced by the
+    /// trimmed output of their command. Each command runs at most once per
+    /// resolution, and only if the template uses the variable.
+    ///
+    /// Numbered placeholders without a value are left untouched, while missing
+    /// named variables are an error listing every missing name. Unset
+    /// environment variables are left untouched unless
+    /// [`set_strict_env`](Self::set_strict_env) is enabled.
+    pub fn resolve_variables(
+        &self,
+        context: &str,
+        template: &str,
+        args: &[String],
+    ) -> Result<String> {
+        self.resolve_variables_with_defaults(context, None, template, args, &BTreeMap::new())
+    }
+
+    /// Like [`resolve_variables`](Self::resolve_variables), for `command`
+    /// (its command-scoped variables take precedence) and with the `defaults`
+    /// of a detailed command entry as the last fallback
+    pub fn resolve_variables_with_defaults(
+        &self,
+        context: &str,
+        command: Option<&str>,
+        template: &str,
+        args: &[String],
+        defaults: &BTreeMap<String, String>,
+    ) -> Result<String> {
+        Ok(self
+            .resolve_variables_with_values(context, command, template, args, defaults, false)?
+            .0)
+    }
+
+    /// Like [`resolve_variables_with_defaults`](Self::resolve_variables_with_defaults),
+    /// also returning the value substituted for each placeholder, keyed by
+    /// its label (`#1`, `$1`, `#name`, `${NAME}`). With `quote`, substituted
+    /// values are quoted so each stays one argument of the command, e.g.
+    /// `it's` becomes `'it'\''s'`; the returned values are unquoted.
+    pub fn resolve_variables_with_values(
+        &self,
+        context: &str,
+        command: Option<&str>,
+        template: &str,
+        args: &[String],
+        defaults: &BTreeMap<String, String>,
+        quote: bool,
+    ) -> Result<(String, BTreeMap<String, String>)> {
+        crate::timed_span!("variables.resolve", context = context, template = template);
+        let (vars, lists) = self.template_values(context, command, template)?;
+        let sources = Sources {
+            vars: &vars,
+            args,
+            defaults,
+            env: &process_env,
+            quote: quote.then_some(&lists),
+        };
+        resolve_template_with_values(template, &sources, self.strict_env, true)
+    }
+
+    /// Like [`resolve_variables`](Self::resolve_variables), but missing named
+    /// variables are left as written instead of failing, for showing what
+    /// a template resolves to
+    pub fn preview_variables(
+        &self,
+        context: &str,
+        template: &str,
+        args: &[String],
+    ) -> Result<String> {
+        crate::timed_span!("variables.resolve", context = context, template = template);
+        let (vars, _) = self.template_values(context, None, template)?;
+        let sources = Sources {
+            vars: &vars,
+            args,
+            defaults: &BTreeMap::new(),
+            env: &process_env,
+            quote: None,
+        };
+        Ok(resolve_template_with_values(template, &sources, self.strict_env, false)?.0)
+    }
+
+    /// Values of the variables available to `template`, as substituted, with
+    /// dynamic values evaluated and references expanded, and the names of
+    /// those holding lists
+    fn template_values(
+        &self,
+        context: &str,
+        command: Option<&str>,
+        template: &str,
+    ) -> Result<(HashMap<String, String>, HashSet<String>)> {
+        let values = self.load_inherited_values(context, command)?;
+        let lists: HashSet<String> = values
+            .iter()
+            .filter(|(_, value)| value.is_list())
+            .map(|(key, _)| key.clone())
+            .collect();
+        let mut vars = values
+            .into_iter()
+            .map(|(key, value)| (key, value.to_string()))
+            .collect();
+        let executor = CommandExecutor::new();
+        let dynamic = evaluate_dynamic(&mut vars, template, &|command| {
+            executor.execute_with_output(command)
+        })?;
+        expand_references(&mut vars, template, &dynamic)?;
+        Ok((vars, lists))
+    }
+
+    /// Resolve the placeholders in the values of a command's `env` with the
+    /// same inputs as its template
+    pub fn resolve_env(
+        &self,
+        context: &str,
+        command: Option<&str>,
+        env: &BTreeMap<String, String>,
+        args: &[String],
+        defaults: &BTreeMap<String, String>,
+    ) -> Result<BTreeMap<String, String>> {
+        env.iter()
+            .map(|(name, value)| {
+                let value =
+                    self.resolve_variables_with_defaults(context, command, value, args, defaults)?;
+                Ok((name.clone(), value))
+            })
+            .collect()
+    }
+
+    /// Problems in `template` that would make it misbehave when run, such as
+    /// malformed placeholders or numbered placeholders that skip a position.
+    /// Stored variables are not needed to find them.
+    pub fn validate_template(template: &str) -> Vec<TemplateWarning> {
+        let mut warnings: Vec<TemplateWarning> = template::malformed(template)
+            .into_iter()
+            .map(TemplateWarning::UnknownSyntax)
+            .collect();
+        let tokens = template::parse(template);
+        let mut positions = BTreeMap::new();
+        let mut rest: Option<String> = None;
+        for placeholder in template::placeholders(&tokens) {
+            if let Err(e) = placeholder.check_filters() {
+                warnings.push(TemplateWarning::UnknownFilter(e.to_string()));
+            }
+            let position = match placeholder.kind {
+                PlaceholderKind::Argument(n) | PlaceholderKind::Variable(n) => n,
+                PlaceholderKind::Rest => {
+                    rest.get_or_insert_with(|| placeholder.label());
+                    continue;
+                }
+                _ => continue,
+            };
+            positions
+                .entry(position)
+                .or_insert_with(|| placeholder.label());
+            if let Some(rest) = &rest {
+                warnings.push(TemplateWarning::AfterRest {
+                    placeholder: placeholder.label(),
+                    rest: rest.clone(),
+                });
+            }
+        }
+        let skipped = (1..).find(|n| !positions.contains_key(n));
+        if let Some((missing, (_, label))) =
+            skipped.and_then(|missing| Some((missing, positions.range(missing..).next()?)))
+        {
+            warnings.push(TemplateWarning::SkippedPosition {
+                placeholder: label.clone(),
+                missing: format!("{}{missing}", &label[..1]),
+            });
+        }
+        warnings
+    }
+
+    /// Placeholders of `template` that would have no value when resolved with
+    /// the same inputs, as written in the template (`#2`, `$1`, `#name`)
+    pub fn missing_placeholders(
+        &self,
+        context: &str,
+        command: Option<&str>,
+        template: &str,
+        args: &[String],
+        defaults: &BTreeMap<String, String>,
+    ) -> Result<Vec<String>> {
+        let vars = self.load_inherited_variables(context, command)?;
+        let sources = Sources {
+            vars: &vars,
+            args,
+            defaults,
+            env: &process_env,
+            quote: None,
+        };
+        Ok(missing_in_template(template, &sources))
+    }
+
+    /// Every distinct placeholder of `template` in order of appearance, with
+    /// the value it would get in `context` for `command` before any runtime
+    /// arguments. Stored values are shown as they are, without running
+    /// dynamic ones or expanding references, and secrets are masked.
+    pub fn inspect_placeholders(
+        &self,
+        context: &str,
+        command: Option<&str>,
+        template: &str,
+        defaults: &BTreeMap<String, String>,
+    ) -> Result<Vec<PlaceholderStatus>> {
+        let chain = ContextMeta::load(&self.config_dir)?.chain(context)?;
+        let now = Utc::now();
+        let mut stored = Vec::new();
+        for name in &chain {
+            let mut variables = self.load_variables(name)?;
+            let mut secrets = self.load_secrets(name)?;
+            let scoped_variables = command
+                .and_then(|command| variables.commands.remove(command))
+                .unwrap_or_default();
+            let scoped_secrets = command
+                .and_then(|command| secrets.commands.remove(command))
+                .unwrap_or_default();
+            stored.push((
+                name,
+                [
+                    (scoped_secrets, ValueSource::CommandVariable, true),
+                    (scoped_variables, ValueSource::CommandVariable, false),
+                    (secrets.active(now), ValueSource::Variable, true),
+                    (variables.active(now), ValueSource::Variable, false),
+                ],
+            ));
+        }
+        // Nearest context first, its command-scoped values before the others
+        let lookup = |name: &str| {
+            stored.iter().find_map(|(context, layers)| {
+                layers.iter().find_map(|(values, source, secret)| {
+                    values
+                        .get(name)
+                        .map(|value| (value.clone(), *source, (*context).clone(), *secret))
+                })
+            })
+        };
+
+        let tokens = template::parse(template);
+        let mut statuses: Vec<PlaceholderStatus> = Vec::new();
+        for placeholder in template::placeholders(&tokens) {
+            let label = placeholder.label();
+            if statuses.iter().any(|status| status.label == label) {
+                continue;
+            }
+            let mut status = PlaceholderStatus {
+                label,
+                raw: placeholder.raw.clone(),
+                value: None,
+                source: ValueSource::Missing,
+                context: None,
+                secret: false,
+                optional: placeholder.optional,
+            };
+            let variable = match &placeholder.kind {
+                PlaceholderKind::Argument(_) | PlaceholderKind::Rest => {
+                    status.source = ValueSource::Argument;
+                    statuses.push(status);
+                    continue;
+                }
+                PlaceholderKind::Env(name) => format!("#{name}"),
+                _ => placeholder.variable_name().unwrap_or_default(),
+            };
+            if let Some((value, source, context, secret)) = lookup(&variable) {
+                status.value = Some(if secret {
+                    VarValue::from(SECRET_MASK)
+                } else {
+                    value
+                });
+                status.source = source;
+                status.context = Some(context);
+                status.secret = secret;
+            } else if let Some(value) = self.dotenv.get(&variable) {
+                status.value = Some(value.as_str().into());
+                status.source = ValueSource::Dotenv;
+            } else if let Some(value) = match &placeholder.kind {
+                PlaceholderKind::Env(name) => process_env(name),
+                _ => None,
+            } {
+                status.value = Some(value.into());
+                status.source = ValueSource::Environment;
+            } else if let Some(value) = placeholder
+                .default
+                .clone()
+                .or_else(|| configured_default(placeholder, defaults).cloned())
+            {
+                status.value = Some(value.into());
+                status.source = ValueSource::Default;
+            }
+            statuses.push(status);
+        }
+        Ok(statuses)
+    }
+
+    /// Variables and secrets of `context` merged over those of its parent
+    /// chain and a loaded `.env` file, leaving out expired ones
+    fn load_inherited_variables(
+        &self,
+        context: &str,
+        command: Option<&str>,
+    ) -> Result<HashMap<String, String>> {
+        Ok(self
+            .load_inherited_values(context, command)?
+            .into_iter()
+            .map(|(key, value)| (key, value.to_string()))
+            .collect())
+    }
+
+    /// [`load_inherited_variables`](Self::load_inherited_variables) keeping
+    /// list values apart. Entries of a loaded `.env` file come first, so any
+    /// context overrides them. With `command`, the values scoped to it
+    /// override the context-wide ones of the same context.
+    fn load_inherited_values(
+        &self,
+        context: &str,
+        command: Option<&str>,
+    ) -> Result<HashMap<String, VarValue>> {
+        let chain = ContextMeta::load(&self.config_dir)?.chain(context)?;
+        let now = Utc::now();
+        let mut vars: HashMap<String, VarValue> = self
+            .dotenv
+            .iter()
+            .map(|(key, value)| (key.clone(), value.as_str().into()))
+            .collect();
+        for name in chain.iter().rev() {
+            let mut variables = self.load_variables(name)?;
+            let mut secrets = self.load_revealed_secrets(name)?;
+            let scoped: Vec<_> = command
+                .into_iter()
+                .flat_map(|command| {
+                    let variables = variables.commands.remove(command).unwrap_or_default();
+                    let secrets = secrets.commands.remove(command).unwrap_or_default();
+                    variables.into_iter().chain(secrets)
+                })
+                .collect();
+            vars.extend(variables.active(now));
+            vars.extend(secrets.active(now));
+            vars.extend(scoped);
+        }
+        Ok(vars)
+    }
+
+    /// File the plain variables of `context` are stored in, whether or not it
+    /// exists yet
+    pub fn variables_file(&self, context: &str) -> PathBuf {
+        self.config_dir
+            .join("variables")
+            .join(format!("{context}.yaml"))
+    }
+
+    /// Decrypted contents of `path`, a file of `context`; `None` if the file
+    /// doesn't exist or isn't encrypted
+    fn read_encrypted(&self, context: &str, path: &Path) -> Result<Option<String>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents =
+            fs::read_to_string(path).with_context(|| format!("Failed to read {path:?}"))?;
+        if !encryption::is_encrypted(&contents) {
+            return Ok(None);
+        }
+        self.vault.decrypt(context, &contents).map(Some)
+    }
+
+    /// `contents` for a file of `context`, encrypted if the context is
+    fn seal(&self, context: &str, contents: String) -> Result<String> {
+        if !self.is_encrypted(context)? {
+            return Ok(contents);
+        }
+        self.unlock(context)?;
+        self.vault.encrypt(context, &contents)
+    }
+
+    /// Make sure the key of `context` is known if it is encrypted.
+    /// Decrypting the variables file checks the passphrase, so a mistyped one
+    /// can't end up encrypting some of the files.
+    fn unlock(&self, context: &str) -> Result<()> {
+        if !self.vault.is_unlocked(context) && self.is_encrypted(context)? {
+            self.read_encrypted(context, &self.variables_file(context))?;
+        }
+        Ok(())
+    }
+
+    /// Lock the config directory to change the files of `context`. The
+    /// passphrase of an encrypted context is asked for first, so the prompt
+    /// doesn't block other doo processes.
+    fn lock_context(&self, context: &str) -> Result<ConfigLock> {
+        check_context_name(context)?;
+        self.unlock(context)?;
+        ConfigLock::acquire(&self.config_dir)
+    }
+
+    /// Variables file of `context` at `path`, decrypted if necessary
+    fn read_context_file(&self, context: &str, path: &Path) -> Result<Variables> {
+        check_context_name(context)?;
+        match self.read_encrypted(context, path)? {
+            Some(contents) => serde_yaml::from_str(&contents)
+                .with_context(|| format!("Failed to parse {}", path.display())),
+            None => read_variables_file(path),
+        }
+    }
+
+    fn load_variables(&self, context: &str) -> Result<Variables> {
+        self.read_context_file(context, &self.variables_file(context))
+    }
+
+    fn save_variables(&self, context: &str, variables: &Variables) -> Result<()> {
+        let yaml_content =
+            serde_yaml::to_string(variables).context("Failed to serialize variables")?;
+        let contents = self.seal(context, yaml_content)?;
+        atomic_write_with_backup(&self.variables_file(context), &contents)
+            .context("Failed to write variables file")?;
+        Ok(())
+    }
+
+    fn load_secrets(&self, context: &str) -> Result<Variables> {
+        self.read_context_file(context, &secrets_file(&self.config_dir, context))
+    }
+
+    /// Secrets of `context` with `keyring:` references replaced by the
+    /// values they refer to
+    fn load_revealed_secrets(&self, context: &str) -> Result<Variables> {
+        let mut secrets = self.load_secrets(context)?;
+        let scoped = secrets.commands.values_mut().flat_map(HashMap::iter_mut);
+        for (key, value) in secrets.vars.iter_mut().chain(scoped) {
+            if keychain::referenced_account(value).is_some() {
+                *value = self.reveal(key, value.clone())?;
+            }
+        }
+        Ok(secrets)
+    }
+
+    fn save_secrets(&self, context: &str, secrets: &Variables) -> Result<()> {
+        self.write_secrets(context, secrets, self.keychain_secrets)
+    }
+
+    /// Write the secrets file of `context`. With `to_keychain`, values go to
+    /// the keychain and the file gets references to them. References to
+    /// another context's entries are always given entries of their own, so a
+    /// context never depends on another one's secrets. Entries of `context`
+    /// (or of contexts that no longer exist) the file stops referring to are
+    /// deleted from the keychain.
+    fn write_secrets(&self, context: &str, secrets: &Variables, to_keychain: bool) -> Result<()> {
+        let previous = self.load_secrets(context)?;
+        let mut secrets = secrets.clone();
+        let scoped = secrets.commands.iter_mut().flat_map(|(command, vars)| {
+            vars.iter_mut()
+                .map(move |(key, value)| (Some(command.as_str()), key, value))
+        });
+        let entries = secrets
+            .vars
+            .iter_mut()
+            .map(|(key, value)| (None, key, value))
+            .chain(scoped);
+        for (command, key, value) in entries {
+            let account = keychain::account(context, command, key);
+            let secret = match keychain::referenced_account(value) {
+                Some(current) if current == account => continue,
+                Some(_) => self.reveal(key, value.clone())?,
+                None if to_keychain && !value.is_list() => value.clone(),
+                None => continue,
+            };
+            self.keychain
+                .set(&account, &secret.to_string())
+                .map_err(|e| anyhow!("Can't store {key} in the system keychain: {e}"))?;
+            *value = keychain::reference(&account);
+        }
+
+        let path = secrets_file(&self.config_dir, context);
+        let yaml_content =
+            serde_yaml::to_string(&secrets).context("Failed to serialize secrets")?;
+        let contents = self.seal(context, yaml_content)?;
+        atomic_write_private(&path, &contents).context("Failed to write secrets file")?;
+
+        let kept: HashSet<&str> = referenced_accounts(&secrets).collect();
+        let obsolete: Vec<String> = referenced_accounts(&previous)
+            .filter(|account| !kept.contains(account))
+            .filter(|account| {
+                let owner = account.split('/').next().unwrap_or_default();
+                owner == context || !secrets_file(&self.config_dir, owner).exists()
+            })
+            .map(str::to_string)
+            .collect();
+        self.delete_from_keychain(&obsolete);
+        Ok(())
+    }
+
+    fn load_history(&self, context: &str) -> Result<VariableHistory> {
+        check_context_name(context)?;
+        let path = history_file(&self.config_dir, context);
+        if !path.exists() {
+            return Ok(VariableHistory::default());
+        }
+        let contents = match self.read_encrypted(context, &path)? {
+            Some(contents) => contents,
+            None => fs::read_to_string(&path).context("Failed to read history file")?,
+        };
+        serde_yaml::from_str(&contents).context("Failed to parse history file")
+    }
+
+    /// The history can contain previous secret values, so it is only
+    /// readable by the current user like the secrets file
+    fn save_history(&self, context: &str, history: &VariableHistory) -> Result<()> {
+        let yaml_content = serde_yaml::to_string(history).context("Failed to serialize history")?;
+        let contents = self.seal(context, yaml_content)?;
+        atomic_write_private(&history_file(&self.config_dir, context), &contents)
+            .context("Failed to write history file")
+    }
+}
+
+/// File holding the secret variables of `context`
+/// Keychain entries the values of `secrets` refer to
+fn referenced_accounts(secrets: &Variables) -> impl Iterator<Item = &str> {
+    let scoped = secrets.commands.values().flat_map(HashMap::values);
+    secrets
+        .vars
+        .values()
+        .chain(scoped)
+        .filter_map(keychain::referenced_account)
+}
+
+/// Remove the value of `key` for `command`, dropping the command's section
+/// once it is empty
+fn remove_scoped(variables: &mut Variables, command: &str, key: &str) -> Option<VarValue> {
+    let vars = variables.commands.get_mut(command)?;
+    let removed = vars.remove(key);
+    if vars.is_empty() {
+        variables.commands.remove(command);
+    }
+    removed
+}
+
+pub fn secrets_file(config_dir: &Path, context: &str) -> PathBuf {
+    config_dir
+        .join("variables")
+        .join(format!("{context}{SECRETS_SUFFIX}"))
+}
+
+pub fn history_file(config_dir: &Path, context: &str) -> PathBuf {
+    config_dir
+        .join("variables")
+        .join(format!("{context}{HISTORY_SUFFIX}"))
+}
+
+/// Remove `<file>.bak` of `path`, which would keep the previous plaintext
+/// around after encrypting or the previous ciphertext after decrypting
+fn remove_backup(path: &Path) -> Result<()> {
+    let backup = backup_path(path);
+    if backup.exists() {
+        fs::remove_file(&backup).with_context(|| format!("Failed to remove {backup:?}"))?;
+    }
+    Ok(())
+}
+
+fn read_variables_file(path: &Path) -> Result<Variables> {
+    if path.exists() {
+        // Offer to restore `<context>.yaml.bak` if the file is broken
+        let interactive = std::io::stdin().is_terminal() && std::io::stderr().is_terminal();
+        read_yaml_or_recover(path, interactive)
+    } else {
+        Ok(Variables::default())
+    }
+}
+
+/// Parse a TTL such as `30m`, `2h`, `1d` or `1h30m` (units `s`, `m`, `h`
+/// and `d`)
+pub fn parse_ttl(input: &str) -> Result<Duration> {
+    let invalid = || anyhow!("Invalid TTL '{}': use e.g. 30m, 2h or 1d", input);
+    let mut total: u64 = 0;
+    let mut number = String::new();
+    for c in input.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        let amount: u64 = number.parse().map_err(|_| invalid())?;
+        total = amount
+            .checked_mul(unit)
+            .and_then(|secs| total.checked_add(secs))
+            .ok_or_else(invalid)?;
+        number.clear();
+    }
+    if !number.is_empty() || total == 0 {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs(total))
+}
+
+/// Files named `.env`, `.env.<something>` or `<something>.env`
+fn is_env_file(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    name == ".env"
+        || name.starts_with(".env.")
+        || path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("env"))
+}
+
+fn read_env_file(path: &Path) -> Result<BTreeMap<String, String>> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    parse_env(&contents).map_err(|e| anyhow!("Invalid env file {}: {}", path.display(), e))
+}
+
+/// Parse dotenv content: `KEY=value` lines, optionally prefixed with
+/// `export`. Blank lines and lines starting with `#` are ignored, as is a
+/// ` #` comment after an unquoted value. Single-quoted values are taken
+/// literally; double-quoted values may span lines and support `\n`, `\t`,
+/// `\"` and `\\` escapes.
+pub fn parse_env(contents: &str) -> Result<BTreeMap<String, String>> {
+    let (vars, mut errors) = parse_env_entries(contents);
+    if errors.is_empty() {
+        Ok(vars)
+    } else {
+        Err(errors.remove(0).1)
+    }
+}
+
+/// [`parse_env`] that skips malformed lines instead of failing, returning
+/// their line numbers along with the entries
+pub fn parse_env_lenient(contents: &str) -> (BTreeMap<String, String>, Vec<usize>) {
+    let (vars, errors) = parse_env_entries(contents);
+    (vars, errors.into_iter().map(|(line, _)| line).collect())
+}
+
+fn parse_env_entries(contents: &str) -> (BTreeMap<String, String>, Vec<(usize, anyhow::Error)>) {
+    let mut vars = BTreeMap::new();
+    let mut errors = Vec::new();
+    let mut lines = contents.lines().enumerate();
+    while let Some((index, line)) = lines.next() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // A value with an unterminated quote must not swallow the lines after it
+        let rest = lines.clone();
+        match parse_env_line(line, index + 1, &mut lines) {
+            Ok((key, value)) => {
+                vars.insert(key, value);
+            }
+            Err(e) => {
+                errors.push((index + 1, e));
+                lines = rest;
+            }
+        }
+    }
+    (vars, errors)
+}
+
+/// Parse the entry on `line`, taking the continuation lines of a multi-line
+/// double-quoted value from `lines`
+fn parse_env_line<'a>(
+    line: &str,
+    line_number: usize,
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+) -> Result<(String, String)> {
+    let line = line
+        .strip_prefix("export")
+        .filter(|rest| rest.starts_with(char::is_whitespace))
+        .map(str::trim_start)
+        .unwrap_or(line);
+    let (key, value) = line
+        .split_once('=')
+        .ok_or_else(|| anyhow!("line {}: expected KEY=value", line_number))?;
+    let key = key.trim();
+    if !is_env_name(key) {
+        return Err(anyhow!("line {}: invalid key '{}'", line_number, key));
+    }
+
+    let value = value.trim_start();
+    let value = if let Some(quoted) = value.strip_prefix('\'') {
+        let end = quoted
+            .find('\'')
+            .ok_or_else(|| anyhow!("line {}: unterminated quote", line_number))?;
+        quoted[..end].to_string()
+    } else if let Some(quoted) = value.strip_prefix('"') {
+        // Double-quoted values continue until the closing quote
+        let mut raw = quoted.to_string();
+        loop {
+            if let Some(end) = closing_quote(&raw) {
+                raw.truncate(end);
+                break;
+            }
+            let (_, next) = lines
+                .next()
+                .ok_or_else(|| anyhow!("line {}: unterminated quote", line_number))?;
+            raw.push('\n');
+            raw.push_str(next);
+        }
+        unescape(&raw)
+    } else {
+        let end = value.find(" #").unwrap_or(value.len());
+        value[..end].trim_end().to_string()
+    };
+    Ok((key.to_string(), value))
+}
+
+/// Whether `name` can be used as an environment variable name
+fn is_env_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Index of the first `"` in `text` that is not escaped with a backslash
+fn closing_quote(text: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn unescape(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            output.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => output.push('\n'),
+            Some('t') => output.push('\t'),
+            Some('r') => output.push('\r'),
+            Some(other) => output.push(other),
+            None => output.push('\\'),
+        }
+    }
+    output
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+}
+
+/// Replace every occurrence of `values` in `text` with [`SECRET_MASK`],
+/// longest first so a secret containing another one is masked as a whole
+fn mask_values(text: &str, mut values: Vec<String>) -> String {
+    values.retain(|value| !value.is_empty());
+    values.sort_by_key(|value| std::cmp::Reverse(value.len()));
+    values.iter().fold(text.to_string(), |text, value| {
+        text.replace(value, SECRET_MASK)
+    })
+}
+
+/// Everything the placeholders of a template can take their value from
+struct Sources<'a> {
+    vars: &'a HashMap<String, String>,
+    args: &'a [String],
+    defaults: &'a BTreeMap<String, String>,
+    env: &'a dyn Fn(&str) -> Option<String>,
+    /// Quote substituted values for the command line, except the values of
+    /// these variables, which already are (lists)
+    quote: Option<&'a HashSet<String>>,
+}
+
+fn process_env(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+/// Resolve a template against stored variables and runtime arguments, see
+/// [`VariableManager::resolve_variables`] for the precedence rules
+#[cfg(test)]
+fn resolve_template(template: &str, sources: &Sources, strict_env: bool) -> Result<String> {
+    Ok(resolve_template_with_values(template, sources, strict_env, true)?.0)
+}
+
+/// [`resolve_template`] that also returns the values substituted for each
+/// placeholder, keyed by label. Without `require_named`, missing named
+/// variables keep their text like missing numbered ones.
+fn resolve_template_with_values(
+    template: &str,
+    sources: &Sources,
+    strict_env: bool,
+    require_named: bool,
+) -> Result<(String, BTreeMap<String, String>)> {
+    let tokens = template::parse(template);
+    for placeholder in template::placeholders(&tokens) {
+        placeholder.check_filters()?;
+    }
+    let (resolved, missing, values) = render_tokens(&tokens, sources);
+
+    let missing_labels = |kind: fn(&PlaceholderKind) -> bool| -> Vec<String> {
+        missing
+            .iter()
+            .filter(|placeholder| kind(&placeholder.kind))
+            .map(|placeholder| placeholder.label())
+            .collect()
+    };
+    let missing_named = missing_labels(|kind| matches!(kind, PlaceholderKind::Named(_)));
+    if require_named && !missing_named.is_empty() {
+        return Err(anyhow!(
+            "Missing values for variables: {}. Set them with 'doo var <name> <value>', or write ## for a literal #",
+            missing_named.join(", ")
+        ));
+    }
+    let missing_env = missing_labels(|kind| matches!(kind, PlaceholderKind::Env(_)));
+    if strict_env && !missing_env.is_empty() {
+        return Err(anyhow!(
+            "Environment variables not set: {}",
+            missing_env.join(", ")
+        ));
+    }
+    Ok((resolved, values))
+}
+
+/// Placeholders of a template that nothing provides a value for, see
+/// [`VariableManager::missing_placeholders`]. Environment variables are not
+/// included.
+fn missing_in_template(template: &str, sources: &Sources) -> Vec<String> {
+    let tokens = template::parse(template);
+    let (_, missing, _) = render_tokens(&tokens, sources);
+    missing
+        .iter()
+        .filter(|placeholder| !matches!(placeholder.kind, PlaceholderKind::Env(_)))
+        .map(|placeholder| placeholder.label())
+        .collect()
+}
+
+/// Render `tokens`, returning the result, the distinct placeholders that had
+/// no value in order of appearance, and the values of the others by label
+fn render_tokens(
+    tokens: &[template::Token],
+    sources: &Sources,
+) -> (String, Vec<template::Placeholder>, BTreeMap<String, String>) {
+    let Sources {
+        vars,
+        args,
+        defaults,
+        env,
+        quote,
+    } = sources;
+
+    // Unset `#N` placeholders are filled from the runtime arguments in
+    // ascending order, each distinct placeholder consuming one argument
+    let mut fallback_numbers: Vec<usize> = template::placeholders(tokens)
+        .filter_map(
+            |placeholder| match (&placeholder.kind, placeholder.variable_name()) {
+                (PlaceholderKind::Variable(n), Some(name)) if !vars.contains_key(&name) => Some(*n),
+                _ => None,
+            },
+        )
+        .collect();
+    fallback_numbers.sort_unstable();
+    fallback_numbers.dedup();
+    // `#*` forwards the arguments after the last one used by `$N` or as
+    // a `#N` fallback
+    let used_args = template::placeholders(tokens)
+        .filter_map(|placeholder| match placeholder.kind {
+            PlaceholderKind::Argument(n) => Some(n),
+            _ => None,
+        })
+        .chain(Some(fallback_numbers.len()))
+        .max()
+        .unwrap_or(0)
+        .min(args.len());
+    let fallback: HashMap<usize, &String> = fallback_numbers.into_iter().zip(*args).collect();
+
+    let mut missing: Vec<template::Placeholder> = Vec::new();
+    let mut values = BTreeMap::new();
+    let resolved = template::render(tokens, |placeholder| {
+        let value = match &placeholder.kind {
+            PlaceholderKind::Argument(n) => n.checked_sub(1).and_then(|i| args.get(i)).cloned(),
+            PlaceholderKind::Variable(n) => placeholder
+                .variable_name()
+                .and_then(|name| vars.get(&name))
+                .or_else(|| fallback.get(n).copied())
+                .cloned(),
+            PlaceholderKind::Named(_) => placeholder
+                .variable_name()
+                .and_then(|name| vars.get(&name))
+                .cloned(),
+            // doo variables take precedence over the environment
+            PlaceholderKind::Env(name) => {
+                vars.get(&format!("#{name}")).cloned().or_else(|| env(name))
+            }
+            // Quoted so every argument stays one argument when the command
+            // line is split again
+            PlaceholderKind::Rest => Some(
+                args[used_args..]
+                    .iter()
+                    .map(|arg| shell_words::quote(arg))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+        };
+        let value = value
+            .or_else(|| placeholder.default.clone())
+            .or_else(|| configured_default(placeholder, defaults).cloned());
+        // An optional placeholder without a value is not missing; it expands
+        // to nothing
+        if value.is_none() && placeholder.optional {
+            return Some(String::new());
+        }
+        match &value {
+            Some(value) => {
+                values
+                    .entry(placeholder.label())
+                    .or_insert_with(|| value.clone());
+            }
+            None if !missing.iter().any(|m| m.label() == placeholder.label()) => {
+                missing.push(placeholder.clone());
+            }
+            None => {}
+        }
+        let value = value.map(|value| placeholder.apply_filters(value));
+        // `#*` and list variables are quoted already
+        let quoted = match (quote, &placeholder.kind) {
+            (None, _) | (_, PlaceholderKind::Rest) => false,
+            (Some(lists), _) => placeholder
+                .reference_key()
+                .filter(|key| vars.contains_key(key))
+                .is_some_and(|key| lists.contains(&key)),
+        };
+        match quote {
+            Some(_) if !quoted => value.map(|value| shell_words::quote(&value).into_owned()),
+            _ => value,
+        }
+    });
+    (resolved, missing, values)
+}
+
+/// Keys of the stored variables that `text` refers to, in order of appearance
+fn referenced_keys(vars: &HashMap<String, String>, text: &str) -> Vec<String> {
+    let tokens = template::parse(text);
+    template::placeholders(&tokens)
+        .filter_map(template::Placeholder::reference_key)
+        .filter(|key| vars.contains_key(key))
+        .collect()
+}
+
+/// Replace the dynamic values of variables used by `template`, directly or
+/// through other variables, with the trimmed output of `run`, running every
+/// distinct command once. Returns the keys of the replaced values.
+fn evaluate_dynamic(
+    vars: &mut HashMap<String, String>,
+    template: &str,
+    run: &dyn Fn(&str) -> Result<String>,
+) -> Result<HashSet<String>> {
+    let mut used = referenced_keys(vars, template);
+    let mut seen: HashSet<String> = used.iter().cloned().collect();
+    let mut i = 0;
+    while let Some(key) = used.get(i) {
+        if !vars[key].starts_with(DYNAMIC_PREFIX) {
+            for reference in referenced_keys(vars, &vars[key]) {
+                if seen.insert(reference.clone()) {
+                    used.push(reference);
+                }
+            }
+        }
+        i += 1;
+    }
+
+    let mut evaluated = HashSet::new();
+    let mut cache: HashMap<String, String> = HashMap::new();
+    for key in used {
+        let Some(command) = vars
+            .get(&key)
+            .and_then(|value| value.strip_prefix(DYNAMIC_PREFIX))
+            .map(|command| command.trim().to_string())
+        else {
+            continue;
+        };
+        let value = match cache.get(&command) {
+            Some(value) => value.clone(),
+            None => {
+                let output = run(&command).map_err(|e| {
+                    anyhow!("Dynamic variable {} ('{}') failed: {}", key, command, e)
+                })?;
+                let value = output.trim().to_string();
+                cache.insert(command, value.clone());
+                value
+            }
+        };
+        evaluated.insert(key.clone());
+        vars.insert(key, value);
+    }
+    Ok(evaluated)
+}
+
+/// Expand references to other stored variables (`#host`, `${HOST}`) in the
+/// values of the variables `template` uses, so `#url` set to
+/// `https://#host:#port` resolves through `#host` and `#port`. References
+/// without a stored value, runtime arguments and the output of dynamic
+/// variables (`verbatim`) are left as they are.
+fn expand_references(
+    vars: &mut HashMap<String, String>,
+    template: &str,
+    verbatim: &HashSet<String>,
+) -> Result<()> {
+    let mut expanded = HashMap::new();
+    for key in referenced_keys(vars, template) {
+        let mut chain = Vec::new();
+        expand_variable(&key, vars, verbatim, &mut chain, &mut expanded)?;
+    }
+    vars.extend(expanded);
+    Ok(())
+}
+
+/// Expanded value of the variable `key`; `chain` holds the variables whose
+/// expansion led here, to report cycles
+fn expand_variable(
+    key: &str,
+    vars: &HashMap<String, String>,
+    verbatim: &HashSet<String>,
+    chain: &mut Vec<String>,
+    expanded: &mut HashMap<String, String>,
+) -> Result<String> {
+    if let Some(value) = expanded.get(key) {
+        return Ok(value.clone());
+    }
+    if chain.iter().any(|seen| seen == key) {
+        chain.push(key.to_string());
+        return Err(anyhow!(
+            "Variable {} refers to itself: {}",
+            key,
+            chain.join(" -> ")
+        ));
+    }
+    if chain.len() >= MAX_REFERENCE_DEPTH {
+        chain.push(key.to_string());
+        return Err(anyhow!(
+            "Variable references are nested more than {} levels deep: {}",
+            MAX_REFERENCE_DEPTH,
+            chain.join(" -> ")
+        ));
+    }
+
+    let value = &vars[key];
+    if verbatim.contains(key) || referenced_keys(vars, value).is_empty() {
+        return Ok(value.clone());
+    }
+    chain.push(key.to_string());
+    let tokens = template::parse(value);
+    for placeholder in template::placeholders(&tokens) {
+        placeholder
+            .check_filters()
+            .with_context(|| format!("Invalid value of variable {key}"))?;
+    }
+    let mut error = None;
+    let result = template::render(&tokens, |placeholder| {
+        let reference = placeholder
+            .reference_key()
+            .filter(|key| vars.contains_key(key))?;
+        if error.is_some() {
+            return None;
+        }
+        expand_variable(&reference, vars, verbatim, chain, expanded)
+            .map(|value| placeholder.apply_filters(value))
+            .map_err(|e| error = Some(e))
+            .ok()
+    });
+    chain.pop();
+    if let Some(e) = error {
+        return Err(e);
+    }
+    expanded.insert(key.to_string(), result.clone());
+    Ok(result)
+}
+
+/// Key under which a variable is stored; `name` may be given with or without
+/// the leading `#`
+pub fn variable_key(name: &str) -> String {
+    if name.starts_with('#') {
+        name.to_string()
+    } else {
+        format!("#{name}")
+    }
+}
+
+/// Default for a placeholder from a command's `defaults` map, keyed by the
+/// placeholder's label (`#1`, `$1`, `#name`); variables may omit the `#`
+fn configured_default<'a>(
+    placeholder: &template::Placeholder,
+    defaults: &'a BTreeMap<String, String>,
+) -> Option<&'a String> {
+    let label = placeholder.label();
+    defaults.get(&label).or_else(|| {
+        placeholder
+            .variable_name()
+            .and_then(|name| defaults.get(name.trim_start_matches('#')))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigManager;
+    use crate::keychain::MemoryKeychain;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_variable_operations() {
+        let temp_dir = TempDir::new().unwrap();
+
+        // Create a config manager with the temp directory
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+
+        // Test setting and getting variables
+        variable_manager
+            .set_variable("test", "#1", "production")
+            .unwrap();
+        assert_eq!(
+            variable_manager.get_variable("test", "#1").unwrap(),
+            Some("production".to_string())
+        );
+
+        // Test variable resolution
+        let resolved = variable_manager
+            .resolve_variables("test", "kubectl get pods -n #1", &[])
+            .unwrap();
+        assert_eq!(resolved, "kubectl get pods -n production");
+    }
+
+    #[test]
+    fn test_positional_args() {
+        let temp_dir = TempDir::new().unwrap();
+
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+        let variable_manager = VariableManager::new(&config_manager).unwrap();
+
+        let resolved = variable_manager
+            .resolve_variables(
+                "default",
+                "kubectl logs #1 #2",
+                &["pod-name".to_string(), "-f".to_string()],
+            )
+            .unwrap();
+        assert_eq!(resolved, "kubectl logs pod-name -f");
+    }
+
+    #[test]
+    fn test_dollar_positional_args() {
+        let temp_dir = TempDir::new().unwrap();
+
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+        let variable_manager = VariableManager::new(&config_manager).unwrap();
+
+        // Test $1, $2 direct positional argument replacement
+        let resolved = variable_manager
+            .resolve_variables(
+                "default",
+                "kubectl -n $1 get pods",
+                &["console".to_string()],
+            )
+            .unwrap();
+        assert_eq!(resolved, "kubectl -n console get pods");
+
+        // Test multiple $1, $2 arguments
+        let resolved = variable_manager
+            .resolve_variables(
+                "default",
+                "kubectl logs $1 -n $2",
+                &["my-pod".to_string(), "staging".to_string()],
+            )
+            .unwrap();
+        assert_eq!(resolved, "kubectl logs my-pod -n staging");
+    }
+
+    #[test]
+    fn test_mixed_placeholders() {
+        let temp_dir = TempDir::new().unwrap();
+
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+
+        // Set a persistent variable #1
+        variable_manager
+            .set_variable("test", "#1", "production")
+            .unwrap();
+
+        // Test mixing persistent #1 with direct $1 arguments
+        let resolved = variable_manager
+            .resolve_variables(
+                "test",
+                "kubectl -n #1 get pods $1",
+                &["--watch".to_string()],
+            )
+            .unwrap();
+        assert_eq!(resolved, "kubectl -n production get pods --watch");
+    }
+
+    #[test]
+    fn test_concurrent_set_variable_keeps_all_updates() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+
+        let handles: Vec<_> = ["a", "b"]
+            .into_iter()
+            .map(|prefix| {
+                let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+                std::thread::spawn(move || {
+                    for i in 0..20 {
+                        variable_manager
+                            .set_variable("shared", &format!("#{prefix}{i}"), prefix)
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let variable_manager = VariableManager::new(&config_manager).unwrap();
+        assert_eq!(variable_manager.list_variables("shared").unwrap().len(), 40);
+    }
+
+    #[test]
+    fn test_secret_variables() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir.clone()).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+        let mut context_manager = crate::context::ContextManager::new(&config_manager).unwrap();
+
+        variable_manager
+            .set_variable("default", "token", "plain")
+            .unwrap();
+        variable_manager
+            .set_secret("default", "token", "s3cr3t")
+            .unwrap();
+
+        // The secret replaces the plain variable and lives in its own file
+        assert!(variable_manager
+            .list_variables("default")
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            variable_manager.get_variable("default", "#token").unwrap(),
+            Some("s3cr3t".to_string())
+        );
+        let path = secrets_file(&config_dir, "default");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // Secrets are resolved, also in child contexts, but masked for display
+        context_manager
+            .switch_context("staging", Some("default"))
+            .unwrap();
+        let resolved = variable_manager
+            .resolve_variables("staging", "curl -H 'Token: #token'", &[])
+            .unwrap();
+        assert_eq!(resolved, "curl -H 'Token: s3cr3t'");
+        assert_eq!(
+            variable_manager.mask_secrets("staging", &resolved).unwrap(),
+            "curl -H 'Token: *****'"
+        );
+
+        assert!(variable_manager
+            .remove_variable("default", "token")
+            .unwrap());
+        assert!(variable_manager.list_secrets("default").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_keychain_secrets() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir.clone()).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+        let keychain = MemoryKeychain::default();
+        let entries = keychain.entries.clone();
+        variable_manager.set_keychain(Box::new(keychain), true);
+        let entry = |account: &str| entries.lock().unwrap().get(account).cloned();
+        let stored =
+            |context: &str| fs::read_to_string(secrets_file(&config_dir, context)).unwrap();
+
+        // Only a reference ends up in the secrets file
+        variable_manager
+            .set_secret("default", "token", "s3cr3t")
+            .unwrap();
+        assert!(stored("default").contains("keyring:default/token"));
+        assert!(!stored("default").contains("s3cr3t"));
+        assert_eq!(entry("default/token").as_deref(), Some("s3cr3t"));
+        assert_eq!(
+            variable_manager.list_secrets("default").unwrap()["#token"],
+            VarValue::from("keyring:default/token")
+        );
+
+        // Everything that needs the value gets it from the keychain
+        assert_eq!(
+            variable_manager
+                .get_variable("default", "token")
+                .unwrap()
+                .as_deref(),
+            Some("s3cr3t")
+        );
+        let resolved = variable_manager
+            .resolve_variables("default", "echo #token", &[])
+            .unwrap();
+        assert_eq!(resolved, "echo s3cr3t");
+        assert_eq!(
+            variable_manager.mask_secrets("default", &resolved).unwrap(),
+            "echo *****"
+        );
+
+        // The keychain only has the current value, so there is no history
+        variable_manager
+            .set_secret("default", "token", "n3w")
+            .unwrap();
+        assert_eq!(entry("default/token").as_deref(), Some("n3w"));
+        assert!(variable_manager
+            .history("default", "token")
+            .unwrap()
+            .is_empty());
+
+        // Copies get their own entry and outlive the original
+        variable_manager
+            .copy_variables("default", "prod", &[], |_| Ok(true))
+            .unwrap();
+        assert!(stored("prod").contains("keyring:prod/token"));
+        assert!(variable_manager
+            .remove_variable("default", "token")
+            .unwrap());
+        assert_eq!(entry("default/token"), None);
+        assert_eq!(
+            variable_manager
+                .get_variable("prod", "token")
+                .unwrap()
+                .as_deref(),
+            Some("n3w")
+        );
+
+        // A copied secrets file refers to the original until it is adopted
+        fs::copy(
+            secrets_file(&config_dir, "prod"),
+            secrets_file(&config_dir, "clone"),
+        )
+        .unwrap();
+        variable_manager.adopt_secrets("clone").unwrap();
+        assert!(stored("clone").contains("keyring:clone/token"));
+        assert_eq!(entry("clone/token").as_deref(), Some("n3w"));
+        assert_eq!(entry("prod/token").as_deref(), Some("n3w"));
+
+        // A missing entry says how to fix it
+        entries.lock().unwrap().remove("prod/token");
+        let error = variable_manager
+            .resolve_variables("prod", "echo #token", &[])
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("missing from the system keychain"));
+        assert!(error.contains("doo var --secret token <value>"));
+    }
+
+    #[test]
+    fn test_migrate_secrets_to_keychain() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir.clone()).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+        let keychain = MemoryKeychain::default();
+        let entries = keychain.entries.clone();
+        variable_manager.set_keychain(Box::new(keychain), false);
+
+        variable_manager
+            .set_secret("staging", "api", "old")
+            .unwrap();
+        variable_manager
+            .set_secret("staging", "api", "xyz")
+            .unwrap();
+        variable_manager
+            .set_command_variable("staging", "deploy", "#1", "d3pl0y".into(), true)
+            .unwrap();
+        variable_manager
+            .set_variable("staging", "ns", "web")
+            .unwrap();
+        let stored = || fs::read_to_string(secrets_file(&config_dir, "staging")).unwrap();
+        assert!(stored().contains("xyz") && entries.lock().unwrap().is_empty());
+
+        let moved = variable_manager.migrate_secrets("staging").unwrap();
+        assert_eq!(moved, vec!["#1 (--command deploy)", "#api"]);
+        assert!(!stored().contains("xyz") && !stored().contains("d3pl0y"));
+        assert_eq!(entries.lock().unwrap()["staging/api"], "xyz");
+        assert_eq!(entries.lock().unwrap()["staging/deploy/1"], "d3pl0y");
+        // Previous secret values leave the history too
+        assert!(variable_manager
+            .history("staging", "api")
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            variable_manager
+                .resolve_variables_with_defaults(
+                    "staging",
+                    Some("deploy"),
+                    "deploy #1 #api #ns",
+                    &[],
+                    &BTreeMap::new()
+                )
+                .unwrap(),
+            "deploy d3pl0y xyz web"
+        );
+
+        // Running it again has nothing left to do
+        assert!(variable_manager
+            .migrate_secrets("staging")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_encrypted_context() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir.clone()).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+        variable_manager.set_variable("jump", "ns", "ops").unwrap();
+        variable_manager.set_variable("jump", "ns", "shop").unwrap();
+        variable_manager
+            .set_secret("jump", "token", "s3cr3t")
+            .unwrap();
+        let variables_file = config_dir.join("variables").join("jump.yaml");
+        assert!(backup_path(&variables_file).exists());
+
+        variable_manager
+            .encrypt_context("jump", "correct horse")
+            .unwrap();
+        assert!(variable_manager.is_encrypted("jump").unwrap());
+        assert!(variable_manager.encrypt_context("jump", "other").is_err());
+        let files = [
+            variables_file.clone(),
+            secrets_file(&config_dir, "jump"),
+            history_file(&config_dir, "jump"),
+        ];
+        for file in &files {
+            let contents = fs::read_to_string(file).unwrap();
+            assert!(encryption::is_encrypted(&contents), "{file:?}");
+            assert!(!contents.contains("shop") && !contents.contains("s3cr3t"));
+        }
+        // The backup still held the plaintext
+        assert!(!backup_path(&variables_file).exists());
+
+        // Another process needs the passphrase; a wrong one fails and leaves
+        // the files alone
+        let other = VariableManager::new(&config_manager).unwrap();
+        other.set_passphrase("jump", "battery staple");
+        let err = other.get_variable("jump", "ns").unwrap_err();
+        assert!(err.to_string().starts_with("Wrong passphrase"));
+        let mut other = VariableManager::new(&config_manager).unwrap();
+        other.set_passphrase("jump", "battery staple");
+        // The passphrase is checked before the config directory is locked,
+        // so asking for it doesn't block other processes
+        let held = ConfigLock::acquire(&config_dir).unwrap();
+        let err = other.set_variable("jump", "region", "eu").unwrap_err();
+        assert!(err.to_string().starts_with("Wrong passphrase"));
+        let import = temp_dir.path().join("import.yaml");
+        fs::write(&import, "vars:\n  region: eu\n").unwrap();
+        other.set_passphrase("jump", "battery staple");
+        let err = other.import_variables("jump", &import, false).unwrap_err();
+        assert!(err.to_string().starts_with("Wrong passphrase"));
+        drop(held);
+        assert!(encryption::is_encrypted(
+            &fs::read_to_string(&variables_file).unwrap()
+        ));
+
+        let mut other = VariableManager::new(&config_manager).unwrap();
+        other.set_passphrase("jump", "correct horse");
+        assert_eq!(
+            other.get_variable("jump", "ns").unwrap().as_deref(),
+            Some("shop")
+        );
+        assert_eq!(
+            other.get_variable("jump", "token").unwrap().as_deref(),
+            Some("s3cr3t")
+        );
+        other.set_variable("jump", "region", "eu").unwrap();
+        assert_eq!(other.history("jump", "#ns").unwrap().len(), 1);
+        let contents = fs::read_to_string(&variables_file).unwrap();
+        assert!(encryption::is_encrypted(&contents) && !contents.contains("region"));
+
+        other.decrypt_context("jump").unwrap();
+        assert!(!other.is_encrypted("jump").unwrap());
+        assert!(other.decrypt_context("jump").is_err());
+        let contents = fs::read_to_string(&variables_file).unwrap();
+        assert!(contents.contains("region") && contents.contains("eu"));
+        assert!(fs::read_to_string(secrets_file(&config_dir, "jump"))
+            .unwrap()
+            .contains("s3cr3t"));
+        assert_eq!(
+            variable_manager
+                .get_variable("jump", "region")
+                .unwrap()
+                .as_deref(),
+            Some("eu")
+        );
+    }
+
+    #[test]
+    fn test_inspect_placeholders() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+        let mut context_manager = crate::context::ContextManager::new(&config_manager).unwrap();
+
+        variable_manager
+            .set_variable("default", "ns", "shop")
+            .unwrap();
+        variable_manager
+            .set_secret("default", "token", "s3cr3t")
+            .unwrap();
+        context_manager
+            .switch_context("staging", Some("default"))
+            .unwrap();
+        variable_manager
+            .set_command_variable("staging", "deploy", "ns", "web".into(), false)
+            .unwrap();
+
+        let template = "deploy -n #ns --token #token #region:-eu #1 $1 #tag? #* ${HOME} #ns";
+        let defaults = BTreeMap::new();
+        let statuses = variable_manager
+            .inspect_placeholders("staging", Some("deploy"), template, &defaults)
+            .unwrap();
+        let summary: Vec<_> = statuses
+            .iter()
+            .map(|status| {
+                (
+                    status.raw.as_str(),
+                    status.value.as_ref().map(VarValue::to_string),
+                    status.source,
+                    status.context.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    "#ns",
+                    Some("web".to_string()),
+                    ValueSource::CommandVariable,
+                    Some("staging")
+                ),
+                (
+                    "#token",
+                    Some(SECRET_MASK.to_string()),
+                    ValueSource::Variable,
+                    Some("default")
+                ),
+                (
+                    "#region:-eu",
+                    Some("eu".to_string()),
+                    ValueSource::Default,
+                    None
+                ),
+                ("#1", None, ValueSource::Missing, None),
+                ("$1", None, ValueSource::Argument, None),
+                ("#tag?", None, ValueSource::Missing, None),
+                ("#*", None, ValueSource::Argument, None),
+                (
+                    "${HOME}",
+                    std::env::var("HOME").ok(),
+                    if std::env::var("HOME").is_ok() {
+                        ValueSource::Environment
+                    } else {
+                        ValueSource::Missing
+                    },
+                    None
+                ),
+            ]
+        );
+        assert!(statuses[1].secret && statuses[5].optional);
+
+        // Without the command its scoped value doesn't apply
+        let statuses = variable_manager
+            .inspect_placeholders("staging", None, "#ns", &defaults)
+            .unwrap();
+        assert_eq!(statuses[0].value, Some("shop".into()));
+        assert_eq!(statuses[0].source, ValueSource::Variable);
+    }
+
+    #[test]
+    fn test_diff_contexts() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+
+        variable_manager
+            .set_variable("staging", "ns", "shop")
+            .unwrap();
+        variable_manager.set_variable("prod", "ns", "shop").unwrap();
+        assert!(variable_manager
+            .diff_contexts("staging", "prod")
+            .unwrap()
+            .is_empty());
+
+        variable_manager
+            .set_variable("staging", "replicas", "1")
+            .unwrap();
+        variable_manager
+            .set_variable("prod", "replicas", "3")
+            .unwrap();
+        variable_manager
+            .set_variable("staging", "debug", "on")
+            .unwrap();
+        variable_manager
+            .set_secret("staging", "token", "abc")
+            .unwrap();
+        variable_manager.set_secret("prod", "token", "xyz").unwrap();
+        variable_manager
+            .set_secret("staging", "api", "same")
+            .unwrap();
+        variable_manager.set_secret("prod", "api", "same").unwrap();
+        variable_manager.set_variable("prod", "temp", "x").unwrap();
+        let mut variables = variable_manager.load_variables("prod").unwrap();
+        variables.expires.insert(
+            "#temp".to_string(),
+            Utc::now() - chrono::Duration::minutes(1),
+        );
+        variable_manager.save_variables("prod", &variables).unwrap();
+
+        let diff = variable_manager.diff_contexts("staging", "prod").unwrap();
+        assert_eq!(diff.only_in_a.keys().collect::<Vec<_>>(), vec!["#debug"]);
+        assert_eq!(diff.only_in_a["#debug"].value, "on");
+        // Expired variables don't count
+        assert!(diff.only_in_b.is_empty());
+        assert_eq!(
+            diff.changed.keys().collect::<Vec<_>>(),
+            vec!["#replicas", "#token"]
+        );
+        assert_eq!(diff.changed["#replicas"].a.value, "1");
+        assert_eq!(diff.changed["#replicas"].b.value, "3");
+        let token = &diff.changed["#token"];
+        assert!(token.a.secret && token.b.secret);
+        assert_eq!(token.a.value, SECRET_MASK);
+        assert_eq!(token.b.value, SECRET_MASK);
+
+        let reversed = variable_manager.diff_contexts("prod", "staging").unwrap();
+        assert_eq!(reversed.only_in_b, diff.only_in_a);
+        assert_eq!(reversed.changed["#replicas"].a.value, "3");
+    }
+
+    #[test]
+    fn test_copy_variables() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+
+        variable_manager.set_variable("eu", "#1", "eu-ns").unwrap();
+        variable_manager
+            .set_variable("eu", "cluster", "eu-1")
+            .unwrap();
+        variable_manager
+            .set_secret("eu", "token", "s3cr3t")
+            .unwrap();
+        variable_manager
+            .set_variable("us", "cluster", "us-1")
+            .unwrap();
+
+        // Existing variables are only replaced when `overwrite` agrees
+        let mut asked = Vec::new();
+        let outcome = variable_manager
+            .copy_variables("eu", "us", &[], |name| {
+                asked.push(name.to_string());
+                Ok(false)
+            })
+            .unwrap();
+        assert_eq!(asked, vec!["#cluster"]);
+        assert_eq!(outcome.copied, vec!["#1", "#token"]);
+        assert_eq!(outcome.skipped, vec!["#cluster"]);
+        assert_eq!(
+            variable_manager.get_variable("us", "cluster").unwrap(),
+            Some("us-1".to_string())
+        );
+        assert_eq!(
+            variable_manager.list_secrets("us").unwrap()["#token"],
+            "s3cr3t"
+        );
+
+        let outcome = variable_manager
+            .copy_variables("eu", "us", &["cluster".to_string()], |_| Ok(true))
+            .unwrap();
+        assert_eq!(outcome.copied, vec!["#cluster"]);
+        assert_eq!(
+            variable_manager.get_variable("us", "#cluster").unwrap(),
+            Some("eu-1".to_string())
+        );
+
+        let err = variable_manager
+            .copy_variables("eu", "us", &["missing".to_string()], |_| Ok(true))
+            .unwrap_err();
+        assert!(err.to_string().contains("'#missing' is not set"));
+        assert!(variable_manager
+            .copy_variables("empty", "us", &[], |_| Ok(true))
+            .is_err());
+    }
+
+    #[test]
+    fn test_export_import_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+
+        variable_manager
+            .set_variable("staging", "#1", "staging-ns")
+            .unwrap();
+        variable_manager
+            .set_variable("staging", "url", "http://x:8080/a b")
+            .unwrap();
+        variable_manager
+            .set_secret("staging", "token", "s3cr3t")
+            .unwrap();
+
+        for file in ["vars.yaml", "vars.json"] {
+            let path = temp_dir.path().join(file);
+            assert_eq!(
+                variable_manager
+                    .export_variables("staging", &path, false)
+                    .unwrap(),
+                2
+            );
+            assert!(!fs::read_to_string(&path).unwrap().contains("s3cr3t"));
+
+            let context = format!("copy-{}", file.replace('.', "-"));
+            let outcome = variable_manager
+                .import_variables(&context, &path, false)
+                .unwrap();
+            assert_eq!(outcome.added, vec!["#1", "#url"]);
+            assert_eq!(
+                variable_manager.list_variables(&context).unwrap(),
+                variable_manager.list_variables("staging").unwrap()
+            );
+        }
+
+        // Secrets stay secrets when included
+        let path = temp_dir.path().join("all.yaml");
+        variable_manager
+            .export_variables("staging", &path, true)
+            .unwrap();
+        variable_manager
+            .set_variable("prod", "#1", "prod-ns")
+            .unwrap();
+        variable_manager.set_variable("prod", "old", "x").unwrap();
+        let outcome = variable_manager
+            .import_variables("prod", &path, false)
+            .unwrap();
+        assert_eq!(outcome.added, vec!["#token", "#url"]);
+        assert_eq!(outcome.overwritten, vec!["#1"]);
+        assert_eq!(
+            variable_manager.list_secrets("prod").unwrap()["#token"],
+            "s3cr3t"
+        );
+        assert!(variable_manager
+            .list_variables("prod")
+            .unwrap()
+            .contains_key("#old"));
+
+        // --replace starts from an empty context
+        variable_manager
+            .import_variables("prod", &path, true)
+            .unwrap();
+        assert!(!variable_manager
+            .list_variables("prod")
+            .unwrap()
+            .contains_key("#old"));
+    }
+
+    #[test]
+    fn test_export_as_shell() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+        variable_manager.set_variable("prod", "NS", "prod").unwrap();
+        variable_manager
+            .set_variable("prod", "MSG", "it's $HOME")
+            .unwrap();
+        // Not valid environment variable names
+        variable_manager.set_variable("prod", "#1", "one").unwrap();
+        variable_manager
+            .set_variable("prod", "kube-ctx", "x")
+            .unwrap();
+        variable_manager
+            .set_secret("prod", "TOKEN", "s3cr3t")
+            .unwrap();
+
+        let export = |shell, include_secrets| {
+            variable_manager
+                .export_as_shell("prod", shell, include_secrets)
+                .unwrap()
+        };
+        assert_eq!(
+            export(ShellFormat::Sh, false),
+            "export MSG='it'\\''s $HOME'\nexport NS=prod\n"
+        );
+        assert_eq!(
+            export(ShellFormat::Fish, true),
+            "set -gx MSG 'it\\'s $HOME'\nset -gx NS 'prod'\nset -gx TOKEN 's3cr3t'\n"
+        );
+        assert_eq!(
+            export(ShellFormat::PowerShell, false),
+            "$env:MSG = 'it''s $HOME'\n$env:NS = 'prod'\n"
+        );
+        assert!(ShellFormat::from_name("tcsh").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_export_as_shell_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+        let values = [
+            ("PLAIN", "production"),
+            ("QUOTES", r#"it's "quoted""#),
+            ("SPECIAL", "$HOME `id` \\ ; & | * #"),
+            ("LINES", "first\nsecond"),
+            ("EMPTY", ""),
+        ];
+        for (name, value) in values {
+            variable_manager.set_variable("dev", name, value).unwrap();
+        }
+
+        let script = variable_manager
+            .export_as_shell("dev", ShellFormat::Sh, false)
+            .unwrap();
+        let script = format!("{script}env");
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(script)
+            .env_clear()
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let env = String::from_utf8(output.stdout).unwrap();
+        for (name, value) in values {
+            assert!(
+                env.contains(&format!("{name}={value}\n")),
+                "{name} missing from {env}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_import_rejects_invalid_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+        variable_manager.set_variable("prod", "#1", "keep").unwrap();
+
+        let path = temp_dir.path().join("bad.yaml");
+        fs::write(&path, "variables:\n  \"#1\": other\n").unwrap();
+        assert!(variable_manager
+            .import_variables("prod", &path, true)
+            .is_err());
+        fs::write(&path, "vars:\n  \"#\": other\n").unwrap();
+        assert!(variable_manager
+            .import_variables("prod", &path, true)
+            .is_err());
+
+        assert_eq!(
+            variable_manager.get_variable("prod", "#1").unwrap(),
+            Some("keep".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_env() {
+        let vars = parse_env(
+            r#"# Shared settings
+NAMESPACE=production
+
+export CLUSTER = eu-1   # inline comment
+URL=https://example.com/#anchor
+EMPTY=
+SINGLE='literal $HOME \n #1'
+DOUBLE="tab\there \"quoted\""
+MULTI="first
+second"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            vars,
+            BTreeMap::from([
+                ("CLUSTER".to_string(), "eu-1".to_string()),
+                ("DOUBLE".to_string(), "tab\there \"quoted\"".to_string()),
+                ("EMPTY".to_string(), String::new()),
+                ("MULTI".to_string(), "first\nsecond".to_string()),
+                ("NAMESPACE".to_string(), "production".to_string()),
+                ("SINGLE".to_string(), r"literal $HOME \n #1".to_string()),
+                ("URL".to_string(), "https://example.com/#anchor".to_string()),
+            ])
+        );
+
+        let err = parse_env("A=1\nnot a pair\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 2: expected KEY=value");
+        assert!(parse_env("1ABC=x").is_err());
+        assert!(parse_env("A=\"open\n").is_err());
+    }
+
+    #[test]
+    fn test_load_from_env_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+        variable_manager
+            .set_variable("staging", "NAMESPACE", "old")
+            .unwrap();
+
+        let path = temp_dir.path().join(".env");
+        fs::write(
+            &path,
+            "# staging\nNAMESPACE=staging\nREGION=eu-west-1\n\nexport TOKEN=abc\n",
+        )
+        .unwrap();
+        assert_eq!(
+            variable_manager
+                .load_from_env_file("staging", &path)
+                .unwrap(),
+            3
+        );
+        let vars = variable_manager.list_variables("staging").unwrap();
+        assert_eq!(vars["#NAMESPACE"], "staging");
+        assert_eq!(vars["#REGION"], "eu-west-1");
+        assert_eq!(vars["#TOKEN"], "abc");
+        let resolved = variable_manager
+            .resolve_variables("staging", "kubectl -n ${NAMESPACE} --region #REGION", &[])
+            .unwrap();
+        assert_eq!(resolved, "kubectl -n staging --region eu-west-1");
+
+        // `doo var import` recognizes dotenv files by name
+        let path = temp_dir.path().join("prod.env");
+        fs::write(&path, "NAMESPACE=prod\n").unwrap();
+        let outcome = variable_manager
+            .import_variables("staging", &path, false)
+            .unwrap();
+        assert_eq!(outcome.overwritten, vec!["#NAMESPACE"]);
+    }
+
+    #[test]
+    fn test_mask_values_prefers_longest_secret() {
+        assert_eq!(
+            mask_values("a abc ab", vec!["ab".to_string(), "abc".to_string()]),
+            "a ***** *****"
+        );
+        assert_eq!(mask_values("echo x", vec![String::new()]), "echo x");
+    }
+
+    #[test]
+    fn test_child_context_inherits_parent_variables() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+        let mut context_manager = crate::context::ContextManager::new(&config_manager).unwrap();
+
+        variable_manager
+            .set_variable("default", "#1", "shared-ns")
+            .unwrap();
+        variable_manager
+            .set_variable("default", "#2", "default-pod")
+            .unwrap();
+        context_manager
+            .switch_context("staging", Some("default"))
+            .unwrap();
+        variable_manager
+            .set_variable("staging", "#2", "staging-pod")
+            .unwrap();
+
+        let resolved = variable_manager
+            .resolve_variables("staging", "kubectl logs -n #1 #2", &[])
+            .unwrap();
+        assert_eq!(resolved, "kubectl logs -n shared-ns staging-pod");
+
+        // The parent is not affected by its child
+        let resolved = variable_manager
+            .resolve_variables("default", "kubectl logs -n #1 #2", &[])
+            .unwrap();
+        assert_eq!(resolved, "kubectl logs -n shared-ns default-pod");
+
+        assert_eq!(
+            variable_manager.effective_variable("staging", "1").unwrap(),
+            Some("shared-ns".to_string())
+        );
+        assert_eq!(
+            variable_manager.get_variable("staging", "#1").unwrap(),
+            None
+        );
+
+        // Only values the child doesn't set are inherited, with their origin
+        let inherited = variable_manager.list_inherited("staging").unwrap();
+        assert_eq!(inherited.keys().collect::<Vec<_>>(), vec!["#1"]);
+        assert_eq!(inherited["#1"].context, "default");
+        assert_eq!(inherited["#1"].value, VarValue::from("shared-ns"));
+
+        // Removing the child's value brings back the parent's
+        assert!(variable_manager.remove_variable("staging", "#2").unwrap());
+        assert_eq!(
+            variable_manager
+                .effective_variable("staging", "#2")
+                .unwrap(),
+            Some("default-pod".to_string())
+        );
+        assert!(!variable_manager.remove_variable("staging", "#1").unwrap());
+        assert_eq!(
+            variable_manager.get_variable("default", "#1").unwrap(),
+            Some("shared-ns".to_string())
+        );
+    }
+
+    #[test]
+    fn test_history_and_undo() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+
+        for value in ["a", "b", "b", "c"] {
+            variable_manager
+                .set_variable("default", "#1", value)
+                .unwrap();
+        }
+        variable_manager.set_variable("staging", "#1", "s").unwrap();
+        let values = |manager: &VariableManager, context| -> Vec<String> {
+            manager
+                .history(context, "#1")
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.value.to_string())
+                .collect()
+        };
+        // Setting the same value again is not recorded
+        assert_eq!(values(&variable_manager, "default"), vec!["b", "a"]);
+        assert!(values(&variable_manager, "staging").is_empty());
+
+        // History is read from disk, so a new manager sees it too
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+        let restored = variable_manager.undo_variable("default", "1").unwrap();
+        assert_eq!(restored.unwrap().value, "b");
+        assert_eq!(
+            variable_manager.get_variable("default", "#1").unwrap(),
+            Some("b".to_string())
+        );
+        assert_eq!(values(&variable_manager, "default"), vec!["a"]);
+        variable_manager.undo_variable("default", "#1").unwrap();
+        assert!(variable_manager
+            .undo_variable("default", "#1")
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            variable_manager.get_variable("default", "#1").unwrap(),
+            Some("a".to_string())
+        );
+
+        // Secrets are restored as secrets
+        variable_manager
+            .set_secret("default", "token", "old")
+            .unwrap();
+        variable_manager
+            .set_variable("default", "token", "new")
+            .unwrap();
+        assert!(variable_manager.history("default", "token").unwrap()[0].secret);
+        variable_manager.undo_variable("default", "token").unwrap();
+        assert_eq!(
+            variable_manager.list_secrets("default").unwrap()["#token"],
+            "old"
+        );
+        assert!(!variable_manager
+            .list_variables("default")
+            .unwrap()
+            .contains_key("#token"));
+    }
+
+    #[test]
+    fn test_snapshot_and_restore() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir.clone()).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+
+        variable_manager
+            .set_variable("default", "#1", "web")
+            .unwrap();
+        variable_manager
+            .set_command_variable("default", "deploy", "region", "eu".into(), false)
+            .unwrap();
+        variable_manager
+            .describe_variable("default", "#1", Some("Namespace"))
+            .unwrap();
+        let snapshot = variable_manager.snapshot("default").unwrap();
+        assert_eq!(snapshot.context(), "default");
+        let before = fs::read_to_string(config_dir.join("variables/default.yaml")).unwrap();
+
+        variable_manager
+            .set_variable("default", "#1", "override")
+            .unwrap();
+        variable_manager
+            .set_variable("default", "replicas", "1")
+            .unwrap();
+        variable_manager
+            .set_secret("default", "token", "s3cret")
+            .unwrap();
+        variable_manager
+            .remove_command_variable("default", "deploy", "region")
+            .unwrap();
+        variable_manager.restore(snapshot).unwrap();
+
+        let vars = variable_manager.list_variables("default").unwrap();
+        assert_eq!(vars.len(), 1);
+        assert_eq!(vars["#1"], "web");
+        assert!(variable_manager.list_secrets("default").unwrap().is_empty());
+        assert!(!config_dir.join("variables/default.secrets.yaml").exists());
+        assert_eq!(
+            fs::read_to_string(config_dir.join("variables/default.yaml")).unwrap(),
+            before
+        );
+
+        // A context without variables is empty again after restoring
+        let snapshot = variable_manager.snapshot("staging").unwrap();
+        variable_manager
+            .set_variable("staging", "#1", "stage")
+            .unwrap();
+        variable_manager.restore(snapshot).unwrap();
+        assert!(!config_dir.join("variables/staging.yaml").exists());
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+
+        for i in 0..HISTORY_LIMIT + 5 {
+            variable_manager
+                .set_variable("default", "#1", &i.to_string())
+                .unwrap();
+        }
+        let history = variable_manager.history("default", "#1").unwrap();
+        assert_eq!(history.len(), HISTORY_LIMIT);
+        assert_eq!(history[0].value, (HISTORY_LIMIT + 3).to_string());
+        assert_eq!(history[HISTORY_LIMIT - 1].value, "4");
+    }
+
+    #[test]
+    fn test_parse_ttl() {
+        assert_eq!(parse_ttl("30m").unwrap(), Duration::from_secs(30 * 60));
+        assert_eq!(parse_ttl("2h").unwrap(), Duration::from_secs(2 * 3600));
+        assert_eq!(parse_ttl("1d").unwrap(), Duration::from_secs(86_400));
+        assert_eq!(parse_ttl("1h30m").unwrap(), Duration::from_secs(5400));
+        for invalid in ["", "30", "m", "0h", "1w", "-1h", "1.5h"] {
+            assert!(parse_ttl(invalid).is_err(), "{invalid:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn test_expired_variables_are_unset() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+        let mut context_manager = crate::context::ContextManager::new(&config_manager).unwrap();
+
+        variable_manager
+            .set_variable("default", "#1", "parent-pod")
+            .unwrap();
+        context_manager
+            .switch_context("staging", Some("default"))
+            .unwrap();
+        variable_manager
+            .set_with_ttl("staging", "#1", "my-pod", false, parse_ttl("1h").unwrap())
+            .unwrap();
+        assert_eq!(
+            variable_manager.get_variable("staging", "#1").unwrap(),
+            Some("my-pod".to_string())
+        );
+
+        // Let the TTL run out
+        let mut variables = variable_manager.load_variables("staging").unwrap();
+        variables
+            .expires
+            .insert("#1".to_string(), Utc::now() - chrono::Duration::minutes(1));
+        variable_manager
+            .save_variables("staging", &variables)
+            .unwrap();
+
+        assert_eq!(
+            variable_manager.get_variable("staging", "#1").unwrap(),
+            None
+        );
+        let resolved = variable_manager
+            .resolve_variables("staging", "kubectl logs #1", &[])
+            .unwrap();
+        assert_eq!(resolved, "kubectl logs parent-pod");
+
+        // Setting the variable again without a TTL drops the expiry
+        variable_manager
+            .set_variable("staging", "#1", "my-pod")
+            .unwrap();
+        assert!(variable_manager.expiry("staging").unwrap().is_empty());
+        assert_eq!(
+            variable_manager.get_variable("staging", "#1").unwrap(),
+            Some("my-pod".to_string())
+        );
+    }
+
+    #[test]
+    fn test_files_without_expiry_still_load() {
+        let variables: Variables = serde_yaml::from_str("vars:\n  '#1': value\n").unwrap();
+        assert_eq!(variables.vars["#1"], "value");
+        assert!(variables.expires.is_empty());
+        assert!(!serde_yaml::to_string(&variables)
+            .unwrap()
+            .contains("expires"));
+    }
+
+    #[test]
+    fn test_variable_metadata() {
+        // Old flat files still parse and are written back unchanged
+        let flat = "vars:\n  '#1': value\n";
+        let variables: Variables = serde_yaml::from_str(flat).unwrap();
+        assert_eq!(variables.vars["#1"], "value");
+        assert!(variables.meta.is_empty());
+        assert_eq!(serde_yaml::to_string(&variables).unwrap(), flat);
+
+        let temp_dir = TempDir::new().unwrap();
+        let config_manager = ConfigManager::new_with_dir(temp_dir.path().to_path_buf()).unwrap();
+        let mut manager = VariableManager::new(&config_manager).unwrap();
+        let before = Utc::now();
+        manager
+            .set_variable("default", "region", "eu-west-1")
+            .unwrap();
+        manager.set_variable("default", "token", "abc").unwrap();
+        assert!(manager
+            .describe_variable("default", "region", Some("AWS region to deploy to"))
+            .unwrap());
+        assert!(!manager
+            .describe_variable("default", "missing", Some("nothing"))
+            .unwrap());
+
+        let meta = manager.metadata("default").unwrap();
+        assert_eq!(
+            meta["#region"].description.as_deref(),
+            Some("AWS region to deploy to")
+        );
+        assert!(meta["#region"].updated_at.is_some_and(|at| at >= before));
+        assert_eq!(meta["#token"].description, None);
+
+        // Described entries use the detailed form, the others stay flat
+        let path = temp_dir.path().join("variables").join("default.yaml");
+        let written = fs::read_to_string(path).unwrap();
+        let file: serde_yaml::Value = serde_yaml::from_str(&written).unwrap();
+        assert_eq!(file["vars"]["#region"]["value"], "eu-west-1");
+        assert_eq!(
+            file["vars"]["#region"]["description"],
+            "AWS region to deploy to"
+        );
+        assert!(file["vars"]["#region"]["updated_at"].is_null());
+        assert_eq!(file["vars"]["#token"], "abc");
+        assert!(file["updated"]["#region"].is_string());
+        assert!(file["updated"]["#token"].is_string());
+        assert_eq!(
+            manager
+                .get_variable("default", "region")
+                .unwrap()
+                .as_deref(),
+            Some("eu-west-1")
+        );
+
+        // Setting a new value keeps the description
+        manager
+            .set_variable("default", "region", "us-east-1")
+            .unwrap();
+        let meta = manager.metadata("default").unwrap();
+        assert_eq!(
+            meta["#region"].description.as_deref(),
+            Some("AWS region to deploy to")
+        );
+        manager.remove_variable("default", "region").unwrap();
+        assert!(!manager.metadata("default").unwrap().contains_key("#region"));
+    }
+
+    #[test]
+    fn test_plain_set_records_a_timestamp() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_manager = ConfigManager::new_with_dir(temp_dir.path().to_path_buf()).unwrap();
+        let mut manager = VariableManager::new(&config_manager).unwrap();
+        let before = Utc::now();
+        manager.set_variable("default", "1", "first").unwrap();
+        manager.set_variable("default", "1", "second").unwrap();
+        let meta = manager.metadata("default").unwrap();
+        assert!(meta["#1"].updated_at.is_some_and(|at| at >= before));
+        assert_eq!(meta["#1"].description, None);
+
+        // The value stays a bare scalar, the time goes to `updated`
+        let path = temp_dir.path().join("variables").join("default.yaml");
+        let file: serde_yaml::Value =
+            serde_yaml::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(file["vars"]["#1"], "second");
+        assert!(file["updated"]["#1"].is_string());
+
+        // Times stored next to the value by earlier versions are moved over
+        let old = "vars:\n  '#1':\n    value: x\n    updated_at: 2024-01-02T03:04:05Z\n";
+        let variables: Variables = serde_yaml::from_str(old).unwrap();
+        assert!(variables.meta["#1"].updated_at.is_some());
+        assert_eq!(
+            serde_yaml::to_string(&variables).unwrap(),
+            "vars:\n  '#1': x\nupdated:\n  '#1': 2024-01-02T03:04:05Z\n"
+        );
+    }
+
+    #[test]
+    fn test_placeholder_filters() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_manager = ConfigManager::new_with_dir(temp_dir.path().to_path_buf()).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+
+        variable_manager
+            .set_variable("default", "#region", "eu-west-1")
+            .unwrap();
+        variable_manager
+            .set_variable("default", "#dir", "/srv/#region/")
+            .unwrap();
+        let args = vec!["  Prod ".to_string()];
+
+        let resolved = variable_manager
+            .resolve_variables(
+                "default",
+                "deploy #region|upper #1|trim|lower #dir|basename $1|trim",
+                &args,
+            )
+            .unwrap();
+        assert_eq!(resolved, "deploy EU-WEST-1 prod eu-west-1 Prod");
+
+        // Filters also apply inside the values of other variables
+        variable_manager
+            .set_variable("default", "#label", "region=#region|upper")
+            .unwrap();
+        let resolved = variable_manager
+            .resolve_variables("default", "echo #label", &[])
+            .unwrap();
+        assert_eq!(resolved, "echo region=EU-WEST-1");
+
+        let error = variable_manager
+            .resolve_variables("default", "echo #region|reverse", &[])
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("Unknown filter 'reverse' in placeholder #region|reverse"));
+    }
+
+    #[test]
+    fn test_dotenv_variables() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_manager = ConfigManager::new_with_dir(temp_dir.path().join("doo")).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+
+        let env_file = temp_dir.path().join(".env");
+        fs::write(
+            &env_file,
+            "# Local settings\nREGION=eu-west-1\nexport BUCKET='my bucket'\nnot a pair\nGREETING=\"hello\nworld\"\n1BAD=x\nNAME=\"unterminated\nTAG=v1 # release\n",
+        )
+        .unwrap();
+        let skipped = variable_manager.load_dotenv(&env_file).unwrap();
+        assert_eq!(skipped, vec![4, 7, 8]);
+
+        let resolved = variable_manager
+            .resolve_variables("default", "deploy #REGION #TAG ${BUCKET}", &[])
+            .unwrap();
+        assert_eq!(resolved, "deploy eu-west-1 v1 my bucket");
+        assert_eq!(
+            variable_manager
+                .effective_variable("default", "GREETING")
+                .unwrap()
+                .as_deref(),
+            Some("hello\nworld")
+        );
+
+        // Variables set with doo take precedence
+        variable_manager
+            .set_variable("default", "#REGION", "us-east-1")
+            .unwrap();
+        let resolved = variable_manager
+            .resolve_variables("default", "deploy #REGION", &[])
+            .unwrap();
+        assert_eq!(resolved, "deploy us-east-1");
+        // The .env entries are not stored
+        assert!(!variable_manager
+            .list_variables("default")
+            .unwrap()
+            .contains_key("#TAG"));
+    }
+
+    #[test]
+    fn test_set_variables() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join(".config").join("doo");
+        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+        variable_manager
+            .set_variable("prod", "#1", "staging")
+            .unwrap();
+
+        let expires = variable_manager
+            .set_variables(
+                "prod",
+                vec![
+                    ("#1".to_string(), "prod".into()),
+                    ("#2".to_string(), "api-pod".into()),
+                    ("region".to_string(), "eu-west-1".into()),
+                ],
+                false,
+                None,
+            )
+            .unwrap();
+        assert_eq!(expires, None);
+
+        let get = |name: &str| variable_manager.get_variable("prod", name).unwrap();
+        assert_eq!(get("#1").as_deref(), Some("prod"));
+        assert_eq!(get("#2").as_deref(), Some("api-pod"));
+        assert_eq!(get("#region").as_deref(), Some("eu-west-1"));
+        let history = variable_manager.history("prod", "#1").unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].value, VarValue::Single("staging".to_string()));
+    }
+
+    #[test]
+    fn test_command_scoped_variables() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_manager = ConfigManager::new_with_dir(temp_dir.path().to_path_buf()).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+        let no_defaults = BTreeMap::new();
+
+        // Files written before command scopes existed still parse
+        let variables_file = temp_dir.path().join("variables").join("default.yaml");
+        fs::write(&variables_file, "vars:\n  '#1': default-ns\n").unwrap();
+
+        variable_manager
+            .set_command_variable("default", "pods", "#1", "web".into(), false)
+            .unwrap();
+        variable_manager
+            .set_command_variable("default", "cat", "#1", "notes.txt".into(), false)
+            .unwrap();
+
+        let resolve = |variable_manager: &VariableManager, command: Option<&str>| {
+            variable_manager
+                .resolve_variables_with_defaults("default", command, "use #1", &[], &no_defaults)
+                .unwrap()
+        };
+        assert_eq!(resolve(&variable_manager, Some("pods")), "use web");
+        assert_eq!(resolve(&variable_manager, Some("cat")), "use notes.txt");
+        assert_eq!(resolve(&variable_manager, Some("logs")), "use default-ns");
+        assert_eq!(resolve(&variable_manager, None), "use default-ns");
+
+        // A child context's scoped value beats the parent's
+        crate::context::ContextManager::new(&config_manager)
+            .unwrap()
+            .switch_context("staging", Some("default"))
+            .unwrap();
+        variable_manager
+            .set_command_variable("staging", "pods", "#1", "api".into(), true)
+            .unwrap();
+        let resolved = variable_manager
+            .resolve_variables_with_defaults("staging", Some("pods"), "#1", &[], &no_defaults)
+            .unwrap();
+        assert_eq!(resolved, "api");
+        assert_eq!(
+            variable_manager
+                .mask_secrets("staging", "pods in api")
+                .unwrap(),
+            format!("pods in {SECRET_MASK}")
+        );
+
+        let scoped = variable_manager.list_command_variables("default").unwrap();
+        assert_eq!(
+            scoped["pods"]["#1"],
+            (VarValue::from("web"), false),
+            "{scoped:?}"
+        );
+        assert_eq!(scoped.keys().collect::<Vec<_>>(), ["cat", "pods"]);
+
+        assert!(variable_manager
+            .remove_command_variable("default", "pods", "1")
+            .unwrap());
+        assert!(!variable_manager
+            .remove_command_variable("default", "pods", "1")
+            .unwrap());
+        assert_eq!(resolve(&variable_manager, Some("pods")), "use default-ns");
+        let contents = fs::read_to_string(&variables_file).unwrap();
+        assert!(!contents.contains("pods"));
+    }
+
+    #[test]
+    fn test_quoted_values() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_manager = ConfigManager::new_with_dir(temp_dir.path().to_path_buf()).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+
+        let values = ["two words", "it's", "say \"hi\"", "$HOME", "plain"];
+        for (i, value) in values.iter().enumerate() {
+            variable_manager
+                .set_variable("default", &format!("#v{i}"), value)
+                .unwrap();
+        }
+        variable_manager
+            .set_list(
+                "default",
+                "flags",
+                vec!["-n".to_string(), "a b".to_string()],
+                false,
+            )
+            .unwrap();
+        let template = "echo #v0 #v1 #v2 #v3 #v4 #1 #flags";
+        let args = vec!["arg with 'quotes'".to_string()];
+
+        let (resolved, substituted) = variable_manager
+            .resolve_variables_with_values("default", None, template, &args, &BTreeMap::new(), true)
+            .unwrap();
+        assert_eq!(
+            resolved,
+            r#"echo 'two words' 'it'\''s' 'say "hi"' '$HOME' plain 'arg with '\''quotes'\''' -n 'a b'"#
+        );
+        // Every value stays one argument, list items stay separate
+        let mut expected = vec!["echo"];
+        expected.extend(values);
+        expected.extend(["arg with 'quotes'", "-n", "a b"]);
+        assert_eq!(shell_words::split(&resolved).unwrap(), expected);
+        // Substituted values are reported unquoted
+        assert_eq!(substituted["#v1"], "it's");
+
+        // Without quoting, values are inserted as they are
+        let (resolved, _) = variable_manager
+            .resolve_variables_with_values(
+                "default",
+                None,
+                "echo #v0",
+                &[],
+                &BTreeMap::new(),
+                false,
+            )
+            .unwrap();
+        assert_eq!(resolved, "echo two words");
+    }
+
+    #[test]
+    fn test_list_variables() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_manager = ConfigManager::new_with_dir(temp_dir.path().to_path_buf()).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+
+        let flags = vec![
+            "--context=prod".to_string(),
+            "--label".to_string(),
+            "app=my web".to_string(),
+        ];
+        variable_manager
+            .set_list("default", "flags", flags.clone(), false)
+            .unwrap();
+        variable_manager
+            .set_variable("default", "#1", "pods")
+            .unwrap();
+
+        // Items become separate arguments, quoted where needed
+        let resolved = variable_manager
+            .resolve_variables("default", "kubectl get #1 #flags", &[])
+            .unwrap();
+        assert_eq!(
+            resolved,
+            "kubectl get pods '--context=prod' --label 'app=my web'"
+        );
+        assert_eq!(shell_words::split(&resolved).unwrap()[3..], flags[..]);
+
+        // Lists are sequences in the file, next to plain values
+        let variables: Variables =
+            read_variables_file(&temp_dir.path().join("variables").join("default.yaml")).unwrap();
+        assert_eq!(variables.vars["#flags"], VarValue::List(flags.clone()));
+        assert_eq!(variables.vars["#1"], "pods");
+
+        // Export and import keep lists
+        let path = temp_dir.path().join("vars.yaml");
+        variable_manager
+            .export_variables("default", &path, false)
+            .unwrap();
+        variable_manager
+            .import_variables("staging", &path, false)
+            .unwrap();
+        assert_eq!(
+            variable_manager.list_variables("staging").unwrap()["#flags"],
+            VarValue::List(flags)
+        );
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    fn no_env(_: &str) -> Option<String> {
+        None
+    }
+
+    fn resolve_with_defaults(
+        template: &str,
+        vars: &HashMap<String, String>,
+        args: &[String],
+        defaults: &BTreeMap<String, String>,
+    ) -> Result<String> {
+        let sources = Sources {
+            vars,
+            args,
+            defaults,
+            env: &no_env,
+            quote: None,
+        };
+        resolve_template(template, &sources, false)
+    }
+
+    fn resolve(template: &str, vars: &HashMap<String, String>, args: &[String]) -> String {
+        resolve_with_defaults(template, vars, args, &BTreeMap::new()).unwrap()
+    }
+
+    #[test]
+    fn test_resolution_precedence() {
+        // $N always comes from the arguments, even if #N is stored
+        assert_eq!(
+            resolve("$1 #1", &vars(&[("#1", "stored")]), &args(&["arg"])),
+            "arg stored"
+        );
+        // Stored variables win over positional fallback
+        assert_eq!(
+            resolve("#1 #2", &vars(&[("#1", "stored")]), &args(&["a", "b"])),
+            "stored a"
+        );
+        // Fallback fills unset placeholders in ascending order
+        assert_eq!(resolve("#3 #1", &HashMap::new(), &args(&["a", "b"])), "b a");
+        // A repeated placeholder consumes a single argument
+        assert_eq!(
+            resolve("#1 #1 #2", &HashMap::new(), &args(&["a", "b"])),
+            "a a b"
+        );
+        // Missing values leave the placeholder untouched
+        assert_eq!(resolve("#1 $2", &HashMap::new(), &[]), "#1 $2");
+        assert_eq!(resolve("$0", &HashMap::new(), &args(&["a"])), "$0");
+    }
+
+    #[test]
+    fn test_rest_placeholder_forwards_remaining_args() {
+        let none = HashMap::new();
+        // No arguments resolve to nothing rather than the literal `#*`,
+        // without leaving the space before it
+        assert_eq!(
+            resolve("kubectl get pods #*", &none, &[]),
+            "kubectl get pods"
+        );
+        assert_eq!(
+            resolve("kubectl -n #1 #*", &none, &args(&["prod"])),
+            "kubectl -n prod"
+        );
+        // One and several trailing arguments
+        assert_eq!(
+            resolve("kubectl -n #1 #*", &none, &args(&["prod", "get"])),
+            "kubectl -n prod get"
+        );
+        assert_eq!(
+            resolve(
+                "kubectl -n #1 #*",
+                &none,
+                &args(&["prod", "get", "pods", "-w"])
+            ),
+            "kubectl -n prod get pods -w"
+        );
+        // A stored #1 doesn't consume an argument
+        assert_eq!(
+            resolve(
+                "kubectl -n #1 #*",
+                &vars(&[("#1", "dev")]),
+                &args(&["get", "pods"])
+            ),
+            "kubectl -n dev get pods"
+        );
+        // Arguments before the highest $N are skipped
+        assert_eq!(
+            resolve("echo $2 #*", &none, &args(&["a", "b", "c"])),
+            "echo b c"
+        );
+        assert_eq!(resolve("echo $3 #*", &none, &args(&["a"])), "echo $3");
+    }
+
+    #[test]
+    fn test_optional_placeholders() {
+        let none = HashMap::new();
+        // Present values expand as usual
+        assert_eq!(
+            resolve("kubectl logs #1 #2?", &none, &args(&["api", "web"])),
+            "kubectl logs api web"
+        );
+        // Missing ones vanish with the space before them
+        assert_eq!(
+            resolve("kubectl logs #1 #2?", &none, &args(&["api"])),
+            "kubectl logs api"
+        );
+        assert_eq!(
+            resolve("kubectl logs #1 #2? --tail 10", &none, &args(&["api"])),
+            "kubectl logs api --tail 10"
+        );
+        // ... and so does a flag joined to them with `=`
+        assert_eq!(
+            resolve("kubectl logs #1 -n=#2? -f", &none, &args(&["api"])),
+            "kubectl logs api -f"
+        );
+        assert_eq!(
+            resolve("kubectl get pods --namespace=#ns?", &none, &[]),
+            "kubectl get pods"
+        );
+        // Separate flags stay, they may not take a value
+        assert_eq!(resolve("ls -la #dir?", &none, &[]), "ls -la");
+        assert_eq!(
+            resolve("kubectl logs -f #pod? --tail 10", &none, &[]),
+            "kubectl logs -f --tail 10"
+        );
+        assert_eq!(
+            resolve("kubectl get pods -n #ns?", &vars(&[("#ns", "prod")]), &[]),
+            "kubectl get pods -n prod"
+        );
+        // Named and environment placeholders are never reported as missing,
+        // not even in strict mode
+        let sources = Sources {
+            vars: &none,
+            args: &[],
+            defaults: &BTreeMap::new(),
+            env: &no_env,
+            quote: None,
+        };
+        assert_eq!(
+            resolve_template("echo #name? ${UNSET_VAR}? done", &sources, true).unwrap(),
+            "echo done"
+        );
+        assert_eq!(missing_in_template("echo #1? #2", &sources), vec!["#2"]);
+    }
+
+    #[test]
+    fn test_rest_placeholder_aliases() {
+        let none = HashMap::new();
+        for template in ["terraform -chdir=#1 #@", "terraform -chdir=#1 $@"] {
+            assert_eq!(
+                resolve(template, &none, &args(&["infra", "plan", "-out=my plan"])),
+                "terraform -chdir=infra plan '-out=my plan'"
+            );
+            // Nothing left over expands to nothing, without a trailing space
+            assert_eq!(
+                resolve(template, &none, &args(&["infra"])),
+                "terraform -chdir=infra"
+            );
+        }
+        // The remaining arguments are not also used for later `#N`
+        assert_eq!(
+            resolve("run #1 #@ --to #2", &none, &args(&["a", "b", "c", "d"])),
+            "run a c d --to b"
+        );
+        assert_eq!(
+            resolve("run #1 #@ --verbose", &none, &args(&["a"])),
+            "run a --verbose"
+        );
+    }
 
-#[allow(dead_code)]
-impl VariableManager {
-    pub fn new(config_manager: &ConfigManager) -> Result<Self> {
-        let config_dir = config_manager.config_dir().clone();
-        let variables_dir = config_dir.join("variables");
+    #[test]
+    fn test_rest_placeholder_quotes_args() {
+        let resolved = resolve(
+            "grep #*",
+            &HashMap::new(),
+            &args(&["hello world", "it's", "plain", ""]),
+        );
+        assert_eq!(resolved, r#"grep 'hello world' 'it'\''s' plain ''"#);
+        assert_eq!(
+            shell_words::split(&resolved).unwrap(),
+            vec!["grep", "hello world", "it's", "plain", ""]
+        );
+    }
 
-        // Create variables directory if it doesn't exist
-        fs::create_dir_all(&variables_dir).context("Failed to create variables directory")?;
+    #[test]
+    fn test_multi_digit_placeholders_are_not_split() {
+        // Stored variables, whatever order the map iterates in
+        let stored = vars(&[
+            ("#1", "one"),
+            ("#2", "two"),
+            ("#10", "ten"),
+            ("#12", "twelve"),
+        ]);
+        assert_eq!(
+            resolve("#1 #2 #10 #12 #1x #100", &stored, &[]),
+            "one two ten twelve onex #100"
+        );
 
-        Ok(Self { config_dir })
-    }
+        // Positional fallback fills #1 < #2 < #10 < #12 numerically
+        assert_eq!(
+            resolve(
+                "#12 #10 #2 #1",
+                &HashMap::new(),
+                &args(&["a", "b", "c", "d"])
+            ),
+            "d c b a"
+        );
+        assert_eq!(
+            resolve(
+                "$1 $10 $2",
+                &HashMap::new(),
+                &args(&["a", "b", "c", "d", "e", "f", "g", "h", "i", "j"])
+            ),
+            "a j b"
+        );
 
-    pub fn set_variable(&mut self, context: &str, name: &str, value: &str) -> Result<()> {
-        let mut variables = self.load_variables(context)?;
-        variables.vars.insert(name.to_string(), value.to_string());
-        self.save_variables(context, &variables)?;
-        Ok(())
+        // Mixed: #1 stored, the rest from arguments
+        assert_eq!(
+            resolve("#1 #10 #2", &vars(&[("#1", "one")]), &args(&["x", "y"])),
+            "one y x"
+        );
     }
 
-    pub fn get_variable(&self, context: &str, name: &str) -> Result<Option<String>> {
-        let variables = self.load_variables(context)?;
-        Ok(variables.vars.get(name).cloned())
+    #[test]
+    fn test_escaped_sigils_resolve_to_literals() {
+        // Escapes don't consume positional arguments
+        assert_eq!(
+            resolve("grep ##1 #1 $$1 #2", &HashMap::new(), &args(&["a", "b"])),
+            "grep #1 a $1 b"
+        );
+        assert_eq!(
+            resolve(
+                "curl https://#host/page##section?user=$$USER",
+                &vars(&[("#host", "example.com")]),
+                &[]
+            ),
+            "curl https://example.com/page#section?user=$USER"
+        );
+        // A value containing an escape sequence is inserted as it is
+        assert_eq!(resolve("echo #1", &vars(&[("#1", "##")]), &[]), "echo ##");
     }
 
-    pub fn list_variables(&self, context: &str) -> Result<HashMap<String, String>> {
-        let variables = self.load_variables(context)?;
-        Ok(variables.vars)
+    #[test]
+    fn test_substituted_values_are_not_rescanned() {
+        assert_eq!(
+            resolve("echo #1 #2", &vars(&[("#1", "#2")]), &args(&["x"])),
+            "echo #2 x"
+        );
     }
 
-    pub fn remove_variable(&mut self, context: &str, name: &str) -> Result<bool> {
-        let mut variables = self.load_variables(context)?;
-        let removed = variables.vars.remove(name).is_some();
-        if removed {
-            self.save_variables(context, &variables)?;
-        }
-        Ok(removed)
+    #[test]
+    fn test_inline_default_applied_when_arg_missing() {
+        assert_eq!(
+            resolve("kubectl get pods -n #1:=default", &HashMap::new(), &[]),
+            "kubectl get pods -n default"
+        );
+        assert_eq!(resolve("echo $1:=none", &HashMap::new(), &[]), "echo none");
     }
 
-    pub fn resolve_variables(
-        &self,
-        context: &str,
-        template: &str,
-        args: &[String],
-    ) -> Result<String> {
-        let variables = self.load_variables(context)?;
-        let mut resolved = template.to_string();
+    #[test]
+    fn test_inline_default_overridden_by_stored_variable() {
+        assert_eq!(
+            resolve(
+                "kubectl get pods -n #1:=default",
+                &vars(&[("#1", "stored")]),
+                &args(&["arg"])
+            ),
+            "kubectl get pods -n stored"
+        );
+    }
 
-        // First, replace direct positional arguments ($1, $2, etc.) with provided args
-        for (i, arg) in args.iter().enumerate() {
-            let placeholder = format!("${}", i + 1);
-            resolved = resolved.replace(&placeholder, arg);
-        }
+    #[test]
+    fn test_inline_default_overridden_by_runtime_arg() {
+        assert_eq!(
+            resolve(
+                "kubectl get pods -n #1:=default",
+                &HashMap::new(),
+                &args(&["arg"])
+            ),
+            "kubectl get pods -n arg"
+        );
+        assert_eq!(
+            resolve("echo $1:=none", &HashMap::new(), &args(&["a"])),
+            "echo a"
+        );
+    }
 
-        // Then replace persistent variable placeholders like #1, #2, etc.
-        for (name, value) in &variables.vars {
-            resolved = resolved.replace(name, value);
-        }
+    #[test]
+    fn test_fallback_default_with_variables_and_args() {
+        let template = "kubectl get pods -n #1:-default-ns";
+        assert_eq!(
+            resolve(template, &HashMap::new(), &[]),
+            "kubectl get pods -n default-ns"
+        );
+        assert_eq!(
+            resolve(template, &vars(&[("#1", "stored")]), &[]),
+            "kubectl get pods -n stored"
+        );
+        assert_eq!(
+            resolve(template, &HashMap::new(), &args(&["from-arg"])),
+            "kubectl get pods -n from-arg"
+        );
 
-        // Finally, replace remaining positional arguments (#1, #2, etc.) with provided args
-        // This handles cases where #1, #2 are not set as persistent variables
-        let mut arg_index = 0;
-        for i in 1..=args.len() + 10 {
-            // Check up to 10 more placeholders than we have args
-            let placeholder = format!("#{i}");
-            if resolved.contains(&placeholder)
-                && !variables.vars.contains_key(&placeholder)
-                && arg_index < args.len()
-            {
-                resolved = resolved.replace(&placeholder, &args[arg_index]);
-                arg_index += 1;
-            }
-        }
+        let template = "kubectl logs $1:-web -n #namespace:-default";
+        assert_eq!(
+            resolve(template, &HashMap::new(), &[]),
+            "kubectl logs web -n default"
+        );
+        assert_eq!(
+            resolve(template, &vars(&[("#namespace", "prod")]), &args(&["api"])),
+            "kubectl logs api -n prod"
+        );
+    }
 
-        Ok(resolved)
+    #[test]
+    fn test_quoted_fallback_default_stays_one_argument() {
+        let resolved = resolve(r#"sh -c #1:-"echo hello world""#, &HashMap::new(), &[]);
+        assert_eq!(resolved, r#"sh -c "echo hello world""#);
+        assert_eq!(shell_words::split(&resolved).unwrap().len(), 3);
     }
 
-    fn load_variables(&self, context: &str) -> Result<Variables> {
-        let variables_file = self
-            .config_dir
-            .join("variables")
-            .join(format!("{context}.yaml"));
+    #[test]
+    fn test_variable_listing_round_trip() {
+        let entry = |value: Option<VarValue>, used_by: &[&str]| ListedVariable {
+            value,
+            secret: false,
+            expires_at: None,
+            expired: false,
+            description: None,
+            updated_at: None,
+            used_by: used_by.iter().map(|command| command.to_string()).collect(),
+            inherited_from: None,
+        };
+        let mut token = entry(Some(VarValue::from(SECRET_MASK)), &[]);
+        token.secret = true;
+        token.expires_at = Some("2026-01-02T03:04:05Z".parse().unwrap());
+        let mut region = entry(Some(VarValue::from("eu-west-1")), &[]);
+        region.inherited_from = Some("staging".to_string());
+        let listing = VariableListing {
+            context: "prod".to_string(),
+            vars: BTreeMap::from([
+                (
+                    "#ns".to_string(),
+                    entry(Some(VarValue::from("web")), &["pods"]),
+                ),
+                (
+                    "#hosts".to_string(),
+                    entry(Some(vec!["a".to_string(), "b".to_string()].into()), &[]),
+                ),
+                ("#token".to_string(), token),
+                ("#region".to_string(), region),
+                ("#1".to_string(), entry(None, &["logs"])),
+            ]),
+            commands: BTreeMap::from([(
+                "pods".to_string(),
+                BTreeMap::from([("#1".to_string(), entry(Some("api".into()), &["pods"]))]),
+            )]),
+        };
 
-        if variables_file.exists() {
-            let contents =
-                fs::read_to_string(&variables_file).context("Failed to read variables file")?;
-            serde_yaml::from_str(&contents).context("Failed to parse variables file")
-        } else {
-            Ok(Variables::default())
-        }
+        let json: serde_json::Value = serde_json::to_value(&listing).unwrap();
+        assert_eq!(json["context"], "prod");
+        assert_eq!(json["vars"]["#ns"]["value"], "web");
+        assert_eq!(
+            json["vars"]["#hosts"]["value"],
+            serde_json::json!(["a", "b"])
+        );
+        assert_eq!(json["vars"]["#token"]["expires_at"], "2026-01-02T03:04:05Z");
+        assert!(json["vars"]["#1"]["value"].is_null());
+        assert_eq!(json["vars"]["#region"]["inherited_from"], "staging");
+        assert!(json["vars"]["#ns"]["inherited_from"].is_null());
+        assert_eq!(
+            json["commands"]["pods"]["#1"]["used_by"],
+            serde_json::json!(["pods"])
+        );
+        assert_eq!(
+            serde_json::from_value::<VariableListing>(json).unwrap(),
+            listing
+        );
     }
 
-    fn save_variables(&self, context: &str, variables: &Variables) -> Result<()> {
-        let variables_file = self
-            .config_dir
-            .join("variables")
-            .join(format!("{context}.yaml"));
-        let yaml_content =
-            serde_yaml::to_string(variables).context("Failed to serialize variables")?;
-        fs::write(&variables_file, yaml_content).context("Failed to write variables file")?;
-        Ok(())
+    #[test]
+    fn test_validate_template() {
+        assert!(VariableManager::validate_template("kubectl logs -n #1 #2 #name -f").is_empty());
+        assert!(VariableManager::validate_template("echo #1 #*").is_empty());
+
+        let warnings = VariableManager::validate_template("cp #1 #3");
+        assert_eq!(
+            warnings,
+            vec![TemplateWarning::SkippedPosition {
+                placeholder: "#3".to_string(),
+                missing: "#2".to_string(),
+            }]
+        );
+        assert_eq!(
+            warnings[0].to_string(),
+            "#3 is used but #2 isn't, so arguments won't line up with the numbers"
+        );
+
+        assert_eq!(
+            VariableManager::validate_template("run #1 #* #2"),
+            vec![TemplateWarning::AfterRest {
+                placeholder: "#2".to_string(),
+                rest: "#*".to_string(),
+            }]
+        );
+        assert!(matches!(
+            VariableManager::validate_template("echo ${HOME").as_slice(),
+            [TemplateWarning::UnknownSyntax(_)]
+        ));
+        assert!(matches!(
+            VariableManager::validate_template("echo #1|nope").as_slice(),
+            [TemplateWarning::UnknownFilter(_)]
+        ));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::config::ConfigManager;
-    use tempfile::TempDir;
+    #[test]
+    fn test_missing_placeholders() {
+        let set = vars(&[("#set", "value")]);
+        let no_defaults = BTreeMap::new();
+        let sources = Sources {
+            vars: &set,
+            args: &[],
+            defaults: &no_defaults,
+            env: &no_env,
+            quote: None,
+        };
+        let missing = missing_in_template(
+            "kubectl logs -n #1 #2 $1 #name #2 #other:-x #set ${UNSET_ENV}",
+            &sources,
+        );
+        assert_eq!(missing, vec!["#1", "#2", "$1", "#name"]);
+
+        // Arguments and defaults (including `$N` keys) fill placeholders
+        let defaults = BTreeMap::from([("$1".to_string(), "answer".to_string())]);
+        let sources = Sources {
+            vars: &HashMap::new(),
+            args: &args(&["a"]),
+            defaults: &defaults,
+            env: &no_env,
+            quote: None,
+        };
+        assert_eq!(missing_in_template("#1 #2 $1", &sources), vec!["#2"]);
+        assert_eq!(
+            resolve_with_defaults("#1 $1", &HashMap::new(), &[], &defaults).unwrap(),
+            "#1 answer"
+        );
+    }
 
     #[test]
-    fn test_variable_operations() {
-        let temp_dir = TempDir::new().unwrap();
+    fn test_environment_expansion() {
+        let env = |name: &str| (name == "BUCKET").then(|| "from-env".to_string());
+        let no_defaults = BTreeMap::new();
+        let stored = vars(&[("#1", "key")]);
+        let sources = Sources {
+            vars: &stored,
+            args: &[],
+            defaults: &no_defaults,
+            env: &env,
+            quote: None,
+        };
+        assert_eq!(
+            resolve_template("aws s3 ls s3://${BUCKET}/#1", &sources, false).unwrap(),
+            "aws s3 ls s3://from-env/key"
+        );
 
-        // Create a config manager with the temp directory
-        let config_dir = temp_dir.path().join(".config").join("doo");
-        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
-        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
+        // Unset variables are left untouched unless strict, defaults apply
+        assert_eq!(
+            resolve_template("echo ${MISSING} ${REGION:-eu}", &sources, false).unwrap(),
+            "echo ${MISSING} eu"
+        );
+        let err = resolve_template("echo ${MISSING} ${OTHER}", &sources, true).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Environment variables not set: ${MISSING}, ${OTHER}"
+        );
 
-        // Test setting and getting variables
-        variable_manager
-            .set_variable("test", "#1", "production")
-            .unwrap();
+        // Escaped `${` is literal, even in strict mode
         assert_eq!(
-            variable_manager.get_variable("test", "#1").unwrap(),
-            Some("production".to_string())
+            resolve_template("echo $${BUCKET}", &sources, true).unwrap(),
+            "echo ${BUCKET}"
         );
+    }
 
-        // Test variable resolution
-        let resolved = variable_manager
-            .resolve_variables("test", "kubectl get pods -n #1", &[])
-            .unwrap();
-        assert_eq!(resolved, "kubectl get pods -n production");
+    #[test]
+    fn test_doo_variables_override_environment() {
+        let env = |_: &str| Some("from-env".to_string());
+        let no_defaults = BTreeMap::new();
+        let stored = vars(&[("#BUCKET", "from-doo")]);
+        let sources = Sources {
+            vars: &stored,
+            args: &[],
+            defaults: &no_defaults,
+            env: &env,
+            quote: None,
+        };
+        assert_eq!(
+            resolve_template("s3://${BUCKET}", &sources, true).unwrap(),
+            "s3://from-doo"
+        );
     }
 
     #[test]
-    fn test_positional_args() {
-        let temp_dir = TempDir::new().unwrap();
+    fn test_dynamic_variables_run_once() {
+        let calls = std::cell::RefCell::new(Vec::new());
+        let run = |command: &str| {
+            calls.borrow_mut().push(command.to_string());
+            Ok(format!("{command}-output\n"))
+        };
+        let mut stored = vars(&[
+            ("#ctx", "!cmd:kubectl config current-context"),
+            ("#same", "!cmd: kubectl config current-context"),
+            ("#unused", "!cmd:never"),
+            ("#plain", "value"),
+        ]);
+        evaluate_dynamic(&mut stored, "#ctx #ctx ${same} #plain", &run).unwrap();
 
-        let config_dir = temp_dir.path().join(".config").join("doo");
-        let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
-        let variable_manager = VariableManager::new(&config_manager).unwrap();
+        assert_eq!(*calls.borrow(), vec!["kubectl config current-context"]);
+        assert_eq!(stored["#ctx"], "kubectl config current-context-output");
+        assert_eq!(stored["#same"], "kubectl config current-context-output");
+        assert_eq!(stored["#unused"], "!cmd:never");
+        assert_eq!(stored["#plain"], "value");
+    }
 
-        let resolved = variable_manager
-            .resolve_variables(
-                "default",
-                "kubectl logs #1 #2",
-                &["pod-name".to_string(), "-f".to_string()],
-            )
-            .unwrap();
-        assert_eq!(resolved, "kubectl logs pod-name -f");
+    #[test]
+    fn test_variables_referencing_variables() {
+        let mut stored = vars(&[
+            ("#url", "https://#host/api"),
+            ("#host", "#name:${PORT}"),
+            ("#name", "example.com"),
+            ("#PORT", "8443"),
+            ("#args", "#1 $1 #* #unset"),
+            ("#1", "first"),
+            ("#escaped", "##host"),
+        ]);
+        expand_references(&mut stored, "curl #url #args #escaped", &HashSet::new()).unwrap();
+        assert_eq!(stored["#url"], "https://example.com:8443/api");
+        assert_eq!(stored["#host"], "example.com:8443");
+        // Runtime arguments and unset variables are not expanded
+        assert_eq!(stored["#args"], "first $1 #* #unset");
+        // Values without references are taken as they are
+        assert_eq!(stored["#escaped"], "##host");
+
+        let sources = Sources {
+            vars: &stored,
+            args: &args(&["arg"]),
+            defaults: &BTreeMap::new(),
+            env: &no_env,
+            quote: None,
+        };
+        assert_eq!(
+            resolve_template("curl #url #args", &sources, false).unwrap(),
+            "curl https://example.com:8443/api first $1 #* #unset"
+        );
     }
 
     #[test]
-    fn test_dollar_positional_args() {
-        let temp_dir = TempDir::new().unwrap();
+    fn test_reference_cycles_are_errors() {
+        let mut stored = vars(&[("#a", "x-#a")]);
+        let err = expand_references(&mut stored, "echo #a", &HashSet::new()).unwrap_err();
+        assert_eq!(err.to_string(), "Variable #a refers to itself: #a -> #a");
+
+        let mut stored = vars(&[("#a", "#b"), ("#b", "${c}"), ("#c", "#a"), ("#d", "#a")]);
+        let err = expand_references(&mut stored, "echo #d", &HashSet::new()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Variable #a refers to itself: #d -> #a -> #b -> #c -> #a"
+        );
+
+        // Unused cycles don't matter
+        let mut stored = vars(&[("#a", "#b"), ("#b", "#a"), ("#ok", "fine")]);
+        expand_references(&mut stored, "echo #ok", &HashSet::new()).unwrap();
+
+        let pairs: Vec<(String, String)> = (0..=MAX_REFERENCE_DEPTH)
+            .map(|i| (format!("#v{i}"), format!("#v{}", i + 1)))
+            .collect();
+        let mut stored: HashMap<String, String> = pairs.into_iter().collect();
+        let err = expand_references(&mut stored, "echo #v0", &HashSet::new()).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Variable references are nested more than"));
+    }
+
+    #[test]
+    fn test_dynamic_variables_behind_references() {
+        let run = |command: &str| Ok(format!("#{command}\n"));
+        let mut stored = vars(&[("#url", "https://#host"), ("#host", "!cmd:url")]);
+        let dynamic = evaluate_dynamic(&mut stored, "curl #url", &run).unwrap();
+        expand_references(&mut stored, "curl #url", &dynamic).unwrap();
+        // Command output is not expanded again
+        assert_eq!(stored["#url"], "https://#url");
+    }
+
+    #[test]
+    fn test_failing_dynamic_variable_names_the_variable() {
+        let run = |_: &str| Err(anyhow!("Command failed: no such context"));
+        let mut stored = vars(&[("#ctx", "!cmd:kubectl config current-context")]);
+        let err = evaluate_dynamic(&mut stored, "echo #ctx", &run).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Dynamic variable #ctx ('kubectl config current-context') failed: \
+             Command failed: no such context"
+        );
+    }
 
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_dynamic_variable() {
+        let temp_dir = TempDir::new().unwrap();
         let config_dir = temp_dir.path().join(".config").join("doo");
         let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
-        let variable_manager = VariableManager::new(&config_manager).unwrap();
+        let mut variable_manager = VariableManager::new(&config_manager).unwrap();
 
-        // Test $1, $2 direct positional argument replacement
-        let resolved = variable_manager
-            .resolve_variables(
-                "default",
-                "kubectl -n $1 get pods",
-                &["console".to_string()],
-            )
+        variable_manager
+            .set_variable("default", "pod", "!cmd:echo  web-1 ")
             .unwrap();
-        assert_eq!(resolved, "kubectl -n console get pods");
-
-        // Test multiple $1, $2 arguments
         let resolved = variable_manager
-            .resolve_variables(
-                "default",
-                "kubectl logs $1 -n $2",
-                &["my-pod".to_string(), "staging".to_string()],
-            )
+            .resolve_variables("default", "kubectl logs #pod", &[])
             .unwrap();
-        assert_eq!(resolved, "kubectl logs my-pod -n staging");
+        assert_eq!(resolved, "kubectl logs web-1");
     }
 
     #[test]
-    fn test_mixed_placeholders() {
-        let temp_dir = TempDir::new().unwrap();
+    fn test_configured_defaults() {
+        let defaults = BTreeMap::from([
+            ("#1".to_string(), "from-config".to_string()),
+            ("2".to_string(), "bare-key".to_string()),
+        ]);
+        assert_eq!(
+            resolve_with_defaults("#1 #2", &HashMap::new(), &[], &defaults).unwrap(),
+            "from-config bare-key"
+        );
+        // Inline defaults, stored variables and arguments all take precedence
+        assert_eq!(
+            resolve_with_defaults("#1:=inline #2", &HashMap::new(), &args(&["a"]), &defaults)
+                .unwrap(),
+            "a bare-key"
+        );
+        assert_eq!(
+            resolve_with_defaults("#1:=inline", &HashMap::new(), &[], &defaults).unwrap(),
+            "inline"
+        );
+        assert_eq!(
+            resolve_with_defaults("#2", &vars(&[("#2", "stored")]), &[], &defaults).unwrap(),
+            "stored"
+        );
+    }
 
+    #[test]
+    fn test_named_variables() {
+        let temp_dir = TempDir::new().unwrap();
         let config_dir = temp_dir.path().join(".config").join("doo");
         let config_manager = ConfigManager::new_with_dir(config_dir).unwrap();
         let mut variable_manager = VariableManager::new(&config_manager).unwrap();
 
-        // Set a persistent variable #1
+        // Names are accepted with or without the leading '#'
         variable_manager
-            .set_variable("test", "#1", "production")
+            .set_variable("test", "namespace", "staging")
+            .unwrap();
+        variable_manager
+            .set_variable("test", "#cluster", "eu-1")
             .unwrap();
+        assert_eq!(
+            variable_manager.get_variable("test", "#namespace").unwrap(),
+            Some("staging".to_string())
+        );
 
-        // Test mixing persistent #1 with direct $1 arguments
+        // Named placeholders never consume positional arguments
         let resolved = variable_manager
             .resolve_variables(
                 "test",
-                "kubectl -n #1 get pods $1",
-                &["--watch".to_string()],
+                "kubectl --context #cluster -n #namespace logs #1",
+                &args(&["my-pod"]),
             )
             .unwrap();
-        assert_eq!(resolved, "kubectl -n production get pods --watch");
+        assert_eq!(resolved, "kubectl --context eu-1 -n staging logs my-pod");
+    }
+
+    #[test]
+    fn test_missing_named_variables_are_listed() {
+        let err = resolve_with_defaults(
+            "kubectl --context #cluster -n #namespace get #kind #cluster",
+            &vars(&[("#kind", "pods")]),
+            &args(&["ignored"]),
+            &BTreeMap::new(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Missing values for variables: #cluster, #namespace. Set them with 'doo var <name> <value>', or write ## for a literal #"
+        );
+
+        // Defaults satisfy named placeholders too
+        assert_eq!(
+            resolve("-n #namespace:=default", &HashMap::new(), &[]),
+            "-n default"
+        );
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn prop_literals_preserved(
+            parts in proptest::collection::vec("[a-zA-Z -]{0,8}", 1..6),
+            value in "[a-z]{1,8}",
+        ) {
+            // Literal segments without sigils survive byte-for-byte around placeholders
+            let template = parts.join("#1");
+            let expected = parts.join(&value);
+            proptest::prop_assert_eq!(
+                resolve(&template, &vars(&[("#1", &value)]), &[]),
+                expected
+            );
+        }
+
+        #[test]
+        fn prop_no_placeholder_survives_with_values(
+            numbers in proptest::collection::vec(1usize..20, 0..8),
+        ) {
+            let template = numbers
+                .iter()
+                .map(|n| format!("#{n} ${n}"))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let runtime_args: Vec<String> = (0..20).map(|i| format!("v{i}")).collect();
+            let resolved = resolve(&template, &HashMap::new(), &runtime_args);
+            proptest::prop_assert!(!resolved.contains('#'));
+            proptest::prop_assert!(!resolved.contains('$'));
+        }
     }
 }
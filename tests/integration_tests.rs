@@ -1,7 +1,33 @@
 use anyhow::Result;
-use doo::{ConfigManager, ContextManager, VariableManager};
+use doo::variables::{VarValue, VariableListing};
+use doo::{CommandExecutor, ConfigManager, ContextManager, VariableManager};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::{Command, Output};
+use std::time::{Duration, Instant};
 use tempfile::TempDir;
 
+/// Run the doo binary against `config_dir`
+fn doo(config_dir: &Path, args: &[&str]) -> Output {
+    doo_with_env(config_dir, args, &[])
+}
+
+/// Like [`doo`], with additional environment variables
+fn doo_with_env(config_dir: &Path, args: &[&str], env: &[(&str, &str)]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_doo"))
+        .args(args)
+        .env("DOO_CONFIG_DIR", config_dir)
+        .env("NO_COLOR", "1")
+        .env_remove("DOO_PASSPHRASE")
+        .envs(env.iter().copied())
+        .output()
+        .expect("failed to run doo")
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
 #[test]
 fn test_full_workflow() -> Result<()> {
     let temp_dir = TempDir::new()?;
@@ -14,7 +40,7 @@ fn test_full_workflow() -> Result<()> {
 
     // Test context switching
     assert_eq!(context_manager.current_context(), "default");
-    context_manager.switch_context("test")?;
+    context_manager.switch_context("test", None)?;
     assert_eq!(context_manager.current_context(), "test");
 
     // Test variable management
@@ -39,6 +65,27 @@ fn test_full_workflow() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_multi_digit_placeholders() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join(".config").join("doo");
+
+    let config_manager = ConfigManager::new_with_dir(config_dir)?;
+    let mut variable_manager = VariableManager::new(&config_manager)?;
+    for (name, value) in [("#1", "one"), ("#10", "ten"), ("#12", "twelve")] {
+        variable_manager.set_variable("default", name, value)?;
+    }
+
+    let resolved = variable_manager.resolve_variables(
+        "default",
+        "echo #1 #2 #10 #12",
+        &["two".to_string()],
+    )?;
+    assert_eq!(resolved, "echo one two ten twelve");
+
+    Ok(())
+}
+
 #[test]
 fn test_command_management() -> Result<()> {
     let temp_dir = TempDir::new()?;
@@ -74,7 +121,7 @@ fn test_context_isolation() -> Result<()> {
     variable_manager.set_variable("default", "#1", "default-value")?;
 
     // Switch to test context and set different variable
-    context_manager.switch_context("test")?;
+    context_manager.switch_context("test", None)?;
     variable_manager.set_variable("test", "#1", "test-value")?;
 
     // Verify isolation
@@ -89,3 +136,2488 @@ fn test_context_isolation() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_backup_restore_round_trip() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join(".config").join("doo");
+
+    let mut config_manager = ConfigManager::new_with_dir(config_dir.clone())?;
+    let mut context_manager = ContextManager::new(&config_manager)?;
+    let mut variable_manager = VariableManager::new(&config_manager)?;
+
+    config_manager.add_command("custom", "echo #1")?;
+    context_manager.switch_context("staging", None)?;
+    variable_manager.set_variable("staging", "#1", "staging-ns")?;
+    variable_manager.set_variable("default", "#1", "default-ns")?;
+    std::fs::write(
+        config_dir.join("configs").join("docker.yaml"),
+        "commands:\n  docker-ps: docker ps\n",
+    )?;
+
+    let archive = temp_dir.path().join("backup.tar.gz");
+    let file_count = config_manager.create_backup(&archive, true)?;
+    assert_eq!(file_count, 5);
+
+    // Restore into a fresh config directory
+    let restored_dir = temp_dir.path().join("restored");
+    let restored_manager = ConfigManager::new_with_dir(restored_dir.clone())?;
+    assert_eq!(restored_manager.restore_backup(&archive, false)?, 5);
+
+    for file in [
+        "config.yaml",
+        "current_context",
+        "configs/docker.yaml",
+        "variables/staging.yaml",
+        "variables/default.yaml",
+    ] {
+        assert_eq!(
+            std::fs::read_to_string(config_dir.join(file))?,
+            std::fs::read_to_string(restored_dir.join(file))?,
+            "{file} differs after restore"
+        );
+    }
+
+    // Merge only fills in missing files
+    let merge_dir = temp_dir.path().join("merge");
+    let merge_manager = ConfigManager::new_with_dir(merge_dir.clone())?;
+    std::fs::create_dir_all(merge_dir.join("variables"))?;
+    std::fs::write(
+        merge_dir.join("variables").join("default.yaml"),
+        "vars: {}\n",
+    )?;
+    assert_eq!(merge_manager.restore_backup(&archive, true)?, 4);
+    assert_eq!(
+        std::fs::read_to_string(merge_dir.join("variables").join("default.yaml"))?,
+        "vars: {}\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_backup_restore_round_trip_with_json_main_config() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(&config_dir)?;
+    let json = r#"{"commands": {"hi": "echo hi"}}"#;
+    std::fs::write(config_dir.join("config.json"), json)?;
+    let config_manager = ConfigManager::new_with_dir(config_dir.clone())?;
+    assert!(config_manager.get_command("hi")?.is_some());
+
+    let archive = temp_dir.path().join("backup.tar.gz");
+    config_manager.create_backup(&archive, true)?;
+
+    let restored_dir = temp_dir.path().join("restored");
+    let restored_manager = ConfigManager::new_with_dir(restored_dir.clone())?;
+    restored_manager.restore_backup(&archive, false)?;
+    assert_eq!(
+        std::fs::read_to_string(restored_dir.join("config.json"))?,
+        json
+    );
+
+    // Replacing the state also replaces a JSON main config
+    std::fs::write(config_dir.join("config.json"), r#"{"commands": {}}"#)?;
+    config_manager.restore_backup(&archive, false)?;
+    assert_eq!(
+        std::fs::read_to_string(config_dir.join("config.json"))?,
+        json
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_restore_of_a_corrupt_backup_keeps_the_current_state() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join(".config").join("doo");
+    let mut config_manager = ConfigManager::new_with_dir(config_dir.clone())?;
+    let mut variable_manager = VariableManager::new(&config_manager)?;
+    config_manager.add_command("custom", "echo #1")?;
+    variable_manager.set_variable("default", "#1", "kept")?;
+
+    let archive = temp_dir.path().join("backup.tar.gz");
+    config_manager.create_backup(&archive, true)?;
+    let bytes = std::fs::read(&archive)?;
+    let truncated = temp_dir.path().join("truncated.tar.gz");
+    std::fs::write(&truncated, &bytes[..bytes.len() / 2])?;
+    let garbage = temp_dir.path().join("garbage.tar.gz");
+    std::fs::write(&garbage, "not an archive")?;
+    // A valid archive without any doo files
+    let unrelated = temp_dir.path().join("unrelated.tar.gz");
+    {
+        let file = std::fs::File::create(&unrelated)?;
+        let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        let notes = temp_dir.path().join("notes.txt");
+        std::fs::write(&notes, "hello")?;
+        builder.append_path_with_name(&notes, "notes.txt")?;
+        builder.into_inner()?.finish()?;
+    }
+
+    let before = |file: &str| std::fs::read_to_string(config_dir.join(file));
+    let (config, variables) = (before("config.yaml")?, before("variables/default.yaml")?);
+    for broken in [&truncated, &garbage, &unrelated] {
+        assert!(
+            config_manager.restore_backup(broken, false).is_err(),
+            "{broken:?} was restored"
+        );
+        assert_eq!(before("config.yaml")?, config);
+        assert_eq!(before("variables/default.yaml")?, variables);
+    }
+    // No staging directories are left behind
+    let leftovers: Vec<_> = std::fs::read_dir(&config_dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            name.starts_with(".restore-") || name.starts_with(".previous-")
+        })
+        .collect();
+    assert!(leftovers.is_empty(), "{leftovers:?}");
+
+    // The intact archive still restores
+    variable_manager.set_variable("default", "#1", "changed")?;
+    assert!(config_manager.restore_backup(&archive, false)? > 0);
+    assert_eq!(before("variables/default.yaml")?, variables);
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_var_list_and_remove() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+
+    assert!(doo(&config_dir, &["var", "#1", "production"])
+        .status
+        .success());
+    assert!(doo(&config_dir, &["var", "unused", "value"])
+        .status
+        .success());
+
+    let output = doo(&config_dir, &["var", "list"]);
+    assert!(output.status.success());
+    let listing = stdout(&output);
+    assert!(listing.contains("Variables in context default"));
+    // The default main config uses #1 in describe, logs, pods and watch
+    let line = listing.lines().find(|l| l.contains("#1")).unwrap();
+    assert!(line.contains("production") && line.contains("describe, logs, pods, watch"));
+    let line = listing.lines().find(|l| l.contains("#unused")).unwrap();
+    assert!(line.trim_end().ends_with('-'));
+    // #2 is used by the main config but not set
+    assert!(listing.contains("Placeholders without a value"));
+    assert!(listing.lines().any(|l| l.trim_start().starts_with("#2")));
+
+    let output = doo(&config_dir, &["var", "remove", "unused"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("Variable #unused removed from context default"));
+    assert!(!stdout(&doo(&config_dir, &["var", "list"])).contains("#unused"));
+
+    // Removing a missing variable fails
+    assert!(!doo(&config_dir, &["var", "remove", "unused"])
+        .status
+        .success());
+
+    // Other contexts can be listed explicitly
+    let output = doo(&config_dir, &["var", "list", "--context", "staging"]);
+    assert!(stdout(&output).contains("No variables set in context staging"));
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_search_by_tag() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+
+    let output = doo(
+        &config_dir,
+        &[
+            "command",
+            "add",
+            "events",
+            "kubectl get events -n #1",
+            "--tag",
+            "kubernetes",
+            "--tag",
+            "debugging",
+        ],
+    );
+    assert!(output.status.success());
+    let output = doo(
+        &config_dir,
+        &["command", "add", "df", "df -h", "--tag", "disk"],
+    );
+    assert!(output.status.success());
+
+    let output = doo(&config_dir, &["search", "--tags", "kubernetes"]);
+    let listing = stdout(&output);
+    assert!(listing.contains("events") && listing.contains("(kubernetes, debugging)"));
+    assert!(!listing.contains("df -h"));
+    // The default config has no tagged commands
+    assert!(!listing.contains("pods"));
+
+    let results = json_lines(&doo(
+        &config_dir,
+        &["-o", "json", "search", "--tags", "disk"],
+    ));
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0]["name"], "df");
+    assert_eq!(results[0]["tags"], serde_json::json!(["disk"]));
+
+    // Tags are part of the text search, and a query narrows a tag down
+    assert!(stdout(&doo(&config_dir, &["search", "debugging"])).contains("events"));
+    let output = doo(&config_dir, &["search", "--tags", "kubernetes", "nothing"]);
+    assert!(stdout(&output).contains("No commands tagged 'kubernetes' match 'nothing'"));
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_history() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+
+    let output = doo(&config_dir, &["history"]);
+    assert!(stdout(&output).contains("No commands in the history yet"));
+
+    assert!(doo(
+        &config_dir,
+        &["command", "add", "hello", "echo hello #token"]
+    )
+    .status
+    .success());
+    assert!(
+        doo(&config_dir, &["command", "add", "fail", "sh -c \"exit 3\""])
+            .status
+            .success()
+    );
+    assert!(doo(&config_dir, &["var", "--secret", "token", "s3cr3t"])
+        .status
+        .success());
+    assert!(doo(&config_dir, &["hello"]).status.success());
+    assert_eq!(doo(&config_dir, &["fail"]).status.code(), Some(3));
+    // Dry runs are not recorded
+    assert!(doo(&config_dir, &["--dry-run", "hello"]).status.success());
+
+    let listing = stdout(&doo(&config_dir, &["history"]));
+    assert!(listing.contains("hello") && listing.contains("✗ 3"));
+    assert!(!listing.contains("s3cr3t"));
+
+    let entries = json_lines(&doo(&config_dir, &["-o", "json", "history"]));
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["name"], "hello");
+    assert_eq!(entries[0]["exit_code"], 0);
+    assert_eq!(entries[1]["command"], "sh -c \"exit 3\"");
+    assert_eq!(entries[1]["exit_code"], 3);
+    let entries = json_lines(&doo(
+        &config_dir,
+        &["-o", "json", "history", "--limit", "1"],
+    ));
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["name"], "fail");
+
+    let output = doo(&config_dir, &["history", "clear"]);
+    assert!(stdout(&output).contains("Removed 2 entries"));
+    assert!(json_lines(&doo(&config_dir, &["-o", "json", "history"])).is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_var_diff() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+
+    for (context, replicas) in [("staging", "1"), ("prod", "1")] {
+        assert!(doo(&config_dir, &["context", context]).status.success());
+        assert!(doo(&config_dir, &["var", "replicas", replicas])
+            .status
+            .success());
+    }
+    let output = doo(&config_dir, &["var", "diff", "staging", "prod"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).contains("have the same variables"));
+
+    assert!(doo(&config_dir, &["var", "replicas", "3"]).status.success());
+    assert!(doo(&config_dir, &["var", "--secret", "token", "s3cr3t"])
+        .status
+        .success());
+    let output = doo(&config_dir, &["var", "diff", "staging", "prod"]);
+    assert_eq!(output.status.code(), Some(1));
+    let text = stdout(&output);
+    assert!(text.contains("Only in prod:") && text.contains("#token"));
+    assert!(text.contains("1 → 3"));
+    assert!(!text.contains("s3cr3t"));
+
+    let output = doo(
+        &config_dir,
+        &["var", "diff", "staging", "prod", "-o", "json"],
+    );
+    assert_eq!(output.status.code(), Some(1));
+    let diff: serde_json::Value = serde_json::from_str(&stdout(&output))?;
+    assert_eq!(diff["changed"]["#replicas"]["b"]["value"], "3");
+    assert_eq!(diff["only_in_b"]["#token"]["value"], "*****");
+    assert_eq!(diff["only_in_a"], serde_json::json!({}));
+
+    let output = doo(&config_dir, &["var", "diff", "staging", "prdo"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Context 'prdo' does not exist"));
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_encrypted_context() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    let passphrase = [("DOO_PASSPHRASE", "correct horse")];
+
+    assert!(doo(&config_dir, &["context", "jump"]).status.success());
+    assert!(doo(&config_dir, &["var", "ns", "ops"]).status.success());
+    let output = doo(&config_dir, &["context", "encrypt", "jump"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Set DOO_PASSPHRASE"));
+
+    let output = doo_with_env(&config_dir, &["context", "encrypt", "jump"], &passphrase);
+    assert!(output.status.success());
+    let variables_file = config_dir.join("variables").join("jump.yaml");
+    assert!(!std::fs::read_to_string(&variables_file)?.contains("ops"));
+
+    // Without a terminal the passphrase has to come from the environment
+    let output = doo(&config_dir, &["var", "get", "ns"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Context 'jump' is encrypted; set DOO_PASSPHRASE"));
+    let output = doo_with_env(
+        &config_dir,
+        &["var", "get", "ns"],
+        &[("DOO_PASSPHRASE", "wrong")],
+    );
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Wrong passphrase (from DOO_PASSPHRASE) for context 'jump'"));
+
+    assert!(
+        doo_with_env(&config_dir, &["var", "region", "eu"], &passphrase)
+            .status
+            .success()
+    );
+    let output = doo_with_env(&config_dir, &["var", "get", "ns"], &passphrase);
+    assert_eq!(stdout(&output).trim(), "ops");
+
+    assert!(
+        doo_with_env(&config_dir, &["context", "decrypt", "jump"], &passphrase)
+            .status
+            .success()
+    );
+    let contents = std::fs::read_to_string(&variables_file)?;
+    assert!(contents.contains("ops") && contents.contains("eu"));
+    assert_eq!(
+        stdout(&doo(&config_dir, &["var", "get", "region"])).trim(),
+        "eu"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_status() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(config_dir.join("configs"))?;
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        "commands:\n  hello: 'echo #greeting'\n  ps: ps aux\n",
+    )?;
+    std::fs::write(
+        config_dir.join("configs").join("team.yaml"),
+        "commands:\n  ps: docker ps\norigin:\n  repo: team/commands\n  import_type: Public\n",
+    )?;
+
+    assert!(doo(&config_dir, &["context", "staging"]).status.success());
+    assert!(doo(&config_dir, &["var", "greeting", "hi"])
+        .status
+        .success());
+    assert!(doo(&config_dir, &["var", "--secret", "token", "s3cr3t"])
+        .status
+        .success());
+    assert!(doo(&config_dir, &["hello"]).status.success());
+
+    let status = stdout(&doo(&config_dir, &["status"]));
+    assert!(status.contains("Context   staging"));
+    assert!(status.contains("Variables 2 set, 1 secret"));
+    assert!(status.contains("Configs   2 (main, team)"));
+    assert!(status.contains("team  team/commands  sync time unknown"));
+    assert!(status.contains("ps defined in main, team"));
+    assert!(!status.contains("Missing values"));
+
+    // A recently run command that would now miss a value is pointed out
+    assert!(doo(&config_dir, &["var", "remove", "greeting"])
+        .status
+        .success());
+    let status = stdout(&doo(&config_dir, &["status"]));
+    assert!(status.contains("Missing values in recently run commands"));
+    assert!(status.contains("hello #greeting"));
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_which() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(&config_dir)?;
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        "commands:\n  logs:\n    command: 'kubectl logs -n #ns #pod $1'\n    params:\n      pod:\n        name: pod\n        description: Pod to follow\n",
+    )?;
+    assert!(doo(&config_dir, &["var", "ns", "shop"]).status.success());
+
+    let output = doo(&config_dir, &["which", "logs"]);
+    assert!(output.status.success());
+    let info = stdout(&output);
+    assert!(info.contains("Source:   main"));
+    assert!(info.contains("#ns   shop"));
+    assert!(info.contains("set in default"));
+    assert!(info.contains("#pod  missing"));
+    assert!(info.contains("<pod>, Pod to follow"));
+
+    let output = doo(&config_dir, &["info", "logs", "-o", "json"]);
+    let info = &json_lines(&output)[0];
+    assert_eq!(info["source"], "main");
+    assert_eq!(info["context"], "default");
+    let placeholders = info["placeholders"].as_array().unwrap();
+    assert_eq!(placeholders.len(), 3);
+    assert_eq!(placeholders[0]["value"], "shop");
+    assert_eq!(placeholders[1]["source"], "missing");
+    assert_eq!(placeholders[1]["param"]["name"], "pod");
+    assert_eq!(placeholders[2]["source"], "argument");
+
+    assert!(!doo(&config_dir, &["which", "nope"]).status.success());
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_unreferenced_variable_warning() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(&config_dir)?;
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        "commands:\n  pods: 'kubectl get pods -n #namespace'\n  ps: ps aux\n",
+    )?;
+    let stderr = |output: &Output| String::from_utf8_lossy(&output.stderr).to_string();
+
+    let output = doo(&config_dir, &["var", "#ns", "shop"]);
+    assert!(output.status.success());
+    assert!(stderr(&output).contains("No command references #ns; did you mean #namespace?"));
+
+    let output = doo(&config_dir, &["var", "--quiet", "#ns", "shop"]);
+    assert!(output.status.success());
+    assert!(stderr(&output).is_empty());
+
+    let output = doo(&config_dir, &["var", "namespace", "shop"]);
+    assert!(stderr(&output).is_empty());
+    let output = doo(&config_dir, &["var", "#region", "eu", "#namespace", "shop"]);
+    assert!(stderr(&output).contains("No command references #region\n"));
+    assert!(!stderr(&output).contains("#namespace"));
+    let output = doo(
+        &config_dir,
+        &["var", "--command", "ps", "#namespace", "shop"],
+    );
+    assert!(stderr(&output).contains("Command ps doesn't reference #namespace"));
+
+    // Unknown commands get the same kind of suggestion
+    let output = doo(&config_dir, &["podz"]);
+    assert!(!output.status.success());
+    assert!(stdout(&output).contains("Did you mean 'pods'?"));
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_project_config() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(&config_dir)?;
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        "commands:\n  hello: echo hello from main\n",
+    )?;
+    let project = temp_dir.path().join("project");
+    let nested = project.join("src").join("deep");
+    std::fs::create_dir_all(&nested)?;
+    let doo_in = |dir: &Path, args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_doo"))
+            .args(args)
+            .current_dir(dir)
+            .env("DOO_CONFIG_DIR", &config_dir)
+            .env("NO_COLOR", "1")
+            .output()
+            .expect("failed to run doo")
+    };
+
+    let output = doo_in(&project, &["init"]);
+    assert!(output.status.success());
+    assert!(project.join(".doo.yaml").exists());
+    assert!(!doo_in(&project, &["init"]).status.success());
+
+    // The project's hello overrides main's without asking, from any subdirectory
+    let output = doo_in(&nested, &["hello"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("\nHello from this project\n"));
+    let output = doo_in(temp_dir.path(), &["hello"]);
+    assert!(stdout(&output).contains("\nhello from main\n"));
+
+    let status = stdout(&doo_in(&nested, &["status"]));
+    assert!(status.contains(&format!(
+        "Project   {}",
+        project.join(".doo.yaml").display()
+    )));
+    assert!(status.contains("Overridden by the project"));
+    assert!(!status.contains("Conflicts"));
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_json_config() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(config_dir.join("configs"))?;
+    std::fs::write(
+        config_dir.join("configs").join("tools.json"),
+        r##"{"commands": {"greet": {"command": "echo hello #name", "defaults": {"#name": "world"}}}}"##,
+    )?;
+
+    let output = doo(&config_dir, &["greet"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("\nhello world\n"));
+    let warning = String::from_utf8_lossy(&output.stderr);
+    assert!(warning.contains("Config 'tools' is JSON, which is deprecated"));
+    assert!(warning.contains("doo config convert tools --format yaml"));
+
+    assert!(doo(&config_dir, &["var", "name", "doo"]).status.success());
+    let output = doo(&config_dir, &["greet"]);
+    assert!(stdout(&output).contains("\nhello doo\n"));
+
+    let output = doo(
+        &config_dir,
+        &["config", "convert", "tools", "--format", "yaml"],
+    );
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+    assert!(!config_dir.join("configs").join("tools.json").exists());
+    assert!(config_dir.join("configs").join("tools.yaml").exists());
+
+    let output = doo(&config_dir, &["greet"]);
+    assert!(stdout(&output).contains("\nhello doo\n"));
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("deprecated"));
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_doctor() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(config_dir.join("configs"))?;
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        "commands:\n  hello: 'echo #greeting'\n",
+    )?;
+    // Nothing listens there, so the connectivity check warns right away
+    let offline = [("DOO_GITHUB_API_URL", "http://127.0.0.1:9")];
+
+    let output = doo_with_env(&config_dir, &["doctor"], &offline);
+    assert!(output.status.success());
+    let report = stdout(&output);
+    assert!(report.contains("✓ Main config"));
+    assert!(report.contains("⚠ Internet"));
+    assert!(report.contains("⚠ Placeholders"));
+    assert!(report.contains("hello: #greeting"));
+    assert!(report.contains("No problems"));
+
+    // A broken imported config fails, even though doo can't load its configs
+    std::fs::write(config_dir.join("configs").join("team.yaml"), "commands: [")?;
+    let output = doo_with_env(&config_dir, &["-o", "json", "doctor"], &offline);
+    assert_eq!(output.status.code(), Some(1));
+    let results = json_lines(&output);
+    let imported = results
+        .iter()
+        .find(|result| result["check"] == "Imported configs")
+        .unwrap();
+    assert_eq!(imported["status"], "fail");
+    assert!(imported["details"][0]
+        .as_str()
+        .unwrap()
+        .starts_with("configs/team.yaml: "));
+    assert!(results
+        .iter()
+        .all(|result| result["check"] != "Placeholders"));
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_var_descriptions() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+
+    let output = doo(
+        &config_dir,
+        &[
+            "var",
+            "region",
+            "eu-west-1",
+            "--desc",
+            "AWS region to deploy to",
+        ],
+    );
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("AWS region to deploy to"));
+
+    let listing = stdout(&doo(&config_dir, &["var", "list"]));
+    let mut lines = listing.lines().skip_while(|l| !l.contains("#region"));
+    let row = lines.next().unwrap();
+    assert!(row.contains("eu-west-1 (updated just now)"));
+    assert_eq!(lines.next().unwrap().trim(), "AWS region to deploy to");
+
+    let output = doo(&config_dir, &["-o", "json", "var", "list"]);
+    let listing: VariableListing = serde_json::from_str(&stdout(&output))?;
+    let region = &listing.vars["#region"];
+    assert_eq!(
+        region.description.as_deref(),
+        Some("AWS region to deploy to")
+    );
+    assert!(region.updated_at.is_some());
+
+    // A description belongs to a single variable
+    let output = doo(&config_dir, &["var", "#1", "a", "#2", "b", "--desc", "x"]);
+    assert!(!output.status.success());
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_var_get() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+
+    assert!(doo(&config_dir, &["var", "#1", "production"])
+        .status
+        .success());
+    assert!(doo(&config_dir, &["var", "--secret", "token", "s3cr3t"])
+        .status
+        .success());
+
+    let output = doo(&config_dir, &["var", "get", "#1"]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "production\n");
+    // Secrets are printed as they are, for use in scripts
+    assert_eq!(
+        stdout(&doo(&config_dir, &["var", "get", "token"])),
+        "s3cr3t\n"
+    );
+
+    // Values inherited from a parent context count as set
+    assert!(
+        doo(&config_dir, &["context", "staging", "--parent", "default"])
+            .status
+            .success()
+    );
+    assert_eq!(
+        stdout(&doo(&config_dir, &["var", "get", "1"])),
+        "production\n"
+    );
+
+    let output = doo(&config_dir, &["var", "get", "#2"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let output = doo(&config_dir, &["var", "get", "#1", "--context", "other"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_var_copy() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+
+    assert!(doo(&config_dir, &["var", "#1", "eu-ns"]).status.success());
+    assert!(doo(&config_dir, &["var", "cluster", "eu-1"])
+        .status
+        .success());
+    assert!(doo(&config_dir, &["context", "us"]).status.success());
+    assert!(doo(&config_dir, &["var", "cluster", "us-1"])
+        .status
+        .success());
+
+    // Without a terminal existing variables are kept
+    let output = doo(&config_dir, &["var", "copy", "default", "us"]);
+    assert!(output.status.success());
+    let out = stdout(&output);
+    assert!(out.contains("Copied from default to us:\n  #1\n"));
+    assert!(out.contains("Skipped, already set in us:\n  #cluster\n"));
+
+    let output = doo(
+        &config_dir,
+        &[
+            "var", "copy", "default", "us", "--only", "cluster", "--force",
+        ],
+    );
+    assert!(stdout(&output).contains("Copied from default to us:\n  #cluster\n"));
+    let listing = stdout(&doo(&config_dir, &["var", "list"]));
+    assert!(listing.contains("eu-1") && !listing.contains("us-1"));
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_context_show() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(&config_dir)?;
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        r##"commands:
+  deploy: "deploy #env #region"
+  pods: "kubectl get pods -n #ns"
+  hello: "echo hello $1"
+"##,
+    )?;
+    assert!(doo(&config_dir, &["context", "staging"]).status.success());
+
+    let out = stdout(&doo(&config_dir, &["context", "show"]));
+    assert!(out.contains("Context   staging"));
+    assert!(out.contains("staging.yaml (not created yet)"));
+    assert!(out.contains("deploy  #env, #region"));
+    assert!(out.contains("pods    #ns"));
+    assert!(!out.contains("hello"));
+
+    assert!(doo(&config_dir, &["var", "env", "prod"]).status.success());
+    assert!(doo(&config_dir, &["var", "ns", "web"]).status.success());
+    let output = doo(&config_dir, &["context", "show", "-o", "json"]);
+    let summary = &json_lines(&output)[0];
+    assert_eq!(summary["context"], "staging");
+    assert_eq!(summary["variables"], 2);
+    assert_eq!(summary["variables_file_exists"], true);
+    assert_eq!(summary["last_changed"]["name"], "#ns");
+    assert_eq!(
+        summary["unset"],
+        serde_json::json!([{ "command": "deploy", "placeholders": ["#region"] }])
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_eval() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path();
+    assert!(doo(config_dir, &["var", "ns", "shop", "-q"])
+        .status
+        .success());
+    assert!(
+        doo(config_dir, &["var", "token", "s3cr3t", "--secret", "-q"])
+            .status
+            .success()
+    );
+
+    let eval = |args: &[&str]| {
+        let mut full = vec!["eval"];
+        full.extend(args);
+        let output = doo(config_dir, &full);
+        assert!(output.status.success());
+        (
+            stdout(&output).trim_end().to_string(),
+            String::from_utf8_lossy(&output.stderr).to_string(),
+        )
+    };
+    let (out, err) = eval(&["kubectl -n #1 get pods $2 #ns", "--", "production", "-w"]);
+    assert_eq!(out, "kubectl -n production get pods -w shop");
+    assert!(err.is_empty());
+    assert_eq!(
+        eval(&["curl -H 'Token: #token' #ns|upper"]).0,
+        "curl -H 'Token: *****' SHOP"
+    );
+    assert_eq!(
+        eval(&["curl -H 'Token: #token'", "--reveal"]).0,
+        "curl -H 'Token: s3cr3t'"
+    );
+    assert_eq!(eval(&["echo #2:-none #ns?"]).0, "echo none shop");
+    // Only flags joined with `=` go with an empty optional placeholder
+    assert_eq!(eval(&["ls -la #dir?"]).0, "ls -la");
+    assert_eq!(eval(&["kubectl logs -f #pod?"]).0, "kubectl logs -f");
+    assert_eq!(
+        eval(&["kubectl logs --container=#c? -f"]).0,
+        "kubectl logs -f"
+    );
+
+    // Missing values are reported, the rest is still resolved
+    let (out, err) = eval(&["kubectl -n #ns logs #pod $1"]);
+    assert_eq!(out, "kubectl -n shop logs #pod $1");
+    assert!(err.contains("No value for #pod, $1 in context default"));
+    // Filters don't apply to placeholders without a value
+    assert_eq!(
+        eval(&["echo #x|upper #y|lower #3|upper"]).0,
+        "echo #x|upper #y|lower #3|upper"
+    );
+
+    // The context comes from -c like for commands
+    assert!(doo(config_dir, &["-c", "prod", "var", "ns", "live", "-q"])
+        .status
+        .success());
+    let output = doo(config_dir, &["-c", "prod", "eval", "#ns"]);
+    assert_eq!(stdout(&output).trim_end(), "live");
+
+    Ok(())
+}
+
+#[cfg(feature = "telemetry")]
+#[test]
+fn test_cli_timings_are_printed_on_failure() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path();
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        "commands:\n  fail: \"sh -c 'exit 3'\"\n",
+    )?;
+
+    let output = doo(config_dir, &["--timings", "missing"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Timings") && !stderr.contains("Error:"));
+
+    let output = doo(config_dir, &["--timings", "fail"]);
+    assert_eq!(output.status.code(), Some(3));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Timings") && stderr.contains("execute"));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cli_path_providers_need_opt_in() -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    let bin_dir = temp_dir.path().join("bin");
+    std::fs::create_dir_all(&config_dir)?;
+    std::fs::create_dir_all(&bin_dir)?;
+    let marker = temp_dir.path().join("listed");
+    let provider = bin_dir.join("doo-provider-catalog");
+    std::fs::write(
+        &provider,
+        format!(
+            "#!/bin/sh\ntouch '{}'\necho '[{{\"name\":\"svc\",\"template\":\"echo from-catalog\"}}]'\n",
+            marker.display()
+        ),
+    )?;
+    std::fs::set_permissions(&provider, std::fs::Permissions::from_mode(0o755))?;
+    let path = format!(
+        "{}:{}",
+        bin_dir.display(),
+        std::env::var("PATH").unwrap_or_default()
+    );
+    let env = [("PATH", path.as_str())];
+
+    std::fs::write(config_dir.join("config.yaml"), "commands:\n  hi: echo hi\n")?;
+    let output = doo_with_env(&config_dir, &["svc"], &env);
+    assert!(!output.status.success());
+    assert!(!marker.exists());
+
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        "discover_providers: true\ncommands:\n  hi: echo hi\n",
+    )?;
+    // Commands from config files run without asking the providers
+    let output = doo_with_env(&config_dir, &["hi"], &env);
+    assert!(output.status.success());
+    assert!(!marker.exists());
+
+    let output = doo_with_env(&config_dir, &["svc"], &env);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("from-catalog"));
+    assert!(marker.exists());
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cli_context_hooks() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path();
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        r#"commands: {}
+contexts:
+  prod:
+    on_switch: "echo use-context #name"
+  broken:
+    on_switch:
+      command: sh -c 'exit 3'
+      strict: true
+"#,
+    )?;
+
+    let output = doo(config_dir, &["context", "prod"]);
+    assert!(output.status.success());
+    let out = stdout(&output);
+    assert!(out.contains("on_switch: echo use-context prod"));
+    assert!(out.lines().any(|line| line == "use-context prod"));
+
+    let output = doo(config_dir, &["context", "broken"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("so the switch was undone"));
+    assert_eq!(
+        std::fs::read_to_string(config_dir.join("current_context"))?,
+        "prod"
+    );
+
+    let output = doo(config_dir, &["context", "broken", "--no-hooks"]);
+    assert!(output.status.success());
+    assert!(!stdout(&output).contains("on_switch"));
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_context_env() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path();
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        r##"commands:
+  cloud: "sh -c 'echo profile=$AWS_PROFILE region=$REGION'"
+  local:
+    command: "sh -c 'echo region=$REGION'"
+    env:
+      REGION: local-1
+contexts:
+  prod:
+    env:
+      AWS_PROFILE: "#profile"
+      REGION: us-east-1
+"##,
+    )?;
+
+    assert!(doo(config_dir, &["context", "prod"]).status.success());
+    let output = doo(config_dir, &["var", "profile", "prod-admin"]);
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("No command references"));
+
+    let output = doo(config_dir, &["cloud"]);
+    assert!(stdout(&output).contains("profile=prod-admin region=us-east-1"));
+    let output = doo(config_dir, &["local"]);
+    assert!(stdout(&output).contains("region=local-1"));
+    // Other contexts don't get it
+    let output = doo(config_dir, &["-c", "default", "cloud"]);
+    assert!(stdout(&output).contains("profile= region="));
+
+    let output = doo(config_dir, &["context", "show"]);
+    assert!(stdout(&output).contains("AWS_PROFILE=#profile"));
+    let output = doo(config_dir, &["context", "show", "-o", "json"]);
+    let summary = &json_lines(&output)[0];
+    assert_eq!(summary["env"]["REGION"], "us-east-1");
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_one_off_context() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path();
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        "commands:\n  greet: 'echo #1 #ns'\n",
+    )?;
+
+    assert!(doo(config_dir, &["var", "ns", "dev"]).status.success());
+    let output = doo(config_dir, &["-c", "prod", "var", "ns", "live"]);
+    assert!(stdout(&output).contains("in context prod"));
+
+    // The context name may also be the name of the command
+    let output = doo(config_dir, &["--context", "prod", "greet", "greet"]);
+    assert!(stdout(&output).contains("greet live"));
+    let output = doo(config_dir, &["greet", "hi"]);
+    assert!(stdout(&output).contains("hi dev"));
+    assert!(!config_dir.join("current_context").exists());
+
+    let output = doo(config_dir, &["-c", "prod", "status"]);
+    assert!(stdout(&output).contains("prod (one-off)"));
+    assert!(!doo(config_dir, &["-c", "../prod", "status"])
+        .status
+        .success());
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_var_undo() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path();
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        "commands:\n  ns: 'echo #1'\n",
+    )?;
+
+    for value in ["production", "staging", "dev", "qa"] {
+        assert!(doo(config_dir, &["var", "#1", value]).status.success());
+    }
+    let output = doo(config_dir, &["var", "history", "#1"]);
+    let history = stdout(&output);
+    assert!(history.find("dev").unwrap() < history.find("production").unwrap());
+
+    // Each undo goes back one value, most recent first
+    for expected in ["dev", "staging", "production"] {
+        let output = doo(config_dir, &["var", "undo", "#1"]);
+        assert!(output.status.success());
+        assert!(stdout(&output).contains(&format!("restored to {expected}")));
+        let output = doo(config_dir, &["var", "get", "#1"]);
+        assert_eq!(stdout(&output).trim(), expected);
+    }
+    let output = doo(config_dir, &["var", "undo", "#1"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("has no previous value"));
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_inherited_variables() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path();
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        "commands:\n  target: 'echo #cluster #region'\n",
+    )?;
+
+    assert!(doo(config_dir, &["context", "staging"]).status.success());
+    assert!(doo(config_dir, &["var", "cluster", "k8s-staging"])
+        .status
+        .success());
+    assert!(doo(config_dir, &["var", "region", "us-east-1"])
+        .status
+        .success());
+    assert!(doo(
+        config_dir,
+        &["context", "staging-eu", "--parent", "staging"]
+    )
+    .status
+    .success());
+    assert!(doo(config_dir, &["var", "region", "eu-west-1"])
+        .status
+        .success());
+
+    let output = doo(config_dir, &["target"]);
+    assert!(stdout(&output).contains("k8s-staging eu-west-1"));
+
+    let output = doo(config_dir, &["var", "list"]);
+    let listed = stdout(&output);
+    assert!(listed.contains("k8s-staging (from staging)"), "{listed}");
+    assert!(!listed.contains("Placeholders without a value"));
+    let output = doo(config_dir, &["var", "list", "-o", "json"]);
+    let listing: VariableListing = serde_json::from_str(&stdout(&output))?;
+    assert_eq!(
+        listing.vars["#cluster"].inherited_from.as_deref(),
+        Some("staging")
+    );
+    assert_eq!(listing.vars["#region"].inherited_from, None);
+
+    // Removing the override brings back the parent's value
+    let output = doo(config_dir, &["var", "remove", "region"]);
+    assert!(stdout(&output).contains("The value from context staging applies again"));
+    let output = doo(config_dir, &["target"]);
+    assert!(stdout(&output).contains("k8s-staging us-east-1"));
+
+    // Inherited values are only removed from the context that sets them
+    let output = doo(config_dir, &["var", "remove", "cluster"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("inherited from 'staging'"));
+    let output = doo(config_dir, &["-c", "staging", "var", "get", "cluster"]);
+    assert_eq!(stdout(&output).trim(), "k8s-staging");
+
+    // A hand-edited parent cycle is an error instead of a silent fallback
+    std::fs::write(
+        config_dir.join("context_meta.yaml"),
+        "contexts:\n  staging:\n    parent: staging-eu\n  staging-eu:\n    parent: staging\n",
+    )?;
+    let output = doo(config_dir, &["target"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("staging-eu -> staging -> staging-eu"));
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_context_pin() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    let project = temp_dir.path().join("project");
+    std::fs::create_dir_all(project.join("src"))?;
+    let doo_in = |dir: &Path, args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_doo"))
+            .args(args)
+            .current_dir(dir)
+            .env("DOO_CONFIG_DIR", &config_dir)
+            .env("NO_COLOR", "1")
+            .output()
+            .expect("failed to run doo")
+    };
+
+    assert!(doo_in(&project, &["context", "staging"]).status.success());
+    let output = doo_in(&project, &["context", "pin", "cluster-a"]);
+    assert!(output.status.success());
+    assert!(project.join(".doo-context").exists());
+
+    let output = doo_in(&project.join("src"), &["context", "show", "-o", "json"]);
+    let summary = &json_lines(&output)[0];
+    assert_eq!(summary["context"], "cluster-a");
+    assert!(summary["pinned_by"]
+        .as_str()
+        .unwrap()
+        .ends_with(".doo-context"));
+    let out = stdout(&doo_in(&project, &["context", "show"]));
+    assert!(out.contains("Pinned by"));
+    // Elsewhere the persisted context is still active
+    let out = stdout(&doo_in(temp_dir.path(), &["context", "show"]));
+    assert!(out.contains("Context   staging"));
+
+    let output = doo_in(&project, &["context", "production"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("pins context cluster-a"));
+    let output = doo_in(&project, &["context", "unpin"]);
+    assert!(stdout(&output).contains("Active context: production"));
+    assert!(!project.join(".doo-context").exists());
+    assert!(!doo_in(&project, &["context", "unpin"]).status.success());
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_context_delete() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+
+    assert!(doo(&config_dir, &["context", "staging"]).status.success());
+    assert!(doo(&config_dir, &["var", "#1", "stage-ns"])
+        .status
+        .success());
+    assert!(doo(&config_dir, &["var", "cluster", "eu-1"])
+        .status
+        .success());
+
+    let output = doo(&config_dir, &["context", "delete", "staging", "--yes"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--force"));
+
+    // Without a terminal there is nobody to confirm
+    let output = doo(&config_dir, &["context", "delete", "staging", "--force"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--yes"));
+
+    let output = doo(
+        &config_dir,
+        &["context", "delete", "staging", "--force", "--yes"],
+    );
+    assert!(output.status.success());
+    let out = stdout(&output);
+    assert!(out.contains("Discarded 2 variables"));
+    assert!(out.contains("Active context: default"));
+    assert!(!config_dir.join("variables").join("staging.yaml").exists());
+
+    let output = doo(&config_dir, &["context", "delete", "staging", "--yes"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("does not exist"));
+    let output = doo(&config_dir, &["context", "delete", "default", "--yes"]);
+    assert!(!output.status.success());
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cli_run_all() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(&config_dir)?;
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        r##"commands:
+  test-api: "echo api #env"
+  test-web: "sh -c 'echo web; exit 2'"
+  build: "echo build"
+"##,
+    )?;
+    assert!(doo(&config_dir, &["var", "env", "staging"])
+        .status
+        .success());
+
+    let output = doo(&config_dir, &["run-all", "test-*", "-j", "2"]);
+    assert_eq!(output.status.code(), Some(1));
+    let out = stdout(&output);
+    assert!(out.contains("test-api: echo api staging"));
+    assert!(out.contains("\napi staging\n"));
+    assert!(out.contains("\nweb\n"));
+    assert!(out.contains("exit code 2"));
+    assert!(out.contains("1 of 2 commands failed: test-web"));
+    assert!(!out.contains("build"));
+
+    let output = doo(&config_dir, &["run-all", "b*", "-o", "json"]);
+    assert!(output.status.success());
+    let results = json_lines(&output);
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0]["name"], "build");
+    assert_eq!(results[0]["exit_code"], 0);
+    assert_eq!(results[0]["stdout"], "build\n");
+
+    let output = doo(&config_dir, &["--dry-run", "run-all", "*"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("Dry run: build: echo build"));
+    let history = stdout(&doo(&config_dir, &["history"]));
+    assert!(history.contains("test-web"));
+
+    let output = doo(&config_dir, &["run-all", "deploy*"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No command matches 'deploy*'"));
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_command_add_remove_list() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(config_dir.join("configs"))?;
+    std::fs::write(config_dir.join("config.yaml"), "commands: {}\n")?;
+    std::fs::write(
+        config_dir.join("configs").join("docker.yaml"),
+        "commands:\n  ps: docker ps\n",
+    )?;
+
+    let output = doo(
+        &config_dir,
+        &[
+            "command",
+            "add",
+            "hello",
+            "echo hello #1",
+            "--description",
+            "Greet",
+        ],
+    );
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("Command hello added"));
+    assert!(stdout(&doo(&config_dir, &["hello", "world"])).contains("hello world"));
+
+    // Reserved names are rejected
+    assert!(!doo(&config_dir, &["command", "add", "var", "echo"])
+        .status
+        .success());
+
+    let listing = stdout(&doo(&config_dir, &["command", "list"]));
+    let line = listing.lines().find(|l| l.contains("hello")).unwrap();
+    assert!(line.contains("main") && line.contains("echo hello #1"));
+    let line = listing.lines().find(|l| l.contains("ps")).unwrap();
+    assert!(line.contains("docker") && line.contains("docker ps"));
+
+    let commands = json_lines(&doo(&config_dir, &["-o", "json", "command", "list"]));
+    assert_eq!(
+        commands[0],
+        serde_json::json!({
+            "name": "hello",
+            "command": "echo hello #1",
+            "description": "Greet",
+            "source": "main",
+        })
+    );
+    assert_eq!(commands[1]["source"], "docker");
+
+    let output = doo(&config_dir, &["command", "remove", "hello"]);
+    assert!(output.status.success());
+    assert!(!stdout(&doo(&config_dir, &["command", "list"])).contains("hello"));
+    // Only commands of the main config can be removed
+    assert!(!doo(&config_dir, &["command", "remove", "ps"])
+        .status
+        .success());
+
+    Ok(())
+}
+
+/// Parse newline-delimited JSON output
+fn json_lines(output: &Output) -> Vec<serde_json::Value> {
+    stdout(output)
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("invalid JSON line"))
+        .collect()
+}
+
+#[test]
+fn test_cli_json_output() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(&config_dir)?;
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        "commands:\n  pods:\n    command: \"kubectl get pods -n #ns #1\"\n    description: List pods\n  top: kubectl top nodes\n",
+    )?;
+    assert!(doo(&config_dir, &["var", "ns", "prod"]).status.success());
+    assert!(doo(&config_dir, &["var", "--secret", "token", "s3cr3t"])
+        .status
+        .success());
+
+    let results = json_lines(&doo(&config_dir, &["--output", "json", "search", "pods"]));
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0]["name"], "pods");
+    assert_eq!(results[0]["command"], "kubectl get pods -n #ns #1");
+    assert_eq!(results[0]["description"], "List pods");
+    let results = json_lines(&doo(&config_dir, &["-o", "json", "search"]));
+    assert_eq!(results.len(), 2);
+    assert!(results[1]["description"].is_null());
+
+    let output = doo(&config_dir, &["-o", "json", "var", "list"]);
+    let listing: VariableListing = serde_json::from_str(&stdout(&output))?;
+    assert_eq!(listing.context, "default");
+    let ns = &listing.vars["#ns"];
+    assert_eq!(ns.value, Some(VarValue::from("prod")));
+    assert!(!ns.secret);
+    assert_eq!(ns.used_by, vec!["pods"]);
+    assert_eq!(listing.vars["#token"].value, Some(VarValue::from("*****")));
+    assert!(listing.vars["#token"].secret);
+    assert_eq!(listing.vars["#1"].value, None);
+    assert!(listing.commands.is_empty());
+    // The flag can also be given to the subcommand, without colors
+    let output = doo(&config_dir, &["var", "list", "--output", "json"]);
+    let json: serde_json::Value = serde_json::from_str(&stdout(&output))?;
+    assert_eq!(json["vars"]["#ns"]["value"], "prod");
+    assert!(json["vars"]["#ns"]["expires_at"].is_null());
+
+    assert!(
+        doo(&config_dir, &["context", "staging", "--parent", "default"])
+            .status
+            .success()
+    );
+    let output = doo(&config_dir, &["context", "list", "-o", "json"]);
+    let contexts: serde_json::Value = serde_json::from_str(&stdout(&output))?;
+    assert_eq!(
+        contexts,
+        serde_json::json!([
+            {"name": "default", "parent": null, "active": false, "variables": 2},
+            {"name": "staging", "parent": "default", "active": true, "variables": 0},
+        ])
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_missing_placeholders_without_tty() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(&config_dir)?;
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        "commands:\n  greet: \"echo hello #1 #2\"\n",
+    )?;
+
+    // Without a terminal doo refuses to run a command with holes in it
+    let output = doo(&config_dir, &["greet", "world"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("placeholders without a value: #2"));
+
+    // --no-prompt keeps the old behavior for scripts
+    let output = doo(&config_dir, &["--no-prompt", "greet", "world"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("hello world #2"));
+
+    Ok(())
+}
+
+// Builds with the feature would talk to the real keychain
+#[cfg(not(feature = "keyring"))]
+#[test]
+fn test_cli_keychain_needs_the_keyring_feature() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+
+    let output = doo(&config_dir, &["var", "migrate-secrets"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("No secrets left to migrate"));
+    assert!(stdout(&output).contains("secret_store: keyring"));
+
+    assert!(doo(&config_dir, &["var", "--secret", "token", "s3cr3t"])
+        .status
+        .success());
+    let output = doo(&config_dir, &["var", "migrate-secrets"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--features keyring"));
+    // Nothing was moved
+    assert_eq!(
+        stdout(&doo(&config_dir, &["var", "get", "token"])).trim(),
+        "s3cr3t"
+    );
+
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        "commands: {}\nsecret_store: keyring\n",
+    )?;
+    // Saving with the keychain store moves the file's other secrets too, so
+    // the error may name either of them
+    let output = doo(&config_dir, &["var", "--secret", "api", "xyz"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("in the system keychain") && stderr.contains("--features keyring"));
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_secret_variables_are_masked() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(&config_dir)?;
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        "commands:\n  auth: \"echo token=#token\"\n",
+    )?;
+
+    let output = doo(&config_dir, &["var", "--secret", "token", "s3cr3t"]);
+    assert!(output.status.success());
+    assert!(!stdout(&output).contains("s3cr3t"));
+
+    // The child gets the real value, the echoed command line doesn't
+    let output = doo(&config_dir, &["auth"]);
+    let out = stdout(&output);
+    assert!(out.contains("Executing: echo token=*****"));
+    assert!(out.contains("\ntoken=s3cr3t\n"));
+
+    let listing = stdout(&doo(&config_dir, &["var", "list"]));
+    assert!(listing.contains("#token") && listing.contains("***** (secret, updated just now)"));
+    assert!(!listing.contains("s3cr3t"));
+    let listing = stdout(&doo(&config_dir, &["var", "list", "--reveal"]));
+    assert!(listing.contains("s3cr3t (secret, updated just now)"));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cli_dry_run_does_not_execute() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(&config_dir)?;
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        "commands:\n  create: \"touch #target\"\n",
+    )?;
+    let target = temp_dir.path().join("created");
+    let target = target.to_str().unwrap();
+
+    assert!(doo(&config_dir, &["var", "target", target])
+        .status
+        .success());
+    let output = doo(&config_dir, &["--dry-run", "create"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains(&format!("Dry run: touch {target}")));
+    assert!(!Path::new(target).exists());
+
+    assert!(doo(&config_dir, &["create"]).status.success());
+    assert!(Path::new(target).exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_completions_list_configured_commands() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(&config_dir)?;
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        "commands:\n  deploy: echo deploy\n  pods: echo pods\n",
+    )?;
+
+    let output = doo(&config_dir, &["__complete_commands"]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "deploy\npods\n");
+
+    let output = doo(&config_dir, &["completions", "bash"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("complete -F _doo_with_commands"));
+    assert!(!doo(&config_dir, &["completions", "tcsh"]).status.success());
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_rejects_values_outside_constraints() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(&config_dir)?;
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        r##"commands:
+  deploy:
+    command: "echo deploying #1"
+    args:
+      "#1":
+        pattern: "^(staging|production)$"
+"##,
+    )?;
+
+    let output = doo(&config_dir, &["deploy", "staging"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("deploying staging"));
+
+    // Stored variables are checked as well, before anything runs
+    assert!(doo(&config_dir, &["var", "#1", "prod"]).status.success());
+    let output = doo(&config_dir, &["deploy"]);
+    assert!(!output.status.success());
+    assert!(!stdout(&output).contains("deploying"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains(
+        "Invalid value 'prod' for #1 of command 'deploy': expected a value matching '^(staging|production)$'"
+    ));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cli_escaped_hash_is_passed_literally() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(&config_dir)?;
+    let source = temp_dir.path().join("main.c");
+    std::fs::write(&source, "#include <stdio.h>\nint main;\n#include <x.h>\n")?;
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        "commands:\n  includes: \"grep -c ##include #1\"\n",
+    )?;
+
+    let output = doo(&config_dir, &["includes", source.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains(&format!("grep -c #include {}", source.display())));
+    assert!(stdout(&output).contains("\n2\n"));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cli_command_env_from_config() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(&config_dir)?;
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        r##"commands:
+  show:
+    command: "sh -c 'echo TEST_VAR=$TEST_VAR'"
+    env:
+      TEST_VAR: "#target-$1"
+"##,
+    )?;
+
+    assert!(doo(&config_dir, &["var", "target", "staging"])
+        .status
+        .success());
+    let output = doo(&config_dir, &["show", "eu"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("TEST_VAR=staging-eu\n"));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cli_command_cwd_from_config() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    let project_dir = temp_dir.path().join("projects").join("backend");
+    std::fs::create_dir_all(&config_dir)?;
+    std::fs::create_dir_all(&project_dir)?;
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        format!(
+            "commands:\n  here:\n    command: pwd -P\n    cwd: \"{}/#project\"\n",
+            temp_dir.path().join("projects").display()
+        ),
+    )?;
+
+    assert!(doo(&config_dir, &["var", "project", "backend"])
+        .status
+        .success());
+    let output = doo(&config_dir, &["here"]);
+    assert!(output.status.success());
+    let expected = project_dir.canonicalize()?;
+    assert!(stdout(&output).contains(&format!("{}\n", expected.display())));
+
+    assert!(doo(&config_dir, &["var", "project", "frontend"])
+        .status
+        .success());
+    let output = doo(&config_dir, &["here"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("does not exist"));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_execute_with_timeout_kills_long_running_command() {
+    let executor = CommandExecutor::new();
+    let started = Instant::now();
+    let err = executor
+        .execute_with_timeout(
+            "sleep 60",
+            Duration::from_millis(500),
+            &BTreeMap::new(),
+            None,
+            None,
+        )
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("timed out"));
+    assert!(started.elapsed() < Duration::from_secs(10));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cli_command_timeout_from_config() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(&config_dir)?;
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        "commands:\n  hang:\n    command: sleep 60\n    timeout_secs: 1\n",
+    )?;
+
+    let started = Instant::now();
+    let output = doo(&config_dir, &["hang"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("timed out after 1s"));
+    assert!(started.elapsed() < Duration::from_secs(10));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cli_shell_command() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(&config_dir)?;
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        r#"commands:
+  chain:
+    command: "echo a && echo #1"
+    shell: true
+    quote_args: true
+  split: "echo a && echo b"
+"#,
+    )?;
+
+    let output = doo(&config_dir, &["chain", "b; echo injected"]);
+    assert!(output.status.success());
+    let out = stdout(&output);
+    let lines: Vec<_> = out.lines().collect();
+    assert!(lines.contains(&"a"));
+    // quote_args keeps the value a single argument for the shell
+    assert!(lines.contains(&"b; echo injected"));
+    assert!(!lines.contains(&"injected"));
+
+    let out = stdout(&doo(&config_dir, &["split"]));
+    assert!(out.contains("a && echo b"));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cli_command_retry_from_config() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(&config_dir)?;
+    // Fails the first time it runs in the directory
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        format!(
+            r#"commands:
+  flaky:
+    command: sh -c 'echo x >> attempts; [ $(wc -l < attempts) -gt 1 ]'
+    cwd: {}
+    retry:
+      max_attempts: 3
+      backoff_secs: 0
+  broken:
+    command: "false"
+    retry:
+      max_attempts: 2
+      backoff_secs: 0
+"#,
+            temp_dir.path().display()
+        ),
+    )?;
+
+    let output = doo(&config_dir, &["flaky"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Command failed (attempt 1/3), retrying in 0s..."));
+    assert!(!stderr.contains("attempt 2/3"));
+    assert_eq!(
+        std::fs::read_to_string(temp_dir.path().join("attempts"))?
+            .lines()
+            .count(),
+        2
+    );
+
+    let output = doo(&config_dir, &["broken"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("(attempt 1/2)"));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cli_forwards_exit_code() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(&config_dir)?;
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        "commands:\n  fail: \"false\"\n  three: sh -c 'exit 3'\n  ok: \"true\"\n",
+    )?;
+
+    assert_eq!(doo(&config_dir, &["fail"]).status.code(), Some(1));
+    assert_eq!(doo(&config_dir, &["three"]).status.code(), Some(3));
+    assert_eq!(doo(&config_dir, &["ok"]).status.code(), Some(0));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cli_list_variable_expands_to_arguments() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(&config_dir)?;
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        "commands:\n  count: \"sh -c 'echo $#' sh #args\"\n",
+    )?;
+
+    let output = doo(
+        &config_dir,
+        &["var", "args", "--", "--one", "two words", "three"],
+    );
+    assert!(output.status.success());
+    let output = doo(&config_dir, &["count"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("\n3\n"));
+
+    let output = doo(&config_dir, &["var", "list"]);
+    assert!(stdout(&output).contains("--one 'two words' three (list of 3, updated just now)"));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cli_quote_args_preview_matches_execution() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(&config_dir)?;
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        "commands:\n  say:\n    command: \"echo #1 #msg\"\n    quote_args: true\n",
+    )?;
+
+    assert!(doo(&config_dir, &["var", "msg", "it's $HOME"])
+        .status
+        .success());
+    let output = doo(&config_dir, &["--dry-run", "say", "a  b"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains(r"Dry run: echo 'a  b' 'it'\''s $HOME'"));
+
+    let output = doo(&config_dir, &["say", "a  b"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("a  b it's $HOME\n"));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cli_dotenv_variables() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(&config_dir)?;
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        "commands:\n  region: \"echo region=#REGION\"\n",
+    )?;
+    let project = temp_dir.path().join("project");
+    std::fs::create_dir_all(&project)?;
+    std::fs::write(project.join(".env"), "REGION=eu-west-1\nbroken line\n")?;
+    let doo_in_project = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_doo"))
+            .args(args)
+            .current_dir(&project)
+            .env("DOO_CONFIG_DIR", &config_dir)
+            .env("NO_COLOR", "1")
+            .output()
+            .expect("failed to run doo")
+    };
+
+    // Without --dotenv the file is ignored
+    let output = doo_in_project(&["--no-prompt", "region"]);
+    assert!(!stdout(&output).contains("region=eu-west-1"));
+
+    let output = doo_in_project(&["--dotenv", "region"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("region=eu-west-1"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Skipped malformed lines in .env: 2"));
+
+    // The main config can turn it on for every run
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        "commands:\n  region: \"echo region=#REGION\"\nload_dotenv: true\n",
+    )?;
+    let output = doo_in_project(&["region"]);
+    assert!(stdout(&output).contains("region=eu-west-1"));
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_set_multiple_variables() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+
+    let output = doo(
+        &config_dir,
+        &["var", "#1", "prod", "#2", "api-pod", "#3", "eu-west-1"],
+    );
+    assert!(output.status.success());
+    let text = stdout(&output);
+    assert!(text.contains("Set 3 variables in context default"));
+    assert!(text.contains("#2 = api-pod"));
+    assert_eq!(
+        stdout(&doo(&config_dir, &["var", "get", "#3"])),
+        "eu-west-1\n"
+    );
+
+    // An odd number of arguments writes nothing
+    let output = doo(&config_dir, &["var", "#1", "staging", "#2"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Missing a value for '#2'"));
+    assert_eq!(stdout(&doo(&config_dir, &["var", "get", "#1"])), "prod\n");
+
+    let output = doo(
+        &config_dir,
+        &["var", "--from-pairs", "#1=staging,namespace=web"],
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        stdout(&doo(&config_dir, &["var", "get", "#1"])),
+        "staging\n"
+    );
+    assert_eq!(
+        stdout(&doo(&config_dir, &["var", "get", "namespace"])),
+        "web\n"
+    );
+
+    // Several values after one name still store a list
+    assert!(
+        doo(&config_dir, &["var", "hosts", "web-1", "web-2", "web-3"])
+            .status
+            .success()
+    );
+    assert_eq!(
+        stdout(&doo(&config_dir, &["var", "get", "hosts"])),
+        "web-1 web-2 web-3\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_command_scoped_variables() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(&config_dir)?;
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        "commands:\n  pods: \"echo pods in #1\"\n  show: \"echo show #1\"\n",
+    )?;
+
+    assert!(doo(&config_dir, &["var", "#1", "default-ns"])
+        .status
+        .success());
+    let output = doo(&config_dir, &["var", "--command", "pods", "#1", "web"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("for command pods"));
+
+    assert!(stdout(&doo(&config_dir, &["pods"])).contains("pods in web\n"));
+    assert!(stdout(&doo(&config_dir, &["show"])).contains("show default-ns\n"));
+
+    let listing = stdout(&doo(&config_dir, &["var", "list"]));
+    let scoped = listing
+        .split("Command-scoped variables:")
+        .nth(1)
+        .expect("scoped variables are listed");
+    assert!(scoped.contains("  pods\n    #1  web"));
+
+    let output = doo(&config_dir, &["-o", "json", "var", "list"]);
+    let listing: VariableListing = serde_json::from_str(&stdout(&output))?;
+    let pods = &listing.commands["pods"]["#1"];
+    assert_eq!(pods.value, Some(VarValue::from("web")));
+    assert_eq!(pods.used_by, vec!["pods"]);
+
+    assert!(
+        doo(&config_dir, &["var", "remove", "--command", "pods", "#1"])
+            .status
+            .success()
+    );
+    assert!(stdout(&doo(&config_dir, &["pods"])).contains("pods in default-ns\n"));
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_config_validate() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(&config_dir)?;
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        "commands:\n  pods: \"kubectl get pods -n #1\"\n",
+    )?;
+
+    let output = doo(&config_dir, &["config", "validate"]);
+    assert!(output.status.success());
+
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        "commands:\n  pods: \"kubectl get pods -n #1\"\n  pods: \"echo ${HOME\"\n",
+    )?;
+    let output = doo(&config_dir, &["config", "validate"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("main: command 'pods' is defined more than once"));
+    assert!(stderr.contains("malformed placeholder '${HOME'"));
+
+    Ok(())
+}
+
+/// Answer `connections` HTTP requests on a local port with `respond`, which
+/// gets the request head, returning the base URL
+fn serve<F>(connections: usize, respond: F) -> String
+where
+    F: Fn(&str) -> (u16, &'static str, String) + Send + 'static,
+{
+    use std::io::{Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("failed to bind");
+    let address = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        for _ in 0..connections {
+            let Ok((mut stream, _)) = listener.accept() else {
+                return;
+            };
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                match stream.read(&mut buffer) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => request.extend_from_slice(&buffer[..n]),
+                }
+            }
+            let (status, content_type, body) = respond(&String::from_utf8_lossy(&request));
+            let _ = write!(
+                stream,
+                "HTTP/1.1 {status} X\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+        }
+    });
+    format!("http://{address}")
+}
+
+#[test]
+fn test_cli_import_from_url() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    let base = serve(3, |request| {
+        if request.starts_with("GET /team-commands.yaml ") {
+            (
+                200,
+                "application/yaml",
+                "commands:\n  hello: \"echo hello from url\"\n".to_string(),
+            )
+        } else if request.starts_with("GET /login.yaml ") {
+            (
+                200,
+                "text/html; charset=utf-8",
+                "<html>login</html>".to_string(),
+            )
+        } else {
+            (404, "text/plain", "not found".to_string())
+        }
+    });
+
+    let url = format!("{base}/team-commands.yaml");
+    let output = doo(&config_dir, &["import", &url]);
+    assert!(output.status.success(), "{}", stdout(&output));
+    assert!(stdout(&output).contains("as 'team-commands'"));
+    let saved = std::fs::read_to_string(config_dir.join("configs").join("team-commands.yaml"))?;
+    assert!(saved.contains(&url));
+    let output = doo(&config_dir, &["command", "list"]);
+    assert!(stdout(&output).contains("hello"));
+
+    let output = doo(&config_dir, &["import", &format!("{base}/missing.yaml")]);
+    assert!(!output.status.success());
+    assert!(stdout(&output).contains("HTTP 404"));
+
+    let output = doo(&config_dir, &["import", &format!("{base}/login.yaml")]);
+    assert!(!output.status.success());
+    assert!(stdout(&output).contains("Unexpected content type 'text/html; charset=utf-8'"));
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_command_show() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(&config_dir)?;
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        r##"commands:
+  logs:
+    command: "kubectl logs -n #ns #1"
+    description: Follow the logs of a pod
+    params:
+      "#1": { name: pod, description: Pod to follow }
+      ns: { default: default }
+    examples:
+      - doo logs my-pod
+      - doo logs api-0
+"##,
+    )?;
+
+    let output = doo(&config_dir, &["command", "show", "logs"]);
+    assert!(output.status.success());
+    let text = stdout(&output);
+    assert!(text.contains("Follow the logs of a pod"));
+    assert!(text.contains("Source:   main"));
+    assert!(text.contains("kubectl logs -n #ns #1"));
+    assert!(text.contains("<pod>, Pod to follow"), "{text}");
+    assert!(text.contains("default: default"));
+    assert!(text.contains("Examples:\n  doo logs my-pod\n  doo logs api-0"));
+
+    let output = doo(&config_dir, &["command", "show", "logs", "-o", "json"]);
+    let json: serde_json::Value = serde_json::from_str(&stdout(&output))?;
+    assert_eq!(
+        json["examples"],
+        serde_json::json!(["doo logs my-pod", "doo logs api-0"])
+    );
+    assert_eq!(json["params"]["#1"]["name"], "pod");
+
+    assert!(!doo(&config_dir, &["command", "show", "missing"])
+        .status
+        .success());
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_where() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    let k8s = temp_dir.path().join("k8s.yaml");
+    let helm = temp_dir.path().join("helm.yaml");
+    std::fs::write(
+        &k8s,
+        "commands:\n  deploy:\n    command: kubectl apply -f .\n    description: Apply the manifests\n",
+    )?;
+    std::fs::write(
+        &helm,
+        "commands:\n  deploy: helm upgrade app .\n  lint: helm lint .\norigin:\n  repo: acme/charts\n  import_type: Public\n",
+    )?;
+    for file in [&k8s, &helm] {
+        let output = doo(&config_dir, &["import", file.to_str().unwrap()]);
+        assert!(output.status.success(), "{}", stdout(&output));
+    }
+
+    let output = doo(&config_dir, &["where", "deploy"]);
+    assert!(output.status.success());
+    let text = stdout(&output);
+    assert!(text.contains("defined in 2 configs"), "{text}");
+    assert!(text.contains("k8s") && text.contains("kubectl apply -f ."));
+    assert!(text.contains("Apply the manifests"));
+    assert!(text.contains("helm") && text.contains("helm upgrade app ."));
+
+    let output = doo(&config_dir, &["where", "deploy", "-o", "json"]);
+    let json: serde_json::Value = serde_json::from_str(&stdout(&output))?;
+    let mut sources: Vec<&str> = json["sources"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|source| source["source"].as_str().unwrap())
+        .collect();
+    sources.sort_unstable();
+    assert_eq!(sources, vec!["helm", "k8s"]);
+
+    // A single source also gets its path and the repository it came from
+    let output = doo(&config_dir, &["where", "lint"]);
+    let text = stdout(&output);
+    let path = config_dir.join("configs").join("helm.yaml");
+    assert!(
+        text.contains(&format!("Path:     {}", path.display())),
+        "{text}"
+    );
+    assert!(text.contains("Origin:   https://github.com/acme/charts"));
+
+    let output = doo(&config_dir, &["where", "missing"]);
+    assert!(!output.status.success());
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_config_show() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    let configs_dir = config_dir.join("configs");
+    std::fs::create_dir_all(&configs_dir)?;
+    std::fs::write(config_dir.join("config.yaml"), "commands:\n  hi: echo hi\n")?;
+    let docker = "commands:\n  ps: docker ps\n  build:\n    command: docker build .\n    description: Build the image\norigin:\n  repo: acme/docker\n  import_type: Public\n";
+    std::fs::write(configs_dir.join("docker.yaml"), docker)?;
+
+    let output = doo(&config_dir, &["config", "show", "docker"]);
+    assert!(output.status.success());
+    let text = stdout(&output);
+    assert!(text.contains("Origin:    acme/docker (Public)"));
+    assert!(text.contains("Commands:  2"));
+    assert!(text.contains("  build  docker build .\n         Build the image\n"));
+    assert!(text.contains("  ps     docker ps\n"));
+
+    let output = doo(&config_dir, &["config", "show", "docker", "--raw"]);
+    assert_eq!(stdout(&output), docker);
+
+    let text = stdout(&doo(&config_dir, &["config", "show", "main"]));
+    assert!(text.contains("Origin:    local"));
+    assert!(text.contains("  hi  echo hi\n"));
+
+    let output = doo(&config_dir, &["config", "show", "missing"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Config 'missing' not found"));
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_config_merge() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    let configs_dir = config_dir.join("configs");
+    std::fs::create_dir_all(&configs_dir)?;
+    std::fs::write(config_dir.join("config.yaml"), "commands: {}\n")?;
+    std::fs::write(
+        configs_dir.join("docker.yaml"),
+        "commands:\n  ps: \"echo docker ps\"\n",
+    )?;
+    std::fs::write(
+        configs_dir.join("k8s.yaml"),
+        "commands:\n  pods: \"echo kubectl get pods\"\n",
+    )?;
+    std::fs::write(
+        configs_dir.join("other.yaml"),
+        "commands:\n  ps: \"echo ps aux\"\n",
+    )?;
+
+    let output = doo(&config_dir, &["config", "merge", "docker", "k8s", "devops"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("Merged docker and k8s into devops (2 commands)"));
+    assert!(configs_dir.join("devops.yaml").exists());
+
+    let output = doo(
+        &config_dir,
+        &["config", "merge", "docker", "other", "mixed"],
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("docker: echo docker ps"));
+    assert!(stderr.contains("other: echo ps aux"));
+    assert!(stderr.contains("1 command is defined differently"));
+    assert!(!configs_dir.join("mixed.yaml").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_import_with_namespace() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(&config_dir)?;
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        "commands:\n  deploy: \"echo main deploy\"\n",
+    )?;
+    let base = serve(2, |_| {
+        (
+            200,
+            "application/yaml",
+            "commands:\n  deploy: \"echo infra deploy #1\"\n".to_string(),
+        )
+    });
+    let url = format!("{base}/devops.yaml");
+
+    let output = doo(&config_dir, &["import", &url, "--as", "infra"]);
+    assert!(output.status.success(), "{}", stdout(&output));
+    assert!(stdout(&output).contains("infra:<command>"));
+    assert!(config_dir.join("configs").join("infra.yaml").exists());
+
+    let output = doo(&config_dir, &["infra:deploy", "eu"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("infra deploy eu\n"));
+    assert!(stdout(&doo(&config_dir, &["deploy"])).contains("main deploy\n"));
+
+    // Syncing keeps the namespace
+    assert!(doo(&config_dir, &["sync", "infra"]).status.success());
+    assert!(stdout(&doo(&config_dir, &["infra:deploy", "us"])).contains("infra deploy us\n"));
+
+    let output = doo(&config_dir, &["import", &url, "--as", "infra"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already exists"));
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_sync_single_config() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    // Every fetch after the first serves an updated config
+    let fetches = std::sync::atomic::AtomicUsize::new(0);
+    let base = serve(4, move |_| {
+        let command = match fetches.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+            0 => "echo v1",
+            _ => "echo v2",
+        };
+        (
+            200,
+            "application/yaml",
+            format!("commands:\n  hello: \"{command}\"\n"),
+        )
+    });
+    let url = format!("{base}/team.yaml");
+    assert!(doo(&config_dir, &["import", &url]).status.success());
+    let other = format!("{base}/other.yaml");
+    assert!(doo(&config_dir, &["import", &other]).status.success());
+
+    let output = doo(&config_dir, &["sync", "team"]);
+    assert!(output.status.success(), "{}", stdout(&output));
+    let text = stdout(&output);
+    assert!(text.contains("Before:") && text.contains("After:"));
+    assert!(text.contains("Updated team from its origin"));
+    let saved = std::fs::read_to_string(config_dir.join("configs").join("team.yaml"))?;
+    assert!(saved.contains("echo v2"));
+
+    let output = doo(&config_dir, &["sync", "team"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("team is already up to date"));
+
+    let output = doo(&config_dir, &["sync", "missing"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Config 'missing' not found"));
+
+    std::fs::write(
+        config_dir.join("configs").join("local.yaml"),
+        "commands:\n  hi: echo hi\n",
+    )?;
+    let output = doo(&config_dir, &["sync", "local"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("has no remote origin"));
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_sync_dry_run_reports_drift() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    let fetches = std::sync::atomic::AtomicUsize::new(0);
+    let base = serve(4, move |_| {
+        let commands = match fetches.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+            0 => "  hello: echo hello\n  old: echo old\n",
+            _ => "  hello: echo hi\n  new: echo new\n",
+        };
+        (200, "application/yaml", format!("commands:\n{commands}"))
+    });
+    let url = format!("{base}/team.yaml");
+    assert!(doo(&config_dir, &["import", &url]).status.success());
+    let path = config_dir.join("configs").join("team.yaml");
+    let before = std::fs::read_to_string(&path)?;
+
+    let output = doo(&config_dir, &["sync", "--dry-run"]);
+    assert_eq!(output.status.code(), Some(1));
+    let text = stdout(&output);
+    assert!(text.contains("team differs from its origin: 1 added, 1 removed, 1 changed"));
+    assert!(text.contains("+ new") && text.contains("- old") && text.contains("~ hello"));
+    assert!(text.contains("-  hello: echo hello") && text.contains("+  hello: echo hi"));
+    assert_eq!(std::fs::read_to_string(&path)?, before);
+
+    assert!(doo(&config_dir, &["sync", "team"]).status.success());
+    let output = doo(&config_dir, &["sync", "team", "--dry-run"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).contains("team is up to date"));
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_import_private_repo_with_token() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    // Fake GitHub API that only answers requests with the right token
+    let api = serve(5, |request| {
+        let authorized = request
+            .to_ascii_lowercase()
+            .contains("authorization: bearer ghp_test");
+        let path = request.split(' ').nth(1).unwrap_or_default();
+        match (authorized, path) {
+            (false, _) => (401, "application/json", "{}".to_string()),
+            (true, "/user" | "/repos/acme/private-cmds") => {
+                (200, "application/json", "{}".to_string())
+            }
+            (true, "/repos/acme/private-cmds/contents/doo.yaml") => (
+                200,
+                "application/json",
+                r#"{"name": "doo.yaml", "encoding": "base64", "content": "Y29tbWFuZHM6CiAgc2VjcmV0LWhlbGxv\nOiAiZWNobyBoaSIK"}"#
+                    .to_string(),
+            ),
+            (true, _) => (404, "application/json", "{}".to_string()),
+        }
+    });
+    let import = |token: &str| {
+        Command::new(env!("CARGO_BIN_EXE_doo"))
+            .args(["import", "acme/private-cmds", "--token", token])
+            .env("DOO_CONFIG_DIR", &config_dir)
+            .env("DOO_GITHUB_API_URL", &api)
+            .env("NO_COLOR", "1")
+            .output()
+            .expect("failed to run doo")
+    };
+
+    let output = import("ghp_test");
+    assert!(output.status.success(), "{}", stdout(&output));
+    let saved = std::fs::read_to_string(config_dir.join("configs").join("private-cmds.yaml"))?;
+    assert!(saved.contains("secret-hello"));
+    assert!(saved.contains("import_type: Token"));
+    assert!(!saved.contains("ghp_test"));
+
+    let output = import("wrong");
+    assert!(!output.status.success());
+    assert!(stdout(&output).contains("GitHub rejected the token"));
+
+    let output = doo(
+        &config_dir,
+        &["import", "./local.yaml", "--token", "ghp_test"],
+    );
+    assert!(!output.status.success());
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_broken_variables_point_at_backup() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+
+    assert!(doo(&config_dir, &["var", "ns", "prod"]).status.success());
+    assert!(doo(&config_dir, &["var", "pod", "web"]).status.success());
+    let variables_file = config_dir.join("variables").join("default.yaml");
+    let backup_file = config_dir.join("variables").join("default.yaml.bak");
+    assert!(std::fs::read_to_string(&backup_file)?.contains("prod"));
+
+    // Without a terminal to ask on, doo explains how to restore the backup
+    std::fs::write(&variables_file, "vars: [broken\n")?;
+    let output = doo(&config_dir, &["var", "list"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("default.yaml can't be parsed"));
+    assert!(stderr.contains(&format!("cp {}", backup_file.display())));
+
+    std::fs::copy(&backup_file, &variables_file)?;
+    assert!(stdout(&doo(&config_dir, &["var", "list"])).contains("prod"));
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_command_params() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(&config_dir)?;
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        r##"commands:
+  watch:
+    command: "kubectl -n #1 get #2"
+    params:
+      "#1": { name: namespace, required: true }
+      "#2": { name: resource, default: pods }
+"##,
+    )?;
+
+    let output = doo(&config_dir, &["--dry-run", "watch", "web"]);
+    assert!(stdout(&output).contains("kubectl -n web get pods"));
+
+    let output = doo(&config_dir, &["config", "validate"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("required parameter 'namespace' (#1) has no default and no stored value"));
+
+    assert!(doo(&config_dir, &["var", "#1", "web"]).status.success());
+    assert!(doo(&config_dir, &["config", "validate"]).status.success());
+
+    Ok(())
+}
+
+#[test]
+fn test_cli_ci_fails_on_missing_placeholders() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let config_dir = temp_dir.path().join("doo");
+    std::fs::create_dir_all(&config_dir)?;
+    std::fs::write(
+        config_dir.join("config.yaml"),
+        "commands:\n  logs: \"echo logs #1\"\n",
+    )?;
+
+    for output in [
+        doo(&config_dir, &["--ci", "logs"]),
+        Command::new(env!("CARGO_BIN_EXE_doo"))
+            .arg("logs")
+            .env("DOO_CONFIG_DIR", &config_dir)
+            .env("CI", "true")
+            .output()?,
+    ] {
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr)
+            .contains("placeholders without a value: #1. Pass them as arguments or set them with 'doo var'; doo doesn't ask for values in CI"));
+    }
+    // Arguments still fill the placeholders
+    assert!(stdout(&doo(&config_dir, &["--ci", "logs", "web"])).contains("logs web"));
+
+    Ok(())
+}